@@ -0,0 +1,129 @@
+//! Directory change notifications (`fcntl(F_NOTIFY)`).
+
+use alloc::{
+    string::{String, ToString},
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::{
+    ffi::c_int,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use axerrno::AxResult;
+use axfs_ng_vfs::{Location, path::Path};
+use axsync::Mutex;
+use axtask::current;
+use linux_raw_sys::general::{DN_MULTISHOT, POLL_MSG, POLLIN, POLLMSG, POLLRDNORM};
+use starry_core::task::{AsThread, send_signal_to_process};
+use starry_process::Pid;
+
+use super::{Directory, fasync, with_fs};
+
+struct Watch {
+    /// The open directory the watch was set on; the watch goes away with it.
+    dir: Weak<Directory>,
+    /// The process notified.
+    pid: Pid,
+    /// The `DN_*` events watched for.
+    mask: u32,
+}
+
+static WATCHES: Mutex<Vec<Watch>> = Mutex::new(Vec::new());
+/// Number of watches, to skip the lookup when there are none.
+static WATCH_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Sets the events watched on an open directory for the current process,
+/// replacing any previous watch. A `mask` without events removes the watch.
+pub fn set_watch(dir: &Arc<Directory>, mask: u32) {
+    let mut watches = WATCHES.lock();
+    watches.retain(|it| it.dir.strong_count() > 0 && !Weak::ptr_eq(&it.dir, &Arc::downgrade(dir)));
+    if mask & !DN_MULTISHOT != 0 {
+        watches.push(Watch {
+            dir: Arc::downgrade(dir),
+            pid: current().as_thread().proc_data.proc.pid(),
+            mask,
+        });
+    }
+    WATCH_COUNT.store(watches.len(), Ordering::Release);
+}
+
+fn parent_of(path: &str) -> &str {
+    match path.rsplit_once('/') {
+        Some(("", _)) => "/",
+        Some((parent, _)) => parent,
+        None => path,
+    }
+}
+
+/// Notifies the watchers of directory `dir_path` of `event`.
+fn notify_dir(dir_path: &str, event: u32) {
+    let mut targets = Vec::new();
+    let mut watches = WATCHES.lock();
+    watches.retain(|watch| {
+        let Some(dir) = watch.dir.upgrade() else {
+            return false;
+        };
+        if watch.mask & event == 0
+            || dir
+                .inner()
+                .absolute_path()
+                .map_or(true, |path| path.as_str() != dir_path)
+        {
+            return true;
+        }
+        targets.push((
+            watch.pid,
+            fasync::signal_for(Arc::as_ptr(&dir), POLL_MSG, POLLIN | POLLRDNORM | POLLMSG),
+        ));
+        // Watches without `DN_MULTISHOT` only fire once.
+        watch.mask & DN_MULTISHOT != 0
+    });
+    WATCH_COUNT.store(watches.len(), Ordering::Release);
+    drop(watches);
+
+    for (pid, sig) in targets {
+        let _ = send_signal_to_process(pid, Some(sig));
+    }
+}
+
+/// Returns whether any directory is watched.
+pub fn active() -> bool {
+    WATCH_COUNT.load(Ordering::Acquire) != 0
+}
+
+/// Reports `event` on an entry of directory `dir` to its watchers.
+pub fn notify_in(dir: &Location, event: u32) {
+    if !active() {
+        return;
+    }
+    if let Ok(path) = dir.absolute_path() {
+        notify_dir(path.as_str(), event);
+    }
+}
+
+/// Reports `event` on the file or directory at `loc` to the watchers of its
+/// parent directory.
+pub fn notify(loc: &Location, event: u32) {
+    if !active() {
+        return;
+    }
+    if let Ok(path) = loc.absolute_path() {
+        notify_dir(parent_of(path.as_str()), event);
+    }
+}
+
+/// Reports `event` on the entry `path`, resolved relative to `dirfd`, to the
+/// watchers of its parent directory.
+pub fn notify_at(dirfd: c_int, path: &str, event: u32) {
+    if !active() {
+        return;
+    }
+    let dir_path: AxResult<String> = with_fs(dirfd, |fs| {
+        let (dir, _) = fs.resolve_parent(Path::new(path))?;
+        Ok(dir.absolute_path()?.to_string())
+    });
+    if let Ok(dir_path) = dir_path {
+        notify_dir(&dir_path, event);
+    }
+}
@@ -11,6 +11,7 @@ use alloc::{
     collections::vec_deque::VecDeque,
     sync::{Arc, Weak},
     task::Wake,
+    vec::Vec,
 };
 use core::{
     any::Any,
@@ -21,6 +22,7 @@ use core::{
 
 use axerrno::{AxError, AxResult};
 use axpoll::{IoEvents, PollSet, Pollable};
+use axsync::Mutex;
 use bitflags::bitflags;
 use hashbrown::HashMap;
 use kspin::SpinNoPreempt;
@@ -241,10 +243,19 @@ impl Wake for InterestWaker {
     }
 }
 
+/// The maximum number of epoll instances nested below one, like Linux.
+const EP_MAX_NESTS: usize = 4;
+
+/// Serializes the additions of epoll instances to others, like the
+/// `epnested_mutex` of Linux.
+static NESTING: Mutex<()> = Mutex::new(());
+
 struct EpollInner {
     interests: SpinNoPreempt<HashMap<EntryKey, Arc<EpollInterest>>>,
     ready_queue: SpinNoPreempt<VecDeque<Weak<EpollInterest>>>,
     poll_ready: PollSet,
+    /// The epoll instances this one has been added to.
+    parents: SpinNoPreempt<Vec<Weak<EpollInner>>>,
 }
 
 impl Default for EpollInner {
@@ -253,13 +264,57 @@ impl Default for EpollInner {
             interests: SpinNoPreempt::new(HashMap::new()),
             ready_queue: SpinNoPreempt::new(VecDeque::new()),
             poll_ready: PollSet::new(),
+            parents: SpinNoPreempt::new(Vec::new()),
         }
     }
 }
 
+impl EpollInner {
+    /// Returns the epoll instances added to this one.
+    fn children(&self) -> Vec<Arc<EpollInner>> {
+        self.interests
+            .lock()
+            .keys()
+            .filter_map(|key| key.get_file()?.into_any().downcast::<Epoll>().ok())
+            .map(|epoll| epoll.inner.clone())
+            .collect()
+    }
+
+    /// Returns whether `other` is this instance or nested below it.
+    fn reaches(&self, other: &EpollInner) -> bool {
+        core::ptr::eq(self, other) || self.children().iter().any(|it| it.reaches(other))
+    }
+
+    /// Returns the length of the longest chain of instances nested below.
+    fn depth_below(&self) -> usize {
+        self.children()
+            .iter()
+            .map(|it| it.depth_below() + 1)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Returns the length of the longest chain of instances this one is
+    /// nested in.
+    fn depth_above(&self) -> usize {
+        let parents = self
+            .parents
+            .lock()
+            .iter()
+            .filter_map(Weak::upgrade)
+            .collect::<Vec<_>>();
+        parents
+            .iter()
+            .map(|it| it.depth_above() + 1)
+            .max()
+            .unwrap_or(0)
+    }
+}
+
 #[derive(Default)]
 pub struct Epoll {
     inner: Arc<EpollInner>,
+    non_blocking: AtomicBool,
 }
 
 impl Epoll {
@@ -316,8 +371,29 @@ impl Epoll {
         }
     }
 
+    /// Checks that `target` can be added to this instance without creating a
+    /// cycle or nesting too deep.
+    fn check_nesting(&self, target: &Epoll) -> AxResult<()> {
+        if Arc::ptr_eq(&self.inner, &target.inner) {
+            return Err(AxError::InvalidInput);
+        }
+        if target.inner.reaches(&self.inner)
+            || self.inner.depth_above() + 1 + target.inner.depth_below() > EP_MAX_NESTS
+        {
+            return Err(AxError::FilesystemLoop);
+        }
+        Ok(())
+    }
+
     pub fn add(&self, fd: i32, event: EpollEvent, flags: EpollFlags) -> AxResult<()> {
         let key = EntryKey::new(fd)?;
+        let target = get_file_like(fd)?.into_any().downcast::<Epoll>().ok();
+        // Held until the new link is in place, so that concurrent additions
+        // can't create a cycle together.
+        let _nesting = target.as_ref().map(|_| NESTING.lock());
+        if let Some(target) = &target {
+            self.check_nesting(target)?;
+        }
         let interest = Arc::new(EpollInterest::new(key.clone(), event, flags));
         let mut guard = self.inner.interests.lock();
         if guard.contains_key(&key) {
@@ -325,6 +401,13 @@ impl Epoll {
         }
         guard.insert(key.clone(), Arc::clone(&interest));
         drop(guard);
+        if let Some(target) = target {
+            target
+                .inner
+                .parents
+                .lock()
+                .push(Arc::downgrade(&self.inner));
+        }
         trace!("Epoll add fd: {} interest {:?} ", fd, interest.event.events);
         self.check_and_register_waker(&interest);
         Ok(())
@@ -359,6 +442,18 @@ impl Epoll {
             .lock()
             .remove(&key)
             .ok_or(AxError::NotFound)?;
+        if let Some(target) = key
+            .get_file()
+            .and_then(|file| file.into_any().downcast::<Epoll>().ok())
+        {
+            let mut parents = target.inner.parents.lock();
+            if let Some(index) = parents
+                .iter()
+                .position(|it| core::ptr::eq(it.as_ptr(), Arc::as_ptr(&self.inner)))
+            {
+                parents.swap_remove(index);
+            }
+        }
         trace!("Epoll: delete fd={fd}");
         Ok(())
     }
@@ -453,6 +548,15 @@ impl FileLike for Epoll {
     fn into_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
         self
     }
+
+    fn nonblocking(&self) -> bool {
+        self.non_blocking.load(Ordering::Acquire)
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> AxResult {
+        self.non_blocking.store(nonblocking, Ordering::Release);
+        Ok(())
+    }
 }
 
 impl Pollable for Epoll {
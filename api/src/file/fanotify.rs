@@ -0,0 +1,542 @@
+//! Filesystem event notification (`fanotify`).
+//!
+//! Groups created with `FAN_CLASS_CONTENT` or `FAN_CLASS_PRE_CONTENT` may also
+//! receive permission events: the operation waits until the reader answers
+//! with `FAN_ALLOW` or `FAN_DENY`, and fails with `EPERM` when denied.
+
+use alloc::{
+    borrow::Cow,
+    collections::VecDeque,
+    string::{String, ToString},
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::{
+    any::Any,
+    ffi::c_int,
+    sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering},
+    task::Context,
+};
+
+use axerrno::{AxError, AxResult};
+use axfs_ng::{FS_CONTEXT, OpenOptions, OpenResult};
+use axfs_ng_vfs::Location;
+use axio::{Buf, BufMut, Read, Write};
+use axpoll::{IoEvents, PollSet, Pollable};
+use axsync::Mutex;
+use axtask::{current, future::Poller};
+use linux_raw_sys::general::{O_CLOEXEC, O_NONBLOCK, O_RDWR, O_WRONLY};
+use starry_core::task::AsThread;
+use zerocopy::{Immutable, IntoBytes};
+
+use super::{Directory, File, FileLike, Kstat, SealedBuf, SealedBufMut, add_file_like};
+use crate::vfs::mounts;
+
+pub const FAN_ACCESS: u64 = 0x0000_0001;
+pub const FAN_MODIFY: u64 = 0x0000_0002;
+pub const FAN_CLOSE_WRITE: u64 = 0x0000_0008;
+pub const FAN_CLOSE_NOWRITE: u64 = 0x0000_0010;
+pub const FAN_OPEN: u64 = 0x0000_0020;
+pub const FAN_Q_OVERFLOW: u64 = 0x0000_4000;
+pub const FAN_OPEN_PERM: u64 = 0x0001_0000;
+pub const FAN_ACCESS_PERM: u64 = 0x0002_0000;
+pub const FAN_EVENT_ON_CHILD: u64 = 0x0800_0000;
+pub const FAN_ONDIR: u64 = 0x4000_0000;
+
+/// Permission events.
+pub const FAN_ALL_PERM_EVENTS: u64 = FAN_OPEN_PERM | FAN_ACCESS_PERM;
+/// Events that can be marked.
+pub const FAN_ALL_EVENTS: u64 = FAN_ACCESS
+    | FAN_MODIFY
+    | FAN_CLOSE_WRITE
+    | FAN_CLOSE_NOWRITE
+    | FAN_OPEN
+    | FAN_ALL_PERM_EVENTS
+    | FAN_EVENT_ON_CHILD
+    | FAN_ONDIR;
+
+const FAN_ALLOW: u32 = 0x01;
+const FAN_DENY: u32 = 0x02;
+const FAN_AUDIT: u32 = 0x10;
+
+const FAN_NOFD: c_int = -1;
+const FANOTIFY_METADATA_VERSION: u8 = 3;
+
+/// Default maximum number of queued events, like
+/// `/proc/sys/fs/fanotify/max_queued_events`.
+const MAX_QUEUED_EVENTS: usize = 16384;
+
+/// `struct fanotify_event_metadata`.
+#[repr(C)]
+#[derive(Immutable, IntoBytes)]
+struct EventMetadata {
+    event_len: u32,
+    vers: u8,
+    reserved: u8,
+    metadata_len: u16,
+    mask: u64,
+    fd: i32,
+    pid: i32,
+}
+
+const METADATA_LEN: usize = size_of::<EventMetadata>();
+
+/// `struct fanotify_response`.
+const RESPONSE_LEN: usize = 8;
+
+/// The answer to a permission event.
+struct Decision {
+    /// `FAN_ALLOW`, `FAN_DENY`, or 0 while undecided.
+    response: AtomicU32,
+    poll: PollSet,
+}
+
+impl Decision {
+    fn decide(&self, response: u32) {
+        // Only the first answer counts.
+        if self
+            .response
+            .compare_exchange(0, response, Ordering::AcqRel, Ordering::Acquire)
+            .is_ok()
+        {
+            self.poll.wake();
+        }
+    }
+
+    /// Waits for the answer, returning whether the operation is allowed.
+    fn wait(&self) -> AxResult<bool> {
+        Poller::new(self, IoEvents::IN).poll(|| match self.response.load(Ordering::Acquire) {
+            0 => Err(AxError::WouldBlock),
+            response => Ok(response == FAN_ALLOW),
+        })
+    }
+}
+
+impl Pollable for Decision {
+    fn poll(&self) -> IoEvents {
+        let mut events = IoEvents::empty();
+        events.set(IoEvents::IN, self.response.load(Ordering::Acquire) != 0);
+        events
+    }
+
+    fn register(&self, context: &mut Context<'_>, events: IoEvents) {
+        if events.contains(IoEvents::IN) {
+            self.poll.register(context.waker());
+        }
+    }
+}
+
+struct Event {
+    mask: u64,
+    /// Absolute path of the file, opened when the event is read.
+    path: Option<String>,
+    pid: u32,
+    decision: Option<Arc<Decision>>,
+}
+
+struct Mark {
+    /// Absolute path of the marked file, or of the root of the marked mount.
+    path: String,
+    mount: bool,
+    mask: u64,
+    ignored: u64,
+}
+
+/// A fanotify group, returned by `fanotify_init`.
+pub struct Fanotify {
+    /// Whether the group receives permission events.
+    permission: bool,
+    /// Whether events report the thread ID instead of the process ID.
+    report_tid: bool,
+    /// Flags of the file descriptors opened for events.
+    event_flags: u32,
+    unlimited_queue: bool,
+    marks: Mutex<Vec<Mark>>,
+    queue: Mutex<VecDeque<Event>>,
+    /// Permission events read but not answered yet, by file descriptor.
+    pending: Mutex<Vec<(c_int, Arc<Decision>)>>,
+    non_blocking: AtomicBool,
+    poll_rx: PollSet,
+}
+
+static GROUPS: Mutex<Vec<Weak<Fanotify>>> = Mutex::new(Vec::new());
+/// Number of marks of all groups, to skip the lookup when there are none.
+static MARK_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+impl Fanotify {
+    pub fn new(
+        permission: bool,
+        report_tid: bool,
+        unlimited_queue: bool,
+        event_flags: u32,
+    ) -> Arc<Self> {
+        let group = Arc::new(Self {
+            permission,
+            report_tid,
+            event_flags,
+            unlimited_queue,
+            marks: Mutex::new(Vec::new()),
+            queue: Mutex::new(VecDeque::new()),
+            pending: Mutex::new(Vec::new()),
+            non_blocking: AtomicBool::new(false),
+            poll_rx: PollSet::new(),
+        });
+        let mut groups = GROUPS.lock();
+        groups.retain(|it| it.strong_count() > 0);
+        groups.push(Arc::downgrade(&group));
+        group
+    }
+
+    /// Adds `mask` to the events marked on `loc`, or on its mount if `mount`
+    /// is set. With `ignored`, the events are ignored instead.
+    pub fn add_mark(&self, loc: &Location, mount: bool, mask: u64, ignored: bool) -> AxResult<()> {
+        if mask & FAN_ALL_PERM_EVENTS != 0 && !self.permission {
+            return Err(AxError::InvalidInput);
+        }
+        let path = mark_path(loc, mount)?;
+        let mut marks = self.marks.lock();
+        let mark = match marks
+            .iter_mut()
+            .find(|it| it.mount == mount && it.path == path)
+        {
+            Some(mark) => mark,
+            None => {
+                marks.push(Mark {
+                    path,
+                    mount,
+                    mask: 0,
+                    ignored: 0,
+                });
+                MARK_COUNT.fetch_add(1, Ordering::AcqRel);
+                marks.last_mut().unwrap()
+            }
+        };
+        if ignored {
+            mark.ignored |= mask;
+        } else {
+            mark.mask |= mask;
+        }
+        Ok(())
+    }
+
+    /// Removes `mask` from the events marked on `loc`, or on its mount if
+    /// `mount` is set.
+    pub fn remove_mark(
+        &self,
+        loc: &Location,
+        mount: bool,
+        mask: u64,
+        ignored: bool,
+    ) -> AxResult<()> {
+        let path = mark_path(loc, mount)?;
+        let mut marks = self.marks.lock();
+        let index = marks
+            .iter()
+            .position(|it| it.mount == mount && it.path == path)
+            .ok_or(AxError::NotFound)?;
+        let mark = &mut marks[index];
+        if ignored {
+            mark.ignored &= !mask;
+        } else {
+            mark.mask &= !mask;
+        }
+        if mark.mask & !(FAN_EVENT_ON_CHILD | FAN_ONDIR) == 0 && mark.ignored == 0 {
+            marks.remove(index);
+            MARK_COUNT.fetch_sub(1, Ordering::AcqRel);
+        }
+        Ok(())
+    }
+
+    /// Removes all inode marks, or all mount marks if `mount` is set.
+    pub fn flush_marks(&self, mount: bool) {
+        let mut marks = self.marks.lock();
+        let before = marks.len();
+        marks.retain(|it| it.mount != mount);
+        MARK_COUNT.fetch_sub(before - marks.len(), Ordering::AcqRel);
+    }
+
+    /// Returns whether the marks of the group select `event` on the file at
+    /// `path`, on the mount `mount`.
+    fn wants(&self, path: &str, mount: Option<&str>, is_dir: bool, event: u64) -> bool {
+        let (mut mask, mut ignored) = (0, 0);
+        for mark in self.marks.lock().iter() {
+            let hit = if mark.mount {
+                mount == Some(mark.path.as_str())
+            } else {
+                mark.path == path
+                    || (mark.mask & FAN_EVENT_ON_CHILD != 0 && parent_of(path) == mark.path)
+            };
+            if hit {
+                mask |= mark.mask;
+                ignored |= mark.ignored;
+            }
+        }
+        if is_dir && mask & FAN_ONDIR == 0 {
+            return false;
+        }
+        mask & !ignored & event != 0
+    }
+
+    fn push(&self, event: Event) {
+        let mut queue = self.queue.lock();
+        if event.decision.is_none() && !self.unlimited_queue && queue.len() >= MAX_QUEUED_EVENTS {
+            if queue.back().is_none_or(|it| it.mask != FAN_Q_OVERFLOW) {
+                queue.push_back(Event {
+                    mask: FAN_Q_OVERFLOW,
+                    path: None,
+                    pid: 0,
+                    decision: None,
+                });
+            }
+        } else {
+            queue.push_back(event);
+        }
+        drop(queue);
+        self.poll_rx.wake();
+    }
+
+    /// Opens the file of an event for the reader.
+    fn open_event_file(&self, path: &str) -> AxResult<c_int> {
+        let mut options = OpenOptions::new();
+        match self.event_flags & 0b11 {
+            O_WRONLY => options.write(true),
+            O_RDWR => options.read(true).write(true),
+            _ => options.read(true),
+        };
+        let fs = FS_CONTEXT.lock().clone();
+        let f: Arc<dyn FileLike> = match options.open(&fs, path)? {
+            OpenResult::File(file) => {
+                let file = File::new(file);
+                // Accesses through this file must not generate events, or the
+                // reader could wait on itself.
+                file.set_nonotify();
+                Arc::new(file)
+            }
+            OpenResult::Dir(dir) => Arc::new(Directory::new(dir)),
+        };
+        if self.event_flags & O_NONBLOCK != 0 {
+            f.set_nonblocking(true)?;
+        }
+        add_file_like(f, self.event_flags & O_CLOEXEC != 0)
+    }
+}
+
+impl Drop for Fanotify {
+    fn drop(&mut self) {
+        MARK_COUNT.fetch_sub(self.marks.get_mut().len(), Ordering::AcqRel);
+        // Nobody is left to answer, so let everything waiting proceed.
+        for event in self.queue.get_mut().drain(..) {
+            if let Some(decision) = event.decision {
+                decision.decide(FAN_ALLOW);
+            }
+        }
+        for (_, decision) in self.pending.get_mut().drain(..) {
+            decision.decide(FAN_ALLOW);
+        }
+    }
+}
+
+fn parent_of(path: &str) -> &str {
+    match path.rsplit_once('/') {
+        Some(("", _)) => "/",
+        Some((parent, _)) => parent,
+        None => path,
+    }
+}
+
+fn mount_of(path: &str) -> Option<String> {
+    mounts::containing(path).map(|it| it.target)
+}
+
+fn mark_path(loc: &Location, mount: bool) -> AxResult<String> {
+    let path = loc.absolute_path()?.to_string();
+    if mount {
+        mount_of(&path).ok_or(AxError::NotFound)
+    } else {
+        Ok(path)
+    }
+}
+
+/// Returns whether any group has a mark.
+pub fn active() -> bool {
+    MARK_COUNT.load(Ordering::Acquire) != 0
+}
+
+/// Returns the groups interested in `event` on `loc`, with the path of `loc`.
+fn interested(loc: &Location, event: u64) -> Option<(String, Vec<Arc<Fanotify>>)> {
+    let path = loc.absolute_path().ok()?.to_string();
+    let mount = mount_of(&path);
+    let is_dir = loc.is_dir();
+    let groups = GROUPS
+        .lock()
+        .iter()
+        .filter_map(Weak::upgrade)
+        .filter(|group| group.wants(&path, mount.as_deref(), is_dir, event))
+        .collect::<Vec<_>>();
+    (!groups.is_empty()).then_some((path, groups))
+}
+
+fn event_pid(group: &Fanotify) -> u32 {
+    let curr = current();
+    if group.report_tid {
+        curr.id().as_u64() as _
+    } else {
+        curr.as_thread().proc_data.proc.pid()
+    }
+}
+
+/// Reports the notification event `event` on `loc`.
+pub fn notify(loc: &Location, event: u64) {
+    if !active() {
+        return;
+    }
+    let Some((path, groups)) = interested(loc, event) else {
+        return;
+    };
+    for group in groups {
+        group.push(Event {
+            mask: event,
+            path: Some(path.clone()),
+            pid: event_pid(&group),
+            decision: None,
+        });
+    }
+}
+
+/// Asks the groups with permission marks on `loc` whether `event` may
+/// proceed, waiting for their answers. Fails with `EPERM` if any denies it.
+pub fn permission(loc: &Location, event: u64) -> AxResult<()> {
+    if !active() {
+        return Ok(());
+    }
+    let Some((path, groups)) = interested(loc, event) else {
+        return Ok(());
+    };
+    let mut decisions = Vec::new();
+    for group in groups.iter().filter(|it| it.permission) {
+        let decision = Arc::new(Decision {
+            response: AtomicU32::new(0),
+            poll: PollSet::new(),
+        });
+        group.push(Event {
+            mask: event,
+            path: Some(path.clone()),
+            pid: event_pid(group),
+            decision: Some(decision.clone()),
+        });
+        decisions.push(decision);
+    }
+    drop(groups);
+    for decision in decisions {
+        if !decision.wait()? {
+            return Err(AxError::OperationNotPermitted);
+        }
+    }
+    Ok(())
+}
+
+impl FileLike for Fanotify {
+    fn read(&self, dst: &mut SealedBufMut) -> AxResult<usize> {
+        if dst.remaining_mut() < METADATA_LEN {
+            return Err(AxError::InvalidInput);
+        }
+
+        Poller::new(self, IoEvents::IN)
+            .non_blocking(self.nonblocking())
+            .poll(|| {
+                let mut read = 0;
+                while dst.remaining_mut() >= METADATA_LEN {
+                    let Some(event) = self.queue.lock().pop_front() else {
+                        break;
+                    };
+                    let fd = match &event.path {
+                        Some(path) => self.open_event_file(path).unwrap_or(FAN_NOFD),
+                        None => FAN_NOFD,
+                    };
+                    if let Some(decision) = event.decision {
+                        if fd == FAN_NOFD {
+                            // The reader cannot answer without a file.
+                            decision.decide(FAN_ALLOW);
+                        } else {
+                            self.pending.lock().push((fd, decision));
+                        }
+                    }
+                    let metadata = EventMetadata {
+                        event_len: METADATA_LEN as _,
+                        vers: FANOTIFY_METADATA_VERSION,
+                        reserved: 0,
+                        metadata_len: METADATA_LEN as _,
+                        mask: event.mask,
+                        fd,
+                        pid: event.pid as _,
+                    };
+                    dst.write(metadata.as_bytes())?;
+                    read += METADATA_LEN;
+                }
+                if read == 0 {
+                    Err(AxError::WouldBlock)
+                } else {
+                    Ok(read)
+                }
+            })
+    }
+
+    fn write(&self, src: &mut SealedBuf) -> AxResult<usize> {
+        if src.remaining() < RESPONSE_LEN {
+            return Err(AxError::InvalidInput);
+        }
+        let mut response = [0; RESPONSE_LEN];
+        src.read(&mut response)?;
+        let fd = c_int::from_ne_bytes(response[..4].try_into().unwrap());
+        let response = u32::from_ne_bytes(response[4..].try_into().unwrap());
+        let response = response & !FAN_AUDIT;
+        if response != FAN_ALLOW && response != FAN_DENY {
+            return Err(AxError::InvalidInput);
+        }
+
+        let mut pending = self.pending.lock();
+        let index = pending
+            .iter()
+            .position(|(it, _)| *it == fd)
+            .ok_or(AxError::NotFound)?;
+        let (_, decision) = pending.remove(index);
+        drop(pending);
+        decision.decide(response);
+        Ok(RESPONSE_LEN)
+    }
+
+    fn stat(&self) -> AxResult<Kstat> {
+        Ok(Kstat::default())
+    }
+
+    fn nonblocking(&self) -> bool {
+        self.non_blocking.load(Ordering::Acquire)
+    }
+
+    fn set_nonblocking(&self, non_blocking: bool) -> AxResult {
+        self.non_blocking.store(non_blocking, Ordering::Release);
+        Ok(())
+    }
+
+    fn path(&self) -> Cow<str> {
+        "anon_inode:[fanotify]".into()
+    }
+
+    fn into_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
+        self
+    }
+}
+
+impl Pollable for Fanotify {
+    fn poll(&self) -> IoEvents {
+        let mut events = IoEvents::OUT;
+        events.set(IoEvents::IN, !self.queue.lock().is_empty());
+        events
+    }
+
+    fn register(&self, context: &mut Context<'_>, events: IoEvents) {
+        if events.contains(IoEvents::IN) {
+            self.poll_rx.register(context.waker());
+        }
+    }
+}
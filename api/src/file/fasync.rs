@@ -0,0 +1,259 @@
+//! Asynchronous I/O notification (`O_ASYNC`, `F_SETOWN` and `F_SETSIG`).
+//!
+//! A file with `O_ASYNC` set is watched by a task that waits on the file's
+//! readiness through [`Pollable::register`] and signals the owner whenever
+//! the file wakes it up.
+
+use alloc::{
+    sync::{Arc, Weak},
+    task::Wake,
+    vec::Vec,
+};
+use core::{
+    ffi::{c_int, c_long},
+    future::poll_fn,
+    sync::atomic::{AtomicBool, Ordering},
+    task::{Poll, Waker},
+    time::Duration,
+};
+
+use axerrno::{AxError, AxResult};
+use axpoll::IoEvents;
+use axsync::Mutex;
+use linux_raw_sys::general::{
+    POLL_HUP, POLL_IN, POLL_OUT, POLLHUP, POLLIN, POLLOUT, POLLRDNORM, POLLWRBAND, POLLWRNORM,
+};
+use starry_core::task::{send_signal_to_process, send_signal_to_process_group};
+use starry_process::Pid;
+use starry_signal::{SignalInfo, Signo};
+
+use super::FileLike;
+
+/// How often a watcher checks whether its file is still alive when nothing
+/// happens on it.
+const RECHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The `SIGPOLL` layout of `siginfo_t`.
+#[repr(C)]
+struct SigPollInfo {
+    signo: i32,
+    errno: i32,
+    code: i32,
+    band: c_long,
+    fd: c_int,
+}
+
+const _: () = assert!(size_of::<SigPollInfo>() <= size_of::<SignalInfo>());
+
+struct Fasync {
+    file: Weak<dyn FileLike>,
+    /// The process (if positive) or process group (if negative) signaled.
+    owner: i32,
+    /// The signal sent, or 0 for `SIGIO` without extra information.
+    signal: u32,
+    /// The file descriptor reported in `si_fd`.
+    fd: c_int,
+    enabled: bool,
+    watching: bool,
+}
+
+static FASYNC: Mutex<Vec<Fasync>> = Mutex::new(Vec::new());
+
+fn key<T: ?Sized>(file: *const T) -> *const () {
+    file.cast()
+}
+
+fn with_entry<R>(file: &Arc<dyn FileLike>, f: impl FnOnce(&mut Fasync) -> R) -> R {
+    let mut entries = FASYNC.lock();
+    entries.retain(|it| it.file.strong_count() > 0);
+    let index = match entries
+        .iter()
+        .position(|it| key(it.file.as_ptr()) == key(Arc::as_ptr(file)))
+    {
+        Some(index) => index,
+        None => {
+            entries.push(Fasync {
+                file: Arc::downgrade(file),
+                owner: 0,
+                signal: 0,
+                fd: -1,
+                enabled: false,
+                watching: false,
+            });
+            entries.len() - 1
+        }
+    };
+    f(&mut entries[index])
+}
+
+fn find<R>(file: *const (), f: impl FnOnce(&Fasync) -> R) -> Option<R> {
+    FASYNC
+        .lock()
+        .iter()
+        .find(|it| key(it.file.as_ptr()) == file)
+        .map(f)
+}
+
+/// Sets the owner signaled for `file` (`F_SETOWN`). A negative `owner` is a
+/// process group.
+pub fn set_owner(file: &Arc<dyn FileLike>, owner: i32) {
+    with_entry(file, |it| it.owner = owner);
+}
+
+/// Returns the owner signaled for `file` (`F_GETOWN`).
+pub fn owner(file: &Arc<dyn FileLike>) -> i32 {
+    find(key(Arc::as_ptr(file)), |it| it.owner).unwrap_or(0)
+}
+
+/// Sets the signal sent for `file` (`F_SETSIG`).
+pub fn set_signal(file: &Arc<dyn FileLike>, signal: u32) -> AxResult<()> {
+    if signal != 0 && Signo::from_repr(signal as u8).is_none() {
+        return Err(AxError::InvalidInput);
+    }
+    with_entry(file, |it| it.signal = signal);
+    Ok(())
+}
+
+/// Returns the signal sent for `file` (`F_GETSIG`).
+pub fn signal(file: &Arc<dyn FileLike>) -> u32 {
+    find(key(Arc::as_ptr(file)), |it| it.signal).unwrap_or(0)
+}
+
+/// Returns whether `O_ASYNC` is set on `file`.
+pub fn enabled(file: &Arc<dyn FileLike>) -> bool {
+    find(key(Arc::as_ptr(file)), |it| it.enabled).unwrap_or(false)
+}
+
+/// Sets or clears `O_ASYNC` on `file`, opened as `fd`.
+pub fn set_enabled(file: &Arc<dyn FileLike>, fd: c_int, enabled: bool) {
+    let spawn = with_entry(file, |it| {
+        it.enabled = enabled;
+        it.fd = fd;
+        let spawn = enabled && !it.watching;
+        it.watching |= spawn;
+        spawn
+    });
+    if spawn {
+        let file = Arc::downgrade(file);
+        axtask::spawn(|| axtask::future::block_on(watch(file)), "fasync".into());
+    }
+}
+
+/// Builds the signal reporting `code` and `band` on `fd`.
+fn io_signal(signal: u32, fd: c_int, code: u32, band: u32) -> SignalInfo {
+    let Some(signo) = (signal != 0)
+        .then(|| Signo::from_repr(signal as u8))
+        .flatten()
+    else {
+        return SignalInfo::new_kernel(Signo::SIGIO);
+    };
+    let mut sig = SignalInfo::new_kernel(signo);
+    // SAFETY: `SignalInfo` has the layout of `siginfo_t`, which `SigPollInfo`
+    // is a prefix of.
+    let info = unsafe { &mut *(&mut sig as *mut SignalInfo).cast::<SigPollInfo>() };
+    info.code = code as _;
+    info.band = band as _;
+    info.fd = fd;
+    sig
+}
+
+/// Returns the signal reporting an event on `file`, which is `SIGIO` unless
+/// changed with `F_SETSIG`.
+pub fn signal_for<T: ?Sized>(file: *const T, code: u32, band: u32) -> SignalInfo {
+    let (signal, fd) = find(key(file), |it| (it.signal, it.fd)).unwrap_or((0, -1));
+    io_signal(signal, fd, code, band)
+}
+
+fn send(owner: i32, sig: SignalInfo) {
+    let _ = if owner > 0 {
+        send_signal_to_process(owner as Pid, Some(sig))
+    } else {
+        send_signal_to_process_group(owner.unsigned_abs() as Pid, Some(sig))
+    };
+}
+
+/// Wakes the watcher once the file it registered on becomes ready.
+struct Notify {
+    woken: AtomicBool,
+    waker: Waker,
+}
+
+impl Wake for Notify {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.woken.store(true, Ordering::Release);
+        self.waker.wake_by_ref();
+    }
+}
+
+/// Waits for a readiness change of `file`, returning whether there was one.
+async fn wait_ready(file: &Weak<dyn FileLike>) -> bool {
+    let mut notify: Option<Arc<Notify>> = None;
+    let wait = poll_fn(|cx| {
+        if let Some(notify) = &notify {
+            return if notify.woken.load(Ordering::Acquire) {
+                Poll::Ready(true)
+            } else {
+                Poll::Pending
+            };
+        }
+        let Some(file) = file.upgrade() else {
+            return Poll::Ready(false);
+        };
+        let it = Arc::new(Notify {
+            woken: AtomicBool::new(false),
+            waker: cx.waker().clone(),
+        });
+        let mut context = core::task::Context::from_waker(&Waker::from(it.clone()));
+        file.register(&mut context, IoEvents::IN | IoEvents::OUT);
+        notify = Some(it);
+        Poll::Pending
+    });
+    axtask::future::timeout(Some(RECHECK_INTERVAL), wait)
+        .await
+        .unwrap_or(false)
+}
+
+async fn watch(file: Weak<dyn FileLike>) {
+    loop {
+        let ready = wait_ready(&file).await;
+
+        let target = {
+            let mut entries = FASYNC.lock();
+            let Some(entry) = entries
+                .iter_mut()
+                .find(|it| key(it.file.as_ptr()) == key(file.as_ptr()))
+            else {
+                break;
+            };
+            if !entry.enabled || entry.file.strong_count() == 0 {
+                entry.watching = false;
+                break;
+            }
+            (entry.owner, entry.signal, entry.fd)
+        };
+        let Some(f) = file.upgrade() else {
+            break;
+        };
+        let events = f.poll();
+        drop(f);
+
+        let (owner, signal, fd) = target;
+        if !ready || owner == 0 {
+            continue;
+        }
+        let (code, band) = if events.contains(IoEvents::IN) {
+            (POLL_IN, POLLIN | POLLRDNORM)
+        } else if events.contains(IoEvents::OUT) {
+            (POLL_OUT, POLLOUT | POLLWRNORM | POLLWRBAND)
+        } else if events.contains(IoEvents::HUP) {
+            (POLL_HUP, POLLHUP)
+        } else {
+            continue;
+        };
+        send(owner, io_signal(signal, fd, code, band));
+    }
+}
@@ -1,4 +1,4 @@
-use alloc::{borrow::Cow, string::ToString, sync::Arc};
+use alloc::{borrow::Cow, string::ToString, sync::Arc, vec::Vec};
 use core::{
     any::Any,
     ffi::c_int,
@@ -7,15 +7,23 @@ use core::{
     task::Context,
 };
 
-use axerrno::{AxError, AxResult};
-use axfs_ng::{FS_CONTEXT, FsContext};
-use axfs_ng_vfs::{Location, Metadata, NodeFlags};
+use axerrno::{AxError, AxResult, LinuxError};
+use axfs_ng::{FS_CONTEXT, FileFlags, FsContext};
+use axfs_ng_vfs::{Location, Metadata, NodeFlags, NodeType};
+use axio::{BufMut, Seek, SeekFrom};
 use axpoll::{IoEvents, Pollable};
 use axsync::Mutex;
 use axtask::future::Poller;
-use linux_raw_sys::general::{AT_EMPTY_PATH, AT_FDCWD, AT_SYMLINK_NOFOLLOW};
-
-use super::{FileLike, Kstat, get_file_like};
+use bytemuck::AnyBitPattern;
+use linux_raw_sys::general::{AT_EMPTY_PATH, AT_FDCWD, AT_SYMLINK_NOFOLLOW, DN_ACCESS, DN_MODIFY};
+use starry_core::verity::{self, DIGEST_SIZE, HASH_ALG_SHA256, MAX_SALT_SIZE, VERITY_BLOCK_SIZE};
+use starry_vm::{VmMutPtr, VmPtr, vm_load, vm_write_slice};
+
+use super::{
+    FileLike, Kstat, dnotify,
+    fanotify::{self, FAN_ACCESS, FAN_ACCESS_PERM, FAN_MODIFY},
+    get_file_like,
+};
 use crate::file::{SealedBuf, SealedBufMut};
 
 pub fn with_fs<R>(dirfd: c_int, f: impl FnOnce(&mut FsContext) -> AxResult<R>) -> AxResult<R> {
@@ -97,10 +105,30 @@ pub fn metadata_to_kstat(metadata: &Metadata) -> Kstat {
     }
 }
 
+const FS_IOC_ENABLE_VERITY: u32 = 0x4080_6685;
+const FS_IOC_MEASURE_VERITY: u32 = 0xc004_6686;
+
+/// `struct fsverity_enable_arg`.
+#[repr(C)]
+#[derive(Clone, Copy, AnyBitPattern)]
+struct VerityEnableArg {
+    version: u32,
+    hash_algorithm: u32,
+    block_size: u32,
+    salt_size: u32,
+    salt_ptr: u64,
+    sig_size: u32,
+    reserved1: u32,
+    sig_ptr: u64,
+    reserved2: [u64; 11],
+}
+
 /// File wrapper for `axfs::fops::File`.
 pub struct File {
     inner: axfs_ng::File,
     nonblock: AtomicBool,
+    /// Whether accesses through this file generate no notifications.
+    nonotify: AtomicBool,
 }
 
 impl File {
@@ -108,6 +136,7 @@ impl File {
         Self {
             inner,
             nonblock: AtomicBool::new(false),
+            nonotify: AtomicBool::new(false),
         }
     }
 
@@ -115,9 +144,64 @@ impl File {
         &self.inner
     }
 
+    /// Stops accesses through this file from generating notifications, like
+    /// `FMODE_NONOTIFY`.
+    pub fn set_nonotify(&self) {
+        self.nonotify.store(true, Ordering::Release);
+    }
+
+    fn notifies(&self) -> bool {
+        !self.nonotify.load(Ordering::Acquire)
+    }
+
     fn is_blocking(&self) -> bool {
         self.inner.location().flags().contains(NodeFlags::BLOCKING)
     }
+
+    fn enable_verity(&self, arg: usize) -> AxResult<usize> {
+        let arg = (arg as *const VerityEnableArg).vm_read()?;
+        if arg.version != 1
+            || arg.reserved1 != 0
+            || arg.reserved2 != [0; 11]
+            || arg.hash_algorithm != HASH_ALG_SHA256
+            || arg.block_size as usize != VERITY_BLOCK_SIZE
+        {
+            return Err(AxError::InvalidInput);
+        }
+        if arg.salt_size as usize > MAX_SALT_SIZE {
+            return Err(AxError::Other(LinuxError::EMSGSIZE));
+        }
+        // Built-in signature verification is not supported
+        if arg.sig_size != 0 {
+            return Err(AxError::InvalidInput);
+        }
+        if self.inner.access(FileFlags::READ).is_err() {
+            return Err(AxError::BadFileDescriptor);
+        }
+        if self.inner.access(FileFlags::WRITE).is_ok() {
+            return Err(AxError::Other(LinuxError::ETXTBSY));
+        }
+        let salt = if arg.salt_size > 0 {
+            vm_load(arg.salt_ptr as *const u8, arg.salt_size as usize)?
+        } else {
+            Vec::new()
+        };
+        verity::enable(self.inner.location(), &salt)?;
+        Ok(0)
+    }
+
+    fn measure_verity(&self, arg: usize) -> AxResult<usize> {
+        let digest = verity::measure(self.inner.location())?;
+        // `struct fsverity_digest`, where the size is the room for the digest
+        let header = arg as *mut [u16; 2];
+        let [_, size] = header.vm_read()?;
+        header.vm_write([HASH_ALG_SHA256 as u16, DIGEST_SIZE as u16])?;
+        if (size as usize) < DIGEST_SIZE {
+            return Err(AxError::Other(LinuxError::EOVERFLOW));
+        }
+        vm_write_slice((arg + 4) as *mut u8, &digest)?;
+        Ok(0)
+    }
 }
 
 fn path_for(loc: &Location) -> Cow<'static, str> {
@@ -128,24 +212,42 @@ fn path_for(loc: &Location) -> Cow<'static, str> {
 impl FileLike for File {
     fn read(&self, dst: &mut SealedBufMut) -> AxResult<usize> {
         let inner = self.inner();
-        if likely(self.is_blocking()) {
+        if self.notifies() {
+            fanotify::permission(inner.location(), FAN_ACCESS_PERM)?;
+        }
+        if verity::is_enabled(inner.location()) {
+            let offset = self.inner().seek(SeekFrom::Current(0))?;
+            verity::verify(inner.location(), offset, dst.remaining_mut())?;
+        }
+        let read = if likely(self.is_blocking()) {
             inner.read(dst)
         } else {
             Poller::new(self, IoEvents::IN)
                 .non_blocking(self.nonblocking())
                 .poll(|| inner.read(dst))
+        }?;
+        if self.notifies() {
+            dnotify::notify(inner.location(), DN_ACCESS);
+            fanotify::notify(inner.location(), FAN_ACCESS);
         }
+        Ok(read)
     }
 
     fn write(&self, src: &mut SealedBuf) -> AxResult<usize> {
         let inner = self.inner();
-        if likely(self.is_blocking()) {
+        verity::check_write(inner.location())?;
+        let written = if likely(self.is_blocking()) {
             inner.write(src)
         } else {
             Poller::new(self, IoEvents::OUT)
                 .non_blocking(self.nonblocking())
                 .poll(|| inner.write(src))
+        }?;
+        if self.notifies() {
+            dnotify::notify(inner.location(), DN_MODIFY);
+            fanotify::notify(inner.location(), FAN_MODIFY);
         }
+        Ok(written)
     }
 
     fn stat(&self) -> AxResult<Kstat> {
@@ -157,7 +259,12 @@ impl FileLike for File {
     }
 
     fn ioctl(&self, cmd: u32, arg: usize) -> AxResult<usize> {
-        self.inner().backend()?.location().ioctl(cmd, arg)
+        let is_regular = self.inner().location().node_type() == NodeType::RegularFile;
+        match cmd {
+            FS_IOC_ENABLE_VERITY if is_regular => self.enable_verity(arg),
+            FS_IOC_MEASURE_VERITY if is_regular => self.measure_verity(arg),
+            _ => self.inner().backend()?.location().ioctl(cmd, arg),
+        }
     }
 
     fn set_nonblocking(&self, flag: bool) -> AxResult {
@@ -190,7 +297,15 @@ impl FileLike for File {
 }
 impl Pollable for File {
     fn poll(&self) -> IoEvents {
-        self.inner().location().poll()
+        let location = self.inner().location();
+        let mut events = location.poll();
+        // Like Linux, regular files are always ready for reading and writing,
+        // whatever the filesystem reports. Other events such as `PRI` still
+        // come from the node.
+        if location.node_type() == NodeType::RegularFile {
+            events |= IoEvents::IN | IoEvents::OUT;
+        }
+        events
     }
 
     fn register(&self, context: &mut Context<'_>, events: IoEvents) {
@@ -202,6 +317,7 @@ impl Pollable for File {
 pub struct Directory {
     inner: Location,
     pub offset: Mutex<u64>,
+    non_blocking: AtomicBool,
 }
 
 impl Directory {
@@ -209,6 +325,7 @@ impl Directory {
         Self {
             inner,
             offset: Mutex::new(0),
+            non_blocking: AtomicBool::new(false),
         }
     }
 
@@ -239,6 +356,15 @@ impl FileLike for Directory {
         self
     }
 
+    fn nonblocking(&self) -> bool {
+        self.non_blocking.load(Ordering::Acquire)
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> AxResult {
+        self.non_blocking.store(nonblocking, Ordering::Release);
+        Ok(())
+    }
+
     fn from_fd(fd: c_int) -> AxResult<Arc<Self>> {
         get_file_like(fd)?
             .into_any()
@@ -0,0 +1,196 @@
+//! File descriptors of the new mount API (`fsopen`, `fsmount` and
+//! `move_mount`).
+
+use alloc::{borrow::Cow, format, string::String, sync::Arc, vec::Vec};
+use core::{
+    any::Any,
+    sync::atomic::{AtomicBool, Ordering},
+    task::Context,
+};
+
+use axerrno::{AxError, AxResult};
+use axfs_ng::FsContext;
+use axfs_ng_vfs::Filesystem;
+use axpoll::{IoEvents, Pollable};
+use axsync::Mutex;
+
+use crate::{
+    file::{FileLike, Kstat, SealedBuf, SealedBufMut},
+    vfs::mounts,
+};
+
+#[derive(Default)]
+struct FsContextState {
+    source: Option<String>,
+    /// Filesystem options, as `key` or `key=value`.
+    options: Vec<String>,
+    /// The filesystem, once created with `FSCONFIG_CMD_CREATE`.
+    fs: Option<Filesystem>,
+}
+
+/// A filesystem context, returned by `fsopen` and configured with
+/// `fsconfig`.
+pub struct FsContextFile {
+    fs_type: String,
+    state: Mutex<FsContextState>,
+}
+
+impl FsContextFile {
+    pub fn new(fs_type: String) -> Self {
+        Self {
+            fs_type,
+            state: Mutex::new(FsContextState::default()),
+        }
+    }
+
+    /// Sets the parameter `key`, with an optional value.
+    pub fn set(&self, key: &str, value: Option<&str>) -> AxResult<()> {
+        let mut state = self.state.lock();
+        if state.fs.is_some() {
+            return Err(AxError::ResourceBusy);
+        }
+        match (key, value) {
+            ("source", Some(value)) => state.source = Some(value.into()),
+            ("source", None) => return Err(AxError::InvalidInput),
+            (key, Some(value)) => state.options.push(format!("{key}={value}")),
+            (key, None) => state.options.push(key.into()),
+        }
+        Ok(())
+    }
+
+    /// Creates the filesystem with `create`, if not done yet.
+    pub fn create(&self, create: impl FnOnce() -> Filesystem) {
+        let mut state = self.state.lock();
+        if state.fs.is_none() {
+            state.fs = Some(create());
+        }
+    }
+
+    /// Creates a detached mount of the filesystem, with the mount options
+    /// `flags` (as `MS_*` flags).
+    pub fn mount(&self, flags: u32) -> AxResult<MountFile> {
+        let state = self.state.lock();
+        let fs = state.fs.clone().ok_or(AxError::InvalidInput)?;
+        let mut options = mounts::options_for_flags(flags);
+        for option in &state.options {
+            options.push(',');
+            options.push_str(option);
+        }
+        Ok(MountFile::new(
+            fs,
+            state.source.clone().unwrap_or_else(|| "none".into()),
+            self.fs_type.clone(),
+            options,
+        ))
+    }
+}
+
+/// A mount not attached to the tree yet, returned by `fsmount` and
+/// `open_tree(OPEN_TREE_CLONE)`.
+pub struct MountFile {
+    fs: Filesystem,
+    source: String,
+    fs_type: String,
+    options: String,
+    /// Mounts attached along with this one, by path relative to its root.
+    children: Vec<(String, MountFile)>,
+    attached: AtomicBool,
+}
+
+impl MountFile {
+    pub fn new(fs: Filesystem, source: String, fs_type: String, options: String) -> Self {
+        Self {
+            fs,
+            source,
+            fs_type,
+            options,
+            children: Vec::new(),
+            attached: AtomicBool::new(false),
+        }
+    }
+
+    /// Adds a mount attached at `path` below this one.
+    pub fn add_child(&mut self, path: String, child: MountFile) {
+        self.children.push((path, child));
+    }
+
+    /// Attaches the mount at `path`. A mount can only be attached once.
+    pub fn attach(&self, cx: &FsContext, path: &str) -> AxResult<()> {
+        if self.attached.swap(true, Ordering::AcqRel) {
+            return Err(AxError::InvalidInput);
+        }
+        cx.resolve(path)
+            .and_then(|target| target.mount(&self.fs))
+            .inspect_err(|_| self.attached.store(false, Ordering::Release))?;
+        mounts::add(
+            &cx.resolve(path)?,
+            &self.source,
+            &self.fs_type,
+            &self.options,
+        )?;
+        for (child_path, child) in &self.children {
+            child.attach(cx, &format!("{}/{child_path}", path.trim_end_matches('/')))?;
+        }
+        Ok(())
+    }
+}
+
+impl FileLike for FsContextFile {
+    fn read(&self, _dst: &mut SealedBufMut) -> AxResult<usize> {
+        Err(AxError::InvalidInput)
+    }
+
+    fn write(&self, _src: &mut SealedBuf) -> AxResult<usize> {
+        Err(AxError::InvalidInput)
+    }
+
+    fn stat(&self) -> AxResult<Kstat> {
+        Ok(Kstat::default())
+    }
+
+    fn path(&self) -> Cow<str> {
+        "anon_inode:[fscontext]".into()
+    }
+
+    fn into_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
+        self
+    }
+}
+
+impl Pollable for FsContextFile {
+    fn poll(&self) -> IoEvents {
+        IoEvents::empty()
+    }
+
+    fn register(&self, _context: &mut Context<'_>, _events: IoEvents) {}
+}
+
+impl FileLike for MountFile {
+    fn read(&self, _dst: &mut SealedBufMut) -> AxResult<usize> {
+        Err(AxError::BadFileDescriptor)
+    }
+
+    fn write(&self, _src: &mut SealedBuf) -> AxResult<usize> {
+        Err(AxError::BadFileDescriptor)
+    }
+
+    fn stat(&self) -> AxResult<Kstat> {
+        Ok(Kstat::default())
+    }
+
+    fn path(&self) -> Cow<str> {
+        "/".into()
+    }
+
+    fn into_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
+        self
+    }
+}
+
+impl Pollable for MountFile {
+    fn poll(&self) -> IoEvents {
+        IoEvents::empty()
+    }
+
+    fn register(&self, _context: &mut Context<'_>, _events: IoEvents) {}
+}
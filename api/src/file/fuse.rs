@@ -0,0 +1,102 @@
+use alloc::{borrow::Cow, sync::Arc, vec};
+use core::{
+    any::Any,
+    sync::atomic::{AtomicBool, Ordering},
+    task::Context,
+};
+
+use axerrno::AxResult;
+use axfs_ng_vfs::DeviceId;
+use axio::{Buf, BufMut, Read, Write};
+use axpoll::{IoEvents, Pollable};
+use axtask::future::Poller;
+use linux_raw_sys::general::S_IFCHR;
+
+use super::{FileLike, Kstat, SealedBuf, SealedBufMut};
+use crate::vfs::fuse::FuseConn;
+
+/// The device ID of `/dev/fuse`.
+pub const FUSE_DEVICE_ID: DeviceId = DeviceId::new(10, 229);
+
+/// A file opened from `/dev/fuse`, the server end of a FUSE connection.
+///
+/// The connection is aborted when the file is closed, failing the pending
+/// and later operations on its filesystem.
+pub struct FuseDev {
+    conn: Arc<FuseConn>,
+    non_blocking: AtomicBool,
+}
+
+impl FuseDev {
+    pub fn new() -> Self {
+        Self {
+            conn: FuseConn::new(),
+            non_blocking: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns the connection, to mount its filesystem.
+    pub fn conn(&self) -> &Arc<FuseConn> {
+        &self.conn
+    }
+}
+
+impl Drop for FuseDev {
+    fn drop(&mut self) {
+        self.conn.abort();
+    }
+}
+
+impl FileLike for FuseDev {
+    fn read(&self, dst: &mut SealedBufMut) -> AxResult<usize> {
+        Poller::new(self, IoEvents::IN)
+            .non_blocking(self.nonblocking())
+            .poll(|| {
+                let request = self.conn.read_request(dst.remaining_mut())?;
+                dst.write(&request)
+            })
+    }
+
+    fn write(&self, src: &mut SealedBuf) -> AxResult<usize> {
+        let len = src.remaining();
+        let mut reply = vec![0; len];
+        src.read(&mut reply)?;
+        self.conn.write_reply(&reply)?;
+        Ok(len)
+    }
+
+    fn stat(&self) -> AxResult<Kstat> {
+        Ok(Kstat {
+            mode: S_IFCHR | 0o666,
+            rdev: FUSE_DEVICE_ID,
+            ..Default::default()
+        })
+    }
+
+    fn into_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
+        self
+    }
+
+    fn path(&self) -> Cow<str> {
+        "/dev/fuse".into()
+    }
+
+    fn nonblocking(&self) -> bool {
+        self.non_blocking.load(Ordering::Acquire)
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> AxResult {
+        self.non_blocking.store(nonblocking, Ordering::Release);
+        Ok(())
+    }
+}
+
+impl Pollable for FuseDev {
+    fn poll(&self) -> IoEvents {
+        self.conn.poll()
+    }
+
+    fn register(&self, context: &mut Context<'_>, events: IoEvents) {
+        self.conn.register(context, events);
+    }
+}
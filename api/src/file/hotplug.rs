@@ -0,0 +1,112 @@
+use alloc::{borrow::Cow, format, string::String, sync::Arc};
+use core::{
+    any::Any,
+    sync::atomic::{AtomicBool, Ordering},
+    task::Context,
+};
+
+use axerrno::{AxError, AxResult};
+use axfs_ng_vfs::DeviceId;
+use axio::{BufMut, Write};
+use axpoll::{IoEvents, Pollable};
+use axtask::future::Poller;
+use linux_raw_sys::general::S_IFCHR;
+use starry_core::hotplug::{self, DeviceEvent, DeviceEventQueue};
+
+use super::{FileLike, Kstat, SealedBuf, SealedBufMut};
+
+/// The device ID of `/dev/device-events`.
+pub const DEVICE_EVENTS_DEVICE_ID: DeviceId = DeviceId::new(10, 1025);
+
+fn format_event(event: &DeviceEvent) -> String {
+    format!(
+        "{} {} {}:{} {}\n",
+        event.action.as_str(),
+        event.subsystem(),
+        event.device_id.major(),
+        event.device_id.minor(),
+        event.name
+    )
+}
+
+/// An open `/dev/device-events`, which receives the device events emitted
+/// since it was opened.
+///
+/// Every read returns as many whole events as fit in the buffer, one per line
+/// in the form `<action> <subsystem> <major>:<minor> <name>`.
+pub struct DeviceEvents {
+    queue: Arc<DeviceEventQueue>,
+    non_blocking: AtomicBool,
+}
+
+impl DeviceEvents {
+    pub fn new() -> Self {
+        Self {
+            queue: hotplug::subscribe(),
+            non_blocking: AtomicBool::new(false),
+        }
+    }
+}
+
+impl FileLike for DeviceEvents {
+    fn read(&self, dst: &mut SealedBufMut) -> AxResult<usize> {
+        let len = dst.remaining_mut();
+        Poller::new(self, IoEvents::IN)
+            .non_blocking(self.nonblocking())
+            .poll(|| {
+                let mut read = 0;
+                let mut too_small = false;
+                while let Some(line) = self.queue.pop_if(|event| {
+                    let line = format_event(event);
+                    too_small = read + line.len() > len;
+                    (!too_small).then_some(line)
+                }) {
+                    read += dst.write(line.as_bytes())?;
+                }
+                match read {
+                    0 if too_small => Err(AxError::InvalidInput),
+                    0 => Err(AxError::WouldBlock),
+                    _ => Ok(read),
+                }
+            })
+    }
+
+    fn write(&self, _src: &mut SealedBuf) -> AxResult<usize> {
+        Err(AxError::BadFileDescriptor)
+    }
+
+    fn stat(&self) -> AxResult<Kstat> {
+        Ok(Kstat {
+            mode: S_IFCHR | 0o444,
+            rdev: DEVICE_EVENTS_DEVICE_ID,
+            ..Default::default()
+        })
+    }
+
+    fn into_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
+        self
+    }
+
+    fn path(&self) -> Cow<str> {
+        "/dev/device-events".into()
+    }
+
+    fn nonblocking(&self) -> bool {
+        self.non_blocking.load(Ordering::Acquire)
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> AxResult {
+        self.non_blocking.store(nonblocking, Ordering::Release);
+        Ok(())
+    }
+}
+
+impl Pollable for DeviceEvents {
+    fn poll(&self) -> IoEvents {
+        self.queue.poll()
+    }
+
+    fn register(&self, context: &mut Context<'_>, events: IoEvents) {
+        self.queue.register(context, events);
+    }
+}
@@ -0,0 +1,155 @@
+use alloc::{borrow::Cow, sync::Arc};
+use core::{any::Any, ffi::c_int, task::Context};
+
+use axerrno::{AxError, AxResult};
+use axfs_ng_vfs::{Location, NodeType, path::Path};
+use axpoll::{IoEvents, Pollable};
+use axtask::current;
+use linux_raw_sys::general::{O_ACCMODE, O_CREAT, O_RDONLY, O_TRUNC, O_WRONLY};
+use starry_core::{
+    landlock::{
+        Domain, LANDLOCK_ACCESS_FS_MAKE_BLOCK, LANDLOCK_ACCESS_FS_MAKE_CHAR,
+        LANDLOCK_ACCESS_FS_MAKE_DIR, LANDLOCK_ACCESS_FS_MAKE_FIFO, LANDLOCK_ACCESS_FS_MAKE_REG,
+        LANDLOCK_ACCESS_FS_MAKE_SOCK, LANDLOCK_ACCESS_FS_MAKE_SYM, LANDLOCK_ACCESS_FS_READ_DIR,
+        LANDLOCK_ACCESS_FS_READ_FILE, LANDLOCK_ACCESS_FS_REMOVE_DIR,
+        LANDLOCK_ACCESS_FS_REMOVE_FILE, LANDLOCK_ACCESS_FS_WRITE_FILE, Ruleset,
+    },
+    task::AsThread,
+};
+
+use super::{FileLike, Kstat, SealedBuf, SealedBufMut, with_fs};
+
+/// A Landlock ruleset returned by `landlock_create_ruleset`.
+pub struct LandlockRuleset {
+    ruleset: Ruleset,
+}
+
+impl LandlockRuleset {
+    pub fn new(ruleset: Ruleset) -> Self {
+        Self { ruleset }
+    }
+
+    pub fn ruleset(&self) -> &Ruleset {
+        &self.ruleset
+    }
+}
+
+impl FileLike for LandlockRuleset {
+    fn read(&self, _dst: &mut SealedBufMut) -> AxResult<usize> {
+        Err(AxError::InvalidInput)
+    }
+
+    fn write(&self, _src: &mut SealedBuf) -> AxResult<usize> {
+        Err(AxError::InvalidInput)
+    }
+
+    fn stat(&self) -> AxResult<Kstat> {
+        Ok(Kstat::default())
+    }
+
+    fn path(&self) -> Cow<str> {
+        "anon_inode:landlock-ruleset".into()
+    }
+
+    fn into_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
+        self
+    }
+}
+
+impl Pollable for LandlockRuleset {
+    fn poll(&self) -> IoEvents {
+        IoEvents::empty()
+    }
+
+    fn register(&self, _context: &mut Context<'_>, _events: IoEvents) {}
+}
+
+fn domain() -> Option<Arc<Domain>> {
+    current().as_thread().proc_data.landlock()
+}
+
+/// Fails with `EACCES` unless the current process may access `loc` with the
+/// Landlock access rights `access`.
+pub fn check(loc: &Location, access: u64) -> AxResult<()> {
+    match domain() {
+        Some(domain) => domain.check(&loc.absolute_path()?, access),
+        None => Ok(()),
+    }
+}
+
+/// Like [`check`], for `path` relative to `dirfd`. Paths that can't be
+/// resolved are left to fail in the operation itself.
+pub fn check_at(dirfd: c_int, path: &str, access: u64) -> AxResult<()> {
+    let Some(domain) = domain() else {
+        return Ok(());
+    };
+    match with_fs(dirfd, |fs| fs.resolve(path)) {
+        Ok(loc) => domain.check(&loc.absolute_path()?, access),
+        Err(_) => Ok(()),
+    }
+}
+
+/// Like [`check_at`], for the directory containing `path`.
+pub fn check_parent_at(dirfd: c_int, path: &str, access: u64) -> AxResult<()> {
+    let Some(domain) = domain() else {
+        return Ok(());
+    };
+    match with_fs(dirfd, |fs| Ok(fs.resolve_parent(Path::new(path))?.0)) {
+        Ok(dir) => domain.check(&dir.absolute_path()?, access),
+        Err(_) => Ok(()),
+    }
+}
+
+/// Checks opening `path` relative to `dirfd` with `flags`, which also needs
+/// the right to make a regular file in its directory if it creates it.
+pub fn check_open(dirfd: c_int, path: &str, flags: u32) -> AxResult<()> {
+    let Some(domain) = domain() else {
+        return Ok(());
+    };
+    let mut access = 0;
+    if flags & O_ACCMODE != O_WRONLY {
+        access |= LANDLOCK_ACCESS_FS_READ_FILE;
+    }
+    if flags & O_ACCMODE != O_RDONLY || flags & O_TRUNC != 0 {
+        access |= LANDLOCK_ACCESS_FS_WRITE_FILE;
+    }
+    let loc = match with_fs(dirfd, |fs| fs.resolve(path)) {
+        Ok(loc) if loc.is_dir() => {
+            access = LANDLOCK_ACCESS_FS_READ_DIR;
+            loc
+        }
+        Ok(loc) => loc,
+        Err(_) if flags & O_CREAT != 0 => {
+            // Rules beneath the directory apply to the new file as well
+            access |= LANDLOCK_ACCESS_FS_MAKE_REG;
+            match with_fs(dirfd, |fs| Ok(fs.resolve_parent(Path::new(path))?.0)) {
+                Ok(dir) => dir,
+                Err(_) => return Ok(()),
+            }
+        }
+        Err(_) => return Ok(()),
+    };
+    domain.check(&loc.absolute_path()?, access)
+}
+
+/// Returns the access right to make a node of `node_type` in a directory.
+pub fn make_access(node_type: NodeType) -> u64 {
+    match node_type {
+        NodeType::Directory => LANDLOCK_ACCESS_FS_MAKE_DIR,
+        NodeType::CharacterDevice => LANDLOCK_ACCESS_FS_MAKE_CHAR,
+        NodeType::BlockDevice => LANDLOCK_ACCESS_FS_MAKE_BLOCK,
+        NodeType::Fifo => LANDLOCK_ACCESS_FS_MAKE_FIFO,
+        NodeType::Socket => LANDLOCK_ACCESS_FS_MAKE_SOCK,
+        NodeType::Symlink => LANDLOCK_ACCESS_FS_MAKE_SYM,
+        _ => LANDLOCK_ACCESS_FS_MAKE_REG,
+    }
+}
+
+/// Returns the access right to remove a node from a directory.
+pub fn remove_access(is_dir: bool) -> u64 {
+    if is_dir {
+        LANDLOCK_ACCESS_FS_REMOVE_DIR
+    } else {
+        LANDLOCK_ACCESS_FS_REMOVE_FILE
+    }
+}
@@ -1,15 +1,32 @@
+pub mod dnotify;
 pub mod epoll;
 pub mod event;
+pub mod fanotify;
+pub mod fasync;
 mod fs;
+mod fsmount;
+mod fuse;
+mod hotplug;
+pub mod landlock;
 mod net;
+mod netlink;
+pub mod pagecache;
 mod pidfd;
 mod pipe;
+mod secretmem;
 pub mod signalfd;
+mod watchdog;
+pub mod writeback;
 
 use alloc::{borrow::Cow, sync::Arc};
-use core::{any::Any, ffi::c_int, time::Duration};
+use core::{
+    any::Any,
+    ffi::c_int,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+    time::Duration,
+};
 
-use axerrno::{AxError, AxResult};
+use axerrno::{AxError, AxResult, LinuxError};
 use axfs_ng::{FS_CONTEXT, OpenOptions};
 use axfs_ng_vfs::DeviceId;
 use axio::{Buf, BufMut, Read, Write};
@@ -23,9 +40,15 @@ use starry_core::{resources::AX_FILE_LIMIT, task::AsThread};
 
 pub use self::{
     fs::{Directory, File, ResolveAtResult, metadata_to_kstat, resolve_at, with_fs},
+    fsmount::{FsContextFile, MountFile},
+    fuse::{FUSE_DEVICE_ID, FuseDev},
+    hotplug::{DEVICE_EVENTS_DEVICE_ID, DeviceEvents},
     net::Socket,
+    netlink::NetlinkSocket,
     pidfd::PidFd,
     pipe::Pipe,
+    secretmem::SecretMem,
+    watchdog::{WATCHDOG_DEVICE_ID, Watchdog},
 };
 use crate::{
     io::IoVectorBufIo,
@@ -283,6 +306,28 @@ pub trait FileLike: Pollable + Send + Sync {
 pub struct FileDescriptor {
     pub inner: Arc<dyn FileLike>,
     pub cloexec: bool,
+    /// Counts the file as open while descriptors refer to it.
+    open: Arc<OpenFile>,
+}
+
+/// The number of open files in the system.
+static NR_OPEN_FILES: AtomicUsize = AtomicUsize::new(0);
+
+/// A file counted in [`nr_open_files`], shared by the descriptors referring
+/// to it.
+struct OpenFile;
+
+impl OpenFile {
+    fn new() -> Arc<Self> {
+        NR_OPEN_FILES.fetch_add(1, Ordering::Relaxed);
+        Arc::new(OpenFile)
+    }
+}
+
+impl Drop for OpenFile {
+    fn drop(&mut self) {
+        NR_OPEN_FILES.fetch_sub(1, Ordering::Relaxed);
+    }
 }
 
 scope_local::scope_local! {
@@ -299,14 +344,45 @@ pub fn get_file_like(fd: c_int) -> AxResult<Arc<dyn FileLike>> {
         .ok_or(AxError::BadFileDescriptor)
 }
 
+/// The maximum number of open files in the system
+/// (`/proc/sys/fs/file-max`).
+pub static FILE_MAX: AtomicU64 = AtomicU64::new(65536);
+
+/// Returns the number of open files in the system, counting a file once
+/// however many descriptors refer to it.
+pub fn nr_open_files() -> usize {
+    NR_OPEN_FILES.load(Ordering::Relaxed)
+}
+
 /// Add a file to the file descriptor table.
 pub fn add_file_like(f: Arc<dyn FileLike>, cloexec: bool) -> AxResult<c_int> {
-    let max_nofile = current().as_thread().proc_data.rlim.read()[RLIMIT_NOFILE].current;
+    let proc_data = &current().as_thread().proc_data;
+    // Only a file not yet referred to elsewhere is a newly opened one, which
+    // is limited by `fs.file-max` unless the process is privileged. Like
+    // Linux, duplicating a descriptor is not.
+    if Arc::strong_count(&f) == 1
+        && !proc_data.cred.read().is_privileged()
+        && nr_open_files() as u64 >= FILE_MAX.load(Ordering::Relaxed)
+    {
+        return Err(AxError::Other(LinuxError::ENFILE));
+    }
+    let max_nofile = proc_data.rlim.read()[RLIMIT_NOFILE].current;
     let mut table = FD_TABLE.write();
     if table.count() as u64 >= max_nofile {
         return Err(AxError::TooManyOpenFiles);
     }
-    let fd = FileDescriptor { inner: f, cloexec };
+    // Descriptors duplicated within the process share the count of the
+    // file. A file passed from another process is counted again.
+    let open = table
+        .ids()
+        .filter_map(|fd| table.get(fd))
+        .find(|it| Arc::ptr_eq(&it.inner, &f))
+        .map_or_else(OpenFile::new, |it| it.open.clone());
+    let fd = FileDescriptor {
+        inner: f,
+        cloexec,
+        open,
+    };
     Ok(table.add(fd).map_err(|_| AxError::TooManyOpenFiles)? as c_int)
 }
 
@@ -317,9 +393,22 @@ pub fn close_file_like(fd: c_int) -> AxResult {
         .remove(fd as usize)
         .ok_or(AxError::BadFileDescriptor)?;
     debug!("close_file_like <= count: {}", Arc::strong_count(&f.inner));
+    release_fd(f);
     Ok(())
 }
 
+/// Releases a descriptor removed from the file descriptor table, which must
+/// not be locked anymore.
+///
+/// Closing the last descriptor of a socket lingers as set with `SO_LINGER`.
+pub fn release_fd(f: FileDescriptor) {
+    if Arc::strong_count(&f.inner) == 1
+        && let Ok(socket) = f.inner.into_any().downcast::<Socket>()
+    {
+        socket.close_linger();
+    }
+}
+
 pub fn add_stdio(fd_table: &mut FlattenObjects<FileDescriptor, AX_FILE_LIMIT>) -> AxResult<()> {
     assert_eq!(fd_table.count(), 0);
     let cx = FS_CONTEXT.lock();
@@ -331,22 +420,26 @@ pub fn add_stdio(fd_table: &mut FlattenObjects<FileDescriptor, AX_FILE_LIMIT>) -
 
     let tty_in = open(OpenOptions::new().read(true).write(false))?;
     let tty_out = open(OpenOptions::new().read(false).write(true))?;
+    let tty_out_open = OpenFile::new();
     fd_table
         .add(FileDescriptor {
             inner: tty_in,
             cloexec: false,
+            open: OpenFile::new(),
         })
         .map_err(|_| AxError::TooManyOpenFiles)?;
     fd_table
         .add(FileDescriptor {
             inner: tty_out.clone(),
             cloexec: false,
+            open: tty_out_open.clone(),
         })
         .map_err(|_| AxError::TooManyOpenFiles)?;
     fd_table
         .add(FileDescriptor {
             inner: tty_out,
             cloexec: false,
+            open: tty_out_open,
         })
         .map_err(|_| AxError::TooManyOpenFiles)?;
 
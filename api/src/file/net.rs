@@ -1,34 +1,318 @@
-use alloc::{borrow::Cow, format, sync::Arc};
-use core::{ffi::c_int, ops::Deref, task::Context};
+use alloc::{borrow::Cow, format, string::String, sync::Arc, vec::Vec};
+use core::{ffi::c_int, net::Ipv4Addr, ops::Deref, task::Context, time::Duration};
 
-use axerrno::{AxError, AxResult};
+use axerrno::{AxError, AxResult, LinuxError};
 use axnet::{
-    SocketOps,
+    Shutdown, SocketAddrEx, SocketOps,
     options::{Configurable, GetSocketOption, SetSocketOption},
 };
 use axpoll::{IoEvents, Pollable};
-use linux_raw_sys::general::S_IFSOCK;
+use axsync::Mutex;
+use axtask::future::Poller;
+use linux_raw_sys::{
+    general::S_IFSOCK,
+    ioctl::{SIOCGIFADDR, SIOCGIFCONF, SIOCGIFFLAGS, SIOCSIFADDR},
+    net::AF_INET,
+};
 
 use super::{FileLike, Kstat};
-use crate::file::{SealedBuf, SealedBufMut, get_file_like};
+use crate::{
+    file::{SealedBuf, SealedBufMut, get_file_like},
+    filter::SocketFilter,
+    mm::UserPtr,
+    netif::{self, NetInterface},
+};
+
+/// Size of the interface name in `struct ifreq`.
+const IFNAMSIZ: usize = 16;
+/// Size of `struct ifreq`: the name followed by a 24-byte union.
+const IFREQ_SIZE: usize = 40;
+/// Size of `struct ifconf`: an `int` length and a buffer pointer.
+const IFCONF_SIZE: usize = 16;
+/// Size of the UDP header.
+const UDP_HLEN: usize = 8;
+/// Maximum size of a UDP payload.
+const MAX_UDP_PAYLOAD: usize = 65535;
+
+fn ifreq_name(ifreq: &[u8]) -> AxResult<&str> {
+    let name = &ifreq[..IFNAMSIZ];
+    let len = name.iter().position(|&c| c == 0).unwrap_or(IFNAMSIZ);
+    core::str::from_utf8(&name[..len]).map_err(|_| AxError::InvalidInput)
+}
+
+fn ifreq_interface(ifreq: &[u8]) -> AxResult<NetInterface> {
+    netif::find_by_name(ifreq_name(ifreq)?).ok_or(AxError::Other(LinuxError::ENODEV))
+}
+
+/// Writes an IPv4 address as a `struct sockaddr_in` into the `ifreq` union.
+fn ifreq_set_addr(ifreq: &mut [u8], addr: Ipv4Addr) {
+    let sockaddr = &mut ifreq[IFNAMSIZ..IFNAMSIZ + 16];
+    sockaddr.fill(0);
+    sockaddr[..2].copy_from_slice(&(AF_INET as u16).to_ne_bytes());
+    sockaddr[4..8].copy_from_slice(&addr.octets());
+}
+
+/// Handles the `SIOC*IF*` interface configuration ioctls.
+fn interface_ioctl(cmd: u32, arg: usize) -> AxResult<usize> {
+    match cmd {
+        SIOCGIFCONF => {
+            let ifconf = UserPtr::<u8>::from(arg).get_as_mut_slice(IFCONF_SIZE)?;
+            let len = i32::from_ne_bytes(ifconf[..4].try_into().unwrap());
+            let buf = usize::from_ne_bytes(ifconf[8..16].try_into().unwrap());
+            let interfaces = netif::interfaces();
+            let written = if buf == 0 {
+                // First pass: report the buffer size needed.
+                interfaces.len() * IFREQ_SIZE
+            } else {
+                let count = (len.max(0) as usize / IFREQ_SIZE).min(interfaces.len());
+                let out = UserPtr::<u8>::from(buf).get_as_mut_slice(count * IFREQ_SIZE)?;
+                for (iface, ifreq) in interfaces.iter().zip(out.chunks_exact_mut(IFREQ_SIZE)) {
+                    ifreq.fill(0);
+                    let name_len = iface.name.len().min(IFNAMSIZ - 1);
+                    ifreq[..name_len].copy_from_slice(&iface.name.as_bytes()[..name_len]);
+                    ifreq_set_addr(ifreq, iface.addr);
+                }
+                count * IFREQ_SIZE
+            };
+            ifconf[..4].copy_from_slice(&(written as i32).to_ne_bytes());
+            Ok(0)
+        }
+        SIOCGIFADDR => {
+            let ifreq = UserPtr::<u8>::from(arg).get_as_mut_slice(IFREQ_SIZE)?;
+            let iface = ifreq_interface(ifreq)?;
+            ifreq_set_addr(ifreq, iface.addr);
+            Ok(0)
+        }
+        // The address of an interface is set when axnet brings it up, and
+        // can't be changed afterwards.
+        SIOCSIFADDR => Err(AxError::OperationNotSupported),
+        SIOCGIFFLAGS => {
+            let ifreq = UserPtr::<u8>::from(arg).get_as_mut_slice(IFREQ_SIZE)?;
+            let iface = ifreq_interface(ifreq)?;
+            ifreq[IFNAMSIZ..IFNAMSIZ + 2].copy_from_slice(&(iface.flags as i16).to_ne_bytes());
+            Ok(0)
+        }
+        _ => Err(AxError::NotATty),
+    }
+}
 
-pub struct Socket(pub axnet::Socket);
+pub struct Socket {
+    inner: axnet::Socket,
+    /// The `SO_LINGER` timeout, if enabled.
+    linger: Mutex<Option<Duration>>,
+    /// The interface set with `SO_BINDTODEVICE`.
+    bound_device: Mutex<Option<String>>,
+    /// The filter attached with `SO_ATTACH_FILTER`.
+    filter: Mutex<Option<Arc<SocketFilter>>>,
+}
+
+impl Socket {
+    pub fn new(inner: axnet::Socket) -> Self {
+        Self {
+            inner,
+            linger: Mutex::new(None),
+            bound_device: Mutex::new(None),
+            filter: Mutex::new(None),
+        }
+    }
+
+    /// Returns the `SO_LINGER` timeout, or `None` if lingering is disabled.
+    pub fn linger(&self) -> Option<Duration> {
+        *self.linger.lock()
+    }
+
+    /// Sets the `SO_LINGER` timeout.
+    pub fn set_linger(&self, linger: Option<Duration>) {
+        *self.linger.lock() = linger;
+    }
+
+    /// Returns the name of the interface the socket is bound to.
+    pub fn bound_device(&self) -> Option<String> {
+        self.bound_device.lock().clone()
+    }
+
+    /// Binds the socket to the named interface, or unbinds it if `name` is
+    /// empty.
+    ///
+    /// Only UDP sockets can be bound, as the interface TCP segments pass
+    /// through isn't known outside `axnet`.
+    pub fn bind_device(&self, name: &str) -> AxResult<()> {
+        if !matches!(self.inner, axnet::Socket::Udp(_)) {
+            return Err(AxError::OperationNotSupported);
+        }
+        let device = if name.is_empty() {
+            None
+        } else {
+            let iface = netif::find_by_name(name).ok_or(AxError::Other(LinuxError::ENODEV))?;
+            Some(iface.name)
+        };
+        *self.bound_device.lock() = device;
+        Ok(())
+    }
+
+    /// Returns whether traffic to or from `addr` may pass through the
+    /// interface the socket is bound to.
+    pub fn device_allows(&self, addr: &SocketAddrEx) -> bool {
+        let Some(device) = self.bound_device.lock().clone() else {
+            return true;
+        };
+        match addr {
+            SocketAddrEx::Ip(addr) if !addr.ip().is_unspecified() => {
+                netif::route(addr.ip()).is_some_and(|iface| iface.name == device)
+            }
+            _ => true,
+        }
+    }
+
+    /// Fails with `ENETUNREACH` if `addr`, or the connected peer if `None`,
+    /// can't be reached through the interface the socket is bound to.
+    pub fn check_route(&self, addr: Option<&SocketAddrEx>) -> AxResult<()> {
+        if self.bound_device.lock().is_none() {
+            return Ok(());
+        }
+        let allowed = match addr {
+            Some(addr) => self.device_allows(addr),
+            None => self
+                .peer_addr()
+                .ok()
+                .is_none_or(|peer| self.device_allows(&peer)),
+        };
+        if allowed {
+            Ok(())
+        } else {
+            Err(AxError::Other(LinuxError::ENETUNREACH))
+        }
+    }
+
+    /// Returns the index of the interface traffic with `peer`, or with the
+    /// connected peer if `None`, passes through.
+    pub fn traffic_iface(&self, peer: Option<&SocketAddrEx>) -> Option<u32> {
+        if !matches!(self.inner, axnet::Socket::Tcp(_) | axnet::Socket::Udp(_)) {
+            return None;
+        }
+        let connected;
+        let peer = match peer {
+            Some(peer) => peer,
+            None => {
+                connected = self.peer_addr().ok()?;
+                &connected
+            }
+        };
+        match peer {
+            SocketAddrEx::Ip(addr) if !addr.ip().is_unspecified() => {
+                netif::route(addr.ip()).map(|iface| iface.index)
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the attached socket filter.
+    pub fn filter(&self) -> Option<Arc<SocketFilter>> {
+        self.filter.lock().clone()
+    }
+
+    /// Attaches a socket filter, or detaches the current one if `filter` is
+    /// `None`.
+    ///
+    /// Only UDP sockets can have a filter, as `axnet` hands out the data of
+    /// other sockets as a stream, not as packets.
+    pub fn set_filter(&self, filter: Option<Arc<SocketFilter>>) -> AxResult<()> {
+        if !matches!(self.inner, axnet::Socket::Udp(_)) {
+            return Err(AxError::OperationNotSupported);
+        }
+        *self.filter.lock() = filter;
+        Ok(())
+    }
+
+    /// Returns how many bytes of the payload of a datagram the attached
+    /// filter needs to see.
+    pub fn filter_peek_len(&self) -> usize {
+        self.filter().map_or(0, |filter| {
+            filter
+                .load_len()
+                .map_or(MAX_UDP_PAYLOAD, |len| len.saturating_sub(UDP_HLEN))
+                .min(MAX_UDP_PAYLOAD)
+        })
+    }
+
+    /// Returns whether the attached filter accepts a UDP datagram of `len`
+    /// bytes from `from`, whose payload starts with `payload`.
+    ///
+    /// Like Linux, the filter sees the datagram starting at its UDP header,
+    /// which is rebuilt here since `axnet` only hands out the payload.
+    pub fn filter_accepts(&self, from: &SocketAddrEx, payload: &[u8], len: usize) -> bool {
+        let Some(filter) = self.filter() else {
+            return true;
+        };
+        let port = |addr: &SocketAddrEx| match addr {
+            SocketAddrEx::Ip(addr) => addr.port(),
+            _ => 0,
+        };
+        let src_port = port(from);
+        let dst_port = self.local_addr().map_or(0, |addr| port(&addr));
+        let len = UDP_HLEN + len;
+
+        let mut packet = Vec::with_capacity(UDP_HLEN + payload.len());
+        packet.extend_from_slice(&src_port.to_be_bytes());
+        packet.extend_from_slice(&dst_port.to_be_bytes());
+        packet.extend_from_slice(&(len as u16).to_be_bytes());
+        packet.extend_from_slice(&[0; 2]); // checksum
+        packet.extend_from_slice(payload);
+        filter.run(&packet, len) != 0
+    }
+}
 
 impl Deref for Socket {
     type Target = axnet::Socket;
 
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.inner
+    }
+}
+
+impl Socket {
+    /// Closes the socket as set with `SO_LINGER`, once its last descriptor is
+    /// closed. This may wait, so the file descriptor table must not be
+    /// locked.
+    pub fn close_linger(&self) {
+        let Some(linger) = self.linger() else {
+            return;
+        };
+        if !matches!(self.inner, axnet::Socket::Tcp(_)) || self.peer_addr().is_err() {
+            return;
+        }
+        if self.nonblocking() || self.shutdown(Shutdown::Write).is_err() {
+            return;
+        }
+        // Wait until the connection is fully closed or the linger time expires.
+        let _ = Poller::new(self, IoEvents::HUP)
+            .timeout(Some(linger))
+            .poll(|| {
+                if self.inner.poll().contains(IoEvents::HUP) {
+                    Ok(())
+                } else {
+                    Err(AxError::WouldBlock)
+                }
+            });
     }
 }
 
 impl FileLike for Socket {
     fn read(&self, dst: &mut SealedBufMut) -> AxResult<usize> {
-        self.recv(dst, axnet::RecvOptions::default())
+        let read = self.recv(dst, axnet::RecvOptions::default())?;
+        if let Some(index) = self.traffic_iface(None) {
+            netif::account_socket(index, read, false);
+        }
+        Ok(read)
     }
 
     fn write(&self, src: &mut SealedBuf) -> AxResult<usize> {
-        self.send(src, axnet::SendOptions::default())
+        self.check_route(None)?;
+        let written = self.send(src, axnet::SendOptions::default())?;
+        if let Some(index) = self.traffic_iface(None) {
+            netif::account_socket(index, written, true);
+        }
+        Ok(written)
     }
 
     fn stat(&self) -> AxResult<Kstat> {
@@ -44,6 +328,10 @@ impl FileLike for Socket {
         self
     }
 
+    fn ioctl(&self, cmd: u32, arg: usize) -> AxResult<usize> {
+        interface_ioctl(cmd, arg)
+    }
+
     fn nonblocking(&self) -> bool {
         let mut result = false;
         self.get_option(GetSocketOption::NonBlocking(&mut result))
@@ -52,7 +340,7 @@ impl FileLike for Socket {
     }
 
     fn set_nonblocking(&self, nonblocking: bool) -> AxResult<()> {
-        self.0
+        self.inner
             .set_option(SetSocketOption::NonBlocking(&nonblocking))
     }
 
@@ -72,10 +360,10 @@ impl FileLike for Socket {
 }
 impl Pollable for Socket {
     fn poll(&self) -> IoEvents {
-        self.0.poll()
+        self.inner.poll()
     }
 
     fn register(&self, context: &mut Context<'_>, events: IoEvents) {
-        self.0.register(context, events);
+        self.inner.register(context, events);
     }
 }
@@ -0,0 +1,266 @@
+use alloc::{borrow::Cow, collections::VecDeque, format, sync::Arc, vec::Vec};
+use core::{
+    any::Any,
+    ffi::c_int,
+    sync::atomic::{AtomicBool, Ordering},
+    task::Context,
+};
+
+use axerrno::{AxError, AxResult, LinuxError};
+use axio::{Buf, BufMut, Write};
+use axpoll::{IoEvents, PollSet, Pollable};
+use axsync::Mutex;
+use axtask::{current, future::Poller};
+use linux_raw_sys::{
+    general::S_IFSOCK,
+    netlink::{NETLINK_KOBJECT_UEVENT, NETLINK_ROUTE},
+};
+use starry_core::{
+    hotplug::{self, DeviceEvent, DeviceEventQueue},
+    task::AsThread,
+};
+
+mod route;
+
+use super::{FileLike, Kstat, SealedBuf, SealedBufMut, get_file_like};
+use crate::socket::NetlinkAddr;
+
+/// The multicast group kernel uevents are sent to.
+const UEVENT_GROUP: u32 = 1;
+
+struct Datagram {
+    data: Vec<u8>,
+    from: NetlinkAddr,
+}
+
+/// A netlink socket (`AF_NETLINK`).
+pub struct NetlinkSocket {
+    protocol: u32,
+    local: Mutex<Option<NetlinkAddr>>,
+    rx: Mutex<VecDeque<Datagram>>,
+    uevents: Mutex<Option<Arc<DeviceEventQueue>>>,
+    non_blocking: AtomicBool,
+    poll_rx: PollSet,
+}
+
+impl NetlinkSocket {
+    pub fn new(protocol: u32) -> AxResult<Self> {
+        match protocol {
+            NETLINK_KOBJECT_UEVENT | NETLINK_ROUTE => {}
+            _ => return Err(AxError::Other(LinuxError::EPROTONOSUPPORT)),
+        }
+        Ok(Self {
+            protocol,
+            local: Mutex::new(None),
+            rx: Mutex::new(VecDeque::new()),
+            uevents: Mutex::new(None),
+            non_blocking: AtomicBool::new(false),
+            poll_rx: PollSet::new(),
+        })
+    }
+
+    /// Binds the socket to a port ID and joins the given multicast groups.
+    ///
+    /// A zero port ID lets the kernel pick one.
+    pub fn bind(&self, addr: NetlinkAddr) -> AxResult<()> {
+        let mut local = self.local.lock();
+        let pid = match (*local, addr.pid) {
+            (Some(bound), 0) => bound.pid,
+            (Some(bound), pid) if bound.pid != pid => return Err(AxError::InvalidInput),
+            (_, 0) => current().as_thread().proc_data.proc.pid(),
+            (_, pid) => pid,
+        };
+        *local = Some(NetlinkAddr {
+            pid,
+            groups: addr.groups,
+        });
+
+        if self.protocol == NETLINK_KOBJECT_UEVENT {
+            let mut uevents = self.uevents.lock();
+            if addr.groups & UEVENT_GROUP == 0 {
+                *uevents = None;
+            } else if uevents.is_none() {
+                *uevents = Some(hotplug::subscribe());
+            }
+        }
+        Ok(())
+    }
+
+    /// Binds the socket to the process ID if it is not bound yet, and returns
+    /// the port ID.
+    fn autobind(&self) -> u32 {
+        self.local
+            .lock()
+            .get_or_insert_with(|| NetlinkAddr {
+                pid: current().as_thread().proc_data.proc.pid(),
+                groups: 0,
+            })
+            .pid
+    }
+
+    /// Returns the address the socket is bound to.
+    pub fn local_addr(&self) -> NetlinkAddr {
+        self.local.lock().unwrap_or_default()
+    }
+
+    /// Sends a netlink message to the kernel.
+    pub fn send(&self, src: &mut impl Buf, to: Option<NetlinkAddr>) -> AxResult<usize> {
+        if to.is_some_and(|to| to.pid != 0) {
+            // Unicast between user sockets is not supported.
+            return Err(AxError::Other(LinuxError::ECONNREFUSED));
+        }
+        let mut data = Vec::with_capacity(src.remaining());
+        src.consume(|chunk| {
+            data.extend_from_slice(chunk);
+            Ok(chunk.len())
+        })?;
+
+        if self.protocol == NETLINK_ROUTE {
+            let port = self.autobind();
+            let reply = route::handle_request(&data, port);
+            if !reply.is_empty() {
+                self.rx.lock().push_back(Datagram {
+                    data: reply,
+                    from: NetlinkAddr::default(),
+                });
+                self.poll_rx.wake();
+            }
+        }
+        // The kernel ignores messages sent to a uevent socket.
+        Ok(data.len())
+    }
+
+    /// Receives a single datagram.
+    ///
+    /// Returns the number of bytes copied (or the full length of the datagram
+    /// if `truncate` is set) and the sender address.
+    pub fn recv(
+        &self,
+        dst: &mut impl BufMut,
+        peek: bool,
+        truncate: bool,
+    ) -> AxResult<(usize, NetlinkAddr)> {
+        Poller::new(self, IoEvents::IN)
+            .non_blocking(self.nonblocking())
+            .poll(|| {
+                let mut rx = self.rx.lock();
+                if rx.is_empty()
+                    && let Some(event) = self.uevents.lock().as_ref().and_then(|it| it.pop())
+                {
+                    rx.push_back(Datagram {
+                        data: format_uevent(&event),
+                        from: NetlinkAddr {
+                            pid: 0,
+                            groups: UEVENT_GROUP,
+                        },
+                    });
+                }
+                let Some(datagram) = rx.front() else {
+                    return Err(AxError::WouldBlock);
+                };
+                let copied = dst.write(&datagram.data)?;
+                let len = if truncate {
+                    datagram.data.len()
+                } else {
+                    copied
+                };
+                let from = datagram.from;
+                if !peek {
+                    rx.pop_front();
+                }
+                Ok((len, from))
+            })
+    }
+}
+
+/// Formats a device event as a kernel uevent message, e.g.
+/// `add@/devices/virtual/block/loop0\0ACTION=add\0...`.
+fn format_uevent(event: &DeviceEvent) -> Vec<u8> {
+    let action = event.action.as_str();
+    let subsystem = event.subsystem();
+    let devpath = format!("/devices/virtual/{subsystem}/{}", event.name);
+
+    let mut buf = Vec::new();
+    for field in [
+        format!("{action}@{devpath}"),
+        format!("ACTION={action}"),
+        format!("DEVPATH={devpath}"),
+        format!("SUBSYSTEM={subsystem}"),
+        format!("MAJOR={}", event.device_id.major()),
+        format!("MINOR={}", event.device_id.minor()),
+        format!("DEVNAME={}", event.name),
+        format!("SEQNUM={}", event.seqnum),
+    ] {
+        buf.extend_from_slice(field.as_bytes());
+        buf.push(0);
+    }
+    buf
+}
+
+impl FileLike for NetlinkSocket {
+    fn read(&self, dst: &mut SealedBufMut) -> AxResult<usize> {
+        self.recv(dst, false, false).map(|(len, _)| len)
+    }
+
+    fn write(&self, src: &mut SealedBuf) -> AxResult<usize> {
+        self.send(src, None)
+    }
+
+    fn stat(&self) -> AxResult<Kstat> {
+        Ok(Kstat {
+            mode: S_IFSOCK | 0o777u32, // rwxrwxrwx
+            blksize: 4096,
+            ..Default::default()
+        })
+    }
+
+    fn into_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
+        self
+    }
+
+    fn nonblocking(&self) -> bool {
+        self.non_blocking.load(Ordering::Acquire)
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> AxResult {
+        self.non_blocking.store(nonblocking, Ordering::Release);
+        Ok(())
+    }
+
+    fn path(&self) -> Cow<str> {
+        format!("socket:[{}]", self as *const _ as usize).into()
+    }
+
+    fn from_fd(fd: c_int) -> AxResult<Arc<Self>>
+    where
+        Self: Sized + 'static,
+    {
+        get_file_like(fd)?
+            .into_any()
+            .downcast::<Self>()
+            .map_err(|_| AxError::NotASocket)
+    }
+}
+
+impl Pollable for NetlinkSocket {
+    fn poll(&self) -> IoEvents {
+        let mut events = IoEvents::OUT;
+        let readable = !self.rx.lock().is_empty()
+            || self
+                .uevents
+                .lock()
+                .as_ref()
+                .is_some_and(|it| !it.is_empty());
+        events.set(IoEvents::IN, readable);
+        events
+    }
+
+    fn register(&self, context: &mut Context<'_>, events: IoEvents) {
+        if events.contains(IoEvents::IN) {
+            self.poll_rx.register(context.waker());
+            if let Some(uevents) = self.uevents.lock().as_ref() {
+                uevents.register(context, events);
+            }
+        }
+    }
+}
@@ -0,0 +1,236 @@
+//! A minimal `NETLINK_ROUTE` implementation answering link and address
+//! queries from the kernel interface table.
+
+use alloc::vec::Vec;
+
+use linux_raw_sys::{
+    general::AF_INET,
+    netlink::{
+        IFA_ADDRESS, IFA_BROADCAST, IFA_F_PERMANENT, IFA_LABEL, IFA_LOCAL, IFLA_ADDRESS,
+        IFLA_BROADCAST, IFLA_IFNAME, IFLA_MTU, IFLA_OPERSTATE, NLM_F_ACK, NLM_F_DUMP, NLM_F_MULTI,
+        NLM_F_REQUEST, NLMSG_DONE, NLMSG_ERROR, RT_SCOPE_HOST, RT_SCOPE_UNIVERSE, RTM_GETADDR,
+        RTM_GETLINK, RTM_NEWADDR, RTM_NEWLINK,
+    },
+};
+
+use crate::netif::{self, NetInterface};
+
+const NLMSG_HDRLEN: usize = 16;
+const IFINFOMSG_LEN: usize = 16;
+
+/// `IF_OPER_UP` from `<linux/if.h>`.
+const IF_OPER_UP: u8 = 6;
+/// `IF_OPER_UNKNOWN` from `<linux/if.h>`; reported for loopback like Linux.
+const IF_OPER_UNKNOWN: u8 = 0;
+
+const fn align(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+/// The header of a netlink message (`struct nlmsghdr`).
+#[derive(Debug, Clone, Copy)]
+struct Header {
+    len: u32,
+    ty: u16,
+    flags: u16,
+    seq: u32,
+    pid: u32,
+}
+
+impl Header {
+    fn parse(buf: &[u8]) -> Option<Self> {
+        if buf.len() < NLMSG_HDRLEN {
+            return None;
+        }
+        let u32_at = |i: usize| u32::from_ne_bytes(buf[i..i + 4].try_into().unwrap());
+        let u16_at = |i: usize| u16::from_ne_bytes(buf[i..i + 2].try_into().unwrap());
+        Some(Self {
+            len: u32_at(0),
+            ty: u16_at(4),
+            flags: u16_at(6),
+            seq: u32_at(8),
+            pid: u32_at(12),
+        })
+    }
+
+    fn write_to(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.len.to_ne_bytes());
+        buf.extend_from_slice(&self.ty.to_ne_bytes());
+        buf.extend_from_slice(&self.flags.to_ne_bytes());
+        buf.extend_from_slice(&self.seq.to_ne_bytes());
+        buf.extend_from_slice(&self.pid.to_ne_bytes());
+    }
+}
+
+/// Builds a stream of netlink messages into one datagram.
+struct Reply {
+    buf: Vec<u8>,
+    seq: u32,
+    port: u32,
+}
+
+impl Reply {
+    /// Starts a new message and returns its offset.
+    fn begin(&mut self, ty: u32, flags: u32) -> usize {
+        let start = self.buf.len();
+        Header {
+            len: 0,
+            ty: ty as u16,
+            flags: flags as u16,
+            seq: self.seq,
+            pid: self.port,
+        }
+        .write_to(&mut self.buf);
+        start
+    }
+
+    /// Finishes the message started at `start`, filling in its length.
+    fn end(&mut self, start: usize) {
+        let len = (self.buf.len() - start) as u32;
+        self.buf[start..start + 4].copy_from_slice(&len.to_ne_bytes());
+        self.buf.resize(align(self.buf.len()), 0);
+    }
+
+    fn attr(&mut self, ty: u32, data: &[u8]) {
+        let len = (4 + data.len()) as u16;
+        self.buf.extend_from_slice(&len.to_ne_bytes());
+        self.buf.extend_from_slice(&(ty as u16).to_ne_bytes());
+        self.buf.extend_from_slice(data);
+        self.buf.resize(align(self.buf.len()), 0);
+    }
+
+    fn attr_str(&mut self, ty: u32, s: &str) {
+        let mut data = Vec::with_capacity(s.len() + 1);
+        data.extend_from_slice(s.as_bytes());
+        data.push(0);
+        self.attr(ty, &data);
+    }
+
+    fn link(&mut self, iface: &NetInterface, flags: u32) {
+        let start = self.begin(RTM_NEWLINK as u32, flags);
+        // struct ifinfomsg
+        self.buf.push(0); // ifi_family: AF_UNSPEC
+        self.buf.push(0);
+        self.buf.extend_from_slice(&iface.hw_type.to_ne_bytes());
+        self.buf
+            .extend_from_slice(&(iface.index as i32).to_ne_bytes());
+        self.buf.extend_from_slice(&iface.flags.to_ne_bytes());
+        self.buf.extend_from_slice(&0u32.to_ne_bytes());
+
+        self.attr_str(IFLA_IFNAME as u32, &iface.name);
+        self.attr(IFLA_MTU as u32, &iface.mtu.to_ne_bytes());
+        self.attr(IFLA_ADDRESS as u32, &iface.mac);
+        let broadcast = if iface.is_loopback() {
+            [0; 6]
+        } else {
+            [0xff; 6]
+        };
+        self.attr(IFLA_BROADCAST as u32, &broadcast);
+        let operstate = if iface.is_loopback() {
+            IF_OPER_UNKNOWN
+        } else {
+            IF_OPER_UP
+        };
+        self.attr(IFLA_OPERSTATE as u32, &[operstate]);
+        self.end(start);
+    }
+
+    fn addr(&mut self, iface: &NetInterface, flags: u32) {
+        let start = self.begin(RTM_NEWADDR as u32, flags);
+        // struct ifaddrmsg
+        self.buf.push(AF_INET as u8);
+        self.buf.push(iface.prefix_len);
+        self.buf.push(IFA_F_PERMANENT as u8);
+        self.buf.push(if iface.is_loopback() {
+            RT_SCOPE_HOST as u8
+        } else {
+            RT_SCOPE_UNIVERSE as u8
+        });
+        self.buf.extend_from_slice(&iface.index.to_ne_bytes());
+
+        let addr = iface.addr.octets();
+        self.attr(IFA_ADDRESS as u32, &addr);
+        self.attr(IFA_LOCAL as u32, &addr);
+        if !iface.is_loopback() {
+            self.attr(IFA_BROADCAST as u32, &iface.broadcast().octets());
+        }
+        self.attr_str(IFA_LABEL as u32, &iface.name);
+        self.end(start);
+    }
+
+    fn done(&mut self) {
+        let start = self.begin(NLMSG_DONE as u32, NLM_F_MULTI as u32);
+        self.buf.extend_from_slice(&0i32.to_ne_bytes());
+        self.end(start);
+    }
+
+    /// Appends an `NLMSG_ERROR` message; an `error` of 0 is an ACK.
+    fn error(&mut self, error: i32, request: &Header) {
+        let start = self.begin(NLMSG_ERROR as u32, 0);
+        self.buf.extend_from_slice(&error.to_ne_bytes());
+        request.write_to(&mut self.buf);
+        self.end(start);
+    }
+}
+
+fn handle_message(reply: &mut Reply, header: &Header, payload: &[u8]) -> Result<(), i32> {
+    use linux_raw_sys::general::{EINVAL, ENODEV, EOPNOTSUPP};
+
+    if header.flags as u32 & NLM_F_REQUEST as u32 == 0 {
+        // Not a request; the kernel ignores it.
+        return Ok(());
+    }
+    let dump = header.flags as u32 & NLM_F_DUMP as u32 == NLM_F_DUMP as u32;
+    match header.ty as u32 {
+        RTM_GETLINK if dump => {
+            for iface in netif::interfaces() {
+                reply.link(&iface, NLM_F_MULTI as u32);
+            }
+            reply.done();
+        }
+        RTM_GETLINK => {
+            if payload.len() < IFINFOMSG_LEN {
+                return Err(EINVAL as i32);
+            }
+            let index = i32::from_ne_bytes(payload[4..8].try_into().unwrap());
+            let iface = netif::find_by_index(index as u32).ok_or(ENODEV as i32)?;
+            reply.link(&iface, 0);
+        }
+        RTM_GETADDR if dump => {
+            for iface in netif::interfaces() {
+                reply.addr(&iface, NLM_F_MULTI as u32);
+            }
+            reply.done();
+        }
+        _ => return Err(EOPNOTSUPP as i32),
+    }
+    Ok(())
+}
+
+/// Handles the netlink messages in `data` sent from the socket bound to
+/// `port`, returning the reply datagram (empty if nothing is to be sent back).
+pub fn handle_request(data: &[u8], port: u32) -> Vec<u8> {
+    let mut reply = Reply {
+        buf: Vec::new(),
+        seq: 0,
+        port,
+    };
+    let mut data = data;
+    while let Some(header) = Header::parse(data) {
+        let len = header.len as usize;
+        if len < NLMSG_HDRLEN || len > data.len() {
+            break;
+        }
+        reply.seq = header.seq;
+        let result = handle_message(&mut reply, &header, &data[NLMSG_HDRLEN..len]);
+        match result {
+            Err(err) => reply.error(-err, &header),
+            Ok(()) if header.flags as u32 & (NLM_F_ACK | NLM_F_DUMP) == NLM_F_ACK => {
+                reply.error(0, &header)
+            }
+            Ok(()) => {}
+        }
+        data = &data[align(len).min(data.len())..];
+    }
+    reply.buf
+}
@@ -0,0 +1,97 @@
+//! Page cache residency.
+//!
+//! The page cache doesn't report which pages it holds, so the pages that
+//! reads and writes went through are recorded here, for `cachestat` and
+//! `RWF_NOWAIT`. Pages brought in otherwise, such as by faults on mappings,
+//! aren't known. Recorded pages are assumed to stay cached until the file
+//! is truncated.
+
+use alloc::collections::btree_map::BTreeMap;
+
+use axfs_ng_vfs::Location;
+use axhal::mem::PAGE_SIZE_4K;
+use axsync::Mutex;
+use starry_core::uprobe::FileId;
+
+/// The ranges `[start, end)` of resident page indices of each file, keyed by
+/// their start. The ranges of a file don't overlap nor touch.
+static RESIDENT: Mutex<BTreeMap<FileId, BTreeMap<u64, u64>>> = Mutex::new(BTreeMap::new());
+
+/// Returns the page indices `[start, end)` covering `len` bytes at `offset`.
+fn page_range(offset: u64, len: usize) -> (u64, u64) {
+    let end = offset.saturating_add(len as u64);
+    (
+        offset / PAGE_SIZE_4K as u64,
+        end.div_ceil(PAGE_SIZE_4K as u64),
+    )
+}
+
+/// Records that `len` bytes at `offset` of the file at `loc` went through
+/// the page cache.
+pub fn mark_resident(loc: &Location, offset: u64, len: usize) {
+    if len == 0 {
+        return;
+    }
+    let Ok(id) = FileId::of(loc) else {
+        return;
+    };
+    let (mut start, mut end) = page_range(offset, len);
+    let mut resident = RESIDENT.lock();
+    let ranges = resident.entry(id).or_default();
+    if let Some((&s, &e)) = ranges.range(..=start).next_back()
+        && e >= start
+    {
+        start = s;
+        end = end.max(e);
+    }
+    while let Some((&s, &e)) = ranges.range(start..=end).next() {
+        ranges.remove(&s);
+        end = end.max(e);
+    }
+    ranges.insert(start, end);
+}
+
+/// Returns how many of the pages `[start, end)` of the file at `loc` are
+/// known to be resident.
+pub fn resident_pages(loc: &Location, start: u64, end: u64) -> u64 {
+    let Ok(id) = FileId::of(loc) else {
+        return 0;
+    };
+    let resident = RESIDENT.lock();
+    let Some(ranges) = resident.get(&id) else {
+        return 0;
+    };
+    ranges
+        .range(..end)
+        .map(|(&s, &e)| e.min(end).saturating_sub(s.max(start)))
+        .sum()
+}
+
+/// Returns whether all the pages covering `len` bytes at `offset` of the file
+/// at `loc` are known to be resident.
+pub fn is_resident(loc: &Location, offset: u64, len: usize) -> bool {
+    if len == 0 {
+        return true;
+    }
+    let (start, end) = page_range(offset, len);
+    resident_pages(loc, start, end) == end - start
+}
+
+/// Forgets the pages of the file at `loc` past its new size `size`.
+pub fn truncate(loc: &Location, size: u64) {
+    let Ok(id) = FileId::of(loc) else {
+        return;
+    };
+    let keep = size.div_ceil(PAGE_SIZE_4K as u64);
+    let mut resident = RESIDENT.lock();
+    let Some(ranges) = resident.get_mut(&id) else {
+        return;
+    };
+    ranges.retain(|&s, e| {
+        *e = (*e).min(keep);
+        s < keep
+    });
+    if ranges.is_empty() {
+        resident.remove(&id);
+    }
+}
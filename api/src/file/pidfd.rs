@@ -2,7 +2,10 @@ use alloc::{
     borrow::Cow,
     sync::{Arc, Weak},
 };
-use core::task::Context;
+use core::{
+    sync::atomic::{AtomicBool, Ordering},
+    task::Context,
+};
 
 use axerrno::{AxError, AxResult};
 use axpoll::{IoEvents, PollSet, Pollable};
@@ -13,12 +16,14 @@ use crate::file::{FileLike, Kstat, SealedBuf, SealedBufMut};
 pub struct PidFd {
     proc_data: Weak<ProcessData>,
     exit_event: Arc<PollSet>,
+    non_blocking: AtomicBool,
 }
 impl PidFd {
     pub fn new(proc_data: &Arc<ProcessData>) -> Self {
         Self {
             proc_data: Arc::downgrade(proc_data),
             exit_event: proc_data.exit_event.clone(),
+            non_blocking: AtomicBool::new(false),
         }
     }
 
@@ -46,12 +51,27 @@ impl FileLike for PidFd {
     fn into_any(self: Arc<Self>) -> Arc<dyn core::any::Any + Send + Sync> {
         self
     }
+
+    fn nonblocking(&self) -> bool {
+        self.non_blocking.load(Ordering::Acquire)
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> AxResult {
+        self.non_blocking.store(nonblocking, Ordering::Release);
+        Ok(())
+    }
 }
 
 impl Pollable for PidFd {
     fn poll(&self) -> IoEvents {
+        // Readable once the process has exited, whether or not it has been
+        // reaped yet.
+        let exited = self
+            .proc_data
+            .upgrade()
+            .is_none_or(|proc_data| proc_data.proc.is_zombie());
         let mut events = IoEvents::empty();
-        events.set(IoEvents::IN, self.proc_data.strong_count() > 0);
+        events.set(IoEvents::IN, exited);
         events
     }
 
@@ -152,7 +152,7 @@ impl FileLike for Pipe {
 
         let mut total_written = 0;
         let non_blocking = self.nonblocking();
-        Poller::new(self, IoEvents::OUT)
+        let result = Poller::new(self, IoEvents::OUT)
             .non_blocking(non_blocking)
             .poll(|| {
                 if self.closed() {
@@ -178,7 +178,15 @@ impl FileLike for Pipe {
                     }
                 }
                 Err(AxError::WouldBlock)
-            })
+            });
+        match result {
+            // Like Linux, a write cut short by a signal or by the read end
+            // closing reports the bytes already written
+            Err(AxError::Interrupted | AxError::BrokenPipe) if total_written > 0 => {
+                Ok(total_written)
+            }
+            other => other,
+        }
     }
 
     fn stat(&self) -> AxResult<Kstat> {
@@ -0,0 +1,84 @@
+use alloc::{borrow::Cow, sync::Arc};
+use core::{any::Any, task::Context};
+
+use axerrno::{AxError, AxResult};
+use axhal::paging::PageSize;
+use axmm::backend::SharedPages;
+use axpoll::{IoEvents, Pollable};
+use axsync::Mutex;
+use memory_addr::align_up_4k;
+
+use crate::file::{FileLike, Kstat, SealedBuf, SealedBufMut};
+
+/// Memory returned by `memfd_secret`, only accessible through mappings of the
+/// processes sharing the file.
+///
+/// The memory can't be read or written through the file, and its pages are
+/// allocated once and never reclaimed, so they are never swapped out.
+#[derive(Default)]
+pub struct SecretMem {
+    /// The pages and their total size, once set with `ftruncate`.
+    pages: Mutex<Option<(Arc<SharedPages>, usize)>>,
+}
+
+impl SecretMem {
+    /// Sets the size of the memory. Like Linux, the size can only be set
+    /// once.
+    pub fn set_len(&self, len: u64) -> AxResult<()> {
+        let mut pages = self.pages.lock();
+        if pages.is_some() || len == 0 {
+            return Err(AxError::InvalidInput);
+        }
+        let len = usize::try_from(len).map_err(|_| AxError::InvalidInput)?;
+        let len = align_up_4k(len);
+        *pages = Some((Arc::new(SharedPages::new(len, PageSize::Size4K)?), len));
+        Ok(())
+    }
+
+    /// Returns the pages of the memory, which must cover `len` bytes.
+    pub fn pages(&self, len: usize) -> AxResult<Arc<SharedPages>> {
+        let (pages, size) = self.pages.lock().clone().ok_or(AxError::InvalidInput)?;
+        if len > size {
+            return Err(AxError::InvalidInput);
+        }
+        Ok(pages)
+    }
+
+    fn len(&self) -> usize {
+        self.pages.lock().as_ref().map_or(0, |(_, size)| *size)
+    }
+}
+
+impl FileLike for SecretMem {
+    fn read(&self, _dst: &mut SealedBufMut) -> AxResult<usize> {
+        Err(AxError::InvalidInput)
+    }
+
+    fn write(&self, _src: &mut SealedBuf) -> AxResult<usize> {
+        Err(AxError::InvalidInput)
+    }
+
+    fn stat(&self) -> AxResult<Kstat> {
+        Ok(Kstat {
+            mode: 0o100600,
+            size: self.len() as _,
+            ..Default::default()
+        })
+    }
+
+    fn path(&self) -> Cow<str> {
+        "/secretmem (deleted)".into()
+    }
+
+    fn into_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
+        self
+    }
+}
+
+impl Pollable for SecretMem {
+    fn poll(&self) -> IoEvents {
+        IoEvents::empty()
+    }
+
+    fn register(&self, _context: &mut Context<'_>, _events: IoEvents) {}
+}
@@ -0,0 +1,243 @@
+use alloc::{borrow::Cow, sync::Arc, vec};
+use core::{
+    any::Any,
+    ffi::c_int,
+    sync::atomic::{AtomicBool, Ordering},
+    task::Context,
+    time::Duration,
+};
+
+use axerrno::{AxError, AxResult};
+use axfs_ng_vfs::DeviceId;
+use axhal::time::{TimeValue, monotonic_time};
+use axio::{Buf, Read};
+use axpoll::{IoEvents, Pollable};
+use linux_raw_sys::general::S_IFCHR;
+use spin::Mutex;
+use starry_vm::{VmMutPtr, VmPtr};
+
+use super::{FileLike, Kstat, SealedBuf, SealedBufMut};
+
+/// The device ID of `/dev/watchdog`.
+pub const WATCHDOG_DEVICE_ID: DeviceId = DeviceId::new(10, 130);
+
+// From <linux/watchdog.h>
+const WDIOC_GETSUPPORT: u32 = 0x8028_5700;
+const WDIOC_GETSTATUS: u32 = 0x8004_5701;
+const WDIOC_GETBOOTSTATUS: u32 = 0x8004_5702;
+const WDIOC_SETOPTIONS: u32 = 0x8004_5704;
+const WDIOC_KEEPALIVE: u32 = 0x8004_5705;
+const WDIOC_SETTIMEOUT: u32 = 0xc004_5706;
+const WDIOC_GETTIMEOUT: u32 = 0x8004_5707;
+const WDIOC_GETTIMELEFT: u32 = 0x8004_570a;
+
+const WDIOF_SETTIMEOUT: u32 = 0x0080;
+const WDIOF_MAGICCLOSE: u32 = 0x0100;
+const WDIOF_KEEPALIVEPING: u32 = 0x8000;
+
+const WDIOS_DISABLECARD: c_int = 0x0001;
+const WDIOS_ENABLECARD: c_int = 0x0002;
+
+const DEFAULT_TIMEOUT: u64 = 60;
+const MAX_TIMEOUT: u64 = 3600;
+
+/// How often the watchdog task checks the deadline at most.
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+#[repr(C)]
+#[allow(non_camel_case_types)]
+struct watchdog_info {
+    options: u32,
+    firmware_version: u32,
+    identity: [u8; 32],
+}
+
+struct State {
+    timeout: Duration,
+    /// When the system is reset, or `None` if the watchdog is stopped.
+    deadline: Option<TimeValue>,
+    task_spawned: bool,
+}
+
+// Never held across user memory accesses, as it is also taken when the file
+// is dropped.
+static STATE: Mutex<State> = Mutex::new(State {
+    timeout: Duration::from_secs(DEFAULT_TIMEOUT),
+    deadline: None,
+    task_spawned: false,
+});
+
+/// Whether `/dev/watchdog` is open. Like Linux, it can only be opened once
+/// at a time.
+static OPEN: AtomicBool = AtomicBool::new(false);
+
+/// Resets the system, powering it off instead if the platform can't be
+/// reset.
+fn system_reset() -> ! {
+    #[cfg(target_arch = "aarch64")]
+    axplat_aarch64_dyn::system_reset();
+    #[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
+    let _ = sbi_rt::system_reset(sbi_rt::ColdReboot, sbi_rt::NoReason);
+    // Pulse the CPU reset line through the 8042 keyboard controller.
+    #[cfg(target_arch = "x86_64")]
+    unsafe {
+        x86::io::outb(0x64, 0xfe)
+    };
+    error!("watchdog: failed to reset the system, powering it off");
+    axhal::power::system_off()
+}
+
+async fn watchdog_task() {
+    loop {
+        let remaining = match STATE.lock().deadline {
+            Some(deadline) => deadline.saturating_sub(monotonic_time()),
+            None => CHECK_INTERVAL,
+        };
+        if remaining.is_zero() {
+            error!("watchdog: timeout expired, resetting the system");
+            system_reset();
+        }
+        axtask::future::sleep(remaining.min(CHECK_INTERVAL)).await;
+    }
+}
+
+/// Arms the watchdog (if needed) and pushes the deadline back.
+fn keepalive(state: &mut State) {
+    state.deadline = Some(monotonic_time() + state.timeout);
+    if !state.task_spawned {
+        state.task_spawned = true;
+        axtask::spawn(
+            || axtask::future::block_on(watchdog_task()),
+            "watchdog".into(),
+        );
+    }
+}
+
+/// A file opened from `/dev/watchdog`, the software watchdog.
+///
+/// Opening the device starts the watchdog, which resets the system if it is
+/// not pinged again within the timeout. Closing the file stops it only if
+/// the magic character `V` was written since the last ping without one;
+/// otherwise it keeps running.
+pub struct Watchdog {
+    expect_close: AtomicBool,
+}
+
+impl Watchdog {
+    /// Opens the watchdog and starts it.
+    pub fn open() -> AxResult<Self> {
+        if OPEN.swap(true, Ordering::AcqRel) {
+            return Err(AxError::ResourceBusy);
+        }
+        keepalive(&mut STATE.lock());
+        Ok(Self {
+            expect_close: AtomicBool::new(false),
+        })
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        let mut state = STATE.lock();
+        if self.expect_close.load(Ordering::Acquire) {
+            state.deadline = None;
+        } else if state.deadline.is_some() {
+            warn!("watchdog: closed unexpectedly, not stopping it");
+        }
+        drop(state);
+        OPEN.store(false, Ordering::Release);
+    }
+}
+
+impl FileLike for Watchdog {
+    fn read(&self, _dst: &mut SealedBufMut) -> AxResult<usize> {
+        Err(AxError::InvalidInput)
+    }
+
+    fn write(&self, src: &mut SealedBuf) -> AxResult<usize> {
+        let len = src.remaining();
+        if len == 0 {
+            return Ok(0);
+        }
+        let mut buf = vec![0; len];
+        src.read(&mut buf)?;
+        // Like Linux, only a `V` in the last write arms the magic close.
+        self.expect_close
+            .store(buf.contains(&b'V'), Ordering::Release);
+        keepalive(&mut STATE.lock());
+        Ok(len)
+    }
+
+    fn stat(&self) -> AxResult<Kstat> {
+        Ok(Kstat {
+            mode: S_IFCHR | 0o600,
+            rdev: WATCHDOG_DEVICE_ID,
+            ..Default::default()
+        })
+    }
+
+    fn into_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
+        self
+    }
+
+    fn path(&self) -> Cow<str> {
+        "/dev/watchdog".into()
+    }
+
+    fn ioctl(&self, cmd: u32, arg: usize) -> AxResult<usize> {
+        match cmd {
+            WDIOC_GETSUPPORT => {
+                const IDENTITY: &[u8] = b"Software Watchdog";
+                let mut identity = [0; 32];
+                identity[..IDENTITY.len()].copy_from_slice(IDENTITY);
+                (arg as *mut watchdog_info).vm_write(watchdog_info {
+                    options: WDIOF_SETTIMEOUT | WDIOF_MAGICCLOSE | WDIOF_KEEPALIVEPING,
+                    firmware_version: 0,
+                    identity,
+                })?;
+            }
+            WDIOC_GETSTATUS | WDIOC_GETBOOTSTATUS => {
+                (arg as *mut c_int).vm_write(0)?;
+            }
+            WDIOC_SETOPTIONS => match (arg as *const c_int).vm_read()? {
+                WDIOS_DISABLECARD => STATE.lock().deadline = None,
+                WDIOS_ENABLECARD => keepalive(&mut STATE.lock()),
+                _ => return Err(AxError::InvalidInput),
+            },
+            WDIOC_KEEPALIVE => keepalive(&mut STATE.lock()),
+            WDIOC_SETTIMEOUT => {
+                let timeout = (arg as *const c_int).vm_read()?;
+                if !(1..=MAX_TIMEOUT as c_int).contains(&timeout) {
+                    return Err(AxError::InvalidInput);
+                }
+                let mut state = STATE.lock();
+                state.timeout = Duration::from_secs(timeout as u64);
+                // Setting the timeout also pings the watchdog, like Linux.
+                keepalive(&mut state);
+                drop(state);
+                (arg as *mut c_int).vm_write(timeout)?;
+            }
+            WDIOC_GETTIMEOUT => {
+                let timeout = STATE.lock().timeout;
+                (arg as *mut c_int).vm_write(timeout.as_secs() as c_int)?;
+            }
+            WDIOC_GETTIMELEFT => {
+                let left = STATE
+                    .lock()
+                    .deadline
+                    .map_or(Duration::ZERO, |it| it.saturating_sub(monotonic_time()));
+                (arg as *mut c_int).vm_write(left.as_secs() as c_int)?;
+            }
+            _ => return Err(AxError::NotATty),
+        }
+        Ok(0)
+    }
+}
+
+impl Pollable for Watchdog {
+    fn poll(&self) -> IoEvents {
+        IoEvents::empty()
+    }
+
+    fn register(&self, _context: &mut Context<'_>, _events: IoEvents) {}
+}
@@ -0,0 +1,175 @@
+//! Periodic writeback of dirty page cache data.
+//!
+//! Writes to files backed by the page cache are tracked here, and a
+//! background task flushes the files that have been dirty for too long, like
+//! Linux's flusher threads. Writers that dirty more than a ratio of the
+//! available memory flush the dirty files themselves.
+//!
+//! Dirty files are kept open until they're written back, so closing a file
+//! doesn't lose its data. Writes through shared mappings aren't tracked, so
+//! files with shared writable mappings are written back by every pass while
+//! they're mapped.
+
+use alloc::{collections::btree_map::BTreeMap, vec::Vec};
+use core::{
+    sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use axerrno::AxResult;
+use axfs_ng::FileBackend;
+use axfs_ng_vfs::Location;
+use axhal::{
+    mem::PAGE_SIZE_4K,
+    time::{TimeValue, monotonic_time},
+};
+use axsync::Mutex;
+use starry_core::{
+    psi::{self, Resource},
+    task::processes,
+    uprobe::FileId,
+};
+
+/// Interval between writeback passes, in centiseconds
+/// (`/proc/sys/vm/dirty_writeback_centisecs`). 0 disables periodic writeback.
+pub static DIRTY_WRITEBACK_CENTISECS: AtomicU64 = AtomicU64::new(500);
+/// Age after which dirty data is written back, in centiseconds
+/// (`/proc/sys/vm/dirty_expire_centisecs`).
+pub static DIRTY_EXPIRE_CENTISECS: AtomicU64 = AtomicU64::new(3000);
+/// Percentage of available memory that may be dirty before writers flush
+/// it themselves (`/proc/sys/vm/dirty_ratio`).
+pub static DIRTY_RATIO: AtomicU64 = AtomicU64::new(20);
+
+struct DirtyFile {
+    backend: FileBackend,
+    /// When the file was first dirtied since it was last written back.
+    since: TimeValue,
+}
+
+static DIRTY: Mutex<BTreeMap<FileId, DirtyFile>> = Mutex::new(BTreeMap::new());
+/// The files with shared writable mappings.
+static MAPPED: Mutex<BTreeMap<FileId, FileBackend>> = Mutex::new(BTreeMap::new());
+/// Bytes written since the last writeback.
+static DIRTY_BYTES: AtomicUsize = AtomicUsize::new(0);
+static TASK_SPAWNED: AtomicBool = AtomicBool::new(false);
+
+fn centisecs(value: &AtomicU64) -> Duration {
+    Duration::from_millis(value.load(Ordering::Relaxed).saturating_mul(10))
+}
+
+fn dirty_limit() -> usize {
+    let available = axalloc::global_allocator().available_pages() * PAGE_SIZE_4K;
+    available / 100 * DIRTY_RATIO.load(Ordering::Relaxed).min(100) as usize
+}
+
+fn spawn_task() {
+    if !TASK_SPAWNED.swap(true, Ordering::AcqRel) {
+        axtask::spawn(
+            || axtask::future::block_on(writeback_task()),
+            "writeback".into(),
+        );
+    }
+}
+
+/// Records that `bytes` bytes were written to the file of `backend` through
+/// the page cache.
+pub fn mark_dirty(backend: &FileBackend, bytes: usize) {
+    if bytes == 0 || !matches!(backend, FileBackend::Cached(_)) {
+        return;
+    }
+    let Ok(id) = FileId::of(backend.location()) else {
+        return;
+    };
+    DIRTY.lock().entry(id).or_insert_with(|| DirtyFile {
+        backend: backend.clone(),
+        since: monotonic_time(),
+    });
+    spawn_task();
+
+    if DIRTY_BYTES.fetch_add(bytes, Ordering::Relaxed) + bytes > dirty_limit() {
+        let _stall = psi::stall(Resource::Io);
+        writeback(None);
+    }
+}
+
+/// Records that the file `id` of `backend` has a new shared writable
+/// mapping.
+pub fn mark_mapped(id: FileId, backend: &FileBackend) {
+    if !matches!(backend, FileBackend::Cached(_)) {
+        return;
+    }
+    MAPPED.lock().entry(id).or_insert_with(|| backend.clone());
+    spawn_task();
+}
+
+/// Returns whether the file at `loc` may have data that hasn't been written
+/// back yet.
+pub fn is_dirty(loc: &Location) -> bool {
+    FileId::of(loc)
+        .is_ok_and(|id| DIRTY.lock().contains_key(&id) || MAPPED.lock().contains_key(&id))
+}
+
+/// Writes back the file `id` now, if it's dirty or mapped.
+pub fn flush(id: FileId) -> AxResult<()> {
+    let dirty = DIRTY.lock().remove(&id).map(|it| it.backend);
+    let backend = dirty.or_else(|| MAPPED.lock().get(&id).cloned());
+    match backend {
+        Some(backend) => backend.sync(false),
+        None => Ok(()),
+    }
+}
+
+/// Flushes the dirty files, or only those dirtied before `expired` if set,
+/// and the mapped files.
+fn writeback(expired: Option<TimeValue>) {
+    let mut flush = Vec::new();
+    let mut dirty = DIRTY.lock();
+    dirty.retain(|_, it| {
+        if expired.is_some_and(|expired| it.since > expired) {
+            return true;
+        }
+        flush.push(it.backend.clone());
+        false
+    });
+    if dirty.is_empty() {
+        DIRTY_BYTES.store(0, Ordering::Relaxed);
+    }
+    drop(dirty);
+
+    // Files stay mapped until a pass finds no process mapping them, which
+    // writes them back one last time.
+    let mut mapped = MAPPED.lock();
+    if !mapped.is_empty() {
+        let processes = processes();
+        mapped.retain(|id, backend| {
+            flush.push(backend.clone());
+            processes.iter().any(|proc_data| {
+                proc_data
+                    .shared_file_maps
+                    .lock()
+                    .iter()
+                    .any(|(_, mapped)| mapped == id)
+            })
+        });
+    }
+    drop(mapped);
+
+    for backend in flush {
+        if let Err(err) = backend.sync(false) {
+            warn!("writeback failed: {err:?}");
+        }
+    }
+}
+
+async fn writeback_task() {
+    loop {
+        let interval = centisecs(&DIRTY_WRITEBACK_CENTISECS);
+        if interval.is_zero() {
+            axtask::future::sleep(Duration::from_secs(1)).await;
+            continue;
+        }
+        axtask::future::sleep(interval).await;
+        let expired = monotonic_time().saturating_sub(centisecs(&DIRTY_EXPIRE_CENTISECS));
+        writeback(Some(expired));
+    }
+}
@@ -0,0 +1,286 @@
+//! Classic BPF socket filters (`SO_ATTACH_FILTER`).
+//!
+//! A filter is a program of the classic BPF instruction set run on every
+//! incoming packet. Its return value is the number of bytes of the packet to
+//! keep, with 0 dropping the packet.
+
+use alloc::vec::Vec;
+
+use axerrno::{AxError, AxResult};
+use linux_raw_sys::net::socklen_t;
+
+use crate::mm::UserConstPtr;
+
+/// Maximum number of instructions of a filter.
+const BPF_MAXINSNS: usize = 4096;
+/// Number of scratch memory slots.
+const BPF_MEMWORDS: usize = 16;
+
+// Instruction classes
+const BPF_LD: u16 = 0x00;
+const BPF_LDX: u16 = 0x01;
+const BPF_ST: u16 = 0x02;
+const BPF_STX: u16 = 0x03;
+const BPF_ALU: u16 = 0x04;
+const BPF_JMP: u16 = 0x05;
+const BPF_RET: u16 = 0x06;
+const BPF_MISC: u16 = 0x07;
+
+// Load sizes
+const BPF_W: u16 = 0x00;
+const BPF_H: u16 = 0x08;
+const BPF_B: u16 = 0x10;
+
+// Load modes
+const BPF_IMM: u16 = 0x00;
+const BPF_ABS: u16 = 0x20;
+const BPF_IND: u16 = 0x40;
+const BPF_MEM: u16 = 0x60;
+const BPF_LEN: u16 = 0x80;
+const BPF_MSH: u16 = 0xa0;
+
+// ALU operations
+const BPF_ADD: u16 = 0x00;
+const BPF_SUB: u16 = 0x10;
+const BPF_MUL: u16 = 0x20;
+const BPF_DIV: u16 = 0x30;
+const BPF_OR: u16 = 0x40;
+const BPF_AND: u16 = 0x50;
+const BPF_LSH: u16 = 0x60;
+const BPF_RSH: u16 = 0x70;
+const BPF_NEG: u16 = 0x80;
+const BPF_MOD: u16 = 0x90;
+const BPF_XOR: u16 = 0xa0;
+
+// Jump conditions
+const BPF_JA: u16 = 0x00;
+const BPF_JEQ: u16 = 0x10;
+const BPF_JGT: u16 = 0x20;
+const BPF_JGE: u16 = 0x30;
+const BPF_JSET: u16 = 0x40;
+
+// Operand sources
+const BPF_K: u16 = 0x00;
+const BPF_X: u16 = 0x08;
+const BPF_A: u16 = 0x10;
+
+// Miscellaneous operations
+const BPF_TAX: u16 = 0x00;
+const BPF_TXA: u16 = 0x80;
+
+/// `struct sock_filter`
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct SockFilter {
+    code: u16,
+    jt: u8,
+    jf: u8,
+    k: u32,
+}
+
+/// `struct sock_fprog`
+#[repr(C)]
+struct SockFprog {
+    len: u16,
+    filter: *const SockFilter,
+}
+
+/// A validated classic BPF program.
+#[derive(Debug)]
+pub struct SocketFilter {
+    insns: Vec<SockFilter>,
+}
+
+impl SocketFilter {
+    /// Loads a filter from a user `struct sock_fprog`.
+    pub fn from_user(prog: UserConstPtr<u8>, len: socklen_t) -> AxResult<Self> {
+        if len as usize != size_of::<SockFprog>() {
+            return Err(AxError::InvalidInput);
+        }
+        let prog = prog.cast::<SockFprog>().get_as_ref()?;
+        let insns = UserConstPtr::from(prog.filter).get_as_slice(prog.len as usize)?;
+        Self::new(insns.to_vec())
+    }
+
+    /// Validates a program the way Linux does: it must be non-empty, end with
+    /// a return, only jump forward within the program, not divide by a zero
+    /// constant and only touch valid scratch memory.
+    pub fn new(insns: Vec<SockFilter>) -> AxResult<Self> {
+        if insns.is_empty() || insns.len() > BPF_MAXINSNS {
+            return Err(AxError::InvalidInput);
+        }
+        for (pc, insn) in insns.iter().enumerate() {
+            let remaining = insns.len() - pc - 1;
+            let valid = match insn.code & 0x07 {
+                BPF_LD => match insn.code & 0xe0 {
+                    BPF_MEM => (insn.k as usize) < BPF_MEMWORDS,
+                    BPF_IMM | BPF_ABS | BPF_IND | BPF_LEN => true,
+                    _ => false,
+                },
+                BPF_LDX => match insn.code & 0xe0 {
+                    BPF_MEM => (insn.k as usize) < BPF_MEMWORDS,
+                    BPF_IMM | BPF_LEN | BPF_MSH => true,
+                    _ => false,
+                },
+                BPF_ST | BPF_STX => (insn.k as usize) < BPF_MEMWORDS,
+                BPF_ALU => match insn.code & 0xf0 {
+                    BPF_DIV | BPF_MOD => insn.code & BPF_X != 0 || insn.k != 0,
+                    op => op <= BPF_XOR,
+                },
+                BPF_JMP => match insn.code & 0xf0 {
+                    BPF_JA => (insn.k as usize) < remaining,
+                    BPF_JEQ | BPF_JGT | BPF_JGE | BPF_JSET => {
+                        (insn.jt as usize) < remaining && (insn.jf as usize) < remaining
+                    }
+                    _ => false,
+                },
+                BPF_RET => matches!(insn.code & 0x18, BPF_K | BPF_X | BPF_A),
+                BPF_MISC => matches!(insn.code & 0xf8, BPF_TAX | BPF_TXA),
+                _ => false,
+            };
+            if !valid {
+                return Err(AxError::InvalidInput);
+            }
+        }
+        if insns.last().unwrap().code & 0x07 != BPF_RET {
+            return Err(AxError::InvalidInput);
+        }
+        Ok(Self { insns })
+    }
+
+    /// Returns the number of leading bytes of a packet the filter may load,
+    /// or `None` if it loads at offsets only known at run time.
+    pub fn load_len(&self) -> Option<usize> {
+        let mut len = 0;
+        for insn in &self.insns {
+            let end = match (insn.code & 0x07, insn.code & 0xe0) {
+                (BPF_LD, BPF_ABS) => {
+                    let size = match insn.code & 0x18 {
+                        BPF_W => 4,
+                        BPF_H => 2,
+                        _ => 1,
+                    };
+                    insn.k as usize + size
+                }
+                (BPF_LD, BPF_IND) => return None,
+                (BPF_LDX, BPF_MSH) => insn.k as usize + 1,
+                _ => continue,
+            };
+            len = len.max(end);
+        }
+        Some(len)
+    }
+
+    /// Runs the filter on a packet of `len` bytes, of which `packet` holds
+    /// at least the first [`load_len`](Self::load_len), returning the number
+    /// of bytes to keep.
+    pub fn run(&self, packet: &[u8], len: usize) -> u32 {
+        let load = |offset: u32, size: u16| -> Option<u32> {
+            let offset = offset as usize;
+            let bytes = match size {
+                BPF_W => packet.get(offset..offset.checked_add(4)?)?,
+                BPF_H => packet.get(offset..offset.checked_add(2)?)?,
+                BPF_B => packet.get(offset..offset.checked_add(1)?)?,
+                _ => return None,
+            };
+            Some(bytes.iter().fold(0, |acc, &b| (acc << 8) | b as u32))
+        };
+
+        let mut a: u32 = 0;
+        let mut x: u32 = 0;
+        let mut mem = [0u32; BPF_MEMWORDS];
+        let mut pc = 0;
+        while let Some(insn) = self.insns.get(pc) {
+            pc += 1;
+            let k = insn.k;
+            match insn.code & 0x07 {
+                BPF_LD => {
+                    a = match insn.code & 0xe0 {
+                        BPF_IMM => k,
+                        BPF_LEN => len as u32,
+                        BPF_MEM => mem[k as usize],
+                        BPF_ABS => match load(k, insn.code & 0x18) {
+                            Some(val) => val,
+                            // Out of bounds loads drop the packet.
+                            None => return 0,
+                        },
+                        BPF_IND => match load(x.wrapping_add(k), insn.code & 0x18) {
+                            Some(val) => val,
+                            None => return 0,
+                        },
+                        _ => return 0,
+                    }
+                }
+                BPF_LDX => {
+                    x = match insn.code & 0xe0 {
+                        BPF_IMM => k,
+                        BPF_LEN => len as u32,
+                        BPF_MEM => mem[k as usize],
+                        // The IPv4 header length: 4 * (packet[k] & 0xf)
+                        BPF_MSH => match load(k, BPF_B) {
+                            Some(val) => (val & 0xf) << 2,
+                            None => return 0,
+                        },
+                        _ => return 0,
+                    }
+                }
+                BPF_ST => mem[k as usize] = a,
+                BPF_STX => mem[k as usize] = x,
+                BPF_ALU => {
+                    let operand = if insn.code & BPF_X != 0 { x } else { k };
+                    a = match insn.code & 0xf0 {
+                        BPF_ADD => a.wrapping_add(operand),
+                        BPF_SUB => a.wrapping_sub(operand),
+                        BPF_MUL => a.wrapping_mul(operand),
+                        BPF_DIV => match a.checked_div(operand) {
+                            Some(val) => val,
+                            None => return 0,
+                        },
+                        BPF_MOD => match a.checked_rem(operand) {
+                            Some(val) => val,
+                            None => return 0,
+                        },
+                        BPF_OR => a | operand,
+                        BPF_AND => a & operand,
+                        BPF_XOR => a ^ operand,
+                        BPF_LSH => a.checked_shl(operand).unwrap_or(0),
+                        BPF_RSH => a.checked_shr(operand).unwrap_or(0),
+                        BPF_NEG => a.wrapping_neg(),
+                        _ => return 0,
+                    }
+                }
+                BPF_JMP => {
+                    let operand = if insn.code & BPF_X != 0 { x } else { k };
+                    let taken = match insn.code & 0xf0 {
+                        BPF_JA => {
+                            pc += k as usize;
+                            continue;
+                        }
+                        BPF_JEQ => a == operand,
+                        BPF_JGT => a > operand,
+                        BPF_JGE => a >= operand,
+                        BPF_JSET => a & operand != 0,
+                        _ => return 0,
+                    };
+                    pc += if taken { insn.jt } else { insn.jf } as usize;
+                }
+                BPF_RET => {
+                    return match insn.code & 0x18 {
+                        BPF_X => x,
+                        BPF_A => a,
+                        _ => k,
+                    };
+                }
+                BPF_MISC => {
+                    if insn.code & 0xf8 == BPF_TXA {
+                        a = x;
+                    } else {
+                        x = a;
+                    }
+                }
+                _ => return 0,
+            }
+        }
+        0
+    }
+}
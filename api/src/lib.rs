@@ -11,8 +11,10 @@ extern crate axlog;
 extern crate alloc;
 
 pub mod file;
+pub mod filter;
 pub mod io;
 pub mod mm;
+pub mod netif;
 pub mod signal;
 pub mod socket;
 pub mod syscall;
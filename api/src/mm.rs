@@ -1,10 +1,11 @@
-use alloc::string::String;
+use alloc::{string::String, sync::Arc, vec::Vec};
 use core::{
     alloc::Layout,
     ffi::c_char,
     hint::unlikely,
     mem::{MaybeUninit, transmute},
     ptr, slice, str,
+    sync::atomic::{AtomicU64, Ordering},
 };
 
 use axerrno::{AxError, AxResult};
@@ -13,11 +14,13 @@ use axhal::{
     trap::{PAGE_FAULT, register_trap_handler},
 };
 use axio::{Buf, BufMut, Read, Write};
+use axmm::{AddrSpace, backend::Backend};
 use axtask::current;
-use memory_addr::{MemoryAddr, PAGE_SIZE_4K, VirtAddr};
+use memory_addr::{MemoryAddr, PAGE_SIZE_4K, VirtAddr, VirtAddrRange};
 use starry_core::{
     mm::{access_user_memory, is_accessing_user_memory},
-    task::AsThread,
+    psi::{self, Resource},
+    task::{AsThread, ProcessData, processes},
 };
 use starry_vm::{vm_load_until_nul, vm_read_slice, vm_write_slice};
 
@@ -28,14 +31,22 @@ fn check_region(start: VirtAddr, layout: Layout, access_flags: MappingFlags) ->
     }
 
     let curr = current();
-    let mut aspace = curr.as_thread().proc_data.aspace.lock();
+    let proc_data = &curr.as_thread().proc_data;
+    let mut aspace = proc_data.aspace.lock();
+
+    let page_start = start.align_down_4k();
+    let page_end = (start + layout.size()).align_up_4k();
+    if access_flags.contains(MappingFlags::WRITE) {
+        proc_data
+            .lazy_free
+            .lock()
+            .restore(&mut aspace, VirtAddrRange::new(page_start, page_end))?;
+    }
 
     if !aspace.can_access_range(start, layout.size(), access_flags) {
         return Err(AxError::BadAddress);
     }
 
-    let page_start = start.align_down_4k();
-    let page_end = (start + layout.size()).align_up_4k();
     aspace.populate_area(page_start, page_end - page_start, access_flags)?;
 
     Ok(())
@@ -249,10 +260,71 @@ fn handle_page_fault(vaddr: VirtAddr, access_flags: MappingFlags) -> bool {
         return false;
     };
 
-    thr.proc_data
-        .aspace
-        .lock()
-        .handle_page_fault(vaddr, access_flags)
+    handle_user_page_fault(&thr.proc_data, vaddr, access_flags)
+}
+
+/// Handles a page fault in the address space of a process, returning whether
+/// the access can be retried.
+pub fn handle_user_page_fault(
+    proc_data: &ProcessData,
+    vaddr: VirtAddr,
+    access_flags: MappingFlags,
+) -> bool {
+    // Faults with little free memory left are where Linux would reclaim
+    // memory, so they stall on memory while reclaiming it
+    let _stall = if memory_low() {
+        let stall = psi::stall(Resource::Memory);
+        reclaim_lazy_free();
+        stall
+    } else {
+        None
+    };
+    let mut aspace = proc_data.aspace.lock();
+    // Writing to a page freed with `MADV_FREE` cancels the free
+    if access_flags.contains(MappingFlags::WRITE)
+        && proc_data
+            .lazy_free
+            .lock()
+            .handle_write_fault(&mut aspace, vaddr)
+    {
+        proc_data.faults.account(false);
+        return true;
+    }
+    let major =
+        aspace.page_table().query(vaddr).is_err() && is_file_backed(proc_data, &aspace, vaddr);
+    let handled = aspace.handle_page_fault(vaddr, access_flags);
+    if handled {
+        proc_data.faults.account(major);
+    }
+    handled
+}
+
+/// Reclaims the pages of every process freed with `MADV_FREE`.
+///
+/// The caller must not hold the lock of any address space.
+fn reclaim_lazy_free() {
+    let mut reclaimed = 0;
+    for proc_data in processes() {
+        let mut aspace = proc_data.aspace.lock();
+        let range = VirtAddrRange::new(aspace.base(), aspace.end());
+        match proc_data.lazy_free.lock().reclaim(&mut aspace, range) {
+            Ok(size) => reclaimed += size,
+            Err(err) => warn!("Failed to reclaim freed pages: {err:?}"),
+        }
+    }
+    if reclaimed > 0 {
+        debug!("Reclaimed {reclaimed} bytes of freed pages");
+    }
+}
+
+/// Returns whether `vaddr` is in a mapping of a file. The page cache can't
+/// tell whether a page is cached, so faulting in any such page counts as a
+/// major fault.
+fn is_file_backed(proc_data: &ProcessData, aspace: &AddrSpace, vaddr: VirtAddr) -> bool {
+    proc_data.file_maps.lock().get(vaddr).is_some()
+        || aspace
+            .find_area(vaddr)
+            .is_some_and(|area| matches!(area.backend(), Backend::File(_)))
 }
 
 pub fn vm_load_string(ptr: *const c_char) -> AxResult<String> {
@@ -347,3 +419,96 @@ impl BufMut for VmBytesMut {
         self.len
     }
 }
+
+/// Mappings are checked against the memory size only.
+pub const OVERCOMMIT_GUESS: u64 = 0;
+/// Mappings are never checked.
+pub const OVERCOMMIT_ALWAYS: u64 = 1;
+/// Committed memory is kept under [`commit_limit`].
+pub const OVERCOMMIT_NEVER: u64 = 2;
+
+/// The overcommit policy (`/proc/sys/vm/overcommit_memory`).
+pub static OVERCOMMIT_MEMORY: AtomicU64 = AtomicU64::new(OVERCOMMIT_GUESS);
+/// Percentage of memory that may be committed under [`OVERCOMMIT_NEVER`]
+/// (`/proc/sys/vm/overcommit_ratio`).
+pub static OVERCOMMIT_RATIO: AtomicU64 = AtomicU64::new(50);
+
+/// Returns the size of the memory, in bytes. There is no swap.
+fn total_memory() -> usize {
+    let allocator = axalloc::global_allocator();
+    (allocator.used_pages() + allocator.available_pages()) * PAGE_SIZE_4K
+}
+
+/// Returns whether free memory is below 1/64 of the memory.
+fn memory_low() -> bool {
+    axalloc::global_allocator().available_pages() * PAGE_SIZE_4K < total_memory() / 64
+}
+
+/// Returns the most memory that may be committed under
+/// [`OVERCOMMIT_NEVER`], in bytes.
+pub fn commit_limit() -> usize {
+    let ratio = OVERCOMMIT_RATIO.load(Ordering::Relaxed) as usize;
+    (total_memory() / 100).saturating_mul(ratio)
+}
+
+/// Returns the memory committed by an address space, which is the size of
+/// its private writable mappings.
+pub fn committed_size(aspace: &AddrSpace) -> usize {
+    aspace
+        .areas()
+        .filter(|area| {
+            matches!(area.backend(), Backend::Cow(_)) && area.flags().contains(MappingFlags::WRITE)
+        })
+        .map(|area| area.size())
+        .sum()
+}
+
+/// Returns the memory in `range` that would be committed by making it
+/// writable, which is the size of the private mappings there that aren't
+/// writable yet.
+pub fn uncommitted_size(aspace: &AddrSpace, range: VirtAddrRange) -> usize {
+    aspace
+        .areas()
+        .filter(|area| {
+            matches!(area.backend(), Backend::Cow(_)) && !area.flags().contains(MappingFlags::WRITE)
+        })
+        .map(|area| {
+            let start = area.start().max(range.start);
+            let end = area.end().min(range.end);
+            end.as_usize().saturating_sub(start.as_usize())
+        })
+        .sum()
+}
+
+/// Returns the memory committed by all processes, in bytes.
+///
+/// Like cgroup usage, this is counted by walking the address spaces instead
+/// of being charged and uncharged, so the caller must not hold the lock of
+/// any of them.
+pub fn committed_memory() -> usize {
+    let mut seen = Vec::new();
+    let mut size = 0;
+    for proc_data in processes() {
+        // Processes created with `CLONE_VM` share their address space
+        let ptr = Arc::as_ptr(&proc_data.aspace);
+        if seen.contains(&ptr) {
+            continue;
+        }
+        seen.push(ptr);
+        size += committed_size(&proc_data.aspace.lock());
+    }
+    size
+}
+
+/// Checks that `size` more bytes of private writable memory can be
+/// committed under the overcommit policy, failing with `ENOMEM` otherwise.
+/// Like Linux, `noreserve` mappings are only checked under
+/// [`OVERCOMMIT_NEVER`].
+pub fn check_commit(size: usize, noreserve: bool) -> AxResult<()> {
+    let fits = match OVERCOMMIT_MEMORY.load(Ordering::Relaxed) {
+        OVERCOMMIT_ALWAYS => true,
+        OVERCOMMIT_NEVER => committed_memory().saturating_add(size) <= commit_limit(),
+        _ => noreserve || size <= total_memory(),
+    };
+    if fits { Ok(()) } else { Err(AxError::NoMemory) }
+}
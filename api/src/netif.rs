@@ -0,0 +1,158 @@
+//! Network interfaces.
+//!
+//! `axnet` does not expose its interfaces, so the kernel keeps its own view of
+//! them here. This table backs the interface related user APIs (rtnetlink,
+//! `SIOCGIF*` ioctls, etc.).
+//!
+//! `axnet` doesn't report the NICs it probed nor their configuration, so only
+//! the loopback interface is listed.
+
+use alloc::{string::String, vec, vec::Vec};
+use core::net::{IpAddr, Ipv4Addr};
+
+use axerrno::{AxError, AxResult, LinuxError};
+use lazy_static::lazy_static;
+use linux_raw_sys::net::{IFF_LOOPBACK, IFF_RUNNING, IFF_UP};
+use spin::RwLock;
+
+/// `ARPHRD_LOOPBACK` from `<linux/if_arp.h>`.
+pub const ARPHRD_LOOPBACK: u16 = 772;
+
+/// A network interface.
+#[derive(Debug, Clone)]
+pub struct NetInterface {
+    /// The interface index, starting from 1.
+    pub index: u32,
+    /// The interface name, e.g. `lo`.
+    pub name: String,
+    /// The ARP hardware type (`ARPHRD_*`).
+    pub hw_type: u16,
+    /// The interface flags (`IFF_*`).
+    pub flags: u32,
+    /// The maximum transmission unit.
+    pub mtu: u32,
+    /// The hardware address.
+    pub mac: [u8; 6],
+    /// The IPv4 address.
+    pub addr: Ipv4Addr,
+    /// The prefix length of the IPv4 network.
+    pub prefix_len: u8,
+    /// Packet counters.
+    pub stats: NetStats,
+}
+
+/// Packet counters of a network interface.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NetStats {
+    pub rx_packets: u64,
+    pub rx_bytes: u64,
+    pub rx_dropped: u64,
+    pub tx_packets: u64,
+    pub tx_bytes: u64,
+    pub tx_dropped: u64,
+}
+
+impl NetInterface {
+    /// Returns whether this is a loopback interface.
+    pub fn is_loopback(&self) -> bool {
+        self.flags & IFF_LOOPBACK as u32 != 0
+    }
+
+    /// Returns the IPv4 netmask.
+    pub fn netmask(&self) -> Ipv4Addr {
+        let bits = u32::MAX
+            .checked_shl(32 - self.prefix_len as u32)
+            .unwrap_or(0);
+        Ipv4Addr::from_bits(bits)
+    }
+
+    /// Returns the IPv4 broadcast address.
+    pub fn broadcast(&self) -> Ipv4Addr {
+        Ipv4Addr::from_bits(self.addr.to_bits() | !self.netmask().to_bits())
+    }
+}
+
+lazy_static! {
+    static ref INTERFACES: RwLock<Vec<NetInterface>> = RwLock::new(vec![NetInterface {
+        index: 1,
+        name: "lo".into(),
+        hw_type: ARPHRD_LOOPBACK,
+        flags: (IFF_UP | IFF_LOOPBACK | IFF_RUNNING) as u32,
+        mtu: 65536,
+        mac: [0; 6],
+        addr: Ipv4Addr::LOCALHOST,
+        prefix_len: 8,
+        stats: NetStats::default(),
+    }]);
+}
+
+/// Returns all network interfaces, ordered by index.
+pub fn interfaces() -> Vec<NetInterface> {
+    INTERFACES.read().clone()
+}
+
+/// Looks up a network interface by index.
+pub fn find_by_index(index: u32) -> Option<NetInterface> {
+    INTERFACES
+        .read()
+        .iter()
+        .find(|it| it.index == index)
+        .cloned()
+}
+
+/// Looks up a network interface by name.
+pub fn find_by_name(name: &str) -> Option<NetInterface> {
+    INTERFACES.read().iter().find(|it| it.name == name).cloned()
+}
+
+/// Returns the interface traffic to `addr` goes through.
+pub fn route(addr: IpAddr) -> Option<NetInterface> {
+    let interfaces = INTERFACES.read();
+    let local = match addr {
+        IpAddr::V4(addr) => addr.is_loopback() || interfaces.iter().any(|it| it.addr == addr),
+        IpAddr::V6(addr) => addr.is_loopback(),
+    };
+    let iface = if local {
+        interfaces.iter().find(|it| it.is_loopback())
+    } else {
+        let subnet = match addr {
+            IpAddr::V4(addr) => interfaces.iter().find(|it| {
+                !it.is_loopback()
+                    && !it.addr.is_unspecified()
+                    && (addr.to_bits() ^ it.addr.to_bits()) & it.netmask().to_bits() == 0
+            }),
+            IpAddr::V6(_) => None,
+        };
+        // Fall back to the first NIC as the default route.
+        subnet.or_else(|| interfaces.iter().find(|it| !it.is_loopback()))
+    };
+    iface.cloned()
+}
+
+/// Updates the counters of an interface.
+fn account(index: u32, f: impl FnOnce(&mut NetStats)) -> AxResult<()> {
+    let mut interfaces = INTERFACES.write();
+    let iface = interfaces
+        .iter_mut()
+        .find(|it| it.index == index)
+        .ok_or(AxError::Other(LinuxError::ENODEV))?;
+    f(&mut iface.stats);
+    Ok(())
+}
+
+/// Accounts socket traffic carried by `axnet` on an interface, as `axnet`
+/// does not report it. Data sent through the loopback interface is received
+/// on it as well.
+pub fn account_socket(index: u32, bytes: usize, outgoing: bool) {
+    let loopback = find_by_index(index).is_some_and(|it| it.is_loopback());
+    let _ = account(index, |stats| {
+        if outgoing {
+            stats.tx_packets += 1;
+            stats.tx_bytes += bytes as u64;
+        }
+        if outgoing == loopback {
+            stats.rx_packets += 1;
+            stats.rx_bytes += bytes as u64;
+        }
+    });
+}
@@ -11,7 +11,7 @@ use axerrno::{AxError, AxResult, LinuxError};
 #[cfg(feature = "vsock")]
 use axnet::vsock::VsockAddr;
 use axnet::{SocketAddrEx, unix::UnixSocketAddr};
-use linux_raw_sys::net::*;
+use linux_raw_sys::{net::*, netlink::sockaddr_nl};
 
 use crate::mm::{UserConstPtr, UserPtr};
 
@@ -241,6 +241,45 @@ impl SocketAddrExt for VsockAddr {
     }
 }
 
+/// Address of a netlink socket.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NetlinkAddr {
+    /// The port ID. `0` refers to the kernel.
+    pub pid: u32,
+    /// Bitmask of multicast groups.
+    pub groups: u32,
+}
+
+impl SocketAddrExt for NetlinkAddr {
+    fn read_from_user(addr: UserConstPtr<sockaddr>, addrlen: socklen_t) -> AxResult<Self> {
+        if (addrlen as usize) < size_of::<sockaddr_nl>() {
+            return Err(AxError::InvalidInput);
+        }
+        let addr_nl = addr.cast::<sockaddr_nl>().get_as_ref()?;
+        if addr_nl.nl_family as u32 != AF_NETLINK {
+            return Err(AxError::Other(LinuxError::EAFNOSUPPORT));
+        }
+        Ok(NetlinkAddr {
+            pid: addr_nl.nl_pid,
+            groups: addr_nl.nl_groups,
+        })
+    }
+
+    fn write_to_user(&self, addr: UserPtr<sockaddr>, addrlen: &mut socklen_t) -> AxResult<()> {
+        let addr_nl = sockaddr_nl {
+            nl_family: AF_NETLINK as _,
+            nl_pad: 0,
+            nl_pid: self.pid,
+            nl_groups: self.groups,
+        };
+        fill_addr(addr, addrlen, unsafe { cast_to_slice(&addr_nl) })
+    }
+
+    fn family(&self) -> u16 {
+        AF_NETLINK as u16
+    }
+}
+
 impl SocketAddrExt for SocketAddrEx {
     fn read_from_user(addr: UserConstPtr<sockaddr>, addrlen: socklen_t) -> AxResult<Self> {
         match read_family(addr, addrlen)? as u32 {
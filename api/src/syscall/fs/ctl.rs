@@ -8,18 +8,23 @@ use core::{
 use axerrno::{AxError, AxResult};
 use axfs_ng::{FS_CONTEXT, FsContext};
 use axfs_ng_vfs::{MetadataUpdate, NodePermission, NodeType, path::Path};
-use axhal::time::wall_time;
 use axtask::current;
 use linux_raw_sys::{
     general::*,
-    ioctl::{FIONBIO, TIOCGWINSZ},
+    ioctl::{FIOASYNC, FIONBIO, TIOCGPTPEER, TIOCGWINSZ},
+};
+use starry_core::{
+    landlock::{LANDLOCK_ACCESS_FS_MAKE_DIR, LANDLOCK_ACCESS_FS_MAKE_SYM},
+    task::AsThread,
+    time::realtime,
+    verity,
 };
-use starry_core::task::AsThread;
 use starry_vm::{VmPtr, vm_write_slice};
 
 use crate::{
-    file::{Directory, FileLike, get_file_like, resolve_at, with_fs},
+    file::{Directory, FileLike, dnotify, fasync, get_file_like, landlock, resolve_at, with_fs},
     mm::vm_load_string,
+    syscall::fs::open_pty_peer,
     time::TimeValueLike,
 };
 
@@ -36,6 +41,14 @@ pub fn sys_ioctl(fd: i32, cmd: u32, arg: usize) -> AxResult<isize> {
         f.set_nonblocking(val != 0)?;
         return Ok(0);
     }
+    if cmd == FIOASYNC {
+        let val = (arg as *const c_int).vm_read()?;
+        fasync::set_enabled(&f, fd, val != 0);
+        return Ok(0);
+    }
+    if cmd == TIOCGPTPEER {
+        return open_pty_peer(fd, arg as u32).map(|fd| fd as isize);
+    }
     f.ioctl(cmd, arg)
         .map(|result| result as isize)
         .inspect_err(|err| {
@@ -93,13 +106,12 @@ pub fn sys_mkdirat(dirfd: i32, path: *const c_char, mode: u32) -> AxResult<isize
     let mode = mode & !current().as_thread().proc_data.umask();
     let mode = NodePermission::from_bits_truncate(mode as u16);
 
-    with_fs(dirfd, |fs| {
-        fs.create_dir(path, mode)?;
-        Ok(0)
-    })
+    landlock::check_parent_at(dirfd, &path, LANDLOCK_ACCESS_FS_MAKE_DIR)?;
+    with_fs(dirfd, |fs| fs.create_dir(&path, mode))?;
+    dnotify::notify_at(dirfd, &path, DN_CREATE);
+    Ok(0)
 }
 
-
 pub fn sys_mknodat(dirfd: i32, path: *const c_char, mode: u32, dev: u64) -> Result<isize, AxError> {
     let path = vm_load_string(path)?;
     debug!(
@@ -123,14 +135,15 @@ pub fn sys_mknodat(dirfd: i32, path: *const c_char, mode: u32, dev: u64) -> Resu
     let mode = mode & !current().as_thread().proc_data.umask();
     let mode = NodePermission::from_bits_truncate(mode as u16);
 
-    with_fs(dirfd, |fs| {
+    landlock::check_parent_at(dirfd, &path, landlock::make_access(node_type))?;
+    let ret = with_fs(dirfd, |fs| {
         match node_type {
             NodeType::CharacterDevice | NodeType::BlockDevice => {
                 // For device nodes, we don't support creating them in this implementation
                 Err(AxError::OperationNotSupported)
             }
             NodeType::Directory => {
-                fs.create_dir(path, mode)?;
+                fs.create_dir(&path, mode)?;
                 Ok(0)
             }
             NodeType::RegularFile => {
@@ -147,11 +160,11 @@ pub fn sys_mknodat(dirfd: i32, path: *const c_char, mode: u32, dev: u64) -> Resu
                 // Symlinks require a target, but we don't have one in mknodat
                 Err(AxError::InvalidInput)
             }
-            NodeType::Unknown => {
-                Err(AxError::InvalidInput)
-            }
+            NodeType::Unknown => Err(AxError::InvalidInput),
         }
-    })
+    })?;
+    dnotify::notify_at(dirfd, &path, DN_CREATE);
+    Ok(ret)
 }
 
 // Directory buffer for getdents64 syscall
@@ -264,7 +277,9 @@ pub fn sys_linkat(
     let (new_dir, new_name) =
         with_fs(new_dirfd, |fs| fs.resolve_nonexistent(Path::new(&new_path)))?;
 
+    landlock::check(&new_dir, landlock::make_access(old.node_type()))?;
     new_dir.link(new_name, &old)?;
+    dnotify::notify_in(&new_dir, DN_CREATE);
     Ok(0)
 }
 
@@ -283,14 +298,35 @@ pub fn sys_unlinkat(dirfd: i32, path: *const c_char, flags: usize) -> AxResult<i
 
     debug!("sys_unlinkat <= dirfd: {dirfd}, path: {path:?}, flags: {flags}");
 
+    // Resolve the parent first, as the entry is gone afterwards.
+    let dir = dnotify::active()
+        .then(|| with_fs(dirfd, |fs| Ok(fs.resolve_parent(Path::new(&path))?.0)))
+        .and_then(Result::ok);
+    // fs-verity is forgotten with the last link, before the inode is reused
+    let verity_file = verity::active()
+        .then(|| with_fs(dirfd, |fs| fs.resolve_no_follow(&path)))
+        .and_then(Result::ok)
+        .filter(|loc| loc.metadata().is_ok_and(|it| it.nlink <= 1))
+        .and_then(|loc| verity::id_of(&loc));
+    landlock::check_parent_at(
+        dirfd,
+        &path,
+        landlock::remove_access(flags == AT_REMOVEDIR as _),
+    )?;
     with_fs(dirfd, |fs| {
         if flags == AT_REMOVEDIR as _ {
-            fs.remove_dir(path)?;
+            fs.remove_dir(&path)
         } else {
-            fs.remove_file(path)?;
+            fs.remove_file(&path)
         }
-        Ok(0)
-    })
+    })?;
+    if let Some(dir) = dir {
+        dnotify::notify_in(&dir, DN_DELETE);
+    }
+    if let Some(id) = verity_file {
+        verity::forget(&id);
+    }
+    Ok(0)
 }
 
 #[cfg(target_arch = "x86_64")]
@@ -338,10 +374,10 @@ pub fn sys_symlinkat(
     let linkpath = vm_load_string(linkpath)?;
     debug!("sys_symlinkat <= target: {target:?}, new_dirfd: {new_dirfd}, linkpath: {linkpath:?}");
 
-    with_fs(new_dirfd, |fs| {
-        fs.symlink(target, linkpath)?;
-        Ok(0)
-    })
+    landlock::check_parent_at(new_dirfd, &linkpath, LANDLOCK_ACCESS_FS_MAKE_SYM)?;
+    with_fs(new_dirfd, |fs| fs.symlink(target, &linkpath))?;
+    dnotify::notify_at(new_dirfd, &linkpath, DN_CREATE);
+    Ok(0)
 }
 
 #[cfg(target_arch = "x86_64")]
@@ -411,6 +447,7 @@ pub fn sys_fchownat(
         mode: Some(mode),
         ..Default::default()
     })?;
+    dnotify::notify(&loc, DN_ATTRIB);
     Ok(0)
 }
 
@@ -425,13 +462,14 @@ pub fn sys_fchmod(fd: i32, mode: u32) -> AxResult<isize> {
 
 pub fn sys_fchmodat(dirfd: i32, path: *const c_char, mode: u32, flags: u32) -> AxResult<isize> {
     let path = path.nullable().map(vm_load_string).transpose()?;
-    resolve_at(dirfd, path.as_deref(), flags)?
+    let loc = resolve_at(dirfd, path.as_deref(), flags)?
         .into_file()
-        .ok_or(AxError::BadFileDescriptor)?
-        .update_metadata(MetadataUpdate {
-            mode: Some(NodePermission::from_bits_truncate(mode as u16)),
-            ..Default::default()
-        })?;
+        .ok_or(AxError::BadFileDescriptor)?;
+    loc.update_metadata(MetadataUpdate {
+        mode: Some(NodePermission::from_bits_truncate(mode as u16)),
+        ..Default::default()
+    })?;
+    dnotify::notify(&loc, DN_ATTRIB);
     Ok(0)
 }
 
@@ -443,14 +481,15 @@ fn update_times(
     flags: u32,
 ) -> AxResult<()> {
     let path = path.nullable().map(vm_load_string).transpose()?;
-    resolve_at(dirfd, path.as_deref(), flags)?
+    let loc = resolve_at(dirfd, path.as_deref(), flags)?
         .into_file()
-        .ok_or(AxError::BadFileDescriptor)?
-        .update_metadata(MetadataUpdate {
-            atime,
-            mtime,
-            ..Default::default()
-        })?;
+        .ok_or(AxError::BadFileDescriptor)?;
+    loc.update_metadata(MetadataUpdate {
+        atime,
+        mtime,
+        ..Default::default()
+    })?;
+    dnotify::notify(&loc, DN_ATTRIB);
     Ok(())
 }
 
@@ -472,7 +511,7 @@ pub fn sys_utime(path: *const c_char, times: *const utimbuf) -> AxResult<isize>
             Duration::from_secs(times.modtime as _),
         )
     } else {
-        let time = wall_time();
+        let time = realtime();
         (time, time)
     };
     update_times(AT_FDCWD, path, Some(atime), Some(mtime), 0)?;
@@ -489,7 +528,7 @@ pub fn sys_utimes(
         let [atime, mtime] = unsafe { times.vm_read_uninit()?.assume_init() };
         (atime.try_into_time_value()?, mtime.try_into_time_value()?)
     } else {
-        let time = wall_time();
+        let time = realtime();
         (time, time)
     };
     update_times(AT_FDCWD, path, Some(atime), Some(mtime), 0)?;
@@ -508,7 +547,7 @@ pub fn sys_utimensat(
     fn utime_to_duration(time: &timespec) -> Option<AxResult<Duration>> {
         match time.tv_nsec {
             val if val == UTIME_OMIT as _ => None,
-            val if val == UTIME_NOW as _ => Some(Ok(wall_time())),
+            val if val == UTIME_NOW as _ => Some(Ok(realtime())),
             _ => Some(time.try_into_time_value()),
         }
     }
@@ -521,7 +560,7 @@ pub fn sys_utimensat(
             utime_to_duration(&mtime).transpose()?,
         )
     } else {
-        let time = wall_time();
+        let time = realtime();
         (Some(time), Some(time))
     };
     if atime.is_none() && mtime.is_none() {
@@ -563,8 +602,15 @@ pub fn sys_renameat2(
     let (old_dir, old_name) = with_fs(old_dirfd, |fs| fs.resolve_parent(Path::new(&old_path)))?;
     let (new_dir, new_name) =
         with_fs(new_dirfd, |fs| fs.resolve_nonexistent(Path::new(&new_path)))?;
+    // A rename removes the node from one directory and makes it in another
+    let moved = with_fs(old_dirfd, |fs| fs.resolve_no_follow(&old_path))?;
+    landlock::check(&old_dir, landlock::remove_access(moved.is_dir()))?;
+    landlock::check(&new_dir, landlock::make_access(moved.node_type()))?;
 
     old_dir.rename(&old_name, &new_dir, new_name)?;
+    // Renames within a directory notify it twice, but the signals coalesce.
+    dnotify::notify_in(&old_dir, DN_RENAME);
+    dnotify::notify_in(&new_dir, DN_RENAME);
     Ok(0)
 }
 
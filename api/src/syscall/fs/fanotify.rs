@@ -0,0 +1,150 @@
+use core::ffi::c_char;
+
+use axerrno::{AxError, AxResult};
+use linux_raw_sys::general::{
+    AT_EMPTY_PATH, AT_SYMLINK_NOFOLLOW, O_ACCMODE, O_APPEND, O_CLOEXEC, O_DSYNC, O_LARGEFILE,
+    O_NOATIME, O_NONBLOCK, O_SYNC,
+};
+use starry_vm::VmPtr;
+
+use crate::{
+    file::{
+        FileLike, add_file_like,
+        fanotify::{FAN_ALL_EVENTS, Fanotify},
+        resolve_at,
+    },
+    mm::vm_load_string,
+    syscall::sys::sys_geteuid,
+};
+
+const FAN_CLOEXEC: u32 = 0x0000_0001;
+const FAN_NONBLOCK: u32 = 0x0000_0002;
+const FAN_CLASS_CONTENT: u32 = 0x0000_0004;
+const FAN_CLASS_PRE_CONTENT: u32 = 0x0000_0008;
+const FAN_UNLIMITED_QUEUE: u32 = 0x0000_0010;
+const FAN_UNLIMITED_MARKS: u32 = 0x0000_0020;
+const FAN_ENABLE_AUDIT: u32 = 0x0000_0040;
+const FAN_REPORT_TID: u32 = 0x0000_0100;
+
+const FAN_MARK_ADD: u32 = 0x0000_0001;
+const FAN_MARK_REMOVE: u32 = 0x0000_0002;
+const FAN_MARK_DONT_FOLLOW: u32 = 0x0000_0004;
+const FAN_MARK_ONLYDIR: u32 = 0x0000_0008;
+const FAN_MARK_MOUNT: u32 = 0x0000_0010;
+const FAN_MARK_IGNORED_MASK: u32 = 0x0000_0020;
+const FAN_MARK_IGNORED_SURV_MODIFY: u32 = 0x0000_0040;
+const FAN_MARK_FLUSH: u32 = 0x0000_0080;
+const FAN_MARK_FILESYSTEM: u32 = 0x0000_0100;
+
+pub fn sys_fanotify_init(flags: u32, event_f_flags: u32) -> AxResult<isize> {
+    debug!("sys_fanotify_init <= flags: {flags:#x}, event_f_flags: {event_f_flags:#x}");
+    if flags
+        & !(FAN_CLOEXEC
+            | FAN_NONBLOCK
+            | FAN_CLASS_CONTENT
+            | FAN_CLASS_PRE_CONTENT
+            | FAN_UNLIMITED_QUEUE
+            | FAN_UNLIMITED_MARKS
+            | FAN_ENABLE_AUDIT
+            | FAN_REPORT_TID)
+        != 0
+        || event_f_flags
+            & !(O_ACCMODE
+                | O_CLOEXEC
+                | O_NONBLOCK
+                | O_APPEND
+                | O_DSYNC
+                | O_SYNC
+                | O_NOATIME
+                | O_LARGEFILE)
+            != 0
+    {
+        return Err(AxError::InvalidInput);
+    }
+    let permission = match flags & (FAN_CLASS_CONTENT | FAN_CLASS_PRE_CONTENT) {
+        0 => false,
+        FAN_CLASS_CONTENT | FAN_CLASS_PRE_CONTENT => true,
+        _ => return Err(AxError::InvalidInput),
+    };
+    // Permission events can hold up any process, so they are for the
+    // superuser only.
+    if (permission || flags & (FAN_UNLIMITED_QUEUE | FAN_UNLIMITED_MARKS) != 0)
+        && sys_geteuid()? != 0
+    {
+        return Err(AxError::OperationNotPermitted);
+    }
+
+    let group = Fanotify::new(
+        permission,
+        flags & FAN_REPORT_TID != 0,
+        flags & FAN_UNLIMITED_QUEUE != 0,
+        event_f_flags,
+    );
+    group.set_nonblocking(flags & FAN_NONBLOCK != 0)?;
+    add_file_like(group as _, flags & FAN_CLOEXEC != 0).map(|fd| fd as _)
+}
+
+pub fn sys_fanotify_mark(
+    fd: i32,
+    flags: u32,
+    mask: u64,
+    dirfd: i32,
+    path: *const c_char,
+) -> AxResult<isize> {
+    let path = path.nullable().map(vm_load_string).transpose()?;
+    debug!(
+        "sys_fanotify_mark <= fd: {fd}, flags: {flags:#x}, mask: {mask:#x}, dirfd: {dirfd}, path: \
+         {path:?}"
+    );
+    if flags
+        & !(FAN_MARK_ADD
+            | FAN_MARK_REMOVE
+            | FAN_MARK_DONT_FOLLOW
+            | FAN_MARK_ONLYDIR
+            | FAN_MARK_MOUNT
+            | FAN_MARK_IGNORED_MASK
+            | FAN_MARK_IGNORED_SURV_MODIFY
+            | FAN_MARK_FLUSH
+            | FAN_MARK_FILESYSTEM)
+        != 0
+    {
+        return Err(AxError::InvalidInput);
+    }
+    let group = Fanotify::from_fd(fd)?;
+    // Filesystem marks are approximated by marks on the mount.
+    let mount = flags & (FAN_MARK_MOUNT | FAN_MARK_FILESYSTEM) != 0;
+
+    match flags & (FAN_MARK_ADD | FAN_MARK_REMOVE | FAN_MARK_FLUSH) {
+        FAN_MARK_FLUSH => {
+            group.flush_marks(mount);
+            return Ok(0);
+        }
+        FAN_MARK_ADD | FAN_MARK_REMOVE => {}
+        _ => return Err(AxError::InvalidInput),
+    }
+    if mask == 0 || mask & !FAN_ALL_EVENTS != 0 {
+        return Err(AxError::InvalidInput);
+    }
+
+    let mut at_flags = 0;
+    if flags & FAN_MARK_DONT_FOLLOW != 0 {
+        at_flags |= AT_SYMLINK_NOFOLLOW;
+    }
+    if path.is_none() {
+        at_flags |= AT_EMPTY_PATH;
+    }
+    let loc = resolve_at(dirfd, path.as_deref(), at_flags)?
+        .into_file()
+        .ok_or(AxError::BadFileDescriptor)?;
+    if flags & FAN_MARK_ONLYDIR != 0 && !loc.is_dir() {
+        return Err(AxError::NotADirectory);
+    }
+
+    let ignored = flags & FAN_MARK_IGNORED_MASK != 0;
+    if flags & FAN_MARK_ADD != 0 {
+        group.add_mark(&loc, mount, mask, ignored)?;
+    } else {
+        group.remove_mark(&loc, mount, mask, ignored)?;
+    }
+    Ok(0)
+}
@@ -1,26 +1,31 @@
-use alloc::{format, string::ToString, sync::Arc};
+use alloc::{format, string::ToString, sync::Arc, vec::Vec};
 use core::{
     ffi::{c_char, c_int},
     mem,
     ops::{Deref, DerefMut},
 };
 
-use axerrno::{AxError, AxResult};
+use axerrno::{AxError, AxResult, LinuxError};
 use axfs_ng::{FS_CONTEXT, FileBackend, OpenOptions, OpenResult};
 use axfs_ng_vfs::{DirEntry, FileNode, Location, NodePermission, NodeType, Reference};
 use axtask::current;
 use bitflags::bitflags;
 use linux_raw_sys::general::*;
-use starry_core::{task::AsThread, vfs::Device};
+use starry_core::{task::AsThread, verity, vfs::Device};
 
 use crate::{
     file::{
-        Directory, FD_TABLE, File, FileLike, Pipe, add_file_like, close_file_like, get_file_like,
-        with_fs,
+        DeviceEvents, Directory, FD_TABLE, File, FileLike, FuseDev, Pipe, Watchdog, add_file_like,
+        close_file_like, dnotify,
+        fanotify::{self, FAN_OPEN, FAN_OPEN_PERM},
+        fasync, get_file_like, landlock, pagecache, release_fd, with_fs,
     },
     mm::{UserPtr, vm_load_string},
     syscall::sys::{sys_getegid, sys_geteuid},
-    vfs::dev::tty,
+    vfs::{
+        dev::{fuse, hotplug, hwrng, mem, tty, watchdog},
+        mounts,
+    },
 };
 
 /// Convert open flags to [`OpenOptions`].
@@ -62,10 +67,51 @@ fn flags_to_options(flags: c_int, mode: __kernel_mode_t, (uid, gid): (u32, u32))
 
 fn add_to_fd(result: OpenResult, flags: u32) -> AxResult<i32> {
     let f: Arc<dyn FileLike> = match result {
-        OpenResult::File(mut file) => {
+        OpenResult::File(mut file) => 'file: {
+            let loc = file.location();
+            if matches!(
+                loc.node_type(),
+                NodeType::CharacterDevice | NodeType::BlockDevice
+            ) && mounts::has_option(loc, "nodev")
+            {
+                return Err(AxError::PermissionDenied);
+            }
+            if flags & O_TRUNC != 0 {
+                pagecache::truncate(loc, 0);
+            }
             // /dev/xx handling
             if let Ok(device) = file.location().entry().downcast::<Device>() {
                 let inner = device.inner().as_any();
+                if inner.is::<fuse::FuseClone>() {
+                    // Every open of /dev/fuse is a new connection
+                    break 'file Arc::new(FuseDev::new());
+                }
+                if inner.is::<hotplug::DeviceEventsClone>() {
+                    // Every open of /dev/device-events gets its own queue
+                    break 'file Arc::new(DeviceEvents::new());
+                }
+                if inner.is::<watchdog::WatchdogClone>() {
+                    // Opening /dev/watchdog starts the watchdog
+                    break 'file Arc::new(Watchdog::open()?);
+                }
+                if inner.is::<mem::Mem>() {
+                    mem::check_access()?;
+                }
+                if inner.is::<hwrng::HwRngDevice>() && !hwrng::is_available() {
+                    // Like Linux, /dev/hwrng can only be opened once a
+                    // generator is registered
+                    return Err(AxError::Other(LinuxError::ENODEV));
+                }
+                if inner
+                    .downcast_ref::<tty::PtyDriver>()
+                    .is_some_and(|it| it.is_locked_slave())
+                {
+                    // Like Linux, the slave can't be opened before `unlockpt`
+                    return Err(AxError::Other(LinuxError::EIO));
+                }
+                if let Some(vt) = inner.downcast_ref::<tty::VtDriver>() {
+                    tty::vt::open(vt);
+                }
                 if let Some(ptmx) = inner.downcast_ref::<tty::Ptmx>() {
                     // Opening /dev/ptmx creates a new pseudo-terminal
                     let (master, pty_number) = ptmx.create_pty()?;
@@ -91,6 +137,8 @@ fn add_to_fd(result: OpenResult, flags: u32) -> AxResult<i32> {
                         "/dev/console".to_string()
                     } else if let Some(pts) = term.downcast_ref::<tty::PtyDriver>() {
                         format!("/dev/pts/{}", pts.pty_number())
+                    } else if let Some(vt) = term.downcast_ref::<tty::VtDriver>() {
+                        format!("/dev/tty{}", tty::vt::vt_number(vt))
                     } else {
                         panic!("unknown terminal type")
                     };
@@ -108,6 +156,30 @@ fn add_to_fd(result: OpenResult, flags: u32) -> AxResult<i32> {
     add_file_like(f, flags & O_CLOEXEC != 0)
 }
 
+/// Opens the slave of the pseudo-terminal whose master is `fd`, for
+/// `TIOCGPTPEER`.
+pub fn open_pty_peer(fd: c_int, flags: u32) -> AxResult<c_int> {
+    let file = File::from_fd(fd).map_err(|_| AxError::NotATty)?;
+    let device = file
+        .inner()
+        .location()
+        .entry()
+        .downcast::<Device>()
+        .map_err(|_| AxError::NotATty)?;
+    let pty_number = device
+        .inner()
+        .as_any()
+        .downcast_ref::<tty::PtyDriver>()
+        .filter(|it| it.is_master())
+        .ok_or(AxError::NotATty)?
+        .pty_number();
+
+    let flags = flags & (O_ACCMODE | O_NOCTTY | O_NONBLOCK | O_CLOEXEC);
+    let options = flags_to_options(flags as _, 0, (0, 0));
+    let result = options.open(&FS_CONTEXT.lock(), &format!("/dev/pts/{pty_number}"))?;
+    add_to_fd(result, flags)
+}
+
 /// Open or create a file.
 /// fd: file descriptor
 /// filename: file path to be opened or created
@@ -126,9 +198,32 @@ pub fn sys_openat(
     let mode = mode & !current().as_thread().proc_data.umask();
 
     let options = flags_to_options(flags, mode, (sys_geteuid()? as _, sys_getegid()? as _));
-    with_fs(dirfd, |fs| options.open(fs, path))
-        .and_then(|it| add_to_fd(it, flags as _))
-        .map(|fd| fd as isize)
+    let creating = flags as u32 & O_CREAT != 0
+        && dnotify::active()
+        && with_fs(dirfd, |fs| fs.resolve(&path)).is_err();
+    // `O_PATH` opens do not access the file, so they generate no events.
+    // Files with fs-verity can't be opened for writing
+    if verity::active() && flags as u32 & (O_WRONLY | O_RDWR | O_TRUNC) != 0 {
+        if let Ok(loc) = with_fs(dirfd, |fs| fs.resolve(&path)) {
+            verity::check_write(&loc)?;
+        }
+    }
+    if flags as u32 & O_PATH == 0 {
+        landlock::check_open(dirfd, &path, flags as _)?;
+    }
+    let notify = fanotify::active() && flags as u32 & O_PATH == 0;
+    if notify && let Ok(loc) = with_fs(dirfd, |fs| fs.resolve(&path)) {
+        fanotify::permission(&loc, FAN_OPEN_PERM)?;
+    }
+    let fd =
+        with_fs(dirfd, |fs| options.open(fs, &path)).and_then(|it| add_to_fd(it, flags as _))?;
+    if creating {
+        dnotify::notify_at(dirfd, &path, DN_CREATE);
+    }
+    if notify && let Ok(loc) = with_fs(dirfd, |fs| fs.resolve(&path)) {
+        fanotify::notify(&loc, FAN_OPEN);
+    }
+    Ok(fd as isize)
 }
 
 /// Open a file by `filename` and insert it into the file descriptor table.
@@ -171,6 +266,7 @@ pub fn sys_close_range(first: i32, last: i32, flags: u32) -> AxResult<isize> {
 
     let cloexec = flags.contains(CloseRangeFlags::CLOEXEC);
     let mut fd_table = FD_TABLE.write();
+    let mut closed = Vec::new();
     if let Some(max_index) = fd_table.ids().next_back() {
         for fd in first..=last.min(max_index as i32) {
             if cloexec {
@@ -178,10 +274,12 @@ pub fn sys_close_range(first: i32, last: i32, flags: u32) -> AxResult<isize> {
                     f.cloexec = true;
                 }
             } else {
-                fd_table.remove(fd as _);
+                closed.extend(fd_table.remove(fd as _));
             }
         }
     }
+    drop(fd_table);
+    closed.into_iter().for_each(release_fd);
 
     Ok(0)
 }
@@ -217,9 +315,14 @@ pub fn sys_dup3(old_fd: c_int, new_fd: c_int, flags: c_int) -> AxResult<isize> {
     let flags = Dup3Flags::from_bits(flags).ok_or(AxError::InvalidInput)?;
     debug!("sys_dup3 <= old_fd: {old_fd}, new_fd: {new_fd}, flags: {flags:?}");
 
+    // Unlike `dup2`, duplicating a descriptor onto itself is an error.
     if old_fd == new_fd {
         return Err(AxError::InvalidInput);
     }
+    let max_nofile = current().as_thread().proc_data.rlim.read()[RLIMIT_NOFILE].current;
+    if new_fd < 0 || new_fd as u64 >= max_nofile {
+        return Err(AxError::BadFileDescriptor);
+    }
 
     let mut fd_table = FD_TABLE.write();
     let mut f = fd_table
@@ -228,10 +331,16 @@ pub fn sys_dup3(old_fd: c_int, new_fd: c_int, flags: c_int) -> AxResult<isize> {
         .ok_or(AxError::BadFileDescriptor)?;
     f.cloexec = flags.contains(Dup3Flags::O_CLOEXEC);
 
-    fd_table.remove(new_fd as _);
+    // `new_fd` is closed and reused atomically, but the file it referred to
+    // is only released once the table is unlocked.
+    let closed = fd_table.remove(new_fd as _);
     fd_table
         .add_at(new_fd as _, f)
         .map_err(|_| AxError::BadFileDescriptor)?;
+    drop(fd_table);
+    if let Some(closed) = closed {
+        release_fd(closed);
+    }
 
     Ok(new_fd as _)
 }
@@ -250,7 +359,12 @@ pub fn sys_fcntl(fd: c_int, cmd: c_int, arg: usize) -> AxResult<isize> {
             Ok(0)
         }
         F_SETFL => {
-            get_file_like(fd)?.set_nonblocking(arg & (O_NONBLOCK as usize) > 0)?;
+            let f = get_file_like(fd)?;
+            f.set_nonblocking(arg & (O_NONBLOCK as usize) > 0)?;
+            let async_io = arg & (O_ASYNC as usize) > 0;
+            if async_io || fasync::enabled(&f) {
+                fasync::set_enabled(&f, fd, async_io);
+            }
             Ok(0)
         }
         F_GETFL => {
@@ -260,6 +374,9 @@ pub fn sys_fcntl(fd: c_int, cmd: c_int, arg: usize) -> AxResult<isize> {
             if f.nonblocking() {
                 ret |= O_NONBLOCK;
             }
+            if fasync::enabled(&f) {
+                ret |= O_ASYNC;
+            }
 
             let perm = NodePermission::from_bits_truncate(f.stat()?.mode as _);
             if perm.contains(NodePermission::OWNER_WRITE) {
@@ -298,6 +415,21 @@ pub fn sys_fcntl(fd: c_int, cmd: c_int, arg: usize) -> AxResult<isize> {
             pipe.resize(arg)?;
             Ok(0)
         }
+        F_SETOWN => {
+            fasync::set_owner(&get_file_like(fd)?, arg as i32);
+            Ok(0)
+        }
+        F_GETOWN => Ok(fasync::owner(&get_file_like(fd)?) as _),
+        F_SETSIG => {
+            fasync::set_signal(&get_file_like(fd)?, arg as u32)?;
+            Ok(0)
+        }
+        F_GETSIG => Ok(fasync::signal(&get_file_like(fd)?) as _),
+        F_NOTIFY => {
+            let dir = Directory::from_fd(fd)?;
+            dnotify::set_watch(&dir, arg as u32);
+            Ok(0)
+        }
         _ => {
             warn!("unsupported fcntl parameters: cmd: {cmd}");
             Ok(0)
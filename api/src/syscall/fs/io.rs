@@ -4,17 +4,30 @@ use core::{
     task::Context,
 };
 
-use axerrno::{AxError, AxResult};
-use axfs_ng::{FS_CONTEXT, FileFlags, OpenOptions};
-use axio::{Seek, SeekFrom};
+use axerrno::{AxError, AxResult, LinuxError};
+use axfs_ng::{FS_CONTEXT, FileBackend, FileFlags, OpenOptions};
+use axio::{BufMut, Seek, SeekFrom};
 use axpoll::{IoEvents, Pollable};
 use axtask::current;
-use linux_raw_sys::general::__kernel_off_t;
+use linux_raw_sys::general::{
+    __kernel_off_t, DN_MODIFY, RWF_APPEND, RWF_DSYNC, RWF_HIPRI, RWF_NOWAIT, RWF_SYNC, SEEK_DATA,
+    SEEK_HOLE,
+};
+use memory_addr::PAGE_SIZE_4K;
+use starry_core::{
+    psi::{self, Resource},
+    task::AsThread,
+    verity,
+};
 use starry_vm::{VmMutPtr, VmPtr};
 use syscalls::Sysno;
 
 use crate::{
-    file::{File, FileLike, Pipe, SealedBuf, SealedBufMut, get_file_like},
+    file::{
+        Directory, File, FileLike, Pipe, SealedBuf, SealedBufMut, SecretMem, dnotify,
+        fanotify::{self, FAN_MODIFY},
+        get_file_like, pagecache, writeback,
+    },
     io::{IoVec, IoVectorBuf},
     mm::{UserConstPtr, VmBytes, VmBytesMut},
 };
@@ -59,19 +72,104 @@ pub fn sys_dummy_fd(sysno: Sysno) -> AxResult<isize> {
     DummyFd.add_to_fd_table(false).map(|fd| fd as isize)
 }
 
+/// Returns whether reads and writes of `f` go through the page cache to
+/// storage, for I/O accounting.
+fn is_storage(f: &File) -> bool {
+    matches!(f.inner().backend(), Ok(FileBackend::Cached(_)))
+}
+
+fn as_file(f: &Arc<dyn FileLike>) -> Option<Arc<File>> {
+    f.clone().into_any().downcast::<File>().ok()
+}
+
+/// Returns where an I/O on `file` started: `offset`, or if it was at the
+/// file offset (`None`), `bytes` before the file offset it advanced.
+fn io_start(file: &File, offset: Option<u64>, bytes: usize) -> Option<u64> {
+    match offset {
+        Some(offset) => Some(offset),
+        None => file
+            .inner()
+            .seek(SeekFrom::Current(0))
+            .ok()
+            .map(|end| end.saturating_sub(bytes as u64)),
+    }
+}
+
+/// Accounts a read syscall on `file` at `offset` (`None` for the file
+/// offset) in the I/O counters of the current process, and records the
+/// pages it brought into the page cache.
+fn account_read(
+    file: Option<&Arc<File>>,
+    offset: Option<u64>,
+    result: AxResult<usize>,
+) -> AxResult<isize> {
+    let bytes = *result.as_ref().unwrap_or(&0);
+    let storage = file.is_some_and(|f| is_storage(f));
+    current()
+        .as_thread()
+        .proc_data
+        .io
+        .account_read(bytes, storage);
+    if let Some(file) = file.filter(|_| storage)
+        && let Some(start) = io_start(file, offset, bytes)
+    {
+        pagecache::mark_resident(file.inner().location(), start, bytes);
+    }
+    result.map(|n| n as _)
+}
+
+/// Accounts a write syscall to `file` at `offset` (`None` for the file
+/// offset) in the I/O counters of the current process, and tracks the data
+/// it dirtied in the page cache for writeback.
+fn account_write(
+    file: Option<&Arc<File>>,
+    offset: Option<u64>,
+    result: AxResult<usize>,
+) -> AxResult<isize> {
+    let bytes = *result.as_ref().unwrap_or(&0);
+    let storage = file.is_some_and(|f| is_storage(f));
+    current()
+        .as_thread()
+        .proc_data
+        .io
+        .account_write(bytes, storage);
+    if let Some(file) = file.filter(|_| storage) {
+        mark_dirty(file, bytes);
+        if let Some(start) = io_start(file, offset, bytes) {
+            pagecache::mark_resident(file.inner().location(), start, bytes);
+        }
+    }
+    result.map(|n| n as _)
+}
+
+/// Tracks `bytes` bytes written to `file` for writeback.
+fn mark_dirty(file: &File, bytes: usize) {
+    if let Ok(backend) = file.inner().backend() {
+        writeback::mark_dirty(&backend, bytes);
+    }
+}
+
 /// Read data from the file indicated by `fd`.
 ///
 /// Return the read size if success.
 pub fn sys_read(fd: i32, buf: *mut u8, len: usize) -> AxResult<isize> {
     debug!("sys_read <= fd: {fd}, buf: {buf:p}, len: {len}");
-    Ok(get_file_like(fd)?.read(&mut VmBytesMut::new(buf, len).into())? as _)
+    let f = get_file_like(fd)?;
+    account_read(
+        as_file(&f).as_ref(),
+        None,
+        f.read(&mut VmBytesMut::new(buf, len).into()),
+    )
 }
 
 pub fn sys_readv(fd: i32, iov: *const IoVec, iovcnt: usize) -> AxResult<isize> {
     debug!("sys_readv <= fd: {fd}, iovcnt: {iovcnt}");
     let f = get_file_like(fd)?;
-    f.read(&mut IoVectorBuf::new(iov, iovcnt)?.into_io().into())
-        .map(|n| n as _)
+    account_read(
+        as_file(&f).as_ref(),
+        None,
+        f.read(&mut IoVectorBuf::new(iov, iovcnt)?.into_io().into()),
+    )
 }
 
 /// Write data to the file indicated by `fd`.
@@ -79,28 +177,74 @@ pub fn sys_readv(fd: i32, iov: *const IoVec, iovcnt: usize) -> AxResult<isize> {
 /// Return the written size if success.
 pub fn sys_write(fd: i32, buf: *mut u8, len: usize) -> AxResult<isize> {
     debug!("sys_write <= fd: {fd}, buf: {buf:p}, len: {len}");
-    Ok(get_file_like(fd)?.write(&mut VmBytes::new(buf, len).into())? as _)
+    let f = get_file_like(fd)?;
+    account_write(
+        as_file(&f).as_ref(),
+        None,
+        f.write(&mut VmBytes::new(buf, len).into()),
+    )
 }
 
 pub fn sys_writev(fd: i32, iov: *const IoVec, iovcnt: usize) -> AxResult<isize> {
     debug!("sys_writev <= fd: {fd}, iovcnt: {iovcnt}");
     let f = get_file_like(fd)?;
-    f.write(&mut IoVectorBuf::new(iov, iovcnt)?.into_io().into())
-        .map(|n| n as _)
+    account_write(
+        as_file(&f).as_ref(),
+        None,
+        f.write(&mut IoVectorBuf::new(iov, iovcnt)?.into_io().into()),
+    )
 }
 
 pub fn sys_lseek(fd: c_int, offset: __kernel_off_t, whence: c_int) -> AxResult<isize> {
     debug!("sys_lseek <= {fd} {offset} {whence}");
+    if matches!(whence as u32, SEEK_DATA | SEEK_HOLE) {
+        return seek_hole_data(fd, offset, whence as u32);
+    }
     let pos = match whence {
         0 => SeekFrom::Start(offset as _),
         1 => SeekFrom::Current(offset as _),
         2 => SeekFrom::End(offset as _),
         _ => return Err(AxError::InvalidInput),
     };
+    if let Ok(dir) = Directory::from_fd(fd) {
+        // Directory offsets are the `d_off` cookies returned by `getdents64`,
+        // so seeking relative to the end makes no sense.
+        let mut dir_offset = dir.offset.lock();
+        let new_offset = match pos {
+            SeekFrom::Start(off) => Some(off),
+            SeekFrom::Current(off) => dir_offset.checked_add_signed(off),
+            SeekFrom::End(_) => None,
+        };
+        let new_offset = new_offset
+            .filter(|&it| it <= i64::MAX as u64)
+            .ok_or(AxError::InvalidInput)?;
+        *dir_offset = new_offset;
+        return Ok(new_offset as _);
+    }
     let off = File::from_fd(fd)?.inner().seek(pos)?;
     Ok(off as _)
 }
 
+/// Handles `SEEK_DATA` and `SEEK_HOLE`.
+///
+/// The file backends don't track allocated extents, so the whole file is
+/// reported as data followed by the implicit hole at the end of the file.
+fn seek_hole_data(fd: c_int, offset: __kernel_off_t, whence: u32) -> AxResult<isize> {
+    let f = File::from_fd(fd)?;
+    let inner = f.inner();
+    if offset < 0 {
+        return Err(AxError::Other(LinuxError::ENXIO));
+    }
+    let offset = offset as u64;
+    let size = inner.location().len()?;
+    if offset >= size {
+        return Err(AxError::Other(LinuxError::ENXIO));
+    }
+    let new_offset = if whence == SEEK_DATA { offset } else { size };
+    let off = inner.seek(SeekFrom::Start(new_offset))?;
+    Ok(off as _)
+}
+
 pub fn sys_truncate(path: UserConstPtr<c_char>, length: __kernel_off_t) -> AxResult<isize> {
     let path = path.get_as_str()?;
     debug!("sys_truncate <= {path:?} {length}");
@@ -111,14 +255,26 @@ pub fn sys_truncate(path: UserConstPtr<c_char>, length: __kernel_off_t) -> AxRes
         .write(true)
         .open(&FS_CONTEXT.lock(), path)?
         .into_file()?;
+    verity::check_write(file.location())?;
     file.access(FileFlags::WRITE)?.set_len(length as _)?;
+    pagecache::truncate(file.location(), length as _);
+    dnotify::notify(file.location(), DN_MODIFY);
+    fanotify::notify(file.location(), FAN_MODIFY);
     Ok(0)
 }
 
 pub fn sys_ftruncate(fd: c_int, length: __kernel_off_t) -> AxResult<isize> {
     debug!("sys_ftruncate <= {fd} {length}");
+    if let Ok(secret) = SecretMem::from_fd(fd) {
+        secret.set_len(length.try_into().map_err(|_| AxError::InvalidInput)?)?;
+        return Ok(0);
+    }
     let f = File::from_fd(fd)?;
+    verity::check_write(f.inner().location())?;
     f.inner().access(FileFlags::WRITE)?.set_len(length as _)?;
+    pagecache::truncate(f.inner().location(), length as _);
+    dnotify::notify(f.inner().location(), DN_MODIFY);
+    fanotify::notify(f.inner().location(), FAN_MODIFY);
     Ok(0)
 }
 
@@ -134,6 +290,7 @@ pub fn sys_fallocate(
     }
     let f = File::from_fd(fd)?;
     let inner = f.inner();
+    verity::check_write(inner.location())?;
     let file = inner.access(FileFlags::WRITE)?;
     file.set_len(file.location().len()?.max(offset as u64 + len as u64))?;
     Ok(0)
@@ -142,6 +299,7 @@ pub fn sys_fallocate(
 pub fn sys_fsync(fd: c_int) -> AxResult<isize> {
     debug!("sys_fsync <= {fd}");
     let f = File::from_fd(fd)?;
+    let _stall = psi::stall(Resource::Io);
     f.inner().sync(false)?;
     Ok(0)
 }
@@ -149,10 +307,47 @@ pub fn sys_fsync(fd: c_int) -> AxResult<isize> {
 pub fn sys_fdatasync(fd: c_int) -> AxResult<isize> {
     debug!("sys_fdatasync <= {fd}");
     let f = File::from_fd(fd)?;
+    let _stall = psi::stall(Resource::Io);
     f.inner().sync(true)?;
     Ok(0)
 }
 
+const SYNC_FILE_RANGE_WAIT_BEFORE: u32 = 1;
+const SYNC_FILE_RANGE_WRITE: u32 = 2;
+const SYNC_FILE_RANGE_WAIT_AFTER: u32 = 4;
+
+pub fn sys_sync_file_range(
+    fd: c_int,
+    offset: __kernel_off_t,
+    nbytes: __kernel_off_t,
+    flags: u32,
+) -> AxResult<isize> {
+    debug!(
+        "sys_sync_file_range <= fd: {fd}, offset: {offset}, nbytes: {nbytes}, flags: {flags:#x}"
+    );
+    if flags & !(SYNC_FILE_RANGE_WAIT_BEFORE | SYNC_FILE_RANGE_WRITE | SYNC_FILE_RANGE_WAIT_AFTER)
+        != 0
+        || offset < 0
+        || nbytes < 0
+        || offset.checked_add(nbytes).is_none()
+    {
+        return Err(AxError::InvalidInput);
+    }
+    let f = File::from_fd(fd).map_err(|err| {
+        if get_file_like(fd).is_ok() {
+            AxError::Other(LinuxError::ESPIPE)
+        } else {
+            err
+        }
+    })?;
+    // The page cache can only write back whole files, and does so
+    // synchronously, so there is never writeback in flight to wait for.
+    if flags & SYNC_FILE_RANGE_WRITE != 0 {
+        f.inner().sync(true)?;
+    }
+    Ok(0)
+}
+
 pub fn sys_fadvise64(
     fd: c_int,
     offset: __kernel_off_t,
@@ -169,15 +364,80 @@ pub fn sys_fadvise64(
     Ok(0)
 }
 
+/// `struct cachestat_range`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct CachestatRange {
+    off: u64,
+    len: u64,
+}
+
+/// `struct cachestat`.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct Cachestat {
+    nr_cache: u64,
+    nr_dirty: u64,
+    nr_writeback: u64,
+    nr_evicted: u64,
+    nr_recently_evicted: u64,
+}
+
+pub fn sys_cachestat(
+    fd: c_int,
+    range: *const CachestatRange,
+    result: *mut Cachestat,
+    flags: u32,
+) -> AxResult<isize> {
+    let range = unsafe { range.vm_read_uninit()?.assume_init() };
+    debug!(
+        "sys_cachestat <= fd: {fd}, off: {}, len: {}, flags: {flags:#x}",
+        range.off, range.len
+    );
+    if flags != 0 {
+        return Err(AxError::InvalidInput);
+    }
+    let f = File::from_fd(fd).map_err(|err| {
+        if get_file_like(fd).is_ok() {
+            AxError::Other(LinuxError::ESPIPE)
+        } else {
+            err
+        }
+    })?;
+
+    let mut stat = Cachestat::default();
+    if is_storage(&f) {
+        // Only the pages known to be resident are reported as cached, and
+        // as dirty if data written to the file hasn't been written back yet.
+        let size = f.inner().location().len()?;
+        let start = range.off / PAGE_SIZE_4K as u64;
+        let end = if range.len == 0 {
+            size
+        } else {
+            range.off.saturating_add(range.len).min(size)
+        }
+        .div_ceil(PAGE_SIZE_4K as u64);
+        stat.nr_cache = pagecache::resident_pages(f.inner().location(), start, end);
+        if writeback::is_dirty(f.inner().location()) {
+            stat.nr_dirty = stat.nr_cache;
+        }
+    }
+    result.vm_write(stat)?;
+    Ok(0)
+}
+
 pub fn sys_pread64(fd: c_int, buf: *mut u8, len: usize, offset: __kernel_off_t) -> AxResult<isize> {
     let f = File::from_fd(fd)?;
     if offset < 0 {
         return Err(AxError::InvalidInput);
     }
-    let read = f
-        .inner()
-        .read_at(&mut VmBytesMut::new(buf, len), offset as _)?;
-    Ok(read as _)
+    verity::verify(f.inner().location(), offset as _, len)?;
+    account_read(
+        Some(&f),
+        Some(offset as _),
+        f.inner()
+            .read_at(&mut VmBytesMut::new(buf, len), offset as _),
+    )
 }
 
 pub fn sys_pwrite64(
@@ -190,10 +450,15 @@ pub fn sys_pwrite64(
         return Ok(0);
     }
     let f = File::from_fd(fd)?;
-    let write = f
-        .inner()
-        .write_at(&mut VmBytes::new(buf, len), offset as _)?;
-    Ok(write as _)
+    verity::check_write(f.inner().location())?;
+    let written = account_write(
+        Some(&f),
+        Some(offset as _),
+        f.inner().write_at(&mut VmBytes::new(buf, len), offset as _),
+    )?;
+    dnotify::notify(f.inner().location(), DN_MODIFY);
+    fanotify::notify(f.inner().location(), FAN_MODIFY);
+    Ok(written)
 }
 
 pub fn sys_preadv(
@@ -202,6 +467,10 @@ pub fn sys_preadv(
     iovcnt: usize,
     offset: __kernel_off_t,
 ) -> AxResult<isize> {
+    // Only `preadv2` takes -1 as the current file offset.
+    if offset < 0 {
+        return Err(AxError::InvalidInput);
+    }
     sys_preadv2(fd, iov, iovcnt, offset, 0)
 }
 
@@ -211,21 +480,88 @@ pub fn sys_pwritev(
     iovcnt: usize,
     offset: __kernel_off_t,
 ) -> AxResult<isize> {
+    // Only `pwritev2` takes -1 as the current file offset.
+    if offset < 0 {
+        return Err(AxError::InvalidInput);
+    }
     sys_pwritev2(fd, iov, iovcnt, offset, 0)
 }
 
+/// The `RWF_*` flags supported by `preadv2` and `pwritev2`.
+const RWF_SUPPORTED: u32 = RWF_HIPRI | RWF_DSYNC | RWF_SYNC | RWF_NOWAIT | RWF_APPEND;
+
+/// Checks the `RWF_*` flags of an I/O of `len` bytes at `offset` (-1 for the
+/// file offset) on `f` waiting for `events`.
+///
+/// `RWF_HIPRI` is only a hint and ignored. With `RWF_NOWAIT`, I/O that could
+/// block fails with `EAGAIN` instead. On files backed by the page cache,
+/// that is unless all of the range is known to be resident.
+fn check_rwf_flags(
+    f: &Arc<dyn FileLike>,
+    flags: u32,
+    events: IoEvents,
+    offset: __kernel_off_t,
+    len: usize,
+) -> AxResult<()> {
+    if flags & !RWF_SUPPORTED != 0 {
+        return Err(AxError::OperationNotSupported);
+    }
+    if flags & RWF_NOWAIT == 0 {
+        return Ok(());
+    }
+    let ready = match as_file(f).filter(|it| is_storage(it)) {
+        Some(file) => {
+            let inner = file.inner();
+            let start = if events == IoEvents::OUT && flags & RWF_APPEND != 0 {
+                inner.location().len()?
+            } else if offset == -1 {
+                inner.seek(SeekFrom::Current(0))?
+            } else if offset < 0 {
+                // Rejected by the caller
+                return Ok(());
+            } else {
+                offset as u64
+            };
+            pagecache::is_resident(inner.location(), start, len)
+        }
+        None => f.poll().contains(events),
+    };
+    if !ready {
+        return Err(AxError::WouldBlock);
+    }
+    Ok(())
+}
+
 pub fn sys_preadv2(
     fd: c_int,
     iov: *const IoVec,
     iovcnt: usize,
     offset: __kernel_off_t,
-    _flags: u32,
+    flags: u32,
 ) -> AxResult<isize> {
-    debug!("sys_preadv2 <= fd: {fd}, iovcnt: {iovcnt}, offset: {offset}, flags: {_flags}");
+    debug!("sys_preadv2 <= fd: {fd}, iovcnt: {iovcnt}, offset: {offset}, flags: {flags:#x}");
+    let f = get_file_like(fd)?;
+    let len = IoVectorBuf::new(iov, iovcnt)?.into_io().remaining_mut();
+    check_rwf_flags(&f, flags, IoEvents::IN, offset, len)?;
+    if offset == -1 {
+        // Like `readv`, reads at and advances the file offset.
+        return account_read(
+            as_file(&f).as_ref(),
+            None,
+            f.read(&mut IoVectorBuf::new(iov, iovcnt)?.into_io().into()),
+        );
+    }
+    if offset < 0 {
+        return Err(AxError::InvalidInput);
+    }
     let f = File::from_fd(fd)?;
-    f.inner()
-        .read_at(&mut IoVectorBuf::new(iov, iovcnt)?.into_io(), offset as _)
-        .map(|n| n as _)
+    let mut buf = IoVectorBuf::new(iov, iovcnt)?.into_io();
+    verity::verify(f.inner().location(), offset as _, buf.remaining_mut())?;
+    account_read(
+        Some(&f),
+        Some(offset as _),
+        f.inner().read_at(&mut buf, offset as _),
+    )
 }
 
 pub fn sys_pwritev2(
@@ -233,13 +569,48 @@ pub fn sys_pwritev2(
     iov: *const IoVec,
     iovcnt: usize,
     offset: __kernel_off_t,
-    _flags: u32,
+    flags: u32,
 ) -> AxResult<isize> {
-    debug!("sys_pwritev2 <= fd: {fd}, iovcnt: {iovcnt}, offset: {offset}, flags: {_flags}");
+    debug!("sys_pwritev2 <= fd: {fd}, iovcnt: {iovcnt}, offset: {offset}, flags: {flags:#x}");
+    let f = get_file_like(fd)?;
+    let len = IoVectorBuf::new(iov, iovcnt)?.into_io().remaining_mut();
+    check_rwf_flags(&f, flags, IoEvents::OUT, offset, len)?;
+    if offset == -1 && flags & RWF_APPEND == 0 {
+        // Like `writev`, writes at and advances the file offset.
+        let file = as_file(&f);
+        let written = account_write(
+            file.as_ref(),
+            None,
+            f.write(&mut IoVectorBuf::new(iov, iovcnt)?.into_io().into()),
+        )?;
+        if let Some(file) = file
+            && flags & (RWF_DSYNC | RWF_SYNC) != 0
+        {
+            file.inner().sync(flags & RWF_SYNC == 0)?;
+        }
+        return Ok(written);
+    }
     let f = File::from_fd(fd)?;
-    f.inner()
-        .read_at(&mut IoVectorBuf::new(iov, iovcnt)?.into_io(), offset as _)
-        .map(|n| n as _)
+    let inner = f.inner();
+    verity::check_write(inner.location())?;
+    let offset = if flags & RWF_APPEND != 0 {
+        inner.location().len()?
+    } else if offset < 0 {
+        return Err(AxError::InvalidInput);
+    } else {
+        offset as _
+    };
+    let written = account_write(
+        Some(&f),
+        Some(offset),
+        inner.write_at(&mut IoVectorBuf::new(iov, iovcnt)?.into_io(), offset),
+    )?;
+    if flags & (RWF_DSYNC | RWF_SYNC) != 0 {
+        inner.sync(flags & RWF_SYNC == 0)?;
+    }
+    dnotify::notify(inner.location(), DN_MODIFY);
+    fanotify::notify(inner.location(), FAN_MODIFY);
+    Ok(written)
 }
 
 enum SendFile {
@@ -261,6 +632,7 @@ impl SendFile {
             SendFile::Direct(file) => file.read(&mut buf.into()),
             SendFile::Offset(file, offset) => {
                 let off = offset.vm_read()?;
+                verity::verify(file.inner().location(), off, buf.len())?;
                 let bytes_read = file.inner().read_at(&mut buf, off)?;
                 offset.vm_write(off + bytes_read as u64)?;
                 Ok(bytes_read)
@@ -270,11 +642,19 @@ impl SendFile {
 
     fn write(&mut self, mut buf: &[u8]) -> AxResult<usize> {
         match self {
-            SendFile::Direct(file) => file.write(&mut buf.into()),
+            SendFile::Direct(file) => {
+                let bytes_written = file.write(&mut buf.into())?;
+                if let Some(file) = as_file(file) {
+                    mark_dirty(&file, bytes_written);
+                }
+                Ok(bytes_written)
+            }
             SendFile::Offset(file, offset) => {
                 let off = offset.vm_read()?;
+                verity::check_write(file.inner().location())?;
                 let bytes_written = file.inner().write_at(&mut buf, off)?;
                 offset.vm_write(off + bytes_written as u64)?;
+                mark_dirty(file, bytes_written);
                 Ok(bytes_written)
             }
         }
@@ -293,7 +673,7 @@ fn do_send(mut src: SendFile, mut dst: SendFile, len: usize) -> AxResult<usize>
         let to_read = buf.len().min(remaining);
         let bytes_read = match src.read(&mut buf[..to_read]) {
             Ok(n) => n,
-            Err(AxError::WouldBlock) if total_written > 0 => break,
+            Err(AxError::WouldBlock | AxError::Interrupted) if total_written > 0 => break,
             Err(e) => return Err(e),
         };
         if bytes_read == 0 {
@@ -0,0 +1,108 @@
+use alloc::{string::ToString, sync::Arc};
+use core::ffi::c_int;
+
+use axerrno::{AxError, AxResult, LinuxError};
+use axhal::paging::PageSize;
+use axtask::current;
+use linux_raw_sys::general::AT_EMPTY_PATH;
+use starry_core::{
+    landlock::{ABI_VERSION, Domain, Ruleset},
+    task::AsThread,
+};
+use starry_vm::vm_load;
+
+use crate::file::{FileLike, get_file_like, landlock::LandlockRuleset, resolve_at};
+
+const LANDLOCK_CREATE_RULESET_VERSION: u32 = 1 << 0;
+
+const LANDLOCK_RULE_PATH_BENEATH: u32 = 1;
+
+/// The size of `struct landlock_ruleset_attr` in the supported ABI version.
+const RULESET_ATTR_SIZE: usize = 8;
+/// The size of the packed `struct landlock_path_beneath_attr`.
+const PATH_BENEATH_ATTR_SIZE: usize = 12;
+
+fn ruleset_from_fd(fd: c_int) -> AxResult<Arc<LandlockRuleset>> {
+    get_file_like(fd)?
+        .into_any()
+        .downcast::<LandlockRuleset>()
+        .map_err(|_| AxError::Other(LinuxError::EBADFD))
+}
+
+pub fn sys_landlock_create_ruleset(attr: *const u8, size: usize, flags: u32) -> AxResult<isize> {
+    debug!("sys_landlock_create_ruleset <= attr: {attr:?}, size: {size}, flags: {flags:#x}");
+
+    if flags == LANDLOCK_CREATE_RULESET_VERSION {
+        if !attr.is_null() || size != 0 {
+            return Err(AxError::InvalidInput);
+        }
+        return Ok(ABI_VERSION as _);
+    }
+    if flags != 0 || size < RULESET_ATTR_SIZE {
+        return Err(AxError::InvalidInput);
+    }
+    if size > PageSize::Size4K as usize {
+        return Err(AxError::Other(LinuxError::E2BIG));
+    }
+    let bytes = vm_load(attr, size)?;
+    // Fields of later ABI versions must be left zero.
+    if bytes[RULESET_ATTR_SIZE..].iter().any(|&b| b != 0) {
+        return Err(AxError::Other(LinuxError::E2BIG));
+    }
+    let handled = u64::from_ne_bytes(bytes[..RULESET_ATTR_SIZE].try_into().unwrap());
+    // Like Linux, ruleset file descriptors are always close-on-exec
+    LandlockRuleset::new(Ruleset::new(handled)?)
+        .add_to_fd_table(true)
+        .map(|fd| fd as _)
+}
+
+pub fn sys_landlock_add_rule(
+    ruleset_fd: c_int,
+    rule_type: u32,
+    rule_attr: *const u8,
+    flags: u32,
+) -> AxResult<isize> {
+    debug!(
+        "sys_landlock_add_rule <= ruleset_fd: {ruleset_fd}, rule_type: {rule_type}, flags: \
+         {flags:#x}"
+    );
+
+    if flags != 0 {
+        return Err(AxError::InvalidInput);
+    }
+    let ruleset = ruleset_from_fd(ruleset_fd)?;
+    if rule_type != LANDLOCK_RULE_PATH_BENEATH {
+        return Err(AxError::InvalidInput);
+    }
+    let bytes = vm_load(rule_attr, PATH_BENEATH_ATTR_SIZE)?;
+    let allowed = u64::from_ne_bytes(bytes[..8].try_into().unwrap());
+    let parent_fd = i32::from_ne_bytes(bytes[8..].try_into().unwrap());
+
+    let loc = resolve_at(parent_fd, None, AT_EMPTY_PATH)?
+        .into_file()
+        .ok_or(AxError::Other(LinuxError::EBADFD))?;
+    ruleset
+        .ruleset()
+        .add_rule(loc.absolute_path()?.to_string(), allowed, loc.is_dir())?;
+    Ok(0)
+}
+
+pub fn sys_landlock_restrict_self(ruleset_fd: c_int, flags: u32) -> AxResult<isize> {
+    debug!("sys_landlock_restrict_self <= ruleset_fd: {ruleset_fd}, flags: {flags:#x}");
+
+    if flags != 0 {
+        return Err(AxError::InvalidInput);
+    }
+    let ruleset = ruleset_from_fd(ruleset_fd)?;
+    let curr = current();
+    let proc_data = &curr.as_thread().proc_data;
+    // Like Linux, a sandbox can't be imposed on programs that may gain
+    // privileges, as they could be tricked into misusing them.
+    let cred = *proc_data.cred.read();
+    if !cred.no_new_privs && !cred.is_privileged() {
+        return Err(AxError::OperationNotPermitted);
+    }
+    let domain = Domain::restrict(proc_data.landlock().as_deref(), ruleset.ruleset())?;
+    proc_data.set_landlock(Some(domain));
+    Ok(0)
+}
@@ -3,10 +3,10 @@ use core::ffi::c_char;
 
 use axerrno::{AxError, AxResult};
 use axfs_ng::{FS_CONTEXT, OpenOptions};
-use linux_raw_sys::general::MFD_CLOEXEC;
+use linux_raw_sys::general::{MFD_CLOEXEC, O_CLOEXEC};
 
 use crate::{
-    file::{File, FileLike},
+    file::{File, FileLike, SecretMem},
     mm::UserConstPtr,
 };
 
@@ -30,3 +30,13 @@ pub fn sys_memfd_create(_name: UserConstPtr<c_char>, flags: u32) -> AxResult<isi
     }
     Err(AxError::TooManyOpenFiles)
 }
+
+pub fn sys_memfd_secret(flags: u32) -> AxResult<isize> {
+    debug!("sys_memfd_secret <= flags: {flags:#x}");
+    if flags & !O_CLOEXEC != 0 {
+        return Err(AxError::InvalidInput);
+    }
+    SecretMem::default()
+        .add_to_fd_table(flags & O_CLOEXEC != 0)
+        .map(|fd| fd as _)
+}
@@ -1,7 +1,9 @@
 mod ctl;
 mod event;
+mod fanotify;
 mod fd_ops;
 mod io;
+mod landlock;
 mod memfd;
 mod mount;
 mod pidfd;
@@ -10,5 +12,6 @@ mod signalfd;
 mod stat;
 
 pub use self::{
-    ctl::*, event::*, fd_ops::*, io::*, memfd::*, mount::*, pidfd::*, pipe::*, signalfd::*, stat::*,
+    ctl::*, event::*, fanotify::*, fd_ops::*, io::*, landlock::*, memfd::*, mount::*, pidfd::*,
+    pipe::*, signalfd::*, stat::*,
 };
@@ -1,30 +1,140 @@
-use core::ffi::{c_char, c_void};
+use alloc::{sync::Arc, vec::Vec};
+use core::{
+    ffi::{c_char, c_void},
+    slice,
+};
 
-use axerrno::{AxError, AxResult};
-use axfs_ng::FS_CONTEXT;
+use axerrno::{AxError, AxResult, LinuxError};
+use axfs_ng::{FS_CONTEXT, OpenOptions, OpenResult};
+use linux_raw_sys::general::{
+    AT_EMPTY_PATH, AT_RECURSIVE, AT_SYMLINK_NOFOLLOW, MS_NODEV, MS_NOEXEC, MS_NOSUID, MS_RDONLY,
+    O_CLOEXEC,
+};
+use starry_vm::{VmPtr, vm_write_slice};
 
-use crate::{mm::vm_load_string, vfs::MemoryFs};
+use crate::{
+    file::{
+        Directory, File, FileLike, FsContextFile, FuseDev, MountFile, add_file_like, get_file_like,
+        resolve_at, with_fs,
+    },
+    mm::vm_load_string,
+    vfs::{
+        MemoryFs, cgroup,
+        fuse::{self, FuseFs},
+        mounts::{self, MountInfo},
+    },
+};
+
+// From <linux/mount.h>
+const MNT_ID_REQ_SIZE_VER0: u32 = 24;
+const LSMT_ROOT: u64 = u64::MAX;
+const LISTMOUNT_REVERSE: u32 = 1 << 0;
+
+const STATMOUNT_SB_BASIC: u64 = 0x0001;
+const STATMOUNT_MNT_BASIC: u64 = 0x0002;
+const STATMOUNT_PROPAGATE_FROM: u64 = 0x0004;
+const STATMOUNT_MNT_ROOT: u64 = 0x0008;
+const STATMOUNT_MNT_POINT: u64 = 0x0010;
+const STATMOUNT_FS_TYPE: u64 = 0x0020;
+const STATMOUNT_MNT_OPTS: u64 = 0x0080;
+const STATMOUNT_SB_SOURCE: u64 = 0x0200;
+
+const SB_RDONLY: u32 = 1;
+const MOUNT_ATTR_RDONLY: u64 = 0x0001;
+const MOUNT_ATTR_NOSUID: u64 = 0x0002;
+const MOUNT_ATTR_NODEV: u64 = 0x0004;
+const MOUNT_ATTR_NOEXEC: u64 = 0x0008;
+const MS_PRIVATE: u64 = 1 << 18;
+
+const FSOPEN_CLOEXEC: u32 = 0x0001;
+const FSMOUNT_CLOEXEC: u32 = 0x0001;
+
+const FSCONFIG_SET_FLAG: u32 = 0;
+const FSCONFIG_SET_STRING: u32 = 1;
+const FSCONFIG_CMD_CREATE: u32 = 6;
+const FSCONFIG_CMD_CREATE_EXCL: u32 = 8;
+
+const MOVE_MOUNT_F_EMPTY_PATH: u32 = 0x0004;
+
+const OPEN_TREE_CLONE: u32 = 1;
+
+/// `struct mnt_id_req`, up to `MNT_ID_REQ_SIZE_VER0`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct MntIdReq {
+    size: u32,
+    spare: u32,
+    mnt_id: u64,
+    param: u64,
+}
+
+/// `struct statmount`, without the trailing strings.
+#[repr(C)]
+struct Statmount {
+    size: u32,
+    mnt_opts: u32,
+    mask: u64,
+    sb_dev_major: u32,
+    sb_dev_minor: u32,
+    sb_magic: u64,
+    sb_flags: u32,
+    fs_type: u32,
+    mnt_id: u64,
+    mnt_parent_id: u64,
+    mnt_id_old: u32,
+    mnt_parent_id_old: u32,
+    mnt_attr: u64,
+    mnt_propagation: u64,
+    mnt_peer_group: u64,
+    mnt_master: u64,
+    propagate_from: u64,
+    mnt_root: u32,
+    mnt_point: u32,
+    mnt_ns_id: u64,
+    fs_subtype: u32,
+    sb_source: u32,
+    opt_num: u32,
+    opt_array: u32,
+    opt_sec_num: u32,
+    opt_sec_array: u32,
+    spare: [u64; 46],
+}
+
+const _: [(); 512] = [(); size_of::<Statmount>()];
 
 pub fn sys_mount(
     source: *const c_char,
     target: *const c_char,
     fs_type: *const c_char,
-    _flags: i32,
-    _data: *const c_void,
+    flags: i32,
+    data: *const c_void,
 ) -> AxResult<isize> {
     let source = vm_load_string(source)?;
     let target = vm_load_string(target)?;
     let fs_type = vm_load_string(fs_type)?;
     debug!("sys_mount <= source: {source:?}, target: {target:?}, fs_type: {fs_type:?}");
 
-    if fs_type != "tmpfs" {
-        return Err(AxError::NoSuchDevice);
-    }
+    let fs = match fs_type.as_str() {
+        "tmpfs" => MemoryFs::new(),
+        "cgroup2" => cgroup::new_cgroupfs(),
+        "fuse" => {
+            if data.is_null() {
+                return Err(AxError::InvalidInput);
+            }
+            let fd = fuse::parse_mount_fd(&vm_load_string(data.cast())?)?;
+            FuseFs::new(FuseDev::from_fd(fd)?.conn().clone())?
+        }
+        _ => return Err(AxError::NoSuchDevice),
+    };
 
-    let fs = MemoryFs::new();
-
-    let target = FS_CONTEXT.lock().resolve(target)?;
-    target.mount(&fs)?;
+    let cx = FS_CONTEXT.lock();
+    cx.resolve(&target)?.mount(&fs)?;
+    mounts::add(
+        &cx.resolve(&target)?,
+        &source,
+        &fs_type,
+        &mounts::options_for_flags(flags as u32),
+    )?;
 
     Ok(0)
 }
@@ -34,5 +144,314 @@ pub fn sys_umount2(target: *const c_char, _flags: i32) -> AxResult<isize> {
     debug!("sys_umount2 <= target: {target:?}");
     let target = FS_CONTEXT.lock().resolve(target)?;
     target.unmount()?;
+    mounts::remove(&target)?;
+    fuse::on_unmount(&target);
+    Ok(0)
+}
+
+fn read_mnt_id_req(req: *const MntIdReq) -> AxResult<MntIdReq> {
+    let size = req.cast::<u32>().vm_read()?;
+    if size < MNT_ID_REQ_SIZE_VER0 {
+        return Err(AxError::InvalidInput);
+    }
+    // FIXME: AnyBitPattern
+    Ok(unsafe { req.vm_read_uninit()?.assume_init() })
+}
+
+/// Builds the `struct statmount` of `mount` with the fields in `mask`,
+/// followed by its strings.
+fn statmount(mount: &MountInfo, mask: u64) -> Vec<u8> {
+    // FIXME: Zeroable
+    let mut sm: Statmount = unsafe { core::mem::zeroed() };
+    let mut strings = Vec::new();
+    let mut push_str = |value: &str| {
+        let offset = strings.len() as u32;
+        strings.extend_from_slice(value.as_bytes());
+        strings.push(0);
+        offset
+    };
+
+    if mask & STATMOUNT_SB_BASIC != 0 {
+        sm.sb_dev_major = (mount.device >> 8) as u32;
+        sm.sb_dev_minor = (mount.device & 0xff) as u32;
+        sm.sb_magic = mount.magic;
+        if mount.has_option("ro") {
+            sm.sb_flags |= SB_RDONLY;
+        }
+    }
+    if mask & STATMOUNT_MNT_BASIC != 0 {
+        sm.mnt_id = mount.id;
+        sm.mnt_parent_id = mount.parent;
+        sm.mnt_id_old = mount.old_id();
+        sm.mnt_parent_id_old = mounts::find(mount.parent).map_or(sm.mnt_id_old, |it| it.old_id());
+        for (name, attr) in [
+            ("ro", MOUNT_ATTR_RDONLY),
+            ("nosuid", MOUNT_ATTR_NOSUID),
+            ("nodev", MOUNT_ATTR_NODEV),
+            ("noexec", MOUNT_ATTR_NOEXEC),
+        ] {
+            if mount.has_option(name) {
+                sm.mnt_attr |= attr;
+            }
+        }
+        sm.mnt_propagation = MS_PRIVATE;
+    }
+    // There is no mount propagation, so `propagate_from` stays 0.
+    if mask & STATMOUNT_MNT_ROOT != 0 {
+        sm.mnt_root = push_str("/");
+    }
+    if mask & STATMOUNT_MNT_POINT != 0 {
+        sm.mnt_point = push_str(&mount.target);
+    }
+    if mask & STATMOUNT_FS_TYPE != 0 {
+        sm.fs_type = push_str(&mount.fs_type);
+    }
+    if mask & STATMOUNT_MNT_OPTS != 0 {
+        sm.mnt_opts = push_str(&mount.options);
+    }
+    if mask & STATMOUNT_SB_SOURCE != 0 {
+        sm.sb_source = push_str(&mount.source);
+    }
+
+    sm.mask = mask
+        & (STATMOUNT_SB_BASIC
+            | STATMOUNT_MNT_BASIC
+            | STATMOUNT_PROPAGATE_FROM
+            | STATMOUNT_MNT_ROOT
+            | STATMOUNT_MNT_POINT
+            | STATMOUNT_FS_TYPE
+            | STATMOUNT_MNT_OPTS
+            | STATMOUNT_SB_SOURCE);
+    sm.size = (size_of::<Statmount>() + strings.len()) as u32;
+
+    let mut out = Vec::with_capacity(sm.size as usize);
+    out.extend_from_slice(unsafe {
+        slice::from_raw_parts(
+            (&sm as *const Statmount).cast::<u8>(),
+            size_of::<Statmount>(),
+        )
+    });
+    out.extend_from_slice(&strings);
+    out
+}
+
+pub fn sys_statmount(
+    req: *const MntIdReq,
+    buf: *mut u8,
+    bufsize: usize,
+    flags: u32,
+) -> AxResult<isize> {
+    debug!("sys_statmount <= req: {req:?}, bufsize: {bufsize}, flags: {flags}");
+    if flags != 0 {
+        return Err(AxError::InvalidInput);
+    }
+    let req = read_mnt_id_req(req)?;
+    let mount = mounts::find(req.mnt_id).ok_or(AxError::NotFound)?;
+    let sm = statmount(&mount, req.param);
+    if sm.len() > bufsize {
+        return Err(AxError::Other(LinuxError::EOVERFLOW));
+    }
+    vm_write_slice(buf, &sm)?;
     Ok(0)
 }
+
+pub fn sys_listmount(
+    req: *const MntIdReq,
+    mnt_ids: *mut u64,
+    nr_mnt_ids: usize,
+    flags: u32,
+) -> AxResult<isize> {
+    debug!("sys_listmount <= req: {req:?}, nr_mnt_ids: {nr_mnt_ids}, flags: {flags}");
+    if flags & !LISTMOUNT_REVERSE != 0 {
+        return Err(AxError::InvalidInput);
+    }
+    let req = read_mnt_id_req(req)?;
+    let all = mounts::mounts();
+
+    // Lists the mounts below `req.mnt_id`, or all of them for `LSMT_ROOT`.
+    let mut ids: Vec<u64> = if req.mnt_id == LSMT_ROOT {
+        all.iter().map(|it| it.id).collect()
+    } else {
+        let root = all
+            .iter()
+            .find(|it| it.id == req.mnt_id)
+            .ok_or(AxError::NotFound)?;
+        let below = |mut mount: &MountInfo| loop {
+            if mount.parent == mount.id {
+                return false;
+            }
+            if mount.parent == root.id {
+                return true;
+            }
+            match all.iter().find(|it| it.id == mount.parent) {
+                Some(parent) => mount = parent,
+                None => return false,
+            }
+        };
+        all.iter().filter(|it| below(it)).map(|it| it.id).collect()
+    };
+
+    // `req.param` is the last ID returned by a previous call.
+    if flags & LISTMOUNT_REVERSE != 0 {
+        ids.sort_unstable_by(|a, b| b.cmp(a));
+        if req.param != 0 {
+            ids.retain(|&id| id < req.param);
+        }
+    } else {
+        ids.sort_unstable();
+        ids.retain(|&id| id > req.param);
+    }
+    ids.truncate(nr_mnt_ids);
+    vm_write_slice(mnt_ids, &ids)?;
+    Ok(ids.len() as _)
+}
+
+pub fn sys_fsopen(fs_name: *const c_char, flags: u32) -> AxResult<isize> {
+    let fs_type = vm_load_string(fs_name)?;
+    debug!("sys_fsopen <= fs_name: {fs_type:?}, flags: {flags:#x}");
+    if flags & !FSOPEN_CLOEXEC != 0 {
+        return Err(AxError::InvalidInput);
+    }
+    if fs_type != "tmpfs" {
+        return Err(AxError::NoSuchDevice);
+    }
+    FsContextFile::new(fs_type)
+        .add_to_fd_table(flags & FSOPEN_CLOEXEC != 0)
+        .map(|fd| fd as _)
+}
+
+pub fn sys_fsconfig(
+    fd: i32,
+    cmd: u32,
+    key: *const c_char,
+    value: *const c_void,
+    aux: i32,
+) -> AxResult<isize> {
+    debug!("sys_fsconfig <= fd: {fd}, cmd: {cmd}, aux: {aux}");
+    let ctx = FsContextFile::from_fd(fd)?;
+    match cmd {
+        FSCONFIG_SET_FLAG => ctx.set(&vm_load_string(key)?, None)?,
+        FSCONFIG_SET_STRING => {
+            ctx.set(&vm_load_string(key)?, Some(&vm_load_string(value.cast())?))?
+        }
+        FSCONFIG_CMD_CREATE | FSCONFIG_CMD_CREATE_EXCL => ctx.create(MemoryFs::new),
+        // Binary, path and fd parameters are not used by any supported
+        // filesystem, and there is nothing to reconfigure.
+        _ => return Err(AxError::OperationNotSupported),
+    }
+    Ok(0)
+}
+
+pub fn sys_fsmount(fs_fd: i32, flags: u32, attr_flags: u32) -> AxResult<isize> {
+    debug!("sys_fsmount <= fs_fd: {fs_fd}, flags: {flags:#x}, attr_flags: {attr_flags:#x}");
+    if flags & !FSMOUNT_CLOEXEC != 0 {
+        return Err(AxError::InvalidInput);
+    }
+    let mut ms_flags = 0;
+    for (attr, flag) in [
+        (MOUNT_ATTR_RDONLY, MS_RDONLY),
+        (MOUNT_ATTR_NOSUID, MS_NOSUID),
+        (MOUNT_ATTR_NODEV, MS_NODEV),
+        (MOUNT_ATTR_NOEXEC, MS_NOEXEC),
+    ] {
+        if attr_flags as u64 & attr != 0 {
+            ms_flags |= flag;
+        }
+    }
+    FsContextFile::from_fd(fs_fd)?
+        .mount(ms_flags)?
+        .add_to_fd_table(flags & FSMOUNT_CLOEXEC != 0)
+        .map(|fd| fd as _)
+}
+
+pub fn sys_move_mount(
+    from_dfd: i32,
+    from_path: *const c_char,
+    to_dfd: i32,
+    to_path: *const c_char,
+    flags: u32,
+) -> AxResult<isize> {
+    let from_path = vm_load_string(from_path)?;
+    let to_path = vm_load_string(to_path)?;
+    debug!(
+        "sys_move_mount <= from_dfd: {from_dfd}, from_path: {from_path:?}, to_dfd: {to_dfd}, \
+         to_path: {to_path:?}, flags: {flags:#x}"
+    );
+
+    // Only detached mounts can be attached, moving mounts already in the
+    // tree is not supported.
+    if flags & MOVE_MOUNT_F_EMPTY_PATH == 0 || !from_path.is_empty() {
+        return Err(AxError::InvalidInput);
+    }
+    let mount = MountFile::from_fd(from_dfd)?;
+    with_fs(to_dfd, |cx| mount.attach(cx, &to_path))?;
+    Ok(0)
+}
+
+/// Clones the mount `mount`, and with `recursive` the mounts below it.
+fn clone_mount(mount: &MountInfo, all: &[MountInfo], recursive: bool) -> AxResult<MountFile> {
+    let root = FS_CONTEXT.lock().resolve(&mount.target)?;
+    let mut clone = MountFile::new(
+        root.filesystem().clone(),
+        mount.source.clone(),
+        mount.fs_type.clone(),
+        mount.options.clone(),
+    );
+    if recursive {
+        for child in all
+            .iter()
+            .filter(|it| it.parent == mount.id && it.id != mount.id)
+        {
+            let path = child.target[mount.target.len()..].trim_start_matches('/');
+            clone.add_child(path.into(), clone_mount(child, all, true)?);
+        }
+    }
+    Ok(clone)
+}
+
+pub fn sys_open_tree(dfd: i32, path: *const c_char, flags: u32) -> AxResult<isize> {
+    let path = vm_load_string(path)?;
+    debug!("sys_open_tree <= dfd: {dfd}, path: {path:?}, flags: {flags:#x}");
+    if flags & !(OPEN_TREE_CLONE | O_CLOEXEC | AT_EMPTY_PATH | AT_RECURSIVE | AT_SYMLINK_NOFOLLOW)
+        != 0
+    {
+        return Err(AxError::InvalidInput);
+    }
+    let cloexec = flags & O_CLOEXEC != 0;
+
+    if flags & OPEN_TREE_CLONE == 0 {
+        if flags & AT_RECURSIVE != 0 {
+            return Err(AxError::InvalidInput);
+        }
+        // Without cloning, this is just an `O_PATH` open.
+        if path.is_empty() && flags & AT_EMPTY_PATH != 0 {
+            return add_file_like(get_file_like(dfd)?, cloexec).map(|fd| fd as _);
+        }
+        let mut options = OpenOptions::new();
+        options
+            .read(true)
+            .path(true)
+            .no_follow(flags & AT_SYMLINK_NOFOLLOW != 0);
+        let f: Arc<dyn FileLike> = match with_fs(dfd, |fs| options.open(fs, &path))? {
+            OpenResult::File(file) => Arc::new(File::new(file)),
+            OpenResult::Dir(dir) => Arc::new(Directory::new(dir)),
+        };
+        return add_file_like(f, cloexec).map(|fd| fd as _);
+    }
+
+    let loc = resolve_at(dfd, Some(&path), flags)?
+        .into_file()
+        .ok_or(AxError::InvalidInput)?;
+    let target = loc.absolute_path()?;
+    // Only whole mounts can be cloned, as bind mounts of subdirectories are
+    // not supported.
+    let all = mounts::mounts();
+    let mount = all
+        .iter()
+        .rev()
+        .find(|it| it.target == target.as_str())
+        .ok_or(AxError::InvalidInput)?;
+    clone_mount(mount, &all, flags & AT_RECURSIVE != 0)?
+        .add_to_fd_table(cloexec)
+        .map(|fd| fd as _)
+}
@@ -1,4 +1,5 @@
 use axerrno::{AxError, AxResult};
+use linux_raw_sys::general::O_NONBLOCK;
 use starry_core::task::{get_process_data, send_signal_to_process};
 use starry_signal::SignalInfo;
 
@@ -7,15 +8,19 @@ use crate::{
     syscall::signal::make_queue_signal_info,
 };
 
+/// Like Linux, `PIDFD_NONBLOCK` is `O_NONBLOCK`.
+const PIDFD_NONBLOCK: u32 = O_NONBLOCK;
+
 pub fn sys_pidfd_open(pid: u32, flags: u32) -> AxResult<isize> {
     debug!("sys_pidfd_open <= pid: {pid}, flags: {flags}");
 
-    if flags != 0 {
+    if flags & !PIDFD_NONBLOCK != 0 {
         return Err(AxError::InvalidInput);
     }
 
     let task = get_process_data(pid)?;
     let fd = PidFd::new(&task);
+    fd.set_nonblocking(flags & PIDFD_NONBLOCK != 0)?;
 
     fd.add_to_fd_table(true).map(|fd| fd as _)
 }
@@ -2,11 +2,12 @@ use core::time::Duration;
 
 use axerrno::{AxError, AxResult};
 use axpoll::IoEvents;
-use axtask::future::Poller;
+use axtask::{current, future::Poller};
 use bitflags::bitflags;
 use linux_raw_sys::general::{
     EPOLL_CLOEXEC, EPOLL_CTL_ADD, EPOLL_CTL_DEL, EPOLL_CTL_MOD, epoll_event, timespec,
 };
+use starry_core::task::AsThread;
 use starry_signal::SignalSet;
 
 use crate::{
@@ -93,6 +94,9 @@ fn do_epoll_wait(
     }
     let events = events.get_as_mut_slice(maxevents as usize)?;
 
+    let curr = current();
+    let _wchan = curr.as_thread().wait_in(do_epoll_wait as usize);
+
     with_replacen_blocked(
         nullable!(sigmask.get_as_ref())?.copied(),
         || match Poller::new(epoll.as_ref(), IoEvents::IN)
@@ -3,8 +3,9 @@ use alloc::vec::Vec;
 use axerrno::{AxError, AxResult};
 use axhal::time::TimeValue;
 use axpoll::IoEvents;
-use axtask::future::Poller;
+use axtask::{current, future::Poller};
 use linux_raw_sys::general::{POLLNVAL, pollfd, timespec};
+use starry_core::task::AsThread;
 use starry_signal::SignalSet;
 
 use super::FdPollSet;
@@ -52,6 +53,8 @@ fn do_poll(
     }
     let fds = FdPollSet(fds);
 
+    let curr = current();
+    let _wchan = curr.as_thread().wait_in(do_poll as usize);
     with_replacen_blocked(sigmask, || {
         match Poller::new(&fds, IoEvents::empty())
             .timeout(timeout)
@@ -3,12 +3,10 @@ use core::{fmt, time::Duration};
 
 use axerrno::{AxError, AxResult};
 use axpoll::IoEvents;
-use axtask::future::Poller;
+use axtask::{current, future::Poller};
 use bitmaps::Bitmap;
-use linux_raw_sys::{
-    general::*,
-    select_macros::{FD_ISSET, FD_SET, FD_ZERO},
-};
+use linux_raw_sys::general::*;
+use starry_core::{resources::AX_FILE_LIMIT, task::AsThread};
 use starry_signal::SignalSet;
 
 use super::FdPollSet;
@@ -20,14 +18,16 @@ use crate::{
     time::TimeValueLike,
 };
 
-struct FdSet(Bitmap<{ __FD_SETSIZE as usize }>);
+const BITS_PER_WORD: usize = usize::BITS as usize;
+
+struct FdSet(Bitmap<AX_FILE_LIMIT>);
 
 impl FdSet {
-    fn new(nfds: usize, fds: Option<&__kernel_fd_set>) -> Self {
+    fn new(nfds: usize, fds: Option<&[usize]>) -> Self {
         let mut bitmap = Bitmap::new();
         if let Some(fds) = fds {
             for i in 0..nfds {
-                if unsafe { FD_ISSET(i as _, fds) } {
+                if fds[i / BITS_PER_WORD] & (1 << (i % BITS_PER_WORD)) != 0 {
                     bitmap.set(i, true);
                 }
             }
@@ -36,6 +36,11 @@ impl FdSet {
     }
 }
 
+/// Marks `fd` as ready in a user fd set.
+fn fd_set(fds: &mut [usize], fd: usize) {
+    fds[fd / BITS_PER_WORD] |= 1 << (fd % BITS_PER_WORD);
+}
+
 impl fmt::Debug for FdSet {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_list().entries(&self.0).finish()
@@ -50,9 +55,14 @@ fn do_select(
     timeout: Option<Duration>,
     sigmask: UserConstPtr<SignalSetWithSize>,
 ) -> AxResult<isize> {
-    if nfds > __FD_SETSIZE {
+    if nfds > i32::MAX as u32 {
         return Err(AxError::InvalidInput);
     }
+    // Like Linux, no fd can be at or above the file limit, so larger sets
+    // are only read up to it. Only the words covering `nfds` bits are
+    // accessed, since the caller may have allocated no more.
+    let nfds = (nfds as usize).min(AX_FILE_LIMIT);
+    let words = nfds.div_ceil(BITS_PER_WORD);
     let sigmask = if let Some(sigmask) = nullable!(sigmask.get_as_ref())? {
         check_sigset_size(sigmask.sigsetsize)?;
         let set = sigmask.set;
@@ -61,13 +71,18 @@ fn do_select(
         None
     };
 
-    let mut readfds = nullable!(readfds.get_as_mut())?;
-    let mut writefds = nullable!(writefds.get_as_mut())?;
-    let mut exceptfds = nullable!(exceptfds.get_as_mut())?;
+    let (readfds, writefds, exceptfds) = (
+        readfds.cast::<usize>(),
+        writefds.cast::<usize>(),
+        exceptfds.cast::<usize>(),
+    );
+    let mut readfds = nullable!(readfds.get_as_mut_slice(words))?;
+    let mut writefds = nullable!(writefds.get_as_mut_slice(words))?;
+    let mut exceptfds = nullable!(exceptfds.get_as_mut_slice(words))?;
 
-    let read_set = FdSet::new(nfds as _, readfds.as_deref());
-    let write_set = FdSet::new(nfds as _, writefds.as_deref());
-    let except_set = FdSet::new(nfds as _, exceptfds.as_deref());
+    let read_set = FdSet::new(nfds, readfds.as_deref());
+    let write_set = FdSet::new(nfds, writefds.as_deref());
+    let except_set = FdSet::new(nfds, exceptfds.as_deref());
 
     debug!(
         "sys_select <= nfds: {nfds} sets: [read: {read_set:?}, write: {write_set:?}, except: \
@@ -98,15 +113,14 @@ fn do_select(
     drop(fd_table);
     let fds = FdPollSet(fds);
 
-    if let Some(readfds) = readfds.as_deref_mut() {
-        unsafe { FD_ZERO(readfds) };
-    }
-    if let Some(writefds) = writefds.as_deref_mut() {
-        unsafe { FD_ZERO(writefds) };
-    }
-    if let Some(exceptfds) = exceptfds.as_deref_mut() {
-        unsafe { FD_ZERO(exceptfds) };
+    for set in [&mut readfds, &mut writefds, &mut exceptfds]
+        .into_iter()
+        .flatten()
+    {
+        set.fill(0);
     }
+    let curr = current();
+    let _wchan = curr.as_thread().wait_in(do_select as usize);
     with_replacen_blocked(sigmask.copied(), || {
         match Poller::new(&fds, IoEvents::empty())
             .timeout(timeout)
@@ -118,19 +132,19 @@ fn do_select(
                         && let Some(set) = readfds.as_deref_mut()
                     {
                         res += 1;
-                        unsafe { FD_SET(index as _, set) };
+                        fd_set(set, index);
                     }
                     if events.contains(IoEvents::OUT)
                         && let Some(set) = writefds.as_deref_mut()
                     {
                         res += 1;
-                        unsafe { FD_SET(index as _, set) };
+                        fd_set(set, index);
                     }
                     if events.contains(IoEvents::ERR)
                         && let Some(set) = exceptfds.as_deref_mut()
                     {
                         res += 1;
-                        unsafe { FD_SET(index as _, set) };
+                        fd_set(set, index);
                     }
                 }
                 if res > 0 {
@@ -219,8 +219,12 @@ pub fn sys_shmdt(shmaddr: usize) -> AxResult<isize> {
     let mut shm_inner = shm_inner.lock();
     let va_range = shm_inner.get_addr_range(pid).ok_or(AxError::InvalidInput)?;
 
+    if !proc_data.sealed.lock().overlapping(va_range).is_empty() {
+        return Err(AxError::OperationNotPermitted);
+    }
     let mut aspace = proc_data.aspace.lock();
     aspace.unmap(va_range.start, va_range.size())?;
+    proc_data.mlocked.lock().remove(va_range);
 
     let mut shm_manager = SHM_MANAGER.lock();
     shm_manager.remove_shmaddr(pid, shmaddr);
@@ -0,0 +1,140 @@
+use alloc::{sync::Arc, vec::Vec};
+use core::ffi::c_char;
+
+use axerrno::{AxError, AxResult, LinuxError};
+use axtask::current;
+use starry_core::{
+    keys::{self, Key, KeyType},
+    task::AsThread,
+};
+use starry_vm::{vm_load, vm_write_slice};
+
+use crate::mm::vm_load_string;
+
+const KEY_SPEC_THREAD_KEYRING: i32 = -1;
+const KEY_SPEC_PROCESS_KEYRING: i32 = -2;
+const KEY_SPEC_SESSION_KEYRING: i32 = -3;
+const KEY_SPEC_USER_KEYRING: i32 = -4;
+const KEY_SPEC_USER_SESSION_KEYRING: i32 = -5;
+
+const KEYCTL_GET_KEYRING_ID: u32 = 0;
+const KEYCTL_REVOKE: u32 = 3;
+const KEYCTL_SEARCH: u32 = 10;
+const KEYCTL_READ: u32 = 11;
+
+/// The maximum size of the payload of a `user` key.
+const USER_KEY_MAX_SIZE: usize = 32767;
+
+/// Looks up a key by serial number or special keyring ID for the current
+/// process. A missing process keyring is created if `create` is set.
+fn lookup_key(id: i32, create: bool) -> AxResult<Arc<Key>> {
+    let curr = current();
+    let proc_data = &curr.as_thread().proc_data;
+    let cred = *proc_data.cred.read();
+    let key = match id {
+        KEY_SPEC_PROCESS_KEYRING => {
+            let mut keyrings = proc_data.keyrings.lock();
+            if keyrings.process.is_none() && create {
+                keyrings.process =
+                    Some(Key::new(KeyType::Keyring, "_pid".into(), &cred, Vec::new()));
+            }
+            keyrings
+                .process
+                .clone()
+                .ok_or(AxError::Other(LinuxError::ENOKEY))?
+        }
+        KEY_SPEC_SESSION_KEYRING => proc_data
+            .keyrings
+            .lock()
+            .session
+            .clone()
+            .unwrap_or_else(|| keys::user_session_keyring(&cred)),
+        KEY_SPEC_USER_KEYRING => keys::user_keyring(&cred),
+        KEY_SPEC_USER_SESSION_KEYRING => keys::user_session_keyring(&cred),
+        // Threads share the keyrings of their process
+        KEY_SPEC_THREAD_KEYRING => return Err(AxError::OperationNotSupported),
+        _ if id > 0 => keys::lookup(id).ok_or(AxError::Other(LinuxError::ENOKEY))?,
+        _ => return Err(AxError::InvalidInput),
+    };
+    key.check_access(&cred)?;
+    Ok(key)
+}
+
+pub fn sys_add_key(
+    key_type: *const c_char,
+    description: *const c_char,
+    payload: *const u8,
+    plen: usize,
+    ringid: i32,
+) -> AxResult<isize> {
+    let key_type = vm_load_string(key_type)?;
+    let description = vm_load_string(description)?;
+    debug!("sys_add_key <= type: {key_type:?}, description: {description:?}, ringid: {ringid}");
+
+    let key_type = KeyType::from_name(&key_type).ok_or(AxError::Other(LinuxError::ENODEV))?;
+    if description.is_empty() {
+        return Err(AxError::InvalidInput);
+    }
+    let data = match key_type {
+        KeyType::User => {
+            if plen == 0 || plen > USER_KEY_MAX_SIZE {
+                return Err(AxError::InvalidInput);
+            }
+            vm_load(payload, plen)?
+        }
+        KeyType::Keyring => {
+            if !payload.is_null() || plen != 0 {
+                return Err(AxError::InvalidInput);
+            }
+            Vec::new()
+        }
+    };
+
+    let keyring = lookup_key(ringid, true)?;
+    let cred = *current().as_thread().proc_data.cred.read();
+    // Like Linux, a key of the same description in the keyring is updated
+    if key_type == KeyType::User
+        && let Some(key) = keyring.find(key_type, &description)?
+        && key.check_access(&cred).is_ok()
+    {
+        key.update(data)?;
+        return Ok(key.serial() as _);
+    }
+    let key = Key::new(key_type, description, &cred, data);
+    keyring.link(key.clone())?;
+    Ok(key.serial() as _)
+}
+
+pub fn sys_keyctl(op: u32, arg2: usize, arg3: usize, arg4: usize, arg5: usize) -> AxResult<isize> {
+    debug!("sys_keyctl <= op: {op}, args: {arg2:#x}, {arg3:#x}, {arg4:#x}, {arg5:#x}");
+    match op {
+        KEYCTL_GET_KEYRING_ID => Ok(lookup_key(arg2 as _, arg3 != 0)?.serial() as _),
+        KEYCTL_REVOKE => {
+            lookup_key(arg2 as _, false)?.revoke();
+            Ok(0)
+        }
+        KEYCTL_READ => {
+            let data = lookup_key(arg2 as _, false)?.read()?;
+            let (buf, buflen) = (arg3 as *mut u8, arg4);
+            // The full size is returned, whatever fits in the buffer
+            if !buf.is_null() && buflen > 0 {
+                vm_write_slice(buf, &data[..data.len().min(buflen)])?;
+            }
+            Ok(data.len() as _)
+        }
+        KEYCTL_SEARCH => {
+            let keyring = lookup_key(arg2 as _, false)?;
+            let key_type = KeyType::from_name(&vm_load_string(arg3 as _)?)
+                .ok_or(AxError::Other(LinuxError::ENOKEY))?;
+            let description = vm_load_string(arg4 as _)?;
+            let cred = *current().as_thread().proc_data.cred.read();
+            let key = keyring.search(key_type, &description, &cred)?;
+            // The key found is linked into the destination keyring, if any
+            if arg5 != 0 {
+                lookup_key(arg5 as _, true)?.link(key.clone())?;
+            }
+            Ok(key.serial() as _)
+        }
+        _ => Err(AxError::OperationNotSupported),
+    }
+}
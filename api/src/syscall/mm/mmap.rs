@@ -1,19 +1,29 @@
-use alloc::sync::Arc;
+use alloc::{sync::Arc, vec::Vec};
 
-use axerrno::{AxError, AxResult};
-use axfs_ng::FileBackend;
+use axerrno::{AxError, AxResult, LinuxError};
+use axfs_ng::{FileBackend, FileFlags};
 use axhal::paging::{MappingFlags, PageSize};
-use axmm::backend::{Backend, SharedPages};
+use axmm::{
+    AddrSpace,
+    backend::{Backend, SharedPages},
+};
 use axtask::current;
 use linux_raw_sys::general::*;
-use memory_addr::{MemoryAddr, VirtAddr, VirtAddrRange, align_up_4k};
+use memory_addr::{MemoryAddr, PAGE_SIZE_4K, VirtAddr, VirtAddrRange, align_up_4k};
 use starry_core::{
-    task::AsThread,
+    mm::{SharedFileMapping, page_out},
+    task::{AsThread, ProcessData},
+    uprobe::{self, FileId, FileMapping},
+    verity,
     vfs::{Device, DeviceMmap},
 };
-use starry_vm::{vm_load, vm_write_slice};
+use starry_vm::{VmMutPtr, vm_write_slice};
 
-use crate::file::{File, FileLike};
+use crate::{
+    file::{File, FileLike, SecretMem, writeback},
+    mm::{check_commit, uncommitted_size},
+    vfs::mounts,
+};
 
 bitflags::bitflags! {
     /// `PROT_*` flags for use with [`sys_mmap`].
@@ -100,7 +110,6 @@ pub fn sys_mmap(
     }
 
     let curr = current();
-    let mut aspace = curr.as_thread().proc_data.aspace.lock();
     let permission_flags = MmapProt::from_bits_truncate(prot);
     // TODO: check illegal flags for mmap
     let map_flags = match MmapFlags::from_bits(flags) {
@@ -148,10 +157,23 @@ pub fn sys_mmap(
     let end = (addr + length).align_up(page_size);
     let mut length = end - start;
 
+    // Only private writable memory is committed. This is checked before
+    // locking the address space, as the committed memory is counted by
+    // walking all of them.
+    if map_type == MmapFlags::PRIVATE && permission_flags.contains(MmapProt::WRITE) {
+        check_commit(length, map_flags.contains(MmapFlags::NORESERVE))?;
+    }
+    let mut aspace = curr.as_thread().proc_data.aspace.lock();
+
     let start = if map_flags.intersects(MmapFlags::FIXED | MmapFlags::FIXED_NOREPLACE) {
         let dst_addr = VirtAddr::from(start);
         if !map_flags.contains(MmapFlags::FIXED_NOREPLACE) {
+            let range = VirtAddrRange::from_start_size(dst_addr, length);
+            let proc_data = &curr.as_thread().proc_data;
+            check_unsealed(proc_data, range)?;
+            proc_data.huge_pages.lock().split(&mut aspace, range)?;
             aspace.unmap(dst_addr, length)?;
+            forget_range(proc_data, range);
         }
         dst_addr
     } else {
@@ -169,19 +191,61 @@ pub fn sys_mmap(
             .ok_or(AxError::NoMemory)?
     };
 
-    let file = if fd > 0 {
+    let secret = if fd > 0 {
+        SecretMem::from_fd(fd).ok()
+    } else {
+        None
+    };
+    let file = if fd > 0 && secret.is_none() {
         Some(File::from_fd(fd)?)
     } else {
         None
     };
+    let noexec = file
+        .as_ref()
+        .is_some_and(|file| mounts::has_option(file.inner().location(), "noexec"));
+    if noexec && permission_flags.contains(MmapProt::EXEC) {
+        return Err(AxError::OperationNotPermitted);
+    }
+    if let Some(file) = &file {
+        // Faults don't go through the checks of reads, so the mapped pages of
+        // files with fs-verity are verified here.
+        verity::verify(file.inner().location(), offset as u64, length)?;
+    }
+    let file_id = file
+        .as_ref()
+        .map(|file| FileId::of(file.inner().location()))
+        .transpose()?;
+    // Writes through shared mappings can't be tracked, so their files are
+    // written back as long as they're mapped.
+    let shared_file = match (&file, file_id) {
+        (Some(file), Some(id))
+            if map_type != MmapFlags::PRIVATE && file.inner().access(FileFlags::WRITE).is_ok() =>
+        {
+            Some((id, file.inner().backend()?.clone()))
+        }
+        _ => None,
+    };
 
+    // The page cache and file offset of a shared file mapping, to move it.
+    let mut shared_backend = None;
     let backend = match map_type {
         MmapFlags::SHARED | MmapFlags::SHARED_VALIDATE => {
-            if let Some(file) = file {
+            if let Some(secret) = secret {
+                if offset != 0 {
+                    return Err(AxError::InvalidInput);
+                }
+                Backend::new_shared(start, secret.pages(length)?)
+            } else if let Some(file) = file {
                 let file = file.inner();
                 let backend = file.backend()?.clone();
                 match file.backend()?.clone() {
                     FileBackend::Cached(cache) => {
+                        shared_backend = Some(SharedFileMapping {
+                            cache: cache.clone(),
+                            flags: file.flags(),
+                            base: start.as_usize().wrapping_sub(offset),
+                        });
                         // TODO(mivik): file mmap page size
                         Backend::new_file(
                             start,
@@ -197,7 +261,7 @@ pub fn sys_mmap(
                             .downcast::<Device>()
                             .map_err(|_| AxError::NoSuchDevice)?;
 
-                       match device.mmap(offset as u64) {
+                        match device.mmap(offset as u64) {
                             DeviceMmap::None => {
                                 return Err(AxError::NoSuchDevice);
                             }
@@ -213,13 +277,20 @@ pub fn sys_mmap(
                                     start.as_usize() as isize - range.start.as_usize() as isize,
                                 )
                             }
-                            DeviceMmap::Cache(cache) => Backend::new_file(
-                                start,
-                                cache,
-                                file.flags(),
-                                offset,
-                                &curr.as_thread().proc_data.aspace,
-                            ),
+                            DeviceMmap::Cache(cache) => {
+                                shared_backend = Some(SharedFileMapping {
+                                    cache: cache.clone(),
+                                    flags: file.flags(),
+                                    base: start.as_usize().wrapping_sub(offset),
+                                });
+                                Backend::new_file(
+                                    start,
+                                    cache,
+                                    file.flags(),
+                                    offset,
+                                    &curr.as_thread().proc_data.aspace,
+                                )
+                            }
                         }
                     }
                 }
@@ -228,6 +299,10 @@ pub fn sys_mmap(
             }
         }
         MmapFlags::PRIVATE => {
+            if secret.is_some() {
+                // Private copies of secret memory would leak it.
+                return Err(AxError::InvalidInput);
+            }
             if let Some(file) = file {
                 // Private mapping from a file
                 let backend = file.inner().backend()?.clone();
@@ -241,93 +316,708 @@ pub fn sys_mmap(
 
     let populate = map_flags.contains(MmapFlags::POPULATE);
     aspace.map(start, length, permission_flags.into(), populate, backend)?;
+    if map_type == MmapFlags::PRIVATE && fd <= 0 {
+        curr.as_thread()
+            .proc_data
+            .anon_mappings
+            .lock()
+            .insert(VirtAddrRange::from_start_size(start, length), ());
+    }
+    if noexec {
+        curr.as_thread()
+            .proc_data
+            .noexec_maps
+            .lock()
+            .insert(VirtAddrRange::from_start_size(start, length), ());
+    }
+    if let Some(file_id) = file_id.filter(|_| map_type == MmapFlags::PRIVATE) {
+        let range = VirtAddrRange::from_start_size(start, length);
+        let mapping = FileMapping::new(file_id, start, offset as u64);
+        curr.as_thread()
+            .proc_data
+            .file_maps
+            .lock()
+            .insert(range, mapping);
+        uprobe::on_map(&mut aspace, range, mapping);
+    }
+    if let Some((id, backend)) = shared_file {
+        let range = VirtAddrRange::from_start_size(start, length);
+        curr.as_thread()
+            .proc_data
+            .shared_file_maps
+            .lock()
+            .insert(range, id);
+        writeback::mark_mapped(id, &backend);
+    }
+    if let Some(mapping) = shared_backend {
+        let range = VirtAddrRange::from_start_size(start, length);
+        curr.as_thread()
+            .proc_data
+            .shared_file_backends
+            .lock()
+            .insert(range, mapping);
+    }
 
     Ok(start.as_usize() as _)
 }
 
+/// Fails with `EPERM` if part of `range` is sealed.
+fn check_unsealed(proc_data: &ProcessData, range: VirtAddrRange) -> AxResult<()> {
+    if proc_data.sealed.lock().overlapping(range).is_empty() {
+        Ok(())
+    } else {
+        Err(AxError::OperationNotPermitted)
+    }
+}
+
+/// Forgets the attributes of an unmapped range.
+fn forget_range(proc_data: &ProcessData, range: VirtAddrRange) {
+    proc_data.mlocked.lock().remove(range);
+    proc_data.lazy_free.lock().ranges.remove(range);
+    proc_data.anon_mappings.lock().remove(range);
+    proc_data.huge_pages.lock().ranges.remove(range);
+    proc_data.file_maps.lock().remove(range);
+    proc_data.shared_file_maps.lock().remove(range);
+    proc_data.shared_file_backends.lock().remove(range);
+    proc_data.noexec_maps.lock().remove(range);
+}
+
 pub fn sys_munmap(addr: usize, length: usize) -> AxResult<isize> {
     debug!("sys_munmap <= addr: {addr:#x}, length: {length:x}");
     let curr = current();
-    let mut aspace = curr.as_thread().proc_data.aspace.lock();
+    let proc_data = &curr.as_thread().proc_data;
+    let mut aspace = proc_data.aspace.lock();
     let length = align_up_4k(length);
     let start_addr = VirtAddr::from(addr);
+    let range = VirtAddrRange::from_start_size(start_addr, length);
+    check_unsealed(proc_data, range)?;
+    proc_data.huge_pages.lock().split(&mut aspace, range)?;
     aspace.unmap(start_addr, length)?;
+    forget_range(proc_data, range);
     Ok(0)
 }
 
 pub fn sys_mprotect(addr: usize, length: usize, prot: u32) -> AxResult<isize> {
+    sys_pkey_mprotect(addr, length, prot, -1)
+}
+
+const PKEY_DISABLE_ACCESS: u32 = 1 << 0;
+const PKEY_DISABLE_WRITE: u32 = 1 << 1;
+
+pub fn sys_pkey_mprotect(addr: usize, length: usize, prot: u32, pkey: i32) -> AxResult<isize> {
     // TODO: implement PROT_GROWSUP & PROT_GROWSDOWN
-    let Some(permission_flags) = MmapProt::from_bits(prot) else {
+    let Some(mut permission_flags) = MmapProt::from_bits(prot) else {
         return Err(AxError::InvalidInput);
     };
-    debug!("sys_mprotect <= addr: {addr:#x}, length: {length:x}, prot: {permission_flags:?}");
+    debug!(
+        "sys_pkey_mprotect <= addr: {addr:#x}, length: {length:x}, prot: {permission_flags:?}, \
+         pkey: {pkey}"
+    );
 
     if permission_flags.contains(MmapProt::GROWDOWN | MmapProt::GROWSUP) {
         return Err(AxError::InvalidInput);
     }
 
     let curr = current();
-    let mut aspace = curr.as_thread().proc_data.aspace.lock();
-    let length = align_up_4k(length);
+    let proc_data = &curr.as_thread().proc_data;
+    let mut pkeys = proc_data.pkeys.lock();
     let start_addr = VirtAddr::from(addr);
+    // Plain `mprotect` keeps the key of the range.
+    let key = match pkey {
+        -1 => pkeys.ranges.get(start_addr).unwrap_or(0),
+        _ => pkey as u32,
+    };
+    // Without hardware keys, the access rights of the key are enforced by
+    // the page table. Like hardware keys, they don't restrict execution.
+    let rights = pkeys.rights(key).ok_or(AxError::InvalidInput)?;
+    if rights & PKEY_DISABLE_ACCESS != 0 {
+        permission_flags.remove(MmapProt::READ | MmapProt::WRITE);
+    } else if rights & PKEY_DISABLE_WRITE != 0 {
+        permission_flags.remove(MmapProt::WRITE);
+    }
+
+    let length = align_up_4k(length);
+    let range = VirtAddrRange::from_start_size(start_addr, length);
+    // Private memory made writable is committed, which is checked before
+    // locking the address space, like in `mmap`.
+    if permission_flags.contains(MmapProt::WRITE) {
+        let size = uncommitted_size(&proc_data.aspace.lock(), range);
+        if size != 0 {
+            check_commit(size, false)?;
+        }
+    }
+    let mut aspace = proc_data.aspace.lock();
+    check_unsealed(proc_data, range)?;
+    if permission_flags.contains(MmapProt::EXEC)
+        && !proc_data.noexec_maps.lock().overlapping(range).is_empty()
+    {
+        return Err(AxError::PermissionDenied);
+    }
+    proc_data.huge_pages.lock().split(&mut aspace, range)?;
     aspace.protect(start_addr, length, permission_flags.into())?;
+    // The new flags replace those of freed pages, which are kept
+    proc_data.lazy_free.lock().ranges.remove(range);
+    if pkey != -1 && length != 0 {
+        pkeys.ranges.insert(range, key);
+    }
 
     Ok(0)
 }
 
-pub fn sys_mremap(addr: usize, old_size: usize, new_size: usize, flags: u32) -> AxResult<isize> {
+pub fn sys_pkey_alloc(flags: u32, access_rights: u32) -> AxResult<isize> {
+    debug!("sys_pkey_alloc <= flags: {flags:#x}, access_rights: {access_rights:#x}");
+    if flags != 0 || access_rights & !(PKEY_DISABLE_ACCESS | PKEY_DISABLE_WRITE) != 0 {
+        return Err(AxError::InvalidInput);
+    }
+    let key = current()
+        .as_thread()
+        .proc_data
+        .pkeys
+        .lock()
+        .alloc(access_rights)
+        .ok_or(AxError::Other(LinuxError::ENOSPC))?;
+    Ok(key as _)
+}
+
+pub fn sys_pkey_free(pkey: i32) -> AxResult<isize> {
+    debug!("sys_pkey_free <= pkey: {pkey}");
+    // Like Linux, ranges still protected by the key keep it.
+    if pkey < 0
+        || !current()
+            .as_thread()
+            .proc_data
+            .pkeys
+            .lock()
+            .free(pkey as u32)
+    {
+        return Err(AxError::InvalidInput);
+    }
+    Ok(0)
+}
+
+/// Returns the backend of the pages appended to a mapping with `backend`
+/// when it grows in place to cover `tail`.
+fn grow_backend(backend: &Backend, tail: VirtAddrRange) -> AxResult<Backend> {
+    match backend {
+        // The backend maps addresses past the old end like the rest of the
+        // mapping, e.g. to the following file pages.
+        Backend::Cow(_) | Backend::File(_) => Ok(backend.clone()),
+        Backend::Shared(_) => Ok(Backend::new_shared(
+            tail.start,
+            Arc::new(SharedPages::new(tail.size(), PageSize::Size4K)?),
+        )),
+        _ => Err(AxError::BadAddress),
+    }
+}
+
+/// Moves the pages of the `size` bytes mapped at `src` with `flags` to the
+/// new mapping at `dst`, by moving their page table entries.
+///
+/// The pages are populated first, so that the whole range moves and `src` is
+/// left without pages.
+fn move_pages(
+    aspace: &mut AddrSpace,
+    src: VirtAddr,
+    dst: VirtAddr,
+    size: usize,
+    flags: MappingFlags,
+) -> AxResult<()> {
+    if !flags.contains(MappingFlags::READ) {
+        aspace.protect(src, size, flags | MappingFlags::READ)?;
+    }
+    aspace.populate_area(src, size, MappingFlags::READ)?;
+    if !flags.contains(MappingFlags::READ) {
+        aspace.protect(src, size, flags)?;
+    }
+
+    // The entries keep their flags, e.g. copy-on-write pages stay read-only.
+    let page_table = aspace.page_table_mut();
+    for offset in (0..size).step_by(PAGE_SIZE_4K) {
+        let Ok((paddr, pte_flags, _)) = page_table.query(src + offset) else {
+            continue;
+        };
+        let (_, _, tlb) = page_table
+            .unmap(src + offset)
+            .map_err(|_| AxError::BadState)?;
+        tlb.flush();
+        page_table
+            .map(dst + offset, paddr, PageSize::Size4K, pte_flags)
+            .map_err(|_| AxError::BadState)?
+            .ignore();
+    }
+    Ok(())
+}
+
+pub fn sys_mremap(
+    addr: usize,
+    old_size: usize,
+    new_size: usize,
+    flags: u32,
+    new_addr: usize,
+) -> AxResult<isize> {
     debug!(
         "sys_mremap <= addr: {addr:#x}, old_size: {old_size:x}, new_size: {new_size:x}, flags: \
-         {flags:#x}"
+         {flags:#x}, new_addr: {new_addr:#x}"
     );
 
-    // TODO: full implementation
-
-    if addr % PageSize::Size4K as usize != 0 {
+    if flags & !(MREMAP_MAYMOVE | MREMAP_FIXED | MREMAP_DONTUNMAP) != 0
+        || !PageSize::Size4K.is_aligned(addr)
+    {
+        return Err(AxError::InvalidInput);
+    }
+    let may_move = flags & MREMAP_MAYMOVE != 0;
+    let fixed = flags & MREMAP_FIXED != 0;
+    let dont_unmap = flags & MREMAP_DONTUNMAP != 0;
+    if (fixed || dont_unmap) && !may_move {
         return Err(AxError::InvalidInput);
     }
-    let addr = VirtAddr::from(addr);
-
-    let curr = current();
-    let aspace = curr.as_thread().proc_data.aspace.lock();
     let old_size = align_up_4k(old_size);
     let new_size = align_up_4k(new_size);
+    // Duplicating a shared mapping with an old size of 0 isn't supported.
+    if old_size == 0 || new_size == 0 || (dont_unmap && old_size != new_size) {
+        return Err(AxError::InvalidInput);
+    }
+    let old = VirtAddrRange::from_start_size(VirtAddr::from(addr), old_size);
 
-    let flags = aspace.find_area(addr).ok_or(AxError::NoMemory)?.flags();
-    drop(aspace);
-    let new_addr = sys_mmap(
-        addr.as_usize(),
-        new_size,
-        flags.bits() as _,
-        MmapFlags::PRIVATE.bits(),
-        -1,
-        0,
-    )? as usize;
-
-    let copy_len = new_size.min(old_size);
-    let data = vm_load(addr.as_ptr(), copy_len)?;
-    vm_write_slice(new_addr as *mut u8, &data)?;
-
-    sys_munmap(addr.as_usize(), old_size)?;
-
-    Ok(new_addr as isize)
+    let curr = current();
+    let proc_data = &curr.as_thread().proc_data;
+    let mut aspace = proc_data.aspace.lock();
+    check_unsealed(proc_data, old)?;
+    if !aspace.can_access_range(old.start, old.size(), MappingFlags::empty()) {
+        return Err(AxError::BadAddress);
+    }
+    // Freed and huge pages go back to normal pages, which are what moves.
+    proc_data.lazy_free.lock().restore(&mut aspace, old)?;
+    proc_data.huge_pages.lock().demote(&mut aspace, old)?;
+    let area = aspace.find_area(old.start).ok_or(AxError::BadAddress)?;
+    if area.end() < old.end {
+        // The range spans several mappings
+        return Err(AxError::BadAddress);
+    }
+    let area_start = area.start();
+    let area_end = area.end();
+    let map_flags = area.flags();
+    let backend = area.backend().clone();
+    let anon = proc_data.anon_mappings.lock().covers(old);
+    let noexec = !proc_data.noexec_maps.lock().overlapping(old).is_empty();
+
+    if !fixed && !dont_unmap {
+        if new_size <= old_size {
+            let tail = VirtAddrRange::new(old.start + new_size, old.end);
+            aspace.unmap(tail.start, tail.size())?;
+            forget_range(proc_data, tail);
+            return Ok(old.start.as_usize() as _);
+        }
+        let tail = VirtAddrRange::new(old.end, old.start + new_size);
+        let free = old.end == area_end
+            && aspace.find_free_area(tail.start, tail.size(), tail) == Some(tail.start);
+        if free {
+            let tail_backend = grow_backend(&backend, tail)?;
+            aspace.map(tail.start, tail.size(), map_flags, false, tail_backend)?;
+            if anon {
+                proc_data.anon_mappings.lock().insert(tail, ());
+            }
+            if noexec {
+                proc_data.noexec_maps.lock().insert(tail, ());
+            }
+            let mut shared_file_maps = proc_data.shared_file_maps.lock();
+            if let Some(id) = shared_file_maps.get(old.start) {
+                shared_file_maps.insert(tail, id);
+            }
+            let mut shared_file_backends = proc_data.shared_file_backends.lock();
+            if let Some(mapping) = shared_file_backends.get(old.start) {
+                shared_file_backends.insert(tail, mapping);
+            }
+            return Ok(old.start.as_usize() as _);
+        }
+        if !may_move {
+            return Err(AxError::NoMemory);
+        }
+    }
+
+    // Shared file mappings move with the file offsets they map.
+    let shared_backend = proc_data.shared_file_backends.lock().get(old.start);
+    match backend {
+        Backend::Cow(_) | Backend::Shared(_) => {}
+        Backend::File(_) if shared_backend.is_some() => {}
+        _ => return Err(AxError::InvalidInput),
+    }
+    let dst = if fixed {
+        if !PageSize::Size4K.is_aligned(new_addr) {
+            return Err(AxError::InvalidInput);
+        }
+        let dst = VirtAddrRange::from_start_size(VirtAddr::from(new_addr), new_size);
+        if dst.overlaps(old) {
+            return Err(AxError::InvalidInput);
+        }
+        check_unsealed(proc_data, dst)?;
+        proc_data.huge_pages.lock().split(&mut aspace, dst)?;
+        aspace.unmap(dst.start, dst.size())?;
+        forget_range(proc_data, dst);
+        dst
+    } else {
+        let start = aspace
+            .find_free_area(
+                aspace.base(),
+                new_size,
+                VirtAddrRange::new(aspace.base(), aspace.end()),
+            )
+            .ok_or(AxError::NoMemory)?;
+        VirtAddrRange::from_start_size(start, new_size)
+    };
+
+    let moved = old_size.min(new_size);
+    if let Backend::Shared(shared) = &backend {
+        // Shared pages may be mapped elsewhere too, so the same pages are
+        // mapped at the new address rather than copied. Like futex keys, the
+        // pages are indexed from the start of the area.
+        let offset = old.start - area_start;
+        let base = VirtAddr::from(dst.start.as_usize().wrapping_sub(offset));
+        let moved_backend = Backend::new_shared(base, shared.pages().clone());
+        aspace.map(dst.start, moved, map_flags, false, moved_backend)?;
+        if new_size > old_size {
+            let tail = VirtAddrRange::new(dst.start + old_size, dst.end);
+            let tail_backend = grow_backend(&backend, tail)?;
+            aspace.map(tail.start, tail.size(), map_flags, false, tail_backend)?;
+        }
+    } else if let Some(mapping) = &shared_backend {
+        let base = mapping
+            .base
+            .wrapping_add(dst.start.as_usize().wrapping_sub(old.start.as_usize()));
+        let moved_backend = Backend::new_file(
+            dst.start,
+            mapping.cache.clone(),
+            mapping.flags,
+            dst.start.as_usize().wrapping_sub(base),
+            &proc_data.aspace,
+        );
+        aspace.map(dst.start, dst.size(), map_flags, false, moved_backend)?;
+    } else {
+        aspace.map(
+            dst.start,
+            dst.size(),
+            map_flags,
+            false,
+            Backend::new_alloc(dst.start, PageSize::Size4K),
+        )?;
+        move_pages(&mut aspace, old.start, dst.start, moved, map_flags)?;
+    }
+
+    let locked = proc_data.mlocked.lock().covers(old);
+    let file_map = proc_data.file_maps.lock().find(old.start);
+    let shared_file = proc_data.shared_file_maps.lock().get(old.start);
+    if dont_unmap {
+        // Private pages moved away, leaving the old range empty; shared ones
+        // stay mapped there too.
+        if matches!(backend, Backend::Cow(_)) {
+            aspace.unmap(old.start, old.size())?;
+            aspace.map(
+                old.start,
+                old.size(),
+                map_flags,
+                false,
+                Backend::new_alloc(old.start, PageSize::Size4K),
+            )?;
+        }
+        proc_data.mlocked.lock().remove(old);
+        proc_data.file_maps.lock().remove(old);
+        if matches!(backend, Backend::Cow(_)) {
+            proc_data.noexec_maps.lock().remove(old);
+        }
+    } else {
+        aspace.unmap(old.start, old.size())?;
+        forget_range(proc_data, old);
+    }
+    if anon {
+        proc_data.anon_mappings.lock().insert(dst, ());
+    }
+    if noexec {
+        proc_data.noexec_maps.lock().insert(dst, ());
+    }
+    if let Some((_, mapping)) = file_map {
+        // The pages moved, breakpoints included
+        let shift = dst.start.as_usize().wrapping_sub(old.start.as_usize());
+        let base = mapping.base.wrapping_add(shift);
+        proc_data
+            .file_maps
+            .lock()
+            .insert(dst, FileMapping { base, ..mapping });
+    }
+    if let Some(id) = shared_file {
+        proc_data.shared_file_maps.lock().insert(dst, id);
+    }
+    if let Some(mapping) = shared_backend {
+        let base = mapping
+            .base
+            .wrapping_add(dst.start.as_usize().wrapping_sub(old.start.as_usize()));
+        proc_data
+            .shared_file_backends
+            .lock()
+            .insert(dst, SharedFileMapping { base, ..mapping });
+    }
+    if locked {
+        proc_data.mlocked.lock().insert(dst, ());
+    }
+    Ok(dst.start.as_usize() as _)
+}
+
+#[cfg(target_arch = "x86_64")]
+const SHADOW_STACK_SET_TOKEN: u32 = 1 << 0;
+#[cfg(target_arch = "x86_64")]
+const SHADOW_STACK_SET_MARKER: u32 = 1 << 1;
+
+/// Allocates a shadow stack for Intel CET.
+///
+/// Shadow stacks aren't enabled for user mode yet, so the stack is a
+/// read-only anonymous mapping, set up with the restore token (and marker)
+/// `RSTORSSP` expects.
+#[cfg(target_arch = "x86_64")]
+pub fn sys_map_shadow_stack(addr: usize, size: usize, flags: u32) -> AxResult<isize> {
+    debug!("sys_map_shadow_stack <= addr: {addr:#x}, size: {size:#x}, flags: {flags:#x}");
+    if flags & !(SHADOW_STACK_SET_TOKEN | SHADOW_STACK_SET_MARKER) != 0
+        || !PageSize::Size4K.is_aligned(addr)
+    {
+        return Err(AxError::InvalidInput);
+    }
+    let set_token = flags & SHADOW_STACK_SET_TOKEN != 0;
+    let set_marker = flags & SHADOW_STACK_SET_MARKER != 0;
+    // The token, and the marker above it, must fit in the stack.
+    if size < 8 * (set_token as usize + set_marker as usize) {
+        return Err(AxError::Other(LinuxError::ENOSPC));
+    }
+    // Shadow stacks are placed above 4G.
+    if addr != 0 && addr < 1 << 32 {
+        return Err(AxError::Other(LinuxError::ERANGE));
+    }
+    let aligned_size = size
+        .checked_next_multiple_of(PAGE_SIZE_4K)
+        .ok_or(AxError::Other(LinuxError::EOVERFLOW))?;
+
+    let mut map_flags = MAP_PRIVATE | MAP_ANONYMOUS;
+    if addr != 0 {
+        map_flags |= MAP_FIXED_NOREPLACE;
+    }
+    let start = sys_mmap(addr, aligned_size, PROT_READ | PROT_WRITE, map_flags, -1, 0)? as usize;
+
+    let mut ssp = start + size;
+    if set_marker {
+        ssp -= 8;
+        (ssp as *mut u64).vm_write(0)?;
+    }
+    if set_token {
+        // `RSTORSSP` takes a token right below the new stack pointer, holding
+        // that pointer with bit 0 set for 64-bit mode.
+        let token_addr = (ssp & !7) - 8;
+        (token_addr as *mut u64).vm_write(ssp as u64 | 1)?;
+    }
+    sys_mprotect(start, aligned_size, PROT_READ)?;
+    Ok(start as _)
 }
 
 pub fn sys_madvise(addr: usize, length: usize, advice: i32) -> AxResult<isize> {
     debug!("sys_madvise <= addr: {addr:#x}, length: {length:x}, advice: {advice:#x}");
+    if !PageSize::Size4K.is_aligned(addr) {
+        return Err(AxError::InvalidInput);
+    }
+    let length = length
+        .checked_next_multiple_of(PAGE_SIZE_4K)
+        .ok_or(AxError::InvalidInput)?;
+    let range = VirtAddrRange::from_start_size(VirtAddr::from(addr), length);
+
+    let curr = current();
+    let proc_data = &curr.as_thread().proc_data;
+    match advice as u32 {
+        MADV_FREE => {
+            let mut aspace = proc_data.aspace.lock();
+            if !aspace.can_access_range(range.start, range.size(), MappingFlags::empty()) {
+                return Err(AxError::NoMemory);
+            }
+            // Discarding the contents of sealed memory would change it
+            check_unsealed(proc_data, range)?;
+            proc_data.huge_pages.lock().split(&mut aspace, range)?;
+            proc_data.lazy_free.lock().free(&mut aspace, range)?;
+        }
+        MADV_HUGEPAGE => {
+            let mut aspace = proc_data.aspace.lock();
+            if !aspace.can_access_range(range.start, range.size(), MappingFlags::empty()) {
+                return Err(AxError::NoMemory);
+            }
+            let anon = proc_data.anon_mappings.lock();
+            proc_data
+                .huge_pages
+                .lock()
+                .promote(&mut aspace, &anon, range)?;
+        }
+        MADV_NOHUGEPAGE => {
+            let mut aspace = proc_data.aspace.lock();
+            if !aspace.can_access_range(range.start, range.size(), MappingFlags::empty()) {
+                return Err(AxError::NoMemory);
+            }
+            proc_data.huge_pages.lock().demote(&mut aspace, range)?;
+        }
+        // There are no LRU lists to deactivate pages on, so cold pages are
+        // only reclaimed when paged out.
+        MADV_COLD => {
+            let aspace = proc_data.aspace.lock();
+            if !aspace.can_access_range(range.start, range.size(), MappingFlags::empty()) {
+                return Err(AxError::NoMemory);
+            }
+        }
+        MADV_PAGEOUT => {
+            let mut aspace = proc_data.aspace.lock();
+            if !aspace.can_access_range(range.start, range.size(), MappingFlags::empty()) {
+                return Err(AxError::NoMemory);
+            }
+            // Freed pages are reclaimed, and file pages dropped from the
+            // page table.
+            proc_data.lazy_free.lock().reclaim(&mut aspace, range)?;
+            page_out(&mut aspace, range)?;
+        }
+        // Other advice doesn't change the contents of the pages
+        _ => {}
+    }
+    Ok(0)
+}
+
+pub fn sys_mseal(addr: usize, length: usize, flags: usize) -> AxResult<isize> {
+    debug!("sys_mseal <= addr: {addr:#x}, length: {length:x}, flags: {flags:#x}");
+    if flags != 0 || !PageSize::Size4K.is_aligned(addr) {
+        return Err(AxError::InvalidInput);
+    }
+    let length = length
+        .checked_next_multiple_of(PAGE_SIZE_4K)
+        .filter(|length| addr.checked_add(*length).is_some())
+        .ok_or(AxError::InvalidInput)?;
+    let range = VirtAddrRange::from_start_size(VirtAddr::from(addr), length);
+
+    let curr = current();
+    let proc_data = &curr.as_thread().proc_data;
+    let aspace = proc_data.aspace.lock();
+    if !aspace.can_access_range(range.start, range.size(), MappingFlags::empty()) {
+        return Err(AxError::NoMemory);
+    }
+    if !range.is_empty() {
+        proc_data.sealed.lock().insert(range, ());
+    }
+    Ok(0)
+}
+
+pub fn sys_mincore(addr: usize, length: usize, vec: *mut u8) -> AxResult<isize> {
+    debug!("sys_mincore <= addr: {addr:#x}, length: {length:x}");
+    if !PageSize::Size4K.is_aligned(addr) {
+        return Err(AxError::InvalidInput);
+    }
+    let length = length
+        .checked_next_multiple_of(PAGE_SIZE_4K)
+        .ok_or(AxError::NoMemory)?;
+    let start = VirtAddr::from(addr);
+
+    let curr = current();
+    let aspace = curr.as_thread().proc_data.aspace.lock();
+    if !aspace.can_access_range(start, length, MappingFlags::empty()) {
+        return Err(AxError::NoMemory);
+    }
+    // A page is resident if it is mapped in the page table
+    let resident = (0..length / PAGE_SIZE_4K)
+        .map(|i| {
+            let vaddr = start + i * PAGE_SIZE_4K;
+            aspace.page_table().query(vaddr).is_ok() as u8
+        })
+        .collect::<Vec<_>>();
+    drop(aspace);
+    vm_write_slice(vec, &resident)?;
     Ok(0)
 }
 
 pub fn sys_msync(addr: usize, length: usize, flags: u32) -> AxResult<isize> {
     debug!("sys_msync <= addr: {addr:#x}, length: {length:x}, flags: {flags:#x}");
 
+    if flags & MS_SYNC != 0 {
+        let range = VirtAddrRange::from_start_size(addr.into(), align_up_4k(length));
+        let files = current()
+            .as_thread()
+            .proc_data
+            .shared_file_maps
+            .lock()
+            .overlapping(range);
+        for (_, id) in files {
+            writeback::flush(id)?;
+        }
+    }
     Ok(0)
 }
 
+/// Returns the pages covering `length` bytes at `addr`, which must all be
+/// mapped.
+fn mapped_pages(aspace: &AddrSpace, addr: usize, length: usize) -> AxResult<VirtAddrRange> {
+    let end = addr.checked_add(length).ok_or(AxError::NoMemory)?;
+    let range = VirtAddrRange::new(
+        VirtAddr::from(addr).align_down_4k(),
+        VirtAddr::from(end).align_up_4k(),
+    );
+    if !aspace.can_access_range(range.start, range.size(), MappingFlags::empty()) {
+        return Err(AxError::NoMemory);
+    }
+    Ok(range)
+}
+
 pub fn sys_mlock(addr: usize, length: usize) -> AxResult<isize> {
     sys_mlock2(addr, length, 0)
 }
 
-pub fn sys_mlock2(_addr: usize, _length: usize, _flags: u32) -> AxResult<isize> {
+pub fn sys_mlock2(addr: usize, length: usize, flags: u32) -> AxResult<isize> {
+    debug!("sys_mlock2 <= addr: {addr:#x}, length: {length:x}, flags: {flags:#x}");
+    if flags & !MLOCK_ONFAULT != 0 {
+        return Err(AxError::InvalidInput);
+    }
+
+    let curr = current();
+    let proc_data = &curr.as_thread().proc_data;
+    let mut aspace = proc_data.aspace.lock();
+    let range = mapped_pages(&aspace, addr, length)?;
+
+    let mut mlocked = proc_data.mlocked.lock();
+    let mut locked = mlocked.clone();
+    locked.insert(range, ());
+    if !proc_data.cred.read().is_privileged() {
+        let limit = proc_data.rlim.read()[RLIMIT_MEMLOCK].current;
+        if limit == 0 {
+            return Err(AxError::OperationNotPermitted);
+        }
+        if locked.total_size() as u64 > limit {
+            return Err(AxError::NoMemory);
+        }
+    }
+
+    // Fault the pages in now, breaking copy-on-write of writable ones, unless
+    // they are to be locked as they are faulted in.
+    if flags & MLOCK_ONFAULT == 0 {
+        let areas = aspace
+            .areas()
+            .filter(|area| area.start() < range.end && area.end() > range.start)
+            .map(|area| {
+                let start = area.start().max(range.start);
+                let end = area.end().min(range.end);
+                (start, end - start, area.flags())
+            })
+            .collect::<Vec<_>>();
+        for (start, size, flags) in areas {
+            let access = flags & (MappingFlags::READ | MappingFlags::WRITE);
+            if !access.is_empty() {
+                aspace.populate_area(start, size, access)?;
+            }
+        }
+    }
+    *mlocked = locked;
+    Ok(0)
+}
+
+pub fn sys_munlock(addr: usize, length: usize) -> AxResult<isize> {
+    debug!("sys_munlock <= addr: {addr:#x}, length: {length:x}");
+    let curr = current();
+    let proc_data = &curr.as_thread().proc_data;
+    let aspace = proc_data.aspace.lock();
+    let range = mapped_pages(&aspace, addr, length)?;
+    proc_data.mlocked.lock().remove(range);
     Ok(0)
 }
@@ -1,21 +1,32 @@
-use alloc::{boxed::Box, vec::Vec};
+use alloc::{boxed::Box, vec, vec::Vec};
 use core::net::Ipv4Addr;
 
 use axerrno::{AxError, AxResult};
+use axhal::time::monotonic_time;
 use axio::{Buf, BufMut};
 use axnet::{CMsgData, RecvFlags, RecvOptions, SendFlags, SendOptions, SocketAddrEx, SocketOps};
-use linux_raw_sys::net::{
-    MSG_PEEK, MSG_TRUNC, SCM_RIGHTS, SOL_SOCKET, cmsghdr, msghdr, sockaddr, socklen_t,
+use axpoll::{IoEvents, Pollable};
+use linux_raw_sys::{
+    general::timespec,
+    net::{
+        MSG_DONTWAIT, MSG_PEEK, MSG_TRUNC, MSG_WAITFORONE, SCM_RIGHTS, SOL_SOCKET, cmsghdr,
+        mmsghdr, msghdr, sockaddr, socklen_t,
+    },
 };
 
 use crate::{
-    file::{FileLike, Socket, add_file_like},
+    file::{FileLike, NetlinkSocket, Socket, add_file_like, get_file_like},
     io::{IoVec, IoVectorBuf},
-    mm::{UserConstPtr, UserPtr, VmBytes, VmBytesMut},
-    socket::SocketAddrExt,
+    mm::{UserConstPtr, UserPtr, VmBytes, VmBytesMut, nullable},
+    netif,
+    socket::{NetlinkAddr, SocketAddrExt},
     syscall::net::{CMsg, CMsgBuilder},
+    time::TimeValueLike,
 };
 
+/// Maximum number of messages handled by a single `sendmmsg`/`recvmmsg`.
+const UIO_MAXIOV: u32 = 1024;
+
 fn send_impl(
     fd: i32,
     mut src: impl Buf,
@@ -24,6 +35,16 @@ fn send_impl(
     addrlen: socklen_t,
     cmsg: Vec<CMsgData>,
 ) -> AxResult<isize> {
+    if let Ok(socket) = NetlinkSocket::from_fd(fd) {
+        let addr = if addr.is_null() || addrlen == 0 {
+            None
+        } else {
+            Some(NetlinkAddr::read_from_user(addr, addrlen)?)
+        };
+        debug!("sys_send <= fd: {fd}, flags: {flags}, addr: {addr:?}");
+        return socket.send(&mut src, addr).map(|n| n as isize);
+    }
+
     let addr = if addr.is_null() || addrlen == 0 {
         None
     } else {
@@ -33,6 +54,8 @@ fn send_impl(
     debug!("sys_send <= fd: {fd}, flags: {flags}, addr: {addr:?}");
 
     let socket = Socket::from_fd(fd)?;
+    socket.check_route(addr.as_ref())?;
+    let iface = socket.traffic_iface(addr.as_ref());
     let sent = socket.send(
         &mut src,
         SendOptions {
@@ -41,6 +64,9 @@ fn send_impl(
             cmsg,
         },
     )?;
+    if let Some(index) = iface {
+        netif::account_socket(index, sent, true);
+    }
 
     Ok(sent as isize)
 }
@@ -91,6 +117,16 @@ fn recv_impl(
 ) -> AxResult<isize> {
     debug!("sys_recv <= fd: {fd}, flags: {flags}");
 
+    if let Ok(socket) = NetlinkSocket::from_fd(fd) {
+        let (recv, remote_addr) =
+            socket.recv(&mut dst, flags & MSG_PEEK != 0, flags & MSG_TRUNC != 0)?;
+        if !addr.is_null() {
+            remote_addr.write_to_user(addr, addrlen.get_as_mut()?)?;
+        }
+        debug!("sys_recv => fd: {fd}, recv: {recv}");
+        return Ok(recv as isize);
+    }
+
     let socket = Socket::from_fd(fd)?;
     let mut recv_flags = RecvFlags::empty();
     if flags & MSG_PEEK != 0 {
@@ -100,20 +136,49 @@ fn recv_impl(
         recv_flags |= RecvFlags::TRUNCATE;
     }
 
+    if (socket.bound_device().is_some() || socket.filter().is_some())
+        && matches!(**socket, axnet::Socket::Udp(_))
+    {
+        // Drop datagrams that did not arrive on the bound interface or are
+        // rejected by the socket filter.
+        let mut payload = vec![0u8; socket.filter_peek_len()];
+        loop {
+            let mut from = SocketAddrEx::Ip((Ipv4Addr::UNSPECIFIED, 0).into());
+            // Peek the start of the datagram, along with its whole length.
+            let len = socket.recv(
+                &mut payload.as_mut_slice(),
+                RecvOptions {
+                    from: Some(&mut from),
+                    flags: RecvFlags::PEEK | RecvFlags::TRUNCATE,
+                    cmsg: None,
+                },
+            )?;
+            let peeked = &payload[..len.min(payload.len())];
+            if socket.device_allows(&from) && socket.filter_accepts(&from, peeked, len) {
+                break;
+            }
+            socket.recv(&mut [0u8; 0].as_mut_slice(), RecvOptions::default())?;
+        }
+    }
+
     let mut cmsg = Vec::new();
 
-    let mut remote_addr =
-        (!addr.is_null()).then(|| SocketAddrEx::Ip((Ipv4Addr::UNSPECIFIED, 0).into()));
+    let mut remote_addr = SocketAddrEx::Ip((Ipv4Addr::UNSPECIFIED, 0).into());
     let recv = socket.recv(
         &mut dst,
         RecvOptions {
-            from: remote_addr.as_mut(),
+            from: Some(&mut remote_addr),
             flags: recv_flags,
             cmsg: Some(&mut cmsg),
         },
     )?;
 
-    if let Some(remote_addr) = remote_addr {
+    if !recv_flags.contains(RecvFlags::PEEK)
+        && let Some(index) = socket.traffic_iface(Some(&remote_addr))
+    {
+        netif::account_socket(index, recv, false);
+    }
+    if !addr.is_null() {
         remote_addr.write_to_user(addr, addrlen.get_as_mut()?)?;
     }
 
@@ -172,3 +237,60 @@ pub fn sys_recvmsg(fd: i32, msg: UserPtr<msghdr>, flags: u32) -> AxResult<isize>
         }),
     )
 }
+
+pub fn sys_sendmmsg(fd: i32, msgvec: UserPtr<mmsghdr>, vlen: u32, flags: u32) -> AxResult<isize> {
+    debug!("sys_sendmmsg <= fd: {fd}, vlen: {vlen}, flags: {flags}");
+    let msgvec = msgvec.get_as_mut_slice(vlen.min(UIO_MAXIOV) as usize)?;
+    let mut sent = 0;
+    for msg in msgvec {
+        match sys_sendmsg(fd, UserConstPtr::from(&msg.msg_hdr as *const msghdr), flags) {
+            Ok(len) => msg.msg_len = len as _,
+            // Report the error only if nothing was sent.
+            Err(err) if sent == 0 => return Err(err),
+            Err(_) => break,
+        }
+        sent += 1;
+    }
+    Ok(sent)
+}
+
+pub fn sys_recvmmsg(
+    fd: i32,
+    msgvec: UserPtr<mmsghdr>,
+    vlen: u32,
+    flags: u32,
+    timeout: UserConstPtr<timespec>,
+) -> AxResult<isize> {
+    debug!("sys_recvmmsg <= fd: {fd}, vlen: {vlen}, flags: {flags}");
+    let deadline = nullable!(timeout.get_as_ref())?
+        .map(|ts| ts.try_into_time_value())
+        .transpose()?
+        .map(|timeout| monotonic_time() + timeout);
+    let file = get_file_like(fd)?;
+    let msgvec = msgvec.get_as_mut_slice(vlen.min(UIO_MAXIOV) as usize)?;
+
+    let mut received = 0;
+    for msg in msgvec {
+        // After the first message, only take what is already queued if the
+        // caller asked not to wait.
+        let dont_wait = flags & MSG_DONTWAIT != 0 || (received > 0 && flags & MSG_WAITFORONE != 0);
+        if dont_wait && !file.poll().contains(IoEvents::IN) {
+            if received == 0 {
+                return Err(AxError::WouldBlock);
+            }
+            break;
+        }
+        match sys_recvmsg(fd, UserPtr::from(&mut msg.msg_hdr as *mut msghdr), flags) {
+            Ok(len) => msg.msg_len = len as _,
+            // Report the error only if nothing was received.
+            Err(err) if received == 0 => return Err(err),
+            Err(_) => break,
+        }
+        received += 1;
+        // Like Linux, the timeout is only checked after each message.
+        if deadline.is_some_and(|deadline| monotonic_time() >= deadline) {
+            break;
+        }
+    }
+    Ok(received)
+}
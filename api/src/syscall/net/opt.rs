@@ -1,9 +1,15 @@
+use alloc::sync::Arc;
+use core::time::Duration;
+
 use axerrno::{AxError, AxResult, LinuxError};
 use axnet::options::{Configurable, GetSocketOption, SetSocketOption};
-use linux_raw_sys::net::socklen_t;
+use linux_raw_sys::net::{
+    SO_ATTACH_FILTER, SO_BINDTODEVICE, SO_DETACH_FILTER, SO_LINGER, SOL_SOCKET, linger, socklen_t,
+};
 
 use crate::{
-    file::{FileLike, Socket},
+    file::{FileLike, NetlinkSocket, Socket},
+    filter::SocketFilter,
     mm::{UserConstPtr, UserPtr},
 };
 
@@ -11,6 +17,8 @@ const PROTO_TCP: u32 = linux_raw_sys::net::IPPROTO_TCP as u32;
 
 const PROTO_IP: u32 = linux_raw_sys::net::IPPROTO_IP as u32;
 
+const PROTO_IPV6: u32 = linux_raw_sys::net::IPPROTO_IPV6 as u32;
+
 mod conv {
     use axerrno::{AxError, AxResult};
     use axnet::options::UnixCredentials;
@@ -42,6 +50,25 @@ mod conv {
         }
     }
 
+    /// A hop limit, where -1 selects the default.
+    pub struct HopLimit;
+
+    impl HopLimit {
+        const DEFAULT: u8 = 64;
+
+        pub fn sys_to_rust(val: i32) -> AxResult<u8> {
+            match val {
+                -1 => Ok(Self::DEFAULT),
+                1..=255 => Ok(val as u8),
+                _ => Err(AxError::InvalidInput),
+            }
+        }
+
+        pub fn rust_to_sys(val: u8) -> AxResult<i32> {
+            Ok(val as _)
+        }
+    }
+
     pub struct Duration;
 
     impl Duration {
@@ -97,7 +124,8 @@ macro_rules! call_dispatch {
             (PROTO_TCP, TCP_MAXSEG) => MaxSegment as Int<usize>,
             (PROTO_TCP, TCP_INFO) => TcpInfo,
 
-            (PROTO_IP, IP_TTL) => Ttl as Int<u8>,
+            (PROTO_IP, IP_TTL) => Ttl as HopLimit,
+            (PROTO_IPV6, IPV6_UNICAST_HOPS) => Ttl as HopLimit,
         }
     }};
     ($dispatch:ident, $in:expr, $($pat:pat => $which:ident $(as $conv:ty)?),* $(,)?) => {
@@ -137,18 +165,42 @@ pub fn sys_getsockopt(
         val.cast().get_as_mut()
     }
 
+    if NetlinkSocket::from_fd(fd).is_ok() {
+        return Err(AxError::Other(LinuxError::ENOPROTOOPT));
+    }
+
     let socket = Socket::from_fd(fd)?;
-    macro_rules! dispatch {
-        ($which:ident) => {
-            socket.get_option(GetSocketOption::$which(get(optval, optlen)?))?;
-        };
-        ($which:ident as $conv:ty) => {
-            let mut val = Default::default();
-            socket.get_option(GetSocketOption::$which(&mut val))?;
-            *get(optval, optlen)? = <$conv>::rust_to_sys(val)?;
-        };
+    match (level, optname) {
+        (SOL_SOCKET, SO_LINGER) => {
+            let timeout = socket.linger();
+            *get(optval, optlen)? = linger {
+                l_onoff: timeout.is_some() as _,
+                l_linger: timeout.map_or(0, |it| it.as_secs() as _),
+            };
+        }
+        (SOL_SOCKET, SO_BINDTODEVICE) => {
+            let device = socket.bound_device().unwrap_or_default();
+            let len = (*optlen as usize).min(device.len() + 1);
+            let buf = optval.get_as_mut_slice(len)?;
+            buf.fill(0);
+            let copied = len.min(device.len());
+            buf[..copied].copy_from_slice(&device.as_bytes()[..copied]);
+            *optlen = len as socklen_t;
+        }
+        _ => {
+            macro_rules! dispatch {
+                ($which:ident) => {
+                    socket.get_option(GetSocketOption::$which(get(optval, optlen)?))?;
+                };
+                ($which:ident as $conv:ty) => {
+                    let mut val = Default::default();
+                    socket.get_option(GetSocketOption::$which(&mut val))?;
+                    *get(optval, optlen)? = <$conv>::rust_to_sys(val)?;
+                };
+            }
+            call_dispatch!(dispatch, (level, optname));
+        }
     }
-    call_dispatch!(dispatch, (level, optname));
 
     Ok(0)
 }
@@ -176,17 +228,61 @@ pub fn sys_setsockopt(
         val.cast().get_as_ref()
     }
 
-    let socket = Socket::from_fd(fd)?;
-    macro_rules! dispatch {
-        ($which:ident) => {
-            socket.set_option(SetSocketOption::$which(get(optval, optlen)?))?;
-        };
-        ($which:ident as $conv:ty) => {
-            let mut val = <$conv>::sys_to_rust(*get(optval, optlen)?)?;
-            socket.set_option(SetSocketOption::$which(&mut val))?;
+    if NetlinkSocket::from_fd(fd).is_ok() {
+        if level == SOL_SOCKET && matches!(optname, SO_ATTACH_FILTER | SO_DETACH_FILTER) {
+            return Err(AxError::OperationNotSupported);
+        }
+        // Buffer sizes and credential passing are accepted but have no effect
+        // on netlink sockets.
+        return if level == SOL_SOCKET {
+            Ok(0)
+        } else {
+            Err(AxError::Other(LinuxError::ENOPROTOOPT))
         };
     }
-    call_dispatch!(dispatch, (level, optname));
+
+    let socket = Socket::from_fd(fd)?;
+    match (level, optname) {
+        (SOL_SOCKET, SO_ATTACH_FILTER) => {
+            let filter = SocketFilter::from_user(optval, optlen)?;
+            socket.set_filter(Some(Arc::new(filter)))?;
+        }
+        (SOL_SOCKET, SO_DETACH_FILTER) => {
+            if socket.filter().is_none() {
+                return Err(AxError::NotFound);
+            }
+            socket.set_filter(None)?;
+        }
+        (SOL_SOCKET, SO_LINGER) => {
+            let val = get::<linger>(optval, optlen)?;
+            let timeout =
+                (val.l_onoff != 0).then(|| Duration::from_secs(val.l_linger.max(0) as u64));
+            // A zero timeout asks for an abortive close, but axnet can't
+            // reset a connection.
+            if timeout.is_some_and(|it| it.is_zero()) && matches!(**socket, axnet::Socket::Tcp(_)) {
+                return Err(AxError::OperationNotSupported);
+            }
+            socket.set_linger(timeout);
+        }
+        (SOL_SOCKET, SO_BINDTODEVICE) => {
+            let name = optval.get_as_slice(optlen as usize)?;
+            let len = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+            let name = core::str::from_utf8(&name[..len]).map_err(|_| AxError::InvalidInput)?;
+            socket.bind_device(name)?;
+        }
+        _ => {
+            macro_rules! dispatch {
+                ($which:ident) => {
+                    socket.set_option(SetSocketOption::$which(get(optval, optlen)?))?;
+                };
+                ($which:ident as $conv:ty) => {
+                    let mut val = <$conv>::sys_to_rust(*get(optval, optlen)?)?;
+                    socket.set_option(SetSocketOption::$which(&mut val))?;
+                };
+            }
+            call_dispatch!(dispatch, (level, optname));
+        }
+    }
 
     Ok(0)
 }
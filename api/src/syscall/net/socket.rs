@@ -11,21 +11,33 @@ use axtask::current;
 use linux_raw_sys::{
     general::{O_CLOEXEC, O_NONBLOCK},
     net::{
-        AF_INET, AF_UNIX, AF_VSOCK, IPPROTO_TCP, IPPROTO_UDP, SHUT_RD, SHUT_RDWR, SHUT_WR,
-        SOCK_DGRAM, SOCK_SEQPACKET, SOCK_STREAM, sockaddr, socklen_t,
+        AF_INET, AF_NETLINK, AF_UNIX, AF_VSOCK, IPPROTO_TCP, IPPROTO_UDP, SHUT_RD, SHUT_RDWR,
+        SHUT_WR, SOCK_DGRAM, SOCK_RAW, SOCK_SEQPACKET, SOCK_STREAM, sockaddr, socklen_t,
     },
 };
 use starry_core::task::AsThread;
 
 use crate::{
-    file::{FileLike, Socket},
+    file::{FileLike, NetlinkSocket, Socket},
     mm::{UserConstPtr, UserPtr},
-    socket::SocketAddrExt,
+    socket::{NetlinkAddr, SocketAddrExt},
 };
 
 pub fn sys_socket(domain: u32, raw_ty: u32, proto: u32) -> AxResult<isize> {
     debug!("sys_socket <= domain: {domain}, ty: {raw_ty}, proto: {proto}");
     let ty = raw_ty & 0xFF;
+    let cloexec = raw_ty & O_CLOEXEC != 0;
+
+    if domain == AF_NETLINK {
+        if ty != SOCK_RAW && ty != SOCK_DGRAM {
+            return Err(AxError::Other(LinuxError::ESOCKTNOSUPPORT));
+        }
+        let socket = NetlinkSocket::new(proto)?;
+        if raw_ty & O_NONBLOCK != 0 {
+            socket.set_nonblocking(true)?;
+        }
+        return socket.add_to_fd_table(cloexec).map(|fd| fd as isize);
+    }
 
     let pid = current().as_thread().proc_data.proc.pid();
     let socket = match (domain, ty) {
@@ -55,17 +67,23 @@ pub fn sys_socket(domain: u32, raw_ty: u32, proto: u32) -> AxResult<isize> {
             return Err(AxError::Other(LinuxError::EAFNOSUPPORT));
         }
     };
-    let socket = Socket(socket);
+    let socket = Socket::new(socket);
 
     if raw_ty & O_NONBLOCK != 0 {
         socket.set_nonblocking(true)?;
     }
-    let cloexec = raw_ty & O_CLOEXEC != 0;
 
     socket.add_to_fd_table(cloexec).map(|fd| fd as isize)
 }
 
 pub fn sys_bind(fd: i32, addr: UserConstPtr<sockaddr>, addrlen: u32) -> AxResult<isize> {
+    if let Ok(socket) = NetlinkSocket::from_fd(fd) {
+        let addr = NetlinkAddr::read_from_user(addr, addrlen)?;
+        debug!("sys_bind <= fd: {fd}, addr: {addr:?}");
+        socket.bind(addr)?;
+        return Ok(0);
+    }
+
     let addr = SocketAddrEx::read_from_user(addr, addrlen)?;
     debug!("sys_bind <= fd: {fd}, addr: {addr:?}");
 
@@ -78,7 +96,9 @@ pub fn sys_connect(fd: i32, addr: UserConstPtr<sockaddr>, addrlen: u32) -> AxRes
     let addr = SocketAddrEx::read_from_user(addr, addrlen)?;
     debug!("sys_connect <= fd: {fd}, addr: {addr:?}");
 
-    Socket::from_fd(fd)?.connect(addr).map_err(|e| {
+    let socket = Socket::from_fd(fd)?;
+    socket.check_route(Some(&addr))?;
+    socket.connect(addr).map_err(|e| {
         if e == AxError::WouldBlock {
             AxError::InProgress
         } else {
@@ -120,7 +140,7 @@ pub fn sys_accept4(
     let cloexec = flags & O_CLOEXEC != 0;
 
     let socket = Socket::from_fd(fd)?;
-    let socket = Socket(socket.accept()?);
+    let socket = Socket::new(socket.accept()?);
     if flags & O_NONBLOCK != 0 {
         socket.set_nonblocking(true)?;
     }
@@ -177,8 +197,8 @@ pub fn sys_socketpair(
             return Err(AxError::Other(LinuxError::ESOCKTNOSUPPORT));
         }
     };
-    let sock1 = Socket(axnet::Socket::Unix(sock1));
-    let sock2 = Socket(axnet::Socket::Unix(sock2));
+    let sock1 = Socket::new(axnet::Socket::Unix(sock1));
+    let sock2 = Socket::new(axnet::Socket::Unix(sock2));
 
     if raw_ty & O_NONBLOCK != 0 {
         sock1.set_nonblocking(true)?;
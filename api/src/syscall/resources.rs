@@ -1,3 +1,5 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+
 use axerrno::{AxError, AxResult};
 use axhal::time::TimeValue;
 use axtask::current;
@@ -53,12 +55,18 @@ pub fn sys_prlimit64(
 struct Rusage {
     utime: TimeValue,
     stime: TimeValue,
+    minflt: u64,
+    majflt: u64,
 }
 
 impl Rusage {
     fn from_thread(thread: &Thread) -> Self {
         let (utime, stime) = thread.time.borrow().output();
-        Self { utime, stime }
+        Self {
+            utime,
+            stime,
+            ..Default::default()
+        }
     }
 
     fn collate(mut self, other: Rusage) -> Self {
@@ -66,6 +74,12 @@ impl Rusage {
         self.stime += other.stime;
         self
     }
+
+    fn with_faults(mut self, minflt: &AtomicU64, majflt: &AtomicU64) -> Self {
+        self.minflt = minflt.load(Ordering::Relaxed);
+        self.majflt = majflt.load(Ordering::Relaxed);
+        self
+    }
 }
 
 impl From<Rusage> for rusage {
@@ -74,6 +88,8 @@ impl From<Rusage> for rusage {
         let mut usage: rusage = unsafe { core::mem::zeroed() };
         usage.ru_utime = __kernel_old_timeval::from_time_value(value.utime);
         usage.ru_stime = __kernel_old_timeval::from_time_value(value.stime);
+        usage.ru_minflt = value.minflt as _;
+        usage.ru_majflt = value.majflt as _;
         usage
     }
 }
@@ -118,6 +134,13 @@ pub fn sys_getrusage(who: i32, usage: *mut rusage) -> AxResult<isize> {
         RUSAGE_THREAD => Rusage::from_thread(thr),
         _ => return Err(AxError::InvalidInput),
     };
+    // Page faults are only counted per process
+    let faults = &thr.proc_data.faults;
+    let result = match who {
+        RUSAGE_SELF => result.with_faults(&faults.minflt, &faults.majflt),
+        RUSAGE_CHILDREN => result.with_faults(&faults.cminflt, &faults.cmajflt),
+        _ => result,
+    };
     usage.vm_write(result.into())?;
 
     Ok(0)
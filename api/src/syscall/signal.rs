@@ -251,6 +251,7 @@ pub fn sys_rt_sigtimedwait(
         }
     });
 
+    let _wchan = thr.wait_in(sys_rt_sigtimedwait as usize);
     let Ok(sig) = block_on(future::timeout(timeout, fut)) else {
         // Timeout
         signal.set_blocked(old_blocked);
@@ -283,6 +284,7 @@ pub fn sys_rt_sigsuspend(
 
     uctx.set_retval(-LinuxError::EINTR.code() as usize);
 
+    let _wchan = thr.wait_in(sys_rt_sigsuspend as usize);
     block_on(poll_fn(|context| {
         if check_signals(thr, uctx, Some(old_blocked)) {
             return Poll::Ready(());
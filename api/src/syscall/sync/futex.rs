@@ -66,6 +66,7 @@ pub fn sys_futex(
                 u32::MAX
             };
 
+            let _wchan = thr.wait_in(sys_futex as usize);
             if !futex
                 .wq
                 .wait_if(bitset, timeout, || uaddr.vm_read() == Ok(value))?
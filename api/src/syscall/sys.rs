@@ -4,36 +4,45 @@ use core::ffi::c_char;
 use axconfig::ARCH;
 use axerrno::{AxError, AxResult};
 use axfs_ng::FS_CONTEXT;
+use axtask::current;
 use linux_raw_sys::{
     general::{GRND_INSECURE, GRND_NONBLOCK, GRND_RANDOM},
     system::{new_utsname, sysinfo},
 };
-use starry_core::task::processes;
+use starry_core::task::{AsThread, processes};
 use starry_vm::{VmMutPtr, vm_write_slice};
 
 pub fn sys_getuid() -> AxResult<isize> {
-    Ok(0)
+    Ok(current().as_thread().proc_data.cred.read().uid.real as _)
 }
 
 pub fn sys_geteuid() -> AxResult<isize> {
-    Ok(0)
+    Ok(current().as_thread().proc_data.cred.read().uid.effective as _)
 }
 
 pub fn sys_getgid() -> AxResult<isize> {
-    Ok(0)
+    Ok(current().as_thread().proc_data.cred.read().gid.real as _)
 }
 
 pub fn sys_getegid() -> AxResult<isize> {
-    Ok(0)
+    Ok(current().as_thread().proc_data.cred.read().gid.effective as _)
 }
 
-pub fn sys_setuid(_uid: u32) -> AxResult<isize> {
-    debug!("sys_setuid <= uid: {_uid}");
+pub fn sys_setuid(uid: u32) -> AxResult<isize> {
+    debug!("sys_setuid <= uid: {uid}");
+    let curr = current();
+    let mut cred = curr.as_thread().proc_data.cred.write();
+    let privileged = cred.is_privileged();
+    cred.uid.set(uid, privileged)?;
     Ok(0)
 }
 
-pub fn sys_setgid(_gid: u32) -> AxResult<isize> {
-    debug!("sys_setgid <= gid: {_gid}");
+pub fn sys_setgid(gid: u32) -> AxResult<isize> {
+    debug!("sys_setgid <= gid: {gid}");
+    let curr = current();
+    let mut cred = curr.as_thread().proc_data.cred.write();
+    let privileged = cred.is_privileged();
+    cred.gid.set(gid, privileged)?;
     Ok(0)
 }
 
@@ -1,23 +1,26 @@
 use alloc::sync::Arc;
 
-use axerrno::{AxError, AxResult};
+use axerrno::{AxError, AxResult, LinuxError};
 use axfs_ng::FS_CONTEXT;
-use axhal::uspace::UserContext;
+use axhal::{paging::PageSize, uspace::UserContext};
 use axtask::{TaskExtProxy, current, spawn_task};
 use bitflags::bitflags;
 use kspin::SpinNoIrq;
 use linux_raw_sys::general::*;
 use starry_core::{
-    mm::copy_from_kernel,
-    task::{AsThread, ProcessData, Thread, add_task_to_table},
+    cgroup::Cgroup,
+    mm::{copy_from_kernel, share_mappings},
+    task::{AsThread, ProcessData, Thread, add_task_to_table, check_task_limits, processes},
 };
 use starry_process::Pid;
 use starry_signal::Signo;
+use starry_vm::vm_load;
 
 use crate::{
-    file::{FD_TABLE, FileLike, PidFd},
-    mm::UserPtr,
+    file::{Directory, FD_TABLE, FileLike, PidFd},
+    mm::{UserPtr, check_commit, committed_size},
     task::new_user_task,
+    vfs::cgroup::cgroup_of,
 };
 
 bitflags! {
@@ -85,6 +88,21 @@ bitflags! {
     }
 }
 
+/// The arguments of a clone, shared by [`sys_clone`] and [`sys_clone3`].
+struct CloneArgs {
+    flags: CloneFlags,
+    exit_signal: u32,
+    /// The stack pointer of the child, or 0 to keep the parent's.
+    stack: usize,
+    parent_tid: usize,
+    child_tid: usize,
+    tls: usize,
+    /// Where to store the pidfd with `CLONE_PIDFD`.
+    pidfd: usize,
+    /// The cgroup of the child with `CLONE_INTO_CGROUP`.
+    cgroup: Option<Arc<Cgroup>>,
+}
+
 pub fn sys_clone(
     uctx: &UserContext,
     flags: u32,
@@ -96,24 +114,157 @@ pub fn sys_clone(
 ) -> AxResult<isize> {
     const FLAG_MASK: u32 = 0xff;
     let exit_signal = flags & FLAG_MASK;
-    let mut flags = CloneFlags::from_bits_truncate(flags & !FLAG_MASK);
-    if flags.contains(CloneFlags::VFORK) {
-        debug!("sys_clone: CLONE_VFORK slow path");
-        flags.remove(CloneFlags::VM);
-    }
+    let flags = CloneFlags::from_bits_truncate(flags & !FLAG_MASK);
 
     debug!(
         "sys_clone <= flags: {flags:?}, exit_signal: {exit_signal}, stack: {stack:#x}, ptid: \
          {parent_tid:#x}, ctid: {child_tid:#x}, tls: {tls:#x}"
     );
 
-    if exit_signal != 0 && flags.contains(CloneFlags::THREAD | CloneFlags::PARENT) {
+    // The pidfd is returned through `parent_tid`, so both can't be used.
+    if flags.contains(CloneFlags::PIDFD | CloneFlags::PARENT_SETTID) {
         return Err(AxError::InvalidInput);
     }
-    if flags.contains(CloneFlags::THREAD) && !flags.contains(CloneFlags::VM | CloneFlags::SIGHAND) {
+    do_clone(
+        uctx,
+        CloneArgs {
+            flags,
+            exit_signal,
+            stack,
+            parent_tid,
+            child_tid,
+            tls,
+            pidfd: parent_tid,
+            cgroup: None,
+        },
+    )
+}
+
+const CLONE_ARGS_SIZE_VER0: usize = 64;
+const CLONE_ARGS_SIZE_VER2: usize = 88;
+const CLONE_INTO_CGROUP: u64 = 0x2_0000_0000;
+
+pub fn sys_clone3(uctx: &UserContext, args: *const u8, size: usize) -> AxResult<isize> {
+    if size < CLONE_ARGS_SIZE_VER0 {
         return Err(AxError::InvalidInput);
     }
-    if flags.contains(CloneFlags::PIDFD | CloneFlags::PARENT_SETTID) {
+    if size > PageSize::Size4K as usize {
+        return Err(AxError::Other(LinuxError::E2BIG));
+    }
+    let bytes = vm_load(args, size)?;
+    // Fields unknown to this kernel must be left zero.
+    if bytes
+        .get(CLONE_ARGS_SIZE_VER2..)
+        .is_some_and(|it| it.iter().any(|&b| b != 0))
+    {
+        return Err(AxError::Other(LinuxError::E2BIG));
+    }
+    // Fields of older versions of the struct are zero.
+    let mut fields = [0u64; CLONE_ARGS_SIZE_VER2 / 8];
+    for (field, chunk) in fields.iter_mut().zip(bytes.chunks_exact(8)) {
+        *field = u64::from_ne_bytes(chunk.try_into().unwrap());
+    }
+    let [
+        flags,
+        pidfd,
+        child_tid,
+        parent_tid,
+        exit_signal,
+        stack,
+        stack_size,
+        tls,
+        _set_tid,
+        set_tid_size,
+        cgroup,
+    ] = fields;
+
+    debug!(
+        "sys_clone3 <= flags: {flags:#x}, exit_signal: {exit_signal}, stack: {stack:#x}, \
+         stack_size: {stack_size:#x}, ptid: {parent_tid:#x}, ctid: {child_tid:#x}, tls: {tls:#x}, \
+         set_tid_size: {set_tid_size}, cgroup: {cgroup}"
+    );
+
+    // The exit signal has its own field, and isn't part of the flags.
+    if flags & !(u64::from(u32::MAX) | CLONE_INTO_CGROUP) != 0
+        || flags & 0xff != 0
+        || exit_signal > 64
+    {
+        return Err(AxError::InvalidInput);
+    }
+    let clone_flags = CloneFlags::from_bits(flags as u32).ok_or(AxError::InvalidInput)?;
+    if (stack == 0) != (stack_size == 0) {
+        return Err(AxError::InvalidInput);
+    }
+    // The stack grows down from the end of the given area.
+    let stack = stack.checked_add(stack_size).ok_or(AxError::InvalidInput)?;
+
+    // Choosing the TID with `set_tid` isn't supported: TIDs are the IDs the
+    // scheduler allocates to tasks, and a specific one can't be requested.
+    if set_tid_size != 0 {
+        return Err(AxError::OperationNotSupported);
+    }
+    let cgroup = if flags & CLONE_INTO_CGROUP != 0 {
+        let cgroup = cgroup_of(Directory::from_fd(cgroup as _)?.inner())?;
+        cgroup.check_attach()?;
+        Some(cgroup)
+    } else {
+        None
+    };
+
+    do_clone(
+        uctx,
+        CloneArgs {
+            flags: clone_flags,
+            exit_signal: exit_signal as _,
+            stack: stack as _,
+            parent_tid: parent_tid as _,
+            child_tid: child_tid as _,
+            tls: tls as _,
+            pidfd: pidfd as _,
+            cgroup,
+        },
+    )
+}
+
+/// Checks that the real user of a process may create another process under
+/// its `RLIMIT_NPROC`, failing with `EAGAIN` otherwise.
+fn check_nproc(proc_data: &ProcessData) -> AxResult<()> {
+    let cred = *proc_data.cred.read();
+    // Neither root nor privileged processes are limited
+    if cred.uid.real == 0 || cred.is_privileged() {
+        return Ok(());
+    }
+    let limit = proc_data.rlim.read()[RLIMIT_NPROC].current;
+    let count = processes()
+        .iter()
+        .filter(|it| it.cred.read().uid.real == cred.uid.real)
+        .count();
+    if count as u64 >= limit {
+        return Err(AxError::WouldBlock);
+    }
+    Ok(())
+}
+
+fn do_clone(uctx: &UserContext, args: CloneArgs) -> AxResult<isize> {
+    let CloneArgs {
+        mut flags,
+        exit_signal,
+        stack,
+        parent_tid,
+        child_tid,
+        tls,
+        pidfd,
+        cgroup,
+    } = args;
+    if flags.contains(CloneFlags::VFORK) {
+        debug!("sys_clone: CLONE_VFORK slow path");
+        flags.remove(CloneFlags::VM);
+    }
+
+    if exit_signal != 0 && flags.contains(CloneFlags::THREAD | CloneFlags::PARENT) {
+        return Err(AxError::InvalidInput);
+    }
+    if flags.contains(CloneFlags::THREAD) && !flags.contains(CloneFlags::VM | CloneFlags::SIGHAND) {
         return Err(AxError::InvalidInput);
     }
     let exit_signal = Signo::from_repr(exit_signal as u8);
@@ -134,11 +285,22 @@ pub fn sys_clone(
     };
 
     let curr = current();
+    let sched_attr = curr.as_thread().sched_attr().for_child()?;
     let old_proc_data = &curr.as_thread().proc_data;
+    if !flags.contains(CloneFlags::THREAD) {
+        check_nproc(old_proc_data)?;
+    }
+    // Threads are in the cgroup of their process
+    let cgroup = match cgroup {
+        Some(cgroup) if !flags.contains(CloneFlags::THREAD) => cgroup,
+        _ => old_proc_data.cgroup(),
+    };
+    cgroup.check_new_task()?;
 
     let mut new_task = new_user_task(&curr.name(), new_uctx, set_child_tid);
 
     let tid = new_task.id().as_u64() as Pid;
+    check_task_limits()?;
     if flags.contains(CloneFlags::PARENT_SETTID) {
         *UserPtr::<Pid>::from(parent_tid).get_as_mut()? = tid;
     }
@@ -159,9 +321,16 @@ pub fn sys_clone(
         let aspace = if flags.contains(CloneFlags::VM) {
             old_proc_data.aspace.clone()
         } else {
-            let mut aspace = old_proc_data.aspace.lock();
-            let aspace = aspace.try_clone()?;
-            copy_from_kernel(&mut aspace.lock())?;
+            // The private writable memory copied to the child is committed
+            // too.
+            let size = committed_size(&old_proc_data.aspace.lock());
+            check_commit(size, false)?;
+            let mut old_aspace = old_proc_data.aspace.lock();
+            let aspace = old_aspace.try_clone()?;
+            let mut new_aspace = aspace.lock();
+            copy_from_kernel(&mut new_aspace)?;
+            share_mappings(&old_aspace, &mut new_aspace)?;
+            drop(new_aspace);
             aspace
         };
         new_task
@@ -182,6 +351,24 @@ pub fn sys_clone(
             exit_signal,
         );
         proc_data.set_umask(old_proc_data.umask());
+        *proc_data.rlim.write() = old_proc_data.rlim.read().clone();
+        *proc_data.cred.write() = *old_proc_data.cred.read();
+        *proc_data.home_nodes.lock() = old_proc_data.home_nodes.lock().clone();
+        *proc_data.pkeys.lock() = old_proc_data.pkeys.lock().clone();
+        // Freed pages are still mapped read-only in the copied address space
+        *proc_data.lazy_free.lock() = old_proc_data.lazy_free.lock().clone();
+        *proc_data.anon_mappings.lock() = old_proc_data.anon_mappings.lock().clone();
+        *proc_data.huge_pages.lock() = old_proc_data.huge_pages.lock().clone();
+        *proc_data.sealed.lock() = old_proc_data.sealed.lock().clone();
+        *proc_data.noexec_maps.lock() = old_proc_data.noexec_maps.lock().clone();
+        // The copied pages keep the placed uprobes
+        *proc_data.file_maps.lock() = old_proc_data.file_maps.lock().clone();
+        *proc_data.shared_file_maps.lock() = old_proc_data.shared_file_maps.lock().clone();
+        *proc_data.shared_file_backends.lock() = old_proc_data.shared_file_backends.lock().clone();
+        proc_data.set_cgroup(cgroup);
+        proc_data.enter_time_ns(old_proc_data.time_ns_for_children());
+        *proc_data.keyrings.lock() = old_proc_data.keyrings.lock().for_child();
+        proc_data.set_landlock(old_proc_data.landlock());
 
         {
             let mut scope = proc_data.scope.write();
@@ -210,11 +397,14 @@ pub fn sys_clone(
     new_proc_data.proc.add_thread(tid);
 
     if flags.contains(CloneFlags::PIDFD) {
-        let pidfd = PidFd::new(&new_proc_data);
-        *UserPtr::<i32>::from(parent_tid).get_as_mut()? = pidfd.add_to_fd_table(true)?;
+        let fd = PidFd::new(&new_proc_data).add_to_fd_table(true)?;
+        *UserPtr::<i32>::from(pidfd).get_as_mut()? = fd;
     }
 
     let thr = Thread::new(tid, new_proc_data);
+    // The I/O context and scheduling policy are inherited
+    thr.set_ioprio(curr.as_thread().ioprio());
+    thr.set_sched_attr(sched_attr)?;
     if flags.contains(CloneFlags::CHILD_CLEARTID) {
         thr.set_clear_child_tid(child_tid);
     }
@@ -226,6 +416,24 @@ pub fn sys_clone(
     Ok(tid as _)
 }
 
+pub fn sys_unshare(flags: u32) -> AxResult<isize> {
+    debug!("sys_unshare <= flags: {flags:#x}");
+    // Only time namespaces can be unshared
+    if flags & !CLONE_NEWTIME != 0 {
+        return Err(AxError::InvalidInput);
+    }
+    if flags & CLONE_NEWTIME != 0 {
+        let proc_data = &current().as_thread().proc_data;
+        if !proc_data.cred.read().is_privileged() {
+            return Err(AxError::OperationNotPermitted);
+        }
+        // Like Linux, the caller stays in its namespace and only its children
+        // created afterwards enter the new one.
+        proc_data.set_time_ns_for_children(proc_data.time_ns_for_children().new_child());
+    }
+    Ok(0)
+}
+
 #[cfg(target_arch = "x86_64")]
 pub fn sys_fork(uctx: &UserContext) -> AxResult<isize> {
     sys_clone(uctx, SIGCHLD, 0, 0, 0, 0)
@@ -1,9 +1,14 @@
+use alloc::vec;
 use core::ffi::c_char;
 
 use axerrno::{AxError, AxResult};
 use axtask::current;
 use linux_raw_sys::general::{__user_cap_data_struct, __user_cap_header_struct};
-use starry_core::task::{AsThread, get_process_data};
+use memory_addr::{PAGE_SIZE_4K, VirtAddr, VirtAddrRange, align_up_4k};
+use starry_core::{
+    cred::IdSet,
+    task::{AsThread, get_process_data},
+};
 use starry_vm::{VmMutPtr, VmPtr, vm_write_slice};
 
 use crate::mm::vm_load_string;
@@ -51,26 +56,173 @@ pub fn sys_umask(mask: u32) -> AxResult<isize> {
     Ok(old as isize)
 }
 
-pub fn sys_setreuid(_ruid: u32, _euid: u32) -> AxResult<isize> {
+/// Converts an ID argument, where -1 leaves the ID unchanged.
+fn id_arg(id: u32) -> Option<u32> {
+    (id != u32::MAX).then_some(id)
+}
+
+fn write_ids(ids: IdSet, real: *mut u32, effective: *mut u32, saved: *mut u32) -> AxResult<()> {
+    real.vm_write(ids.real)?;
+    effective.vm_write(ids.effective)?;
+    saved.vm_write(ids.saved)?;
+    Ok(())
+}
+
+pub fn sys_setreuid(ruid: u32, euid: u32) -> AxResult<isize> {
+    let curr = current();
+    let mut cred = curr.as_thread().proc_data.cred.write();
+    let privileged = cred.is_privileged();
+    cred.uid.set_re(id_arg(ruid), id_arg(euid), privileged)?;
     Ok(0)
 }
 
-pub fn sys_setresuid(_ruid: u32, _euid: u32, _suid: u32) -> AxResult<isize> {
+pub fn sys_setregid(rgid: u32, egid: u32) -> AxResult<isize> {
+    let curr = current();
+    let mut cred = curr.as_thread().proc_data.cred.write();
+    let privileged = cred.is_privileged();
+    cred.gid.set_re(id_arg(rgid), id_arg(egid), privileged)?;
     Ok(0)
 }
 
-pub fn sys_setresgid(_rgid: u32, _egid: u32, _sgid: u32) -> AxResult<isize> {
+pub fn sys_setresuid(ruid: u32, euid: u32, suid: u32) -> AxResult<isize> {
+    let curr = current();
+    let mut cred = curr.as_thread().proc_data.cred.write();
+    let privileged = cred.is_privileged();
+    cred.uid
+        .set_res(id_arg(ruid), id_arg(euid), id_arg(suid), privileged)?;
+    Ok(0)
+}
+
+pub fn sys_setresgid(rgid: u32, egid: u32, sgid: u32) -> AxResult<isize> {
+    let curr = current();
+    let mut cred = curr.as_thread().proc_data.cred.write();
+    let privileged = cred.is_privileged();
+    cred.gid
+        .set_res(id_arg(rgid), id_arg(egid), id_arg(sgid), privileged)?;
     Ok(0)
 }
 
+pub fn sys_getresuid(ruid: *mut u32, euid: *mut u32, suid: *mut u32) -> AxResult<isize> {
+    let uid = current().as_thread().proc_data.cred.read().uid;
+    write_ids(uid, ruid, euid, suid)?;
+    Ok(0)
+}
+
+pub fn sys_getresgid(rgid: *mut u32, egid: *mut u32, sgid: *mut u32) -> AxResult<isize> {
+    let gid = current().as_thread().proc_data.cred.read().gid;
+    write_ids(gid, rgid, egid, sgid)?;
+    Ok(0)
+}
+
+const MPOL_DEFAULT: i32 = 0;
+
+const MPOL_F_NODE: u32 = 1 << 0;
+const MPOL_F_ADDR: u32 = 1 << 1;
+const MPOL_F_MEMS_ALLOWED: u32 = 1 << 2;
+
+/// The number of NUMA nodes. The system is a single node.
+const NUMA_NODES: usize = 1;
+
+/// Writes the nodes in `mask` as a node mask of `maxnode` bits.
+fn write_nodemask(nodemask: *mut usize, maxnode: usize, mask: usize) -> AxResult<()> {
+    if nodemask.is_null() {
+        return Ok(());
+    }
+    if maxnode < NUMA_NODES {
+        return Err(AxError::InvalidInput);
+    }
+    let mut words = vec![0; maxnode.div_ceil(usize::BITS as usize)];
+    words[0] = mask;
+    vm_write_slice(nodemask, &words)?;
+    Ok(())
+}
+
 pub fn sys_get_mempolicy(
-    _policy: *mut i32,
-    _nodemask: *mut usize,
-    _maxnode: usize,
-    _addr: usize,
-    _flags: usize,
+    policy: *mut i32,
+    nodemask: *mut usize,
+    maxnode: usize,
+    addr: usize,
+    flags: u32,
 ) -> AxResult<isize> {
-    warn!("Dummy get_mempolicy called");
+    debug!("sys_get_mempolicy <= maxnode: {maxnode}, addr: {addr:#x}, flags: {flags:#x}");
+    if flags & !(MPOL_F_NODE | MPOL_F_ADDR | MPOL_F_MEMS_ALLOWED) != 0 {
+        return Err(AxError::InvalidInput);
+    }
+
+    if flags & MPOL_F_MEMS_ALLOWED != 0 {
+        if flags & (MPOL_F_NODE | MPOL_F_ADDR) != 0 {
+            return Err(AxError::InvalidInput);
+        }
+        if let Some(policy) = policy.nullable() {
+            policy.vm_write(MPOL_DEFAULT)?;
+        }
+        write_nodemask(nodemask, maxnode, 1)?;
+        return Ok(0);
+    }
+
+    let mode = if flags & MPOL_F_ADDR != 0 {
+        let curr = current();
+        let proc_data = &curr.as_thread().proc_data;
+        if proc_data
+            .aspace
+            .lock()
+            .find_area(VirtAddr::from(addr))
+            .is_none()
+        {
+            return Err(AxError::BadAddress);
+        }
+        if flags & MPOL_F_NODE != 0 {
+            // The node the memory at `addr` is allocated from.
+            proc_data
+                .home_nodes
+                .lock()
+                .get(VirtAddr::from(addr))
+                .unwrap_or(0) as i32
+        } else {
+            MPOL_DEFAULT
+        }
+    } else {
+        // `MPOL_F_NODE` alone is only valid for the interleave policy.
+        if addr != 0 || flags & MPOL_F_NODE != 0 {
+            return Err(AxError::InvalidInput);
+        }
+        MPOL_DEFAULT
+    };
+    if let Some(policy) = policy.nullable() {
+        policy.vm_write(mode)?;
+    }
+    // The default policy has an empty node mask.
+    write_nodemask(nodemask, maxnode, 0)?;
+    Ok(0)
+}
+
+pub fn sys_set_mempolicy_home_node(
+    start: usize,
+    len: usize,
+    home_node: usize,
+    flags: usize,
+) -> AxResult<isize> {
+    debug!(
+        "sys_set_mempolicy_home_node <= start: {start:#x}, len: {len:#x}, home_node: {home_node}, \
+         flags: {flags:#x}"
+    );
+    if flags != 0 || !start.is_multiple_of(PAGE_SIZE_4K) || home_node >= NUMA_NODES {
+        return Err(AxError::InvalidInput);
+    }
+    let end = start
+        .checked_add(align_up_4k(len))
+        .ok_or(AxError::InvalidInput)?;
+    if start == end {
+        return Ok(0);
+    }
+    let range = VirtAddrRange::from_start_size(VirtAddr::from(start), end - start);
+
+    current()
+        .as_thread()
+        .proc_data
+        .home_nodes
+        .lock()
+        .insert(range, home_node as u32);
     Ok(0)
 }
 
@@ -97,6 +249,15 @@ pub fn sys_prctl(
             buf[..len].copy_from_slice(&name.as_bytes()[..len]);
             vm_write_slice(arg2 as _, &buf)?;
         }
+        PR_SET_NO_NEW_PRIVS => {
+            if arg2 != 1 || arg3 != 0 || arg4 != 0 || arg5 != 0 {
+                return Err(AxError::InvalidInput);
+            }
+            current().as_thread().proc_data.cred.write().no_new_privs = true;
+        }
+        PR_GET_NO_NEW_PRIVS => {
+            return Ok(current().as_thread().proc_data.cred.read().no_new_privs as _);
+        }
         PR_SET_SECCOMP => {}
         PR_MCE_KILL => {}
         PR_SET_MM_START_CODE
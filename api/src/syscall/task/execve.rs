@@ -1,14 +1,40 @@
-use alloc::{string::ToString, sync::Arc, vec::Vec};
+use alloc::{string::ToString, sync::Arc, vec, vec::Vec};
 use core::ffi::c_char;
 
 use axerrno::{AxError, AxResult};
 use axfs_ng::FS_CONTEXT;
+use axfs_ng_vfs::{Location, NodePermission};
 use axhal::uspace::UserContext;
 use axtask::current;
-use starry_core::{mm::load_user_app, task::AsThread};
+use starry_core::{landlock::LANDLOCK_ACCESS_FS_EXECUTE, mm::load_user_app, task::AsThread};
 use starry_vm::vm_load_until_nul;
 
-use crate::{file::FD_TABLE, mm::vm_load_string};
+use crate::{
+    file::{FD_TABLE, landlock, release_fd},
+    mm::vm_load_string,
+    vfs::mounts,
+};
+
+/// Fails with `EACCES` unless the file at `loc` may be executed. This applies
+/// to the program, the interpreters of scripts and the dynamic linker alike.
+fn check_exec(loc: &Location) -> AxResult<()> {
+    landlock::check(loc, LANDLOCK_ACCESS_FS_EXECUTE)?;
+    if mounts::has_option(loc, "noexec") {
+        return Err(AxError::PermissionDenied);
+    }
+    Ok(())
+}
+
+/// Returns whether the program at `path` is a script, run by the interpreter
+/// named on its `#!` line, or by `/bin/sh` for `.sh` files.
+fn is_script(path: &str, loc: &Location) -> AxResult<bool> {
+    if path.ends_with(".sh") {
+        return Ok(true);
+    }
+    let mut head = vec![0; 2];
+    let len = loc.entry().as_file()?.read_at(&mut head, 0)?;
+    Ok(head[..len] == *b"#!")
+}
 
 pub fn sys_execve(
     uctx: &mut UserContext,
@@ -48,19 +74,57 @@ pub fn sys_execve(
         error!("sys_execve: multi-thread not supported");
         return Err(AxError::WouldBlock);
     }
+    let loc = FS_CONTEXT.lock().resolve(&path)?;
+    check_exec(&loc)?;
+
+    // Set-user-ID and set-group-ID files run as their owner, unless on a
+    // `nosuid` mount. Like Linux, set-group-ID without group execute
+    // permission marks mandatory locking instead, and the bits of scripts
+    // are ignored, their interpreter running with the caller's credentials.
+    let meta = loc.metadata()?;
+    let suid = meta
+        .mode
+        .intersects(NodePermission::SET_UID | NodePermission::SET_GID)
+        && !mounts::has_option(&loc, "nosuid")
+        && !is_script(&path, &loc)?;
+    let sgid_mode = NodePermission::SET_GID | NodePermission::GROUP_EXEC;
+    let set_uid = (suid && meta.mode.contains(NodePermission::SET_UID)).then_some(meta.uid);
+    let set_gid = (suid && meta.mode.contains(sgid_mode)).then_some(meta.gid);
+
+    let mut cred = *proc_data.cred.read();
+    cred.exec(set_uid, set_gid);
 
     let mut aspace = proc_data.aspace.lock();
-    let (entry_point, user_stack_base) =
-        load_user_app(&mut aspace, Some(path.as_str()), &args, &envs)?;
+    let mut file_maps = proc_data.file_maps.lock();
+    let (entry_point, user_stack_base) = load_user_app(
+        &mut aspace,
+        &mut file_maps,
+        Some(path.as_str()),
+        &args,
+        &envs,
+        &check_exec,
+        &cred,
+    )?;
+    drop(file_maps);
     drop(aspace);
 
-    let loc = FS_CONTEXT.lock().resolve(&path)?;
+    *proc_data.cred.write() = cred;
     curr.set_name(loc.name());
 
     *proc_data.exe_path.write() = loc.absolute_path()?.to_string();
     *proc_data.cmdline.write() = Arc::new(args);
 
     *proc_data.signal.actions.lock() = Default::default();
+    proc_data.home_nodes.lock().clear();
+    proc_data.mlocked.lock().clear();
+    proc_data.lazy_free.lock().ranges.clear();
+    proc_data.anon_mappings.lock().clear();
+    proc_data.huge_pages.lock().ranges.clear();
+    proc_data.sealed.lock().clear();
+    proc_data.noexec_maps.lock().clear();
+    proc_data.shared_file_maps.lock().clear();
+    proc_data.shared_file_backends.lock().clear();
+    *proc_data.pkeys.lock() = Default::default();
 
     // Close CLOEXEC file descriptors
     let mut fd_table = FD_TABLE.write();
@@ -68,10 +132,12 @@ pub fn sys_execve(
         .ids()
         .filter(|it| fd_table.get(*it).unwrap().cloexec)
         .collect::<Vec<_>>();
-    for fd in cloexec_fds {
-        fd_table.remove(fd);
-    }
+    let closed = cloexec_fds
+        .into_iter()
+        .filter_map(|fd| fd_table.remove(fd))
+        .collect::<Vec<_>>();
     drop(fd_table);
+    closed.into_iter().for_each(release_fd);
 
     uctx.set_ip(entry_point.as_usize());
     uctx.set_sp(user_stack_base.as_usize());
@@ -1,20 +1,30 @@
-use axerrno::{AxError, AxResult};
-use axhal::time::TimeValue;
+use alloc::{vec, vec::Vec};
+
+use axerrno::{AxError, AxResult, LinuxError};
+use axhal::time::{NANOS_PER_SEC, TimeValue};
 use axtask::{
-    AxCpuMask, current,
+    AxCpuMask, AxTaskRef, current,
     future::{block_on, interruptible, sleep},
 };
 use linux_raw_sys::general::{
     __kernel_clockid_t, CLOCK_MONOTONIC, CLOCK_REALTIME, PRIO_PGRP, PRIO_PROCESS, PRIO_USER,
+    SCHED_BATCH, SCHED_DEADLINE, SCHED_FIFO, SCHED_IDLE, SCHED_NORMAL, SCHED_RESET_ON_FORK,
     SCHED_RR, TIMER_ABSTIME, timespec,
 };
-use starry_core::task::{get_process_data, get_process_group};
+use memory_addr::PAGE_SIZE_4K;
+use starry_core::{
+    sched::{DL_MAX_PERIOD, DL_MIN_PERIOD, DL_MIN_RUNTIME, RR_TIMESLICE_TICKS, SchedAttr},
+    task::{AsThread, get_process_group, get_task, tasks},
+    time::{NsClock, ns_clock_time},
+};
+use starry_process::Pid;
 use starry_vm::{VmMutPtr, VmPtr, vm_load, vm_write_slice};
 
 use crate::time::TimeValueLike;
 
 pub fn sys_sched_yield() -> AxResult<isize> {
     warn!("sys_sched_yield");
+    starry_core::schedstat::record_yield();
     axtask::yield_now();
     warn!("sys_sched_yield =>");
     Ok(0)
@@ -23,6 +33,8 @@ pub fn sys_sched_yield() -> AxResult<isize> {
 fn sleep_impl(clock: impl Fn() -> TimeValue, dur: TimeValue) -> TimeValue {
     debug!("sleep_impl <= {dur:?}");
 
+    let curr = current();
+    let _wchan = curr.as_thread().wait_in(sleep_impl as usize);
     let start = clock();
 
     // TODO: currently ignoring concrete clock type
@@ -57,9 +69,10 @@ pub fn sys_clock_nanosleep(
     req: *const timespec,
     rem: *mut timespec,
 ) -> AxResult<isize> {
-    let clock = match clock_id as u32 {
-        CLOCK_REALTIME => axhal::time::wall_time,
-        CLOCK_MONOTONIC => axhal::time::monotonic_time,
+    let clock: fn() -> TimeValue = match clock_id as u32 {
+        CLOCK_REALTIME => starry_core::time::realtime,
+        // Absolute times are in the time namespace
+        CLOCK_MONOTONIC => || ns_clock_time(NsClock::Monotonic),
         _ => {
             warn!("Unsupported clock_id: {clock_id}");
             return Err(AxError::InvalidInput);
@@ -88,6 +101,16 @@ pub fn sys_clock_nanosleep(
     }
 }
 
+pub fn sys_getcpu(cpu: *mut u32, node: *mut u32) -> AxResult<isize> {
+    if let Some(cpu) = cpu.nullable() {
+        cpu.vm_write(axhal::percpu::this_cpu_id() as u32)?;
+    }
+    if let Some(node) = node.nullable() {
+        node.vm_write(0)?;
+    }
+    Ok(0)
+}
+
 pub fn sys_sched_getaffinity(pid: i32, cpusetsize: usize, user_mask: *mut u8) -> AxResult<isize> {
     if cpusetsize * 8 < axconfig::plat::CPU_NUM {
         return Err(AxError::InvalidInput);
@@ -127,41 +150,377 @@ pub fn sys_sched_setaffinity(
     Ok(0)
 }
 
-pub fn sys_sched_getscheduler(_pid: i32) -> AxResult<isize> {
-    Ok(SCHED_RR as _)
+fn sched_task(pid: i32) -> AxResult<AxTaskRef> {
+    if pid < 0 {
+        return Err(AxError::InvalidInput);
+    }
+    let task = get_task(pid as Pid)?;
+    if task.try_as_thread().is_none() {
+        return Err(AxError::NoSuchProcess);
+    }
+    Ok(task)
 }
 
-pub fn sys_sched_setscheduler(_pid: i32, _policy: i32, _param: *const ()) -> AxResult<isize> {
+/// Checks that `priority` is valid for `policy`.
+fn check_sched_priority(policy: u32, priority: i32) -> AxResult<()> {
+    let valid = match policy {
+        SCHED_FIFO | SCHED_RR => (1..=99).contains(&priority),
+        SCHED_NORMAL | SCHED_BATCH | SCHED_IDLE => priority == 0,
+        _ => false,
+    };
+    if valid {
+        Ok(())
+    } else {
+        Err(AxError::InvalidInput)
+    }
+}
+
+pub fn sys_sched_getscheduler(pid: i32) -> AxResult<isize> {
+    let attr = sched_task(pid)?.as_thread().sched_attr();
+    let mut policy = attr.policy;
+    if attr.reset_on_fork {
+        policy |= SCHED_RESET_ON_FORK;
+    }
+    Ok(policy as _)
+}
+
+/// `param` points to a `struct sched_param`, which holds only the priority.
+pub fn sys_sched_setscheduler(pid: i32, policy: i32, param: *const i32) -> AxResult<isize> {
+    debug!("sys_sched_setscheduler <= pid: {pid}, policy: {policy}");
+    if param.is_null() || policy < 0 {
+        return Err(AxError::InvalidInput);
+    }
+    let reset_on_fork = policy as u32 & SCHED_RESET_ON_FORK != 0;
+    let policy = policy as u32 & !SCHED_RESET_ON_FORK;
+    let priority = param.vm_read()?;
+    check_sched_priority(policy, priority)?;
+
+    let task = sched_task(pid)?;
+    let thr = task.as_thread();
+    let old = thr.sched_attr();
+    thr.set_sched_attr(SchedAttr {
+        policy,
+        reset_on_fork,
+        priority: priority as u32,
+        // The nice value survives switching between policies
+        nice: old.nice,
+        ..Default::default()
+    })?;
     Ok(0)
 }
 
-pub fn sys_sched_getparam(_pid: i32, _param: *mut ()) -> AxResult<isize> {
+pub fn sys_sched_getparam(pid: i32, param: *mut i32) -> AxResult<isize> {
+    if param.is_null() {
+        return Err(AxError::InvalidInput);
+    }
+    let priority = sched_task(pid)?.as_thread().sched_attr().priority;
+    param.vm_write(priority as i32)?;
     Ok(0)
 }
 
-pub fn sys_getpriority(which: u32, who: u32) -> AxResult<isize> {
-    debug!("sys_getpriority <= which: {which}, who: {who}");
+pub fn sys_sched_setparam(pid: i32, param: *const i32) -> AxResult<isize> {
+    if param.is_null() {
+        return Err(AxError::InvalidInput);
+    }
+    let priority = param.vm_read()?;
+    let task = sched_task(pid)?;
+    let thr = task.as_thread();
+    let mut attr = thr.sched_attr();
+    check_sched_priority(attr.policy, priority)?;
+    attr.priority = priority as u32;
+    thr.set_sched_attr(attr)?;
+    Ok(0)
+}
 
-    match which {
-        PRIO_PROCESS => {
-            if who != 0 {
-                let _proc = get_process_data(who)?;
-            }
-            Ok(20)
+pub fn sys_sched_rr_get_interval(pid: i32, interval: *mut timespec) -> AxResult<isize> {
+    let task = sched_task(pid)?;
+    let policy = task.as_thread().sched_attr().policy;
+    let slice = if policy == SCHED_RR {
+        TimeValue::from_nanos(RR_TIMESLICE_TICKS * NANOS_PER_SEC / axconfig::TICKS_PER_SEC as u64)
+    } else {
+        TimeValue::ZERO
+    };
+    interval.vm_write(timespec::from_time_value(slice))?;
+    Ok(0)
+}
+
+// From <linux/sched.h> and <linux/sched/types.h>
+const SCHED_FLAG_RESET_ON_FORK: u64 = 0x01;
+const SCHED_FLAG_KEEP_POLICY: u64 = 0x08;
+const SCHED_FLAG_KEEP_PARAMS: u64 = 0x10;
+const SCHED_FLAG_ALL: u64 = 0x7f;
+/// Size of the first published `struct sched_attr`.
+const SCHED_ATTR_SIZE_VER0: usize = 48;
+/// Size of `struct sched_attr` with utilization clamps.
+const SCHED_ATTR_SIZE_VER1: usize = 56;
+
+/// `struct sched_attr`
+#[repr(C)]
+#[derive(Default)]
+struct SchedAttrRaw {
+    size: u32,
+    sched_policy: u32,
+    sched_flags: u64,
+    sched_nice: i32,
+    sched_priority: u32,
+    sched_runtime: u64,
+    sched_deadline: u64,
+    sched_period: u64,
+    sched_util_min: u32,
+    sched_util_max: u32,
+}
+
+/// Checks the parameters of a `SCHED_DEADLINE` thread, like
+/// `__checkparam_dl` in Linux.
+fn check_deadline(attr: &SchedAttr) -> AxResult<()> {
+    let valid = attr.deadline != 0
+        && attr.runtime >= DL_MIN_RUNTIME
+        && attr.runtime <= attr.deadline
+        && attr.deadline <= attr.period
+        && (DL_MIN_PERIOD..=DL_MAX_PERIOD).contains(&attr.period);
+    if valid {
+        Ok(())
+    } else {
+        Err(AxError::InvalidInput)
+    }
+}
+
+pub fn sys_sched_setattr(pid: i32, uattr: *mut u8, flags: u32) -> AxResult<isize> {
+    if uattr.is_null() || pid < 0 || flags != 0 {
+        return Err(AxError::InvalidInput);
+    }
+    let mut size = (uattr as *const u32).vm_read()? as usize;
+    if size == 0 {
+        size = SCHED_ATTR_SIZE_VER0;
+    }
+    if !(SCHED_ATTR_SIZE_VER0..=PAGE_SIZE_4K).contains(&size) {
+        // Tell userspace the size we expect
+        (uattr as *mut u32).vm_write(SCHED_ATTR_SIZE_VER1 as u32)?;
+        return Err(AxError::Other(LinuxError::E2BIG));
+    }
+    let bytes = vm_load(uattr as *const u8, size)?;
+    // Fields we don't know about must be zero
+    if bytes.iter().skip(SCHED_ATTR_SIZE_VER1).any(|&b| b != 0) {
+        (uattr as *mut u32).vm_write(SCHED_ATTR_SIZE_VER1 as u32)?;
+        return Err(AxError::Other(LinuxError::E2BIG));
+    }
+    let mut raw = SchedAttrRaw::default();
+    let len = size.min(size_of::<SchedAttrRaw>());
+    unsafe {
+        core::slice::from_raw_parts_mut(&mut raw as *mut _ as *mut u8, len)
+            .copy_from_slice(&bytes[..len]);
+    }
+    debug!(
+        "sys_sched_setattr <= pid: {pid}, policy: {}, flags: {:#x}",
+        raw.sched_policy, raw.sched_flags
+    );
+    if raw.sched_flags & !SCHED_FLAG_ALL != 0 {
+        return Err(AxError::InvalidInput);
+    }
+
+    let task = sched_task(pid)?;
+    let thr = task.as_thread();
+    let old = thr.sched_attr();
+    let mut attr = SchedAttr {
+        policy: raw.sched_policy,
+        reset_on_fork: raw.sched_flags & SCHED_FLAG_RESET_ON_FORK != 0,
+        nice: raw.sched_nice,
+        priority: raw.sched_priority,
+        ..Default::default()
+    };
+    if raw.sched_flags & SCHED_FLAG_KEEP_POLICY != 0 {
+        attr.policy = old.policy;
+    }
+    if raw.sched_flags & SCHED_FLAG_KEEP_PARAMS != 0 {
+        attr.priority = old.priority;
+        attr.runtime = old.runtime;
+        attr.deadline = old.deadline;
+        attr.period = old.period;
+    } else if attr.policy == SCHED_DEADLINE {
+        attr.runtime = raw.sched_runtime;
+        attr.deadline = raw.sched_deadline;
+        // The period defaults to the deadline
+        attr.period = if raw.sched_period == 0 {
+            raw.sched_deadline
+        } else {
+            raw.sched_period
+        };
+    }
+
+    if !(-20..=19).contains(&attr.nice) {
+        return Err(AxError::InvalidInput);
+    }
+    if attr.policy == SCHED_DEADLINE {
+        if attr.priority != 0 {
+            return Err(AxError::InvalidInput);
         }
+        check_deadline(&attr)?;
+    } else {
+        check_sched_priority(attr.policy, attr.priority as i32)?;
+    }
+    thr.set_sched_attr(attr)?;
+    Ok(0)
+}
+
+pub fn sys_sched_getattr(pid: i32, uattr: *mut u8, size: u32, flags: u32) -> AxResult<isize> {
+    let size = size as usize;
+    if uattr.is_null()
+        || pid < 0
+        || flags != 0
+        || !(SCHED_ATTR_SIZE_VER0..=PAGE_SIZE_4K).contains(&size)
+    {
+        return Err(AxError::InvalidInput);
+    }
+    let attr = sched_task(pid)?.as_thread().sched_attr();
+    let len = size.min(size_of::<SchedAttrRaw>());
+    let raw = SchedAttrRaw {
+        size: len as u32,
+        sched_policy: attr.policy,
+        sched_flags: if attr.reset_on_fork {
+            SCHED_FLAG_RESET_ON_FORK
+        } else {
+            0
+        },
+        sched_nice: attr.nice,
+        sched_priority: attr.priority,
+        sched_runtime: attr.runtime,
+        sched_deadline: attr.deadline,
+        sched_period: attr.period,
+        sched_util_min: 0,
+        sched_util_max: 1024,
+    };
+    let bytes = unsafe { core::slice::from_raw_parts(&raw as *const _ as *const u8, len) };
+    vm_write_slice(uattr, bytes)?;
+    Ok(0)
+}
+
+/// Collects the threads selected by `which` and `who`, as given to
+/// `setpriority` and `ioprio_set`, whose `PRIO_*` and `IOPRIO_WHO_*` values
+/// are the same.
+fn prio_targets(which: u32, who: u32) -> AxResult<Vec<AxTaskRef>> {
+    match which {
+        PRIO_PROCESS => Ok(vec![get_task(who)?]),
         PRIO_PGRP => {
-            if who != 0 {
-                let _pg = get_process_group(who)?;
-            }
-            Ok(20)
+            let pgid = if who == 0 {
+                current().as_thread().proc_data.proc.group().pgid()
+            } else {
+                who
+            };
+            Ok(get_process_group(pgid)?
+                .processes()
+                .iter()
+                .flat_map(|proc| proc.threads())
+                .filter_map(|tid| get_task(tid).ok())
+                .collect())
         }
         PRIO_USER => {
-            if who == 0 {
-                Ok(20)
+            let uid = if who == 0 {
+                current().as_thread().proc_data.cred.read().uid.real
             } else {
-                Err(AxError::NoSuchProcess)
-            }
+                who
+            };
+            Ok(tasks()
+                .into_iter()
+                .filter(|task| {
+                    task.try_as_thread()
+                        .is_some_and(|thr| thr.proc_data.cred.read().uid.real == uid)
+                })
+                .collect())
         }
         _ => Err(AxError::InvalidInput),
     }
 }
+
+/// Returns the priority of the most favored selected thread, as `20 - nice`
+/// so that it is never negative.
+pub fn sys_getpriority(which: u32, who: u32) -> AxResult<isize> {
+    debug!("sys_getpriority <= which: {which}, who: {who}");
+
+    prio_targets(which, who)?
+        .iter()
+        .map(|task| task.as_thread().sched_attr().nice)
+        .min()
+        .map(|nice| (20 - nice) as isize)
+        .ok_or(AxError::NoSuchProcess)
+}
+
+pub fn sys_setpriority(which: u32, who: u32, prio: i32) -> AxResult<isize> {
+    debug!("sys_setpriority <= which: {which}, who: {who}, prio: {prio}");
+
+    let nice = prio.clamp(-20, 19);
+    let targets = prio_targets(which, who)?;
+    if targets.is_empty() {
+        return Err(AxError::NoSuchProcess);
+    }
+    let cred = *current().as_thread().proc_data.cred.read();
+    for task in targets {
+        let thr = task.as_thread();
+        let target = *thr.proc_data.cred.read();
+        if !cred.is_privileged()
+            && cred.uid.effective != target.uid.real
+            && cred.uid.effective != target.uid.effective
+        {
+            return Err(AxError::OperationNotPermitted);
+        }
+        let mut attr = thr.sched_attr();
+        // Only privileged users may raise the priority
+        if nice < attr.nice && !cred.is_privileged() {
+            return Err(AxError::PermissionDenied);
+        }
+        attr.nice = nice;
+        thr.set_sched_attr(attr)?;
+    }
+    Ok(0)
+}
+
+// From <linux/ioprio.h>
+const IOPRIO_CLASS_SHIFT: u32 = 13;
+const IOPRIO_PRIO_MASK: u32 = (1 << IOPRIO_CLASS_SHIFT) - 1;
+const IOPRIO_NR_LEVELS: u32 = 8;
+
+const IOPRIO_CLASS_NONE: u32 = 0;
+const IOPRIO_CLASS_RT: u32 = 1;
+const IOPRIO_CLASS_BE: u32 = 2;
+const IOPRIO_CLASS_IDLE: u32 = 3;
+
+/// Orders I/O priorities, lower being more favored. Threads without an I/O
+/// priority are treated as best-effort at the default level.
+fn ioprio_rank(ioprio: u16) -> (u32, u32) {
+    let ioprio = ioprio as u32;
+    match ioprio >> IOPRIO_CLASS_SHIFT {
+        IOPRIO_CLASS_NONE => (IOPRIO_CLASS_BE, IOPRIO_NR_LEVELS / 2),
+        class => (class, ioprio & IOPRIO_PRIO_MASK),
+    }
+}
+
+pub fn sys_ioprio_set(which: u32, who: u32, ioprio: u32) -> AxResult<isize> {
+    debug!("sys_ioprio_set <= which: {which}, who: {who}, ioprio: {ioprio:#x}");
+
+    let data = ioprio & IOPRIO_PRIO_MASK;
+    match ioprio >> IOPRIO_CLASS_SHIFT {
+        IOPRIO_CLASS_RT | IOPRIO_CLASS_BE if data < IOPRIO_NR_LEVELS => {}
+        IOPRIO_CLASS_IDLE => {}
+        IOPRIO_CLASS_NONE if data == 0 => {}
+        _ => return Err(AxError::InvalidInput),
+    }
+
+    let targets = prio_targets(which, who)?;
+    if targets.is_empty() {
+        return Err(AxError::NoSuchProcess);
+    }
+    for task in targets {
+        task.as_thread().set_ioprio(ioprio as u16);
+    }
+    Ok(0)
+}
+
+pub fn sys_ioprio_get(which: u32, who: u32) -> AxResult<isize> {
+    debug!("sys_ioprio_get <= which: {which}, who: {who}");
+
+    prio_targets(which, who)?
+        .iter()
+        .map(|task| task.as_thread().ioprio())
+        .min_by_key(|&ioprio| ioprio_rank(ioprio))
+        .map(|ioprio| ioprio as isize)
+        .ok_or(AxError::NoSuchProcess)
+}
@@ -1,29 +1,33 @@
 use axerrno::{AxError, AxResult};
-use axhal::time::{TimeValue, monotonic_time, monotonic_time_nanos, nanos_to_ticks, wall_time};
+use axhal::time::{TimeValue, monotonic_time_nanos, nanos_to_ticks};
 use axtask::current;
 use linux_raw_sys::general::{
     __kernel_clockid_t, CLOCK_BOOTTIME, CLOCK_MONOTONIC, CLOCK_MONOTONIC_COARSE,
     CLOCK_MONOTONIC_RAW, CLOCK_PROCESS_CPUTIME_ID, CLOCK_REALTIME, CLOCK_REALTIME_COARSE,
     CLOCK_THREAD_CPUTIME_ID, itimerval, timespec, timeval,
 };
-use starry_core::{task::AsThread, time::ITimerType};
+use starry_core::{
+    task::AsThread,
+    time::{ITimerType, NsClock, ns_clock_time, realtime, set_realtime},
+};
 use starry_vm::{VmMutPtr, VmPtr};
 
 use crate::time::TimeValueLike;
 
 pub fn sys_clock_gettime(clock_id: __kernel_clockid_t, ts: *mut timespec) -> AxResult<isize> {
     let now = match clock_id as u32 {
-        CLOCK_REALTIME | CLOCK_REALTIME_COARSE => wall_time(),
-        CLOCK_MONOTONIC | CLOCK_MONOTONIC_RAW | CLOCK_MONOTONIC_COARSE | CLOCK_BOOTTIME => {
-            monotonic_time()
+        CLOCK_REALTIME | CLOCK_REALTIME_COARSE => realtime(),
+        CLOCK_MONOTONIC | CLOCK_MONOTONIC_RAW | CLOCK_MONOTONIC_COARSE => {
+            ns_clock_time(NsClock::Monotonic)
         }
+        CLOCK_BOOTTIME => ns_clock_time(NsClock::Boottime),
         CLOCK_PROCESS_CPUTIME_ID | CLOCK_THREAD_CPUTIME_ID => {
             let (utime, stime) = current().as_thread().time.borrow().output();
             utime + stime
         }
         _ => {
             warn!("Called sys_clock_gettime for unsupported clock {clock_id}");
-            wall_time()
+            realtime()
             // return Err(AxError::EINVAL);
         }
     };
@@ -32,7 +36,39 @@ pub fn sys_clock_gettime(clock_id: __kernel_clockid_t, ts: *mut timespec) -> AxR
 }
 
 pub fn sys_gettimeofday(ts: *mut timeval) -> AxResult<isize> {
-    ts.vm_write(timeval::from_time_value(wall_time()))?;
+    ts.vm_write(timeval::from_time_value(realtime()))?;
+    Ok(0)
+}
+
+/// Fails with `EPERM` unless the current process may set the clock.
+fn check_set_time() -> AxResult<()> {
+    if current().as_thread().proc_data.cred.read().is_privileged() {
+        Ok(())
+    } else {
+        Err(AxError::OperationNotPermitted)
+    }
+}
+
+pub fn sys_clock_settime(clock_id: __kernel_clockid_t, ts: *const timespec) -> AxResult<isize> {
+    // Like Linux, only the realtime clock can be set
+    if clock_id as u32 != CLOCK_REALTIME {
+        return Err(AxError::InvalidInput);
+    }
+    let time = unsafe { ts.vm_read_uninit()?.assume_init() }.try_into_time_value()?;
+    check_set_time()?;
+    set_realtime(time);
+    Ok(0)
+}
+
+pub fn sys_settimeofday(tv: *const timeval, _tz: *const ()) -> AxResult<isize> {
+    // The timezone is obsolete and only kept by Linux for compatibility, so
+    // it's ignored
+    let Some(tv) = tv.nullable() else {
+        return Ok(0);
+    };
+    let time = unsafe { tv.vm_read_uninit()?.assume_init() }.try_into_time_value()?;
+    check_set_time()?;
+    set_realtime(time);
     Ok(0)
 }
 
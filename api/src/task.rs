@@ -8,18 +8,21 @@ use linux_raw_sys::general::ROBUST_LIST_LIMIT;
 use starry_core::{
     futex::FutexKey,
     mm::access_user_memory,
+    sched::SchedAttr,
     shm::SHM_MANAGER,
     task::{
         AsThread, get_process_data, get_task, send_signal_to_process, send_signal_to_thread,
         set_timer_state,
     },
     time::TimerState,
+    uprobe,
 };
 use starry_process::Pid;
 use starry_signal::{SignalInfo, Signo};
 use starry_vm::{VmMutPtr, VmPtr};
 
 use crate::{
+    mm::handle_user_page_fault,
     signal::{check_signals, unblock_next_signal},
     syscall::handle_syscall,
 };
@@ -51,7 +54,7 @@ pub fn new_user_task(
                 match reason {
                     ReturnReason::Syscall => handle_syscall(&mut uctx),
                     ReturnReason::PageFault(addr, flags) => {
-                        if !thr.proc_data.aspace.lock().handle_page_fault(addr, flags) {
+                        if !handle_user_page_fault(&thr.proc_data, addr, flags) {
                             info!(
                                 "{:?}: segmentation fault at {:#x} {:?}",
                                 thr.proc_data.proc, addr, flags
@@ -60,7 +63,11 @@ pub fn new_user_task(
                                 .expect("Failed to send SIGSEGV");
                         }
                     }
-                    ReturnReason::Interrupt => {}
+                    ReturnReason::Interrupt => {
+                        if thr.timeslice_expired() {
+                            axtask::yield_now();
+                        }
+                    }
                     #[allow(unused_labels)]
                     ReturnReason::Exception(exc_info) => 'exc: {
                         // TODO: detailed handling
@@ -72,7 +79,12 @@ pub fn new_user_task(
                                 }
                                 Signo::SIGBUS
                             }
-                            ExceptionKind::Breakpoint => Signo::SIGTRAP,
+                            ExceptionKind::Breakpoint => {
+                                if uprobe::handle_breakpoint(thr, &mut uctx) {
+                                    break 'exc;
+                                }
+                                Signo::SIGTRAP
+                            }
                             ExceptionKind::IllegalInstruction => Signo::SIGILL,
                             _ => Signo::SIGTRAP,
                         };
@@ -184,6 +196,9 @@ pub fn do_exit(exit_code: i32, group_exit: bool) {
         warn!("exit robust list failed: {err:?}");
     }
 
+    // Give back the bandwidth of a deadline thread
+    let _ = thr.set_sched_attr(SchedAttr::default());
+
     let process = &thr.proc_data.proc;
     if process.exit_thread(curr.id().as_u64() as Pid, exit_code) {
         process.exit();
@@ -192,6 +207,9 @@ pub fn do_exit(exit_code: i32, group_exit: bool) {
                 let _ = send_signal_to_process(parent.pid(), Some(SignalInfo::new_kernel(signo)));
             }
             if let Ok(data) = get_process_data(parent.pid()) {
+                // The faults of children are added when they exit rather than
+                // when they are waited for
+                data.faults.add_child(&thr.proc_data.faults);
                 data.child_exit_event.wake();
             }
         }
@@ -19,7 +19,7 @@ use ringbuf::{
 use starry_core::task::send_signal_to_process_group;
 use starry_signal::SignalInfo;
 
-use crate::terminal::{Terminal, termios::Termios2};
+use crate::terminal::{SerialICounter, Terminal, termios::Termios2};
 
 const BUF_SIZE: usize = 80;
 
@@ -58,6 +58,33 @@ pub trait TtyRead: Send + Sync + 'static {
 }
 pub trait TtyWrite: Send + Sync + 'static {
     fn write(&self, buf: &[u8]);
+
+    /// Returns the line statistics of the serial port behind the tty, or
+    /// `None` if it's not a serial port.
+    fn icount(&self) -> Option<SerialICounter> {
+        None
+    }
+
+    /// Returns the modem lines of the serial port behind the tty as
+    /// `TIOCM_*` bits, or `None` if it's not a serial port.
+    fn modem_lines(&self) -> Option<u32> {
+        None
+    }
+
+    /// Sets the modem control lines of the serial port behind the tty, given
+    /// as `TIOCM_*` bits.
+    fn set_modem_control(&self, _control: u32) {}
+
+    /// Starts or stops sending a break condition, returning `false` if the
+    /// tty can't send breaks.
+    fn break_ctl(&self, _on: bool) -> bool {
+        false
+    }
+
+    /// Handles the ioctls specific to the device behind the tty.
+    fn ioctl(&self, _cmd: u32, _arg: usize) -> AxResult<usize> {
+        Err(AxError::NotATty)
+    }
 }
 
 struct InputReader<R, W> {
@@ -360,12 +387,18 @@ impl<R: TtyRead, W: TtyWrite> LineDiscipline<R, W> {
             _ => unreachable!(),
         };
         let pollable = WaitPollable(set);
-        Poller::new(&pollable, IoEvents::IN).poll(|| {
+        let result = Poller::new(&pollable, IoEvents::IN).poll(|| {
             total_read += self.buf_rx.pop_slice(&mut buf[total_read..]);
             self.poll_tx.wake();
             (total_read >= vmin)
                 .then_some(total_read)
                 .ok_or(AxError::WouldBlock)
-        })
+        });
+        match result {
+            // Like Linux, a read cut short by a signal before `VMIN` bytes
+            // arrived reports the bytes already read
+            Err(AxError::Interrupted) if total_read > 0 => Ok(total_read),
+            other => other,
+        }
     }
 }
@@ -1,7 +1,7 @@
 //! Terminal module.
 
 use alloc::sync::Arc;
-use core::sync::atomic::AtomicU32;
+use core::sync::atomic::{AtomicBool, AtomicU32};
 
 use bytemuck::AnyBitPattern;
 use kspin::SpinNoPreempt;
@@ -19,11 +19,32 @@ pub struct WindowSize {
     pub ws_ypixel: u16,
 }
 
+/// Line statistics of a serial port, like `serial_icounter_struct`.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, AnyBitPattern)]
+pub struct SerialICounter {
+    pub cts: i32,
+    pub dsr: i32,
+    pub rng: i32,
+    pub dcd: i32,
+    pub rx: i32,
+    pub tx: i32,
+    pub frame: i32,
+    pub overrun: i32,
+    pub parity: i32,
+    pub brk: i32,
+    pub buf_overrun: i32,
+    pub reserved: [i32; 9],
+}
+
 pub struct Terminal {
     pub job_control: job::JobControl,
     pub window_size: SpinNoPreempt<WindowSize>,
     pub termios: SpinNoPreempt<Arc<termios::Termios2>>,
     pub pty_number: AtomicU32,
+    /// Whether the slave of the pseudo-terminal can't be opened yet, until
+    /// `unlockpt`.
+    pub pty_locked: AtomicBool,
 }
 impl Default for Terminal {
     fn default() -> Self {
@@ -37,6 +58,7 @@ impl Default for Terminal {
             }),
             termios: SpinNoPreempt::new(Arc::new(termios::Termios2::default())),
             pty_number: AtomicU32::new(0),
+            pty_locked: AtomicBool::new(false),
         }
     }
 }
@@ -0,0 +1,222 @@
+//! The cgroup v2 filesystem, mounted at `/sys/fs/cgroup`.
+//!
+//! Every mount shows the single hierarchy of [`starry_core::cgroup`].
+//! Cgroups are created and removed with `mkdir` and `rmdir`.
+
+use alloc::{borrow::Cow, boxed::Box, format, string::String, sync::Arc, vec::Vec};
+
+use axerrno::{AxError, AxResult};
+use axfs_ng_vfs::{Filesystem, Location, NodePermission, NodeType, VfsError, VfsResult};
+use starry_core::{
+    cgroup::{self, Cgroup, Controller},
+    task::get_process_data,
+    vfs::{NodeOpsMux, RwFile, SimpleDir, SimpleDirOps, SimpleFile, SimpleFileOperation, SimpleFs},
+};
+
+const CGROUP2_SUPER_MAGIC: u32 = 0x6367_7270;
+
+/// The control files of every cgroup.
+const CONTROL_FILES: &[&str] = &[
+    "cgroup.controllers",
+    "cgroup.procs",
+    "cgroup.subtree_control",
+    "memory.current",
+    "pids.current",
+];
+
+/// The control files of every cgroup but the root.
+const NON_ROOT_CONTROL_FILES: &[&str] = &["pids.max"];
+
+pub fn new_cgroupfs() -> Filesystem {
+    SimpleFs::new_with("cgroup2".into(), CGROUP2_SUPER_MAGIC, |fs| {
+        SimpleDir::new_maker(
+            fs.clone(),
+            Arc::new(CgroupDir {
+                fs,
+                cgroup: cgroup::root().clone(),
+            }),
+        )
+    })
+}
+
+/// Returns the cgroup of the cgroup filesystem directory at `loc`.
+pub fn cgroup_of(loc: &Location) -> AxResult<Arc<Cgroup>> {
+    let dir = loc
+        .entry()
+        .downcast::<SimpleDir<CgroupDir>>()
+        .map_err(|_| AxError::BadFileDescriptor)?;
+    Ok(dir.ops().cgroup.clone())
+}
+
+fn parse_str(data: &[u8]) -> VfsResult<&str> {
+    str::from_utf8(data)
+        .map(str::trim)
+        .map_err(|_| VfsError::InvalidInput)
+}
+
+fn controller_list(controllers: Vec<Controller>) -> Vec<u8> {
+    let names = controllers
+        .into_iter()
+        .map(Controller::name)
+        .collect::<Vec<_>>();
+    format!("{}\n", names.join(" ")).into_bytes()
+}
+
+/// The directory of a cgroup.
+pub struct CgroupDir {
+    fs: Arc<SimpleFs>,
+    cgroup: Arc<Cgroup>,
+}
+
+impl CgroupDir {
+    fn control_names(&self) -> impl Iterator<Item = &'static str> {
+        let non_root: &[&str] = if self.cgroup.is_root() {
+            &[]
+        } else {
+            NON_ROOT_CONTROL_FILES
+        };
+        CONTROL_FILES.iter().chain(non_root).copied()
+    }
+
+    fn control_file(&self, name: &str) -> VfsResult<NodeOpsMux> {
+        let fs = self.fs.clone();
+        let cgroup = self.cgroup.clone();
+        Ok(match name {
+            "cgroup.controllers" => {
+                SimpleFile::new_regular(fs, move || Ok(controller_list(cgroup.controllers())))
+                    .into()
+            }
+            "cgroup.procs" => SimpleFile::new_regular(
+                fs,
+                RwFile::new(move |req| match req {
+                    SimpleFileOperation::Read => {
+                        let mut pids = cgroup
+                            .processes()
+                            .iter()
+                            .map(|it| it.proc.pid())
+                            .collect::<Vec<_>>();
+                        pids.sort_unstable();
+                        let mut out = String::new();
+                        for pid in pids {
+                            out += &format!("{pid}\n");
+                        }
+                        Ok(Some(out.into_bytes()))
+                    }
+                    SimpleFileOperation::Write(data) => {
+                        let pid = parse_str(data)?
+                            .parse()
+                            .map_err(|_| VfsError::InvalidInput)?;
+                        let proc_data = get_process_data(pid)?;
+                        cgroup::move_process(&proc_data, &cgroup)?;
+                        Ok(None)
+                    }
+                }),
+            )
+            .into(),
+            "cgroup.subtree_control" => SimpleFile::new_regular(
+                fs,
+                RwFile::new(move |req| match req {
+                    SimpleFileOperation::Read => {
+                        Ok(Some(controller_list(cgroup.subtree_control())))
+                    }
+                    SimpleFileOperation::Write(data) => {
+                        for token in parse_str(data)?.split_ascii_whitespace() {
+                            let (enabled, name) = match token.split_at_checked(1) {
+                                Some(("+", name)) => (true, name),
+                                Some(("-", name)) => (false, name),
+                                _ => return Err(VfsError::InvalidInput),
+                            };
+                            let controller =
+                                Controller::from_name(name).ok_or(VfsError::InvalidInput)?;
+                            cgroup.set_subtree_control(controller, enabled)?;
+                        }
+                        Ok(None)
+                    }
+                }),
+            )
+            .into(),
+            "memory.current" => {
+                SimpleFile::new_regular(fs, move || Ok(format!("{}\n", cgroup.memory_current())))
+                    .into()
+            }
+            "pids.current" => {
+                SimpleFile::new_regular(fs, move || Ok(format!("{}\n", cgroup.pids_current())))
+                    .into()
+            }
+            "pids.max" if !self.cgroup.is_root() => SimpleFile::new_regular(
+                fs,
+                RwFile::new(move |req| match req {
+                    SimpleFileOperation::Read => Ok(Some(match cgroup.pids_max() {
+                        Some(max) => format!("{max}\n"),
+                        None => "max\n".into(),
+                    })),
+                    SimpleFileOperation::Write(data) => {
+                        let max = match parse_str(data)? {
+                            "max" => None,
+                            value => Some(value.parse().map_err(|_| VfsError::InvalidInput)?),
+                        };
+                        cgroup.set_pids_max(max);
+                        Ok(None)
+                    }
+                }),
+            )
+            .into(),
+            _ => return Err(VfsError::NotFound),
+        })
+    }
+
+    fn child_dir(&self, cgroup: Arc<Cgroup>) -> NodeOpsMux {
+        SimpleDir::new_maker(
+            self.fs.clone(),
+            Arc::new(CgroupDir {
+                fs: self.fs.clone(),
+                cgroup,
+            }),
+        )
+        .into()
+    }
+}
+
+impl SimpleDirOps for CgroupDir {
+    fn child_names<'a>(&'a self) -> Box<dyn Iterator<Item = Cow<'a, str>> + 'a> {
+        Box::new(
+            self.control_names()
+                .map(Cow::Borrowed)
+                .chain(self.cgroup.child_names().into_iter().map(Cow::Owned)),
+        )
+    }
+
+    fn lookup_child(&self, name: &str) -> VfsResult<NodeOpsMux> {
+        if let Some(child) = self.cgroup.child(name) {
+            return Ok(self.child_dir(child));
+        }
+        self.control_file(name)
+    }
+
+    fn is_cacheable(&self) -> bool {
+        false
+    }
+
+    fn create_child(
+        &self,
+        name: &str,
+        node_type: NodeType,
+        _permission: NodePermission,
+    ) -> VfsResult<NodeOpsMux> {
+        if !matches!(node_type, NodeType::Directory) {
+            return Err(VfsError::OperationNotPermitted);
+        }
+        if self.control_names().any(|it| it == name) {
+            return Err(VfsError::AlreadyExists);
+        }
+        let child = self.cgroup.create_child(name)?;
+        Ok(self.child_dir(child))
+    }
+
+    fn remove_child(&self, name: &str) -> VfsResult<()> {
+        if self.cgroup.child(name).is_none() {
+            return Err(VfsError::OperationNotPermitted);
+        }
+        self.cgroup.remove_child(name)
+    }
+}
@@ -0,0 +1,650 @@
+//! Device mapper, with the `linear` target only.
+//!
+//! Mapped devices are created and configured through the `DM_*` ioctls on
+//! `/dev/mapper/control`, as done by `dmsetup`, and show up as
+//! `/dev/mapper/<name>`.
+
+use alloc::{
+    borrow::Cow,
+    boxed::Box,
+    collections::btree_map::BTreeMap,
+    format,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use core::{
+    any::Any,
+    sync::atomic::{AtomicBool, AtomicU32, Ordering},
+};
+
+use axerrno::{AxError, LinuxError};
+use axfs_ng::FS_CONTEXT;
+use axfs_ng_vfs::{DeviceId, NodeFlags, NodeType, VfsError, VfsResult};
+use axsync::Mutex;
+use starry_core::{
+    hotplug::{self, DeviceAction, DeviceEvent},
+    vfs::{BlockInfo, Device, DeviceOps, NodeOpsMux, SimpleDirOps, SimpleFs},
+};
+use starry_vm::{vm_load, vm_write_slice};
+
+/// The device ID for /dev/mapper/control
+pub const DM_CONTROL_DEVICE_ID: DeviceId = DeviceId::new(10, 236);
+
+/// Major number of mapped devices.
+const DM_MAJOR: u32 = 253;
+
+// From <linux/dm-ioctl.h>
+const DM_IOCTL: u32 = 0xfd;
+/// The interface version, as of Linux 3.x.
+const DM_VERSION: [u32; 3] = [4, 27, 0];
+const DM_NAME_LEN: usize = 128;
+/// Size of `struct dm_ioctl`.
+const DM_IOCTL_SIZE: usize = 312;
+/// Size of `struct dm_target_spec`.
+const DM_TARGET_SPEC_SIZE: usize = 40;
+
+const DM_VERSION_CMD: u32 = 0;
+const DM_REMOVE_ALL_CMD: u32 = 1;
+const DM_LIST_DEVICES_CMD: u32 = 2;
+const DM_DEV_CREATE_CMD: u32 = 3;
+const DM_DEV_REMOVE_CMD: u32 = 4;
+const DM_DEV_SUSPEND_CMD: u32 = 6;
+const DM_DEV_STATUS_CMD: u32 = 7;
+const DM_TABLE_LOAD_CMD: u32 = 9;
+const DM_TABLE_CLEAR_CMD: u32 = 10;
+const DM_TABLE_STATUS_CMD: u32 = 12;
+
+const DM_READONLY_FLAG: u32 = 1 << 0;
+const DM_SUSPEND_FLAG: u32 = 1 << 1;
+const DM_STATUS_TABLE_FLAG: u32 = 1 << 4;
+const DM_ACTIVE_PRESENT_FLAG: u32 = 1 << 5;
+const DM_INACTIVE_PRESENT_FLAG: u32 = 1 << 6;
+const DM_BUFFER_FULL_FLAG: u32 = 1 << 8;
+const DM_QUERY_INACTIVE_TABLE_FLAG: u32 = 1 << 12;
+
+/// Upper bound of the buffer passed to the ioctls, as in Linux.
+const DM_MAX_DATA_SIZE: usize = 1 << 20;
+
+/// A range of a mapped device, mapped linearly onto another block device.
+struct Target {
+    /// Start in the mapped device, in bytes.
+    start: u64,
+    /// Length, in bytes.
+    len: u64,
+    dev_id: DeviceId,
+    dev: Arc<dyn DeviceOps>,
+    /// Start in the underlying device, in bytes.
+    offset: u64,
+}
+
+type Table = Arc<Vec<Target>>;
+
+/// A device created by the device mapper.
+struct MappedDevice {
+    name: String,
+    dev_id: DeviceId,
+    live: Mutex<Table>,
+    inactive: Mutex<Option<Table>>,
+    suspended: AtomicBool,
+    read_only: AtomicBool,
+}
+
+impl MappedDevice {
+    /// Splits the byte range `[offset, offset + len)` along the targets of
+    /// the live table, calling `f(target, target_offset, done, len)` for each
+    /// piece, until a piece is transferred only partially. Returns the number
+    /// of bytes transferred.
+    fn remap(
+        &self,
+        offset: u64,
+        len: u64,
+        mut f: impl FnMut(&Target, u64, u64, u64) -> VfsResult<u64>,
+    ) -> VfsResult<u64> {
+        let table = self.live.lock().clone();
+        let mut done = 0;
+        while done < len {
+            let pos = offset + done;
+            let Some(target) = table
+                .iter()
+                .find(|it| (it.start..it.start + it.len).contains(&pos))
+            else {
+                break;
+            };
+            let chunk = (target.start + target.len - pos).min(len - done);
+            let transferred = f(target, target.offset + pos - target.start, done, chunk)?;
+            done += transferred;
+            if transferred < chunk {
+                break;
+            }
+        }
+        Ok(done)
+    }
+
+    fn size(&self) -> u64 {
+        self.live.lock().last().map_or(0, |it| it.start + it.len)
+    }
+}
+
+impl DeviceOps for MappedDevice {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> VfsResult<usize> {
+        let read = self.remap(offset, buf.len() as u64, |target, pos, done, len| {
+            let buf = &mut buf[done as usize..(done + len) as usize];
+            Ok(target.dev.read_at(buf, pos)? as u64)
+        })?;
+        Ok(read as usize)
+    }
+
+    fn write_at(&self, buf: &[u8], offset: u64) -> VfsResult<usize> {
+        if self.read_only.load(Ordering::Acquire) {
+            return Err(AxError::ReadOnlyFilesystem);
+        }
+        let written = self.remap(offset, buf.len() as u64, |target, pos, done, len| {
+            let buf = &buf[done as usize..(done + len) as usize];
+            Ok(target.dev.write_at(buf, pos)? as u64)
+        })?;
+        if written == 0 && !buf.is_empty() {
+            return Err(AxError::StorageFull);
+        }
+        Ok(written as usize)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn block_info(&self) -> Option<BlockInfo> {
+        let table = self.live.lock().clone();
+        let infos = table.iter().filter_map(|it| it.dev.block_info());
+        let (logical_block_size, rotational) = infos.fold((512, false), |(size, rot), info| {
+            (size.max(info.logical_block_size), rot || info.rotational)
+        });
+        Some(BlockInfo {
+            size: self.size(),
+            logical_block_size,
+            rotational,
+            read_only: self.read_only.load(Ordering::Acquire),
+        })
+    }
+
+    fn flags(&self) -> NodeFlags {
+        NodeFlags::NON_CACHEABLE
+    }
+
+    fn flush(&self) -> VfsResult<()> {
+        let table = self.live.lock().clone();
+        for target in table.iter() {
+            match target.dev.flush() {
+                Ok(()) | Err(VfsError::OperationNotSupported) => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+}
+
+static DEVICES: Mutex<BTreeMap<String, Arc<MappedDevice>>> = Mutex::new(BTreeMap::new());
+static NEXT_MINOR: AtomicU32 = AtomicU32::new(0);
+
+/// Looks up a mapped device by its device ID.
+pub fn find_by_id(dev_id: DeviceId) -> Option<Arc<dyn DeviceOps>> {
+    DEVICES
+        .lock()
+        .values()
+        .find(|it| it.dev_id == dev_id)
+        .map(|it| it.clone() as _)
+}
+
+fn notify(action: DeviceAction, device: &MappedDevice) {
+    hotplug::notify(DeviceEvent::new(
+        action,
+        NodeType::BlockDevice,
+        device.dev_id,
+        format!("dm-{}", device.dev_id.minor()),
+    ));
+}
+
+/// A `struct dm_ioctl` along with the data following it.
+struct DmIoctl(Vec<u8>);
+
+impl DmIoctl {
+    fn u32_at(&self, offset: usize) -> u32 {
+        u32::from_ne_bytes(self.0[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn set_u32_at(&mut self, offset: usize, value: u32) {
+        self.0[offset..offset + 4].copy_from_slice(&value.to_ne_bytes());
+    }
+
+    fn data_start(&self) -> usize {
+        self.u32_at(16) as usize
+    }
+
+    fn target_count(&self) -> u32 {
+        self.u32_at(20)
+    }
+
+    fn flags(&self) -> u32 {
+        self.u32_at(28)
+    }
+
+    fn set_flags(&mut self, flags: u32) {
+        self.set_u32_at(28, flags);
+    }
+
+    fn dev(&self) -> u64 {
+        u64::from_ne_bytes(self.0[40..48].try_into().unwrap())
+    }
+
+    fn name(&self) -> VfsResult<&str> {
+        let name = &self.0[48..48 + DM_NAME_LEN];
+        let len = name
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or(AxError::InvalidInput)?;
+        core::str::from_utf8(&name[..len]).map_err(|_| AxError::InvalidInput)
+    }
+
+    fn data(&self) -> &[u8] {
+        &self.0[self.data_start().min(self.0.len())..]
+    }
+
+    /// Writes `data` after the header, or sets `DM_BUFFER_FULL_FLAG` if
+    /// the buffer is too small.
+    fn set_data(&mut self, data: &[u8]) {
+        let start = self.data_start();
+        if start.saturating_add(data.len()) > self.0.len() {
+            self.set_flags(self.flags() | DM_BUFFER_FULL_FLAG);
+            return;
+        }
+        self.0[start..start + data.len()].copy_from_slice(data);
+        // `data_size` reports how much was used
+        self.set_u32_at(12, (start + data.len()) as u32);
+    }
+
+    /// Fills in the status of `device`.
+    fn set_status(&mut self, device: &MappedDevice) {
+        let mut flags = self.flags()
+            & !(DM_SUSPEND_FLAG
+                | DM_READONLY_FLAG
+                | DM_ACTIVE_PRESENT_FLAG
+                | DM_INACTIVE_PRESENT_FLAG);
+        if device.suspended.load(Ordering::Acquire) {
+            flags |= DM_SUSPEND_FLAG;
+        }
+        if device.read_only.load(Ordering::Acquire) {
+            flags |= DM_READONLY_FLAG;
+        }
+        let target_count = device.live.lock().len();
+        if target_count > 0 {
+            flags |= DM_ACTIVE_PRESENT_FLAG;
+        }
+        if device.inactive.lock().is_some() {
+            flags |= DM_INACTIVE_PRESENT_FLAG;
+        }
+        self.set_flags(flags);
+        self.set_u32_at(20, target_count as u32);
+        // open_count and event_nr
+        self.set_u32_at(24, 0);
+        self.set_u32_at(32, 0);
+        self.0[40..48].copy_from_slice(&device.dev_id.0.to_ne_bytes());
+        let name = &mut self.0[48..48 + DM_NAME_LEN];
+        name.fill(0);
+        name[..device.name.len()].copy_from_slice(device.name.as_bytes());
+    }
+
+    /// Finds the device named in the header, or by its device number if no
+    /// name is given.
+    fn find_device(&self) -> VfsResult<Arc<MappedDevice>> {
+        let name = self.name()?;
+        let devices = DEVICES.lock();
+        let device = if name.is_empty() {
+            let dev_id = DeviceId(self.dev());
+            devices.values().find(|it| it.dev_id == dev_id)
+        } else {
+            devices.get(name)
+        };
+        device.cloned().ok_or(AxError::Other(LinuxError::ENXIO))
+    }
+}
+
+/// Resolves the underlying device of a target, given as `major:minor` or as
+/// a path.
+fn lookup_device(spec: &str) -> VfsResult<(DeviceId, Arc<dyn DeviceOps>)> {
+    if let Some((major, minor)) = spec.split_once(':') {
+        let parse = |s: &str| s.parse::<u32>().map_err(|_| AxError::InvalidInput);
+        let dev_id = DeviceId::new(parse(major)?, parse(minor)?);
+        let ops = super::find_block_device_by_id(dev_id).ok_or(AxError::NoSuchDevice)?;
+        return Ok((dev_id, ops));
+    }
+    let loc = FS_CONTEXT.lock().resolve(spec)?;
+    let metadata = loc.metadata()?;
+    let device = loc.entry().downcast::<Device>().ok();
+    match device {
+        Some(device) if metadata.node_type == NodeType::BlockDevice => {
+            Ok((metadata.rdev, device.inner().clone()))
+        }
+        _ => Err(AxError::Other(LinuxError::ENOTBLK)),
+    }
+}
+
+/// Parses the `struct dm_target_spec`s of `DM_TABLE_LOAD` into a table.
+fn parse_table(req: &DmIoctl, device: &MappedDevice) -> VfsResult<Vec<Target>> {
+    let data = req.data();
+    let mut table = Vec::new();
+    let mut pos = 0;
+    let mut end = 0;
+    for i in 0..req.target_count() {
+        let spec = data
+            .get(pos..pos + DM_TARGET_SPEC_SIZE)
+            .ok_or(AxError::InvalidInput)?;
+        let sector_start = u64::from_ne_bytes(spec[0..8].try_into().unwrap());
+        let length = u64::from_ne_bytes(spec[8..16].try_into().unwrap());
+        let next = u32::from_ne_bytes(spec[20..24].try_into().unwrap()) as usize;
+        let target_type = spec[24..40].split(|&b| b == 0).next().unwrap();
+        let params = &data[pos + DM_TARGET_SPEC_SIZE..];
+        let params = params.split(|&b| b == 0).next().unwrap();
+        let params = core::str::from_utf8(params).map_err(|_| AxError::InvalidInput)?;
+
+        if target_type != b"linear" {
+            warn!(
+                "dm: unsupported target type {:?}",
+                core::str::from_utf8(target_type)
+            );
+            return Err(AxError::InvalidInput);
+        }
+        // The targets have to cover the device without gaps
+        if sector_start.checked_mul(512) != Some(end) || length == 0 {
+            return Err(AxError::InvalidInput);
+        }
+        let mut args = params.split_ascii_whitespace();
+        let (Some(dev), Some(offset), None) = (args.next(), args.next(), args.next()) else {
+            return Err(AxError::InvalidInput);
+        };
+        let offset = offset.parse::<u64>().map_err(|_| AxError::InvalidInput)?;
+        let (dev_id, dev) = lookup_device(dev)?;
+        if dev_id == device.dev_id {
+            return Err(AxError::InvalidInput);
+        }
+        let (Some(len), Some(offset)) = (length.checked_mul(512), offset.checked_mul(512)) else {
+            return Err(AxError::InvalidInput);
+        };
+        let size = dev
+            .block_info()
+            .ok_or(AxError::Other(LinuxError::ENOTBLK))?
+            .size;
+        if offset.checked_add(len).is_none_or(|it| it > size) {
+            warn!(
+                "dm: target {i} lies outside of {}:{}",
+                dev_id.major(),
+                dev_id.minor()
+            );
+            return Err(AxError::InvalidInput);
+        }
+        table.push(Target {
+            start: end,
+            len,
+            dev_id,
+            dev,
+            offset,
+        });
+        end = end.checked_add(len).ok_or(AxError::InvalidInput)?;
+        // `next` is relative to the current spec
+        if i + 1 < req.target_count() {
+            pos = pos.checked_add(next).ok_or(AxError::InvalidInput)?;
+            if next < DM_TARGET_SPEC_SIZE {
+                return Err(AxError::InvalidInput);
+            }
+        }
+    }
+    Ok(table)
+}
+
+/// Serializes `table` for `DM_TABLE_STATUS`, with the parameters of each
+/// target if `with_params` is set.
+fn table_status(table: &[Target], with_params: bool) -> Vec<u8> {
+    let mut out = Vec::new();
+    for (i, target) in table.iter().enumerate() {
+        let start = out.len();
+        out.extend_from_slice(&(target.start / 512).to_ne_bytes());
+        out.extend_from_slice(&(target.len / 512).to_ne_bytes());
+        // status
+        out.extend_from_slice(&0i32.to_ne_bytes());
+        // next, filled in below
+        out.extend_from_slice(&0u32.to_ne_bytes());
+        let mut target_type = [0u8; 16];
+        target_type[..6].copy_from_slice(b"linear");
+        out.extend_from_slice(&target_type);
+        if with_params {
+            let params = format!(
+                "{}:{} {}",
+                target.dev_id.major(),
+                target.dev_id.minor(),
+                target.offset / 512
+            );
+            out.extend_from_slice(params.as_bytes());
+        }
+        out.push(0);
+        out.resize(out.len().next_multiple_of(8), 0);
+        // On output, `next` is relative to the start of the data
+        if i + 1 < table.len() {
+            let next = (out.len() as u32).to_ne_bytes();
+            out[start + 20..start + 24].copy_from_slice(&next);
+        }
+    }
+    out
+}
+
+/// Serializes the mapped devices for `DM_LIST_DEVICES`, as a list of
+/// `struct dm_name_list`.
+fn list_devices() -> Vec<u8> {
+    let devices = DEVICES.lock();
+    let mut out = Vec::new();
+    for (i, device) in devices.values().enumerate() {
+        let start = out.len();
+        out.extend_from_slice(&device.dev_id.0.to_ne_bytes());
+        // next, filled in below
+        out.extend_from_slice(&0u32.to_ne_bytes());
+        out.extend_from_slice(device.name.as_bytes());
+        out.push(0);
+        out.resize(out.len().next_multiple_of(8), 0);
+        if i + 1 < devices.len() {
+            let next = ((out.len() - start) as u32).to_ne_bytes();
+            out[start + 8..start + 12].copy_from_slice(&next);
+        }
+    }
+    if out.is_empty() {
+        // An empty list is a single entry with `dev` 0
+        out.resize(16, 0);
+    }
+    out
+}
+
+/// /dev/mapper/control
+pub struct DmControl;
+
+impl DmControl {
+    fn handle(&self, nr: u32, req: &mut DmIoctl) -> VfsResult<()> {
+        match nr {
+            DM_VERSION_CMD => {}
+            DM_REMOVE_ALL_CMD => {
+                let devices = core::mem::take(&mut *DEVICES.lock());
+                for device in devices.values() {
+                    notify(DeviceAction::Remove, device);
+                }
+            }
+            DM_LIST_DEVICES_CMD => {
+                req.set_data(&list_devices());
+            }
+            DM_DEV_CREATE_CMD => {
+                let name = req.name()?;
+                if name.is_empty() || name.contains('/') || name == "control" {
+                    return Err(AxError::InvalidInput);
+                }
+                let mut devices = DEVICES.lock();
+                if devices.contains_key(name) {
+                    return Err(AxError::ResourceBusy);
+                }
+                let minor = NEXT_MINOR.fetch_add(1, Ordering::Relaxed);
+                let device = Arc::new(MappedDevice {
+                    name: name.to_string(),
+                    dev_id: DeviceId::new(DM_MAJOR, minor),
+                    live: Mutex::new(Arc::default()),
+                    inactive: Mutex::new(None),
+                    suspended: AtomicBool::new(false),
+                    read_only: AtomicBool::new(false),
+                });
+                devices.insert(device.name.clone(), device.clone());
+                drop(devices);
+                notify(DeviceAction::Add, &device);
+                req.set_status(&device);
+            }
+            DM_DEV_REMOVE_CMD => {
+                let device = req.find_device()?;
+                DEVICES.lock().remove(&device.name);
+                notify(DeviceAction::Remove, &device);
+                req.set_status(&device);
+            }
+            DM_DEV_SUSPEND_CMD => {
+                let device = req.find_device()?;
+                if req.flags() & DM_SUSPEND_FLAG != 0 {
+                    device.suspended.store(true, Ordering::Release);
+                } else {
+                    // Resuming swaps in the loaded table
+                    if let Some(table) = device.inactive.lock().take() {
+                        *device.live.lock() = table;
+                        notify(DeviceAction::Change, &device);
+                    }
+                    device.suspended.store(false, Ordering::Release);
+                }
+                req.set_status(&device);
+            }
+            DM_DEV_STATUS_CMD => {
+                let device = req.find_device()?;
+                req.set_status(&device);
+            }
+            DM_TABLE_LOAD_CMD => {
+                let device = req.find_device()?;
+                let table = parse_table(req, &device)?;
+                *device.inactive.lock() = Some(Arc::new(table));
+                device
+                    .read_only
+                    .store(req.flags() & DM_READONLY_FLAG != 0, Ordering::Release);
+                req.set_status(&device);
+            }
+            DM_TABLE_CLEAR_CMD => {
+                let device = req.find_device()?;
+                device.inactive.lock().take();
+                req.set_status(&device);
+            }
+            DM_TABLE_STATUS_CMD => {
+                let device = req.find_device()?;
+                let flags = req.flags();
+                req.set_status(&device);
+                let table = if flags & DM_QUERY_INACTIVE_TABLE_FLAG != 0 {
+                    device.inactive.lock().clone().unwrap_or_default()
+                } else {
+                    device.live.lock().clone()
+                };
+                req.set_u32_at(20, table.len() as u32);
+                req.set_data(&table_status(&table, flags & DM_STATUS_TABLE_FLAG != 0));
+            }
+            _ => {
+                warn!("dm: unsupported command {nr}");
+                return Err(AxError::NotATty);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl DeviceOps for DmControl {
+    fn read_at(&self, _buf: &mut [u8], _offset: u64) -> VfsResult<usize> {
+        Err(AxError::InvalidInput)
+    }
+
+    fn write_at(&self, _buf: &[u8], _offset: u64) -> VfsResult<usize> {
+        Err(AxError::InvalidInput)
+    }
+
+    fn ioctl(&self, cmd: u32, arg: usize) -> VfsResult<usize> {
+        if (cmd >> 8) & 0xff != DM_IOCTL {
+            return Err(AxError::NotATty);
+        }
+        let nr = cmd & 0xff;
+
+        let header = DmIoctl(vm_load(arg as *const u8, DM_IOCTL_SIZE)?);
+        if header.u32_at(0) != DM_VERSION[0] {
+            return Err(AxError::InvalidInput);
+        }
+        let data_size = header.u32_at(12) as usize;
+        if !(DM_IOCTL_SIZE..=DM_MAX_DATA_SIZE).contains(&data_size) {
+            return Err(AxError::InvalidInput);
+        }
+        let mut req = DmIoctl(vm_load(arg as *const u8, data_size)?);
+        req.set_flags(req.flags() & !DM_BUFFER_FULL_FLAG);
+
+        self.handle(nr, &mut req)?;
+        for (i, version) in DM_VERSION.into_iter().enumerate() {
+            req.set_u32_at(i * 4, version);
+        }
+        vm_write_slice(arg as *mut u8, &req.0)?;
+        Ok(0)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn flags(&self) -> NodeFlags {
+        NodeFlags::NON_CACHEABLE
+    }
+}
+
+/// /dev/mapper directory
+pub struct MapperDir {
+    fs: Arc<SimpleFs>,
+    control: Arc<Device>,
+}
+
+impl MapperDir {
+    pub fn new(fs: Arc<SimpleFs>) -> Self {
+        let control = Device::new(
+            fs.clone(),
+            NodeType::CharacterDevice,
+            DM_CONTROL_DEVICE_ID,
+            Arc::new(DmControl),
+        );
+        Self { fs, control }
+    }
+}
+
+impl SimpleDirOps for MapperDir {
+    fn child_names<'a>(&'a self) -> Box<dyn Iterator<Item = Cow<'a, str>> + 'a> {
+        let names = DEVICES.lock().keys().cloned().collect::<Vec<_>>();
+        Box::new(
+            core::iter::once(Cow::Borrowed("control")).chain(names.into_iter().map(Cow::Owned)),
+        )
+    }
+
+    fn lookup_child(&self, name: &str) -> VfsResult<NodeOpsMux> {
+        if name == "control" {
+            return Ok(NodeOpsMux::File(self.control.clone()));
+        }
+        let device = DEVICES
+            .lock()
+            .get(name)
+            .cloned()
+            .ok_or(VfsError::NotFound)?;
+        Ok(NodeOpsMux::File(Device::new(
+            self.fs.clone(),
+            NodeType::BlockDevice,
+            device.dev_id,
+            device,
+        )))
+    }
+
+    fn is_cacheable(&self) -> bool {
+        false
+    }
+}
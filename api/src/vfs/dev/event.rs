@@ -1,4 +1,4 @@
-use alloc::{format, sync::Arc};
+use alloc::{collections::vec_deque::VecDeque, format, sync::Arc, vec::Vec};
 use core::{any::Any, task::Context, time::Duration};
 
 #[allow(unused_imports)]
@@ -7,7 +7,6 @@ use axdriver::prelude::{
 };
 use axerrno::{AxError, AxResult};
 use axfs_ng_vfs::{DeviceId, NodeFlags, NodeType, VfsResult};
-use axhal::time::wall_time;
 use axpoll::{IoEvents, Pollable};
 use axsync::Mutex;
 use bitmaps::Bitmap;
@@ -15,20 +14,35 @@ use linux_raw_sys::{
     general::{__kernel_old_time_t, __kernel_suseconds_t},
     ioctl::{EVIOCGID, EVIOCGRAB, EVIOCGVERSION},
 };
-use starry_core::vfs::{Device, DeviceOps, DirMapping, SimpleFs};
+use starry_core::{
+    time::realtime,
+    vfs::{Device, DeviceOps, DirMapping, SimpleFs},
+};
 use zerocopy::{FromBytes, Immutable, IntoBytes};
 
-use crate::mm::UserPtr;
+use crate::{
+    mm::UserPtr,
+    vfs::dev::tty::{
+        console::{self, ConsoleDriver},
+        vt,
+    },
+};
 const KEY_CNT: usize = EventType::Key.bits_count();
+/// The number of events buffered for readers of the device, after which the
+/// oldest ones are dropped.
+const EVENT_BUF_SIZE: usize = 64;
 
 struct Inner {
     device: AxInputDevice,
-    read_ahead: Option<(Duration, Event)>,
+    events: VecDeque<(Duration, Event)>,
     key_state: Bitmap<KEY_CNT>,
+    /// Whether key events are also sent to the console.
+    keyboard: bool,
 }
 impl Inner {
-    fn has_event(&mut self) -> bool {
-        if self.read_ahead.is_none() {
+    /// Moves the pending events of the device into the buffer.
+    fn fetch_events(&mut self) {
+        loop {
             match self.device.read_event() {
                 Ok(event) => {
                     if event.event_type == EventType::Key as u16 {
@@ -37,16 +51,27 @@ impl Inner {
                         } else if event.value == 1 {
                             self.key_state.set(event.code as usize, true);
                         }
+                        if self.keyboard {
+                            vt::handle_key(event.code, event.value as _);
+                        }
+                    }
+                    if self.events.len() == EVENT_BUF_SIZE {
+                        self.events.pop_front();
                     }
-                    self.read_ahead = Some((wall_time(), event));
+                    self.events.push_back((realtime(), event));
                 }
-                Err(DevError::Again) => {}
+                Err(DevError::Again) => break,
                 Err(err) => {
                     warn!("Failed to read event: {err:?}");
+                    break;
                 }
             }
         }
-        self.read_ahead.is_some()
+    }
+
+    fn has_event(&mut self) -> bool {
+        self.fetch_events();
+        !self.events.is_empty()
     }
 }
 
@@ -56,7 +81,7 @@ pub struct EventDev {
 }
 
 impl EventDev {
-    pub fn new(mut device: AxInputDevice) -> Self {
+    pub fn new(mut device: AxInputDevice, keyboard: bool) -> Self {
         let mut ev_bits = Bitmap::new();
         for i in 0..EventType::COUNT {
             let Some(ty) = EventType::from_repr(i) else {
@@ -85,8 +110,9 @@ impl EventDev {
         Self {
             inner: Mutex::new(Inner {
                 device,
-                read_ahead: None,
+                events: VecDeque::new(),
                 key_state: Bitmap::new(),
+                keyboard,
             }),
             ev_bits,
         }
@@ -165,7 +191,7 @@ impl DeviceOps for EventDev {
             if !inner.has_event() {
                 break;
             }
-            let Some((time, event)) = inner.read_ahead.take() else {
+            let Some((time, event)) = inner.events.pop_front() else {
                 break;
             };
             let input_event = InputEvent {
@@ -319,29 +345,49 @@ impl Pollable for EventDev {
     }
 }
 
+/// The keyboards as a console, which only provides input.
+struct KeyboardConsole(Vec<Arc<EventDev>>);
+
+impl ConsoleDriver for KeyboardConsole {
+    fn write(&self, _buf: &[u8]) {}
+
+    fn read(&self, buf: &mut [u8]) -> usize {
+        for keyboard in &self.0 {
+            keyboard.inner.lock().fetch_events();
+        }
+        vt::read_input(buf)
+    }
+}
+
 pub fn input_devices(fs: Arc<SimpleFs>) -> DirMapping {
     let mut inputs = DirMapping::new();
     let mut input_id = 0;
+    let mut keyboards = Vec::new();
     let input_devices = axinput::take_inputs();
     let mut keys = [0; 0x300usize.div_ceil(8)];
     for (i, mut device) in input_devices.into_iter().enumerate() {
         assert!(device.get_event_bits(EventType::Key, &mut keys).unwrap());
 
+        const BTN_MOUSE: usize = 0x110;
+        let mouse = keys[BTN_MOUSE / 8] & (1 << (BTN_MOUSE % 8)) != 0;
+        let event_dev = Arc::new(EventDev::new(device, !mouse));
         let dev = Device::new(
             fs.clone(),
             NodeType::CharacterDevice,
             DeviceId::new(13, (i + 1) as _),
-            Arc::new(EventDev::new(device)),
+            event_dev.clone(),
         );
 
-        const BTN_MOUSE: usize = 0x110;
-        if keys[BTN_MOUSE / 8] & (1 << (BTN_MOUSE % 8)) != 0 {
-            // Mouse
+        if mouse {
             inputs.add("mice", dev);
         } else {
             inputs.add(format!("event{input_id}"), dev);
             input_id += 1;
+            keyboards.push(event_dev);
         }
     }
+    if !keyboards.is_empty() {
+        console::register(vt::VT_CONSOLE, Arc::new(KeyboardConsole(keyboards)));
+    }
     inputs
 }
@@ -0,0 +1,28 @@
+use core::any::Any;
+
+use axerrno::AxResult;
+use starry_core::vfs::DeviceOps;
+
+/// /dev/fuse
+///
+/// Every open of this device yields a new [`crate::file::FuseDev`] file, so
+/// these operations are never called.
+pub struct FuseClone;
+
+impl DeviceOps for FuseClone {
+    fn read_at(&self, _buf: &mut [u8], _offset: u64) -> AxResult<usize> {
+        unreachable!()
+    }
+
+    fn write_at(&self, _buf: &[u8], _offset: u64) -> AxResult<usize> {
+        unreachable!()
+    }
+
+    fn ioctl(&self, _cmd: u32, _arg: usize) -> AxResult<usize> {
+        unreachable!()
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
@@ -0,0 +1,24 @@
+use core::any::Any;
+
+use axerrno::AxResult;
+use starry_core::vfs::DeviceOps;
+
+/// /dev/device-events
+///
+/// Every open of this device yields a new [`crate::file::DeviceEvents`]
+/// file, so these operations are never called.
+pub struct DeviceEventsClone;
+
+impl DeviceOps for DeviceEventsClone {
+    fn read_at(&self, _buf: &mut [u8], _offset: u64) -> AxResult<usize> {
+        unreachable!()
+    }
+
+    fn write_at(&self, _buf: &[u8], _offset: u64) -> AxResult<usize> {
+        unreachable!()
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
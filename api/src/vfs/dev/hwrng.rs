@@ -0,0 +1,128 @@
+use alloc::sync::Arc;
+use core::any::Any;
+
+use axerrno::{AxError, AxResult, LinuxError};
+use axfs_ng_vfs::{DeviceId, NodeFlags, VfsResult};
+use starry_core::{
+    hwrng::{self, HwRng},
+    vfs::DeviceOps,
+};
+
+/// The device ID for /dev/hwrng
+pub const HWRNG_DEVICE_ID: DeviceId = DeviceId::new(10, 183);
+
+/// The random number generator instruction of the CPU (`RDRAND` on x86_64,
+/// `RNDR` on AArch64).
+struct CpuRng;
+
+impl CpuRng {
+    #[cfg(target_arch = "x86_64")]
+    fn is_supported() -> bool {
+        // CPUID.01H:ECX.RDRAND[bit 30]
+        let leaf = unsafe { core::arch::x86_64::__cpuid(1) };
+        leaf.ecx & (1 << 30) != 0
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    fn is_supported() -> bool {
+        // ID_AA64ISAR0_EL1.RNDR, bits [63:60]
+        let isar0: u64;
+        unsafe { core::arch::asm!("mrs {}, id_aa64isar0_el1", out(reg) isar0) };
+        (isar0 >> 60) & 0xf != 0
+    }
+
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    fn is_supported() -> bool {
+        false
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn next_u64() -> Option<u64> {
+        #[target_feature(enable = "rdrand")]
+        unsafe fn rdrand() -> Option<u64> {
+            let mut value = 0;
+            (unsafe { core::arch::x86_64::_rdrand64_step(&mut value) } == 1).then_some(value)
+        }
+        unsafe { rdrand() }
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    fn next_u64() -> Option<u64> {
+        let value: u64;
+        let failed: u64;
+        // RNDR sets NZCV to 0b0100 if no random number is available in
+        // reasonable time.
+        unsafe {
+            core::arch::asm!(
+                "mrs {value}, s3_3_c2_c4_0",
+                "cset {failed}, eq",
+                value = out(reg) value,
+                failed = out(reg) failed,
+                options(nomem, nostack),
+            )
+        };
+        (failed == 0).then_some(value)
+    }
+
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    fn next_u64() -> Option<u64> {
+        None
+    }
+}
+
+impl HwRng for CpuRng {
+    fn name(&self) -> &str {
+        "cpu"
+    }
+
+    fn read(&self, buf: &mut [u8]) -> AxResult<usize> {
+        let mut read = 0;
+        for chunk in buf.chunks_mut(8) {
+            // The instructions may transiently fail when the entropy source
+            // is drained; retry a few times as recommended by Intel.
+            let Some(value) = (0..10).find_map(|_| Self::next_u64()) else {
+                break;
+            };
+            chunk.copy_from_slice(&value.to_ne_bytes()[..chunk.len()]);
+            read += chunk.len();
+        }
+        if read == 0 && !buf.is_empty() {
+            return Err(AxError::WouldBlock);
+        }
+        Ok(read)
+    }
+}
+
+/// Registers the random number generator of the CPU, if any.
+pub fn init() {
+    if CpuRng::is_supported() {
+        hwrng::register(Arc::new(CpuRng));
+    }
+}
+
+/// Returns whether a hardware random number generator is available.
+pub fn is_available() -> bool {
+    hwrng::current().is_some()
+}
+
+/// /dev/hwrng, reading from the current hardware random number generator.
+pub struct HwRngDevice;
+
+impl DeviceOps for HwRngDevice {
+    fn read_at(&self, buf: &mut [u8], _offset: u64) -> VfsResult<usize> {
+        let rng = hwrng::current().ok_or(AxError::Other(LinuxError::ENODEV))?;
+        rng.read(buf)
+    }
+
+    fn write_at(&self, _buf: &[u8], _offset: u64) -> VfsResult<usize> {
+        Err(AxError::InvalidInput)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn flags(&self) -> NodeFlags {
+        NodeFlags::NON_CACHEABLE | NodeFlags::STREAM
+    }
+}
@@ -1,3 +1,4 @@
+use alloc::{format, string::String};
 use core::{
     any::Any,
     sync::atomic::{AtomicBool, AtomicU32, Ordering},
@@ -5,15 +6,19 @@ use core::{
 
 use axerrno::{AxError, AxResult, LinuxError};
 use axfs_ng::FileBackend;
-use axfs_ng_vfs::{DeviceId, NodeFlags, VfsResult};
+use axfs_ng_vfs::{DeviceId, NodeFlags, NodeType, VfsResult};
 use axsync::Mutex;
 use linux_raw_sys::{
-    ioctl::{BLKGETSIZE, BLKGETSIZE64, BLKRAGET, BLKRASET, BLKROGET, BLKROSET},
+    ioctl::{BLKRAGET, BLKRASET, BLKROGET, BLKROSET, BLKRRPART},
     loop_device::{LOOP_CLR_FD, LOOP_GET_STATUS, LOOP_SET_FD, LOOP_SET_STATUS, loop_info},
 };
-use starry_core::vfs::{DeviceMmap, DeviceOps};
+use starry_core::{
+    hotplug::{self, DeviceAction, DeviceEvent},
+    vfs::{BlockInfo, DeviceMmap, DeviceOps},
+};
 use starry_vm::{VmMutPtr, VmPtr};
 
+use super::{find_block_device, partition};
 use crate::file::get_file_like;
 
 /// /dev/loopX devices
@@ -55,6 +60,26 @@ impl LoopDevice {
         Ok(())
     }
 
+    fn name(&self) -> String {
+        format!("loop{}", self.number)
+    }
+
+    /// Rereads the partition table of the bound file.
+    fn scan_partitions(&self) -> VfsResult<usize> {
+        let name = self.name();
+        let (_, ops) = find_block_device(&name).ok_or(AxError::NoSuchDevice)?;
+        partition::rescan(&name, ops)
+    }
+
+    fn notify_change(&self) {
+        hotplug::notify(DeviceEvent::new(
+            DeviceAction::Change,
+            NodeType::BlockDevice,
+            self.dev_id,
+            self.name(),
+        ));
+    }
+
     /// Clone the underlying file of the loop device.
     pub fn clone_file(&self) -> VfsResult<FileBackend> {
         let file = self.file.lock().clone();
@@ -95,6 +120,11 @@ impl DeviceOps for LoopDevice {
                 }
 
                 *guard = Some(file.inner().backend()?.clone());
+                drop(guard);
+                self.notify_change();
+                if let Err(err) = self.scan_partitions() {
+                    warn!("{}: partition scan failed: {err:?}", self.name());
+                }
             }
             LOOP_CLR_FD => {
                 let mut guard = self.file.lock();
@@ -102,6 +132,9 @@ impl DeviceOps for LoopDevice {
                     return Err(AxError::Other(LinuxError::ENXIO));
                 }
                 *guard = None;
+                drop(guard);
+                partition::remove(&self.name());
+                self.notify_change();
             }
             LOOP_GET_STATUS => {
                 (arg as *mut loop_info).vm_write(self.get_info()?)?;
@@ -111,15 +144,11 @@ impl DeviceOps for LoopDevice {
                 let info = unsafe { (arg as *const loop_info).vm_read_uninit()?.assume_init() };
                 self.set_info(info)?;
             }
-            // TODO: the following should apply to any block devices
-            BLKGETSIZE | BLKGETSIZE64 => {
-                let file = self.clone_file()?;
-                let sectors = file.location().len()? / 512;
-                if cmd == BLKGETSIZE {
-                    (arg as *mut u32).vm_write(sectors as _)?;
-                } else {
-                    (arg as *mut u64).vm_write(sectors * 512)?;
+            BLKRRPART => {
+                if self.file.lock().is_none() {
+                    return Err(AxError::Other(LinuxError::ENXIO));
                 }
+                self.scan_partitions()?;
             }
             BLKROGET => {
                 (arg as *mut u32).vm_write(self.ro.load(Ordering::Relaxed) as u32)?;
@@ -150,6 +179,22 @@ impl DeviceOps for LoopDevice {
         self
     }
 
+    fn block_info(&self) -> Option<BlockInfo> {
+        // An unbound loop device has a size of 0.
+        let size = self
+            .file
+            .lock()
+            .as_ref()
+            .and_then(|file| file.location().len().ok())
+            .unwrap_or(0);
+        Some(BlockInfo {
+            size,
+            logical_block_size: 512,
+            rotational: false,
+            read_only: self.ro.load(Ordering::Relaxed),
+        })
+    }
+
     fn mmap(&self, _handle: u64) -> DeviceMmap {
         if let Some(FileBackend::Cached(cache)) = self.file.lock().as_ref() {
             DeviceMmap::Cache(cache.clone())
@@ -161,4 +206,12 @@ impl DeviceOps for LoopDevice {
     fn flags(&self) -> NodeFlags {
         NodeFlags::NON_CACHEABLE
     }
+
+    fn flush(&self) -> VfsResult<()> {
+        let file = self.file.lock().clone();
+        match file {
+            Some(file) => file.sync(false),
+            None => Ok(()),
+        }
+    }
 }
@@ -0,0 +1,108 @@
+use core::any::Any;
+
+use axerrno::AxError;
+use axfs_ng_vfs::{DeviceId, NodeFlags, VfsResult};
+use axhal::mem::{mmio_ranges, phys_to_virt};
+use axtask::current;
+use memory_addr::{PhysAddr, PhysAddrRange};
+use starry_core::{
+    task::AsThread,
+    vfs::{DeviceMmap, DeviceOps},
+};
+
+/// The device ID for /dev/mem
+pub const MEM_DEVICE_ID: DeviceId = DeviceId::new(1, 1);
+
+/// Fails with `EPERM` unless the current process is privileged, which is
+/// required to open and access `/dev/mem`.
+pub fn check_access() -> VfsResult<()> {
+    if current().as_thread().proc_data.cred.read().is_privileged() {
+        Ok(())
+    } else {
+        Err(AxError::OperationNotPermitted)
+    }
+}
+
+/// Returns the accessible physical range starting at `offset`.
+///
+/// Only device memory (MMIO) ranges of the platform are accessible, like
+/// Linux with `CONFIG_STRICT_DEVMEM`, so that RAM owned by the kernel can't be
+/// corrupted.
+fn accessible_range(offset: u64) -> VfsResult<PhysAddrRange> {
+    let addr = usize::try_from(offset).map_err(|_| AxError::InvalidInput)?;
+    mmio_ranges()
+        .iter()
+        .find(|(start, size)| (*start..*start + *size).contains(&addr))
+        .map(|(start, size)| {
+            PhysAddrRange::from_start_size(PhysAddr::from(addr), start + size - addr)
+        })
+        .ok_or(AxError::OperationNotPermitted)
+}
+
+/// Physical memory device.
+///
+/// Accesses are performed with the widest naturally aligned volatile loads and
+/// stores possible, since device registers often require 32-bit accesses.
+pub struct Mem;
+
+impl DeviceOps for Mem {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> VfsResult<usize> {
+        check_access()?;
+        let range = accessible_range(offset)?;
+        let len = buf.len().min(range.size());
+        let src = phys_to_virt(range.start).as_ptr();
+        let mut i = 0;
+        while i < len {
+            unsafe {
+                if (src as usize + i) % 4 == 0 && len - i >= 4 {
+                    let val = src.add(i).cast::<u32>().read_volatile();
+                    buf[i..i + 4].copy_from_slice(&val.to_ne_bytes());
+                    i += 4;
+                } else {
+                    buf[i] = src.add(i).read_volatile();
+                    i += 1;
+                }
+            }
+        }
+        Ok(len)
+    }
+
+    fn write_at(&self, buf: &[u8], offset: u64) -> VfsResult<usize> {
+        check_access()?;
+        let range = accessible_range(offset)?;
+        let len = buf.len().min(range.size());
+        let dst = phys_to_virt(range.start).as_mut_ptr();
+        let mut i = 0;
+        while i < len {
+            unsafe {
+                if (dst as usize + i) % 4 == 0 && len - i >= 4 {
+                    let val = u32::from_ne_bytes(buf[i..i + 4].try_into().unwrap());
+                    dst.add(i).cast::<u32>().write_volatile(val);
+                    i += 4;
+                } else {
+                    dst.add(i).write_volatile(buf[i]);
+                    i += 1;
+                }
+            }
+        }
+        Ok(len)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn mmap(&self, offset: u64) -> DeviceMmap {
+        if check_access().is_err() {
+            return DeviceMmap::None;
+        }
+        match accessible_range(offset) {
+            Ok(range) => DeviceMmap::Physical(range),
+            Err(_) => DeviceMmap::None,
+        }
+    }
+
+    fn flags(&self) -> NodeFlags {
+        NodeFlags::NON_CACHEABLE
+    }
+}
@@ -3,21 +3,28 @@
 #[cfg(feature = "input")]
 mod event;
 mod fb;
+pub mod fuse;
+pub mod hotplug;
+pub mod hwrng;
 #[cfg(feature = "dev-log")]
 mod log;
 mod r#loop;
+pub mod mem;
 #[cfg(feature = "memtrack")]
 mod memtrack;
+mod partition;
 mod rtc;
 pub mod tty;
+pub mod watchdog;
 
-mod dma_heap;
 pub mod card0;
 pub mod card1;
+mod dm;
+mod dma_heap;
 // mod rtc;
 pub mod drm;
 
-use alloc::{format, sync::Arc};
+use alloc::{format, string::String, sync::Arc, vec::Vec};
 use core::any::Any;
 
 use axerrno::AxError;
@@ -26,10 +33,65 @@ use axsync::Mutex;
 #[cfg(feature = "dev-log")]
 pub use log::bind_dev_log;
 use rand::{RngCore, SeedableRng, rngs::SmallRng};
-use starry_core::vfs::{Device, DeviceOps, DirMaker, DirMapping, SimpleDir, SimpleFs};
+use starry_core::{
+    hotplug::{DeviceAction, DeviceEvent},
+    vfs::{Device, DeviceOps, DirMaker, DirMapping, SimpleDir, SimpleFs},
+};
 
 const RANDOM_SEED: &[u8; 32] = b"0123456789abcdef0123456789abcdef";
 
+/// Block devices exposed in devfs, kept around so they can be flushed on
+/// shutdown and listed in sysfs.
+static BLOCK_DEVICES: Mutex<Vec<(String, DeviceId, Arc<dyn DeviceOps>)>> = Mutex::new(Vec::new());
+
+fn register_block_device(
+    name: &str,
+    dev_id: DeviceId,
+    ops: Arc<dyn DeviceOps>,
+) -> Arc<dyn DeviceOps> {
+    BLOCK_DEVICES
+        .lock()
+        .push((name.into(), dev_id, ops.clone()));
+    starry_core::hotplug::notify(DeviceEvent::new(
+        DeviceAction::Add,
+        NodeType::BlockDevice,
+        dev_id,
+        name,
+    ));
+    ops
+}
+
+/// Returns all registered block devices along with their names.
+pub fn block_devices() -> Vec<(String, Arc<dyn DeviceOps>)> {
+    BLOCK_DEVICES
+        .lock()
+        .iter()
+        .map(|(name, _, ops)| (name.clone(), ops.clone()))
+        .collect()
+}
+
+/// Looks up a registered block device by name.
+pub fn find_block_device(name: &str) -> Option<(DeviceId, Arc<dyn DeviceOps>)> {
+    BLOCK_DEVICES
+        .lock()
+        .iter()
+        .find(|(it, ..)| it == name)
+        .map(|(_, dev_id, ops)| (*dev_id, ops.clone()))
+}
+
+/// Looks up a block device by its device ID, including partitions and
+/// mapped devices.
+pub fn find_block_device_by_id(dev_id: DeviceId) -> Option<Arc<dyn DeviceOps>> {
+    let found = BLOCK_DEVICES
+        .lock()
+        .iter()
+        .find(|(_, it, _)| *it == dev_id)
+        .map(|(.., ops)| ops.clone());
+    found
+        .or_else(|| partition::find_by_id(dev_id))
+        .or_else(|| dm::find_by_id(dev_id))
+}
+
 pub(crate) fn new_devfs() -> Filesystem {
     SimpleFs::new_with("devfs".into(), 0x01021994, builder)
 }
@@ -148,7 +210,18 @@ impl DeviceOps for CpuDmaLatency {
 }
 
 fn builder(fs: Arc<SimpleFs>) -> DirMaker {
+    hwrng::init();
+
     let mut root = DirMapping::new();
+    root.add(
+        "mem",
+        Device::new(
+            fs.clone(),
+            NodeType::CharacterDevice,
+            mem::MEM_DEVICE_ID,
+            Arc::new(mem::Mem),
+        ),
+    );
     root.add(
         "null",
         Device::new(
@@ -194,6 +267,15 @@ fn builder(fs: Arc<SimpleFs>) -> DirMaker {
             Arc::new(Random::new()),
         ),
     );
+    root.add(
+        "hwrng",
+        Device::new(
+            fs.clone(),
+            NodeType::CharacterDevice,
+            hwrng::HWRNG_DEVICE_ID,
+            Arc::new(hwrng::HwRngDevice),
+        ),
+    );
     root.add(
         "rtc0",
         Device::new(
@@ -234,6 +316,28 @@ fn builder(fs: Arc<SimpleFs>) -> DirMaker {
         ),
     );
 
+    root.add(
+        "tty1",
+        Device::new(
+            fs.clone(),
+            NodeType::CharacterDevice,
+            DeviceId::new(4, 1),
+            tty::N_TTY.clone(),
+        ),
+    );
+    for vt in tty::vt::VTS.iter() {
+        let number = tty::vt::vt_number(vt);
+        root.add(
+            format!("tty{number}"),
+            Device::new(
+                fs.clone(),
+                NodeType::CharacterDevice,
+                DeviceId::new(4, number),
+                vt.clone(),
+            ),
+        );
+    }
+
     root.add(
         "ptmx",
         Device::new(
@@ -247,6 +351,15 @@ fn builder(fs: Arc<SimpleFs>) -> DirMaker {
         "pts",
         SimpleDir::new_maker(fs.clone(), Arc::new(tty::PtsDir)),
     );
+    root.add(
+        "fuse",
+        Device::new(
+            fs.clone(),
+            NodeType::CharacterDevice,
+            crate::file::FUSE_DEVICE_ID,
+            Arc::new(fuse::FuseClone),
+        ),
+    );
     #[cfg(feature = "dev-log")]
     root.add(
         "log",
@@ -274,13 +387,32 @@ fn builder(fs: Arc<SimpleFs>) -> DirMaker {
         ),
     );
 
+    root.add(
+        "watchdog",
+        Device::new(
+            fs.clone(),
+            NodeType::CharacterDevice,
+            crate::file::WATCHDOG_DEVICE_ID,
+            Arc::new(watchdog::WatchdogClone),
+        ),
+    );
+
+    root.add(
+        "device-events",
+        Device::new(
+            fs.clone(),
+            NodeType::CharacterDevice,
+            crate::file::DEVICE_EVENTS_DEVICE_ID,
+            Arc::new(hotplug::DeviceEventsClone),
+        ),
+    );
+
     // This is mounted to a tmpfs in `new_procfs`
     root.add(
         "shm",
         SimpleDir::new_maker(fs.clone(), Arc::new(DirMapping::new())),
     );
 
-    
     // DMA heap devices
     let mut dma_heap_dir = DirMapping::new();
     dma_heap_dir.add(
@@ -317,25 +449,26 @@ fn builder(fs: Arc<SimpleFs>) -> DirMaker {
             Arc::new(card1::Card1::new()),
         ),
     );
-    root.add(
-        "dri",
-        SimpleDir::new_maker(fs.clone(), Arc::new(dri_dir)),
-    );
+    root.add("dri", SimpleDir::new_maker(fs.clone(), Arc::new(dri_dir)));
 
     // Loop devices
     for i in 0..16 {
-        let dev_id = DeviceId::new(7, 0);
+        let dev_id = DeviceId::new(7, i);
+        let name = format!("loop{i}");
+        let ops =
+            register_block_device(&name, dev_id, Arc::new(r#loop::LoopDevice::new(i, dev_id)));
         root.add(
-            format!("loop{i}"),
-            Device::new(
-                fs.clone(),
-                NodeType::BlockDevice,
-                dev_id,
-                Arc::new(r#loop::LoopDevice::new(i, dev_id)),
-            ),
+            name,
+            Device::new(fs.clone(), NodeType::BlockDevice, dev_id, ops),
         );
     }
 
+    // Device mapper
+    root.add(
+        "mapper",
+        SimpleDir::new_maker(fs.clone(), Arc::new(dm::MapperDir::new(fs.clone()))),
+    );
+
     // Input devices
     #[cfg(feature = "input")]
     root.add(
@@ -343,5 +476,6 @@ fn builder(fs: Arc<SimpleFs>) -> DirMaker {
         SimpleDir::new_maker(fs.clone(), Arc::new(event::input_devices(fs.clone()))),
     );
 
-    SimpleDir::new_maker(fs, Arc::new(root))
+    let partitions = partition::PartitionDir::new(fs.clone());
+    SimpleDir::new_maker(fs, Arc::new(root.chain(partitions)))
 }
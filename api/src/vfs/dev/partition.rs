@@ -0,0 +1,388 @@
+//! Partitions of block devices, found in their GPT or MBR partition table and
+//! exposed as `/dev/<disk>pN` (or `/dev/<disk>N`).
+
+use alloc::{
+    borrow::Cow, boxed::Box, collections::btree_map::BTreeMap, format, string::String, sync::Arc,
+    vec, vec::Vec,
+};
+use core::{
+    any::Any,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use axerrno::{AxError, LinuxError};
+use axfs_ng_vfs::{DeviceId, NodeFlags, NodeType, VfsError, VfsResult};
+use axsync::Mutex;
+use starry_core::{
+    hotplug::{self, DeviceAction, DeviceEvent},
+    vfs::{BlockInfo, Device, DeviceOps, NodeOpsMux, SimpleDirOps, SimpleFs},
+};
+
+/// Major number of dynamically numbered block devices (`blkext`).
+const BLOCK_EXT_MAJOR: u32 = 259;
+
+/// Maximum number of logical partitions followed in an MBR extended
+/// partition, guarding against cyclic chains.
+const MAX_LOGICAL_PARTITIONS: usize = 64;
+
+/// A partition of a disk.
+struct Partition {
+    disk: Arc<dyn DeviceOps>,
+    /// Offset into the disk, in bytes.
+    start: u64,
+    /// Size of the partition, in bytes.
+    size: u64,
+}
+
+impl Partition {
+    /// Clamps a request of `len` bytes at `offset` to the partition.
+    fn clamp(&self, offset: u64, len: usize) -> usize {
+        self.size.saturating_sub(offset).min(len as u64) as usize
+    }
+}
+
+impl DeviceOps for Partition {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> VfsResult<usize> {
+        let len = self.clamp(offset, buf.len());
+        if len == 0 {
+            return Ok(0);
+        }
+        self.disk.read_at(&mut buf[..len], self.start + offset)
+    }
+
+    fn write_at(&self, buf: &[u8], offset: u64) -> VfsResult<usize> {
+        let len = self.clamp(offset, buf.len());
+        if len == 0 && !buf.is_empty() {
+            return Err(AxError::StorageFull);
+        }
+        self.disk.write_at(&buf[..len], self.start + offset)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn block_info(&self) -> Option<BlockInfo> {
+        let info = self.disk.block_info()?;
+        Some(BlockInfo {
+            size: self.size,
+            ..info
+        })
+    }
+
+    fn flags(&self) -> NodeFlags {
+        NodeFlags::NON_CACHEABLE
+    }
+
+    fn flush(&self) -> VfsResult<()> {
+        self.disk.flush()
+    }
+}
+
+struct PartitionNode {
+    disk: String,
+    dev_id: DeviceId,
+    ops: Arc<Partition>,
+}
+
+static PARTITIONS: Mutex<BTreeMap<String, PartitionNode>> = Mutex::new(BTreeMap::new());
+static NEXT_MINOR: AtomicU32 = AtomicU32::new(0);
+
+/// Returns the name of partition `number` of `disk`, e.g. `sda1` or
+/// `loop0p1`.
+fn partition_name(disk: &str, number: usize) -> String {
+    if disk.ends_with(|c: char| c.is_ascii_digit()) {
+        format!("{disk}p{number}")
+    } else {
+        format!("{disk}{number}")
+    }
+}
+
+/// Rereads the partition table of `disk`, replacing the partition nodes
+/// created by a previous scan. Returns the number of partitions found.
+///
+/// A disk without a valid partition table has no partitions.
+pub fn rescan(disk_name: &str, disk: Arc<dyn DeviceOps>) -> VfsResult<usize> {
+    remove(disk_name);
+    let Some(info) = disk.block_info() else {
+        return Err(AxError::Other(LinuxError::ENOTBLK));
+    };
+    let found = match read_table(disk.as_ref(), &info) {
+        Ok(found) => found,
+        Err(err) => {
+            warn!("{disk_name}: failed to read partition table: {err:?}");
+            return Ok(0);
+        }
+    };
+
+    let mut partitions = PARTITIONS.lock();
+    let mut events = Vec::new();
+    for (number, start, size) in found.iter().copied() {
+        let name = partition_name(disk_name, number);
+        let dev_id = DeviceId::new(BLOCK_EXT_MAJOR, NEXT_MINOR.fetch_add(1, Ordering::Relaxed));
+        debug!("{name}: start {start:#x}, size {size:#x}");
+        let ops = Arc::new(Partition {
+            disk: disk.clone(),
+            start,
+            size,
+        });
+        partitions.insert(
+            name.clone(),
+            PartitionNode {
+                disk: disk_name.into(),
+                dev_id,
+                ops,
+            },
+        );
+        events.push(DeviceEvent::new(
+            DeviceAction::Add,
+            NodeType::BlockDevice,
+            dev_id,
+            name,
+        ));
+    }
+    drop(partitions);
+    events.into_iter().for_each(hotplug::notify);
+    Ok(found.len())
+}
+
+/// Removes the partition nodes of `disk`, e.g. when its medium goes away.
+pub fn remove(disk_name: &str) {
+    let mut removed = Vec::new();
+    PARTITIONS.lock().retain(|name, node| {
+        if node.disk == disk_name {
+            removed.push(DeviceEvent::new(
+                DeviceAction::Remove,
+                NodeType::BlockDevice,
+                node.dev_id,
+                name.clone(),
+            ));
+            false
+        } else {
+            true
+        }
+    });
+    removed.into_iter().for_each(hotplug::notify);
+}
+
+/// Looks up a partition by its device ID.
+pub fn find_by_id(dev_id: DeviceId) -> Option<Arc<dyn DeviceOps>> {
+    PARTITIONS
+        .lock()
+        .values()
+        .find(|it| it.dev_id == dev_id)
+        .map(|it| it.ops.clone() as _)
+}
+
+/// The partition nodes in /dev
+pub struct PartitionDir {
+    fs: Arc<SimpleFs>,
+}
+
+impl PartitionDir {
+    pub fn new(fs: Arc<SimpleFs>) -> Self {
+        Self { fs }
+    }
+}
+
+impl SimpleDirOps for PartitionDir {
+    fn child_names<'a>(&'a self) -> Box<dyn Iterator<Item = Cow<'a, str>> + 'a> {
+        let names = PARTITIONS.lock().keys().cloned().collect::<Vec<_>>();
+        Box::new(names.into_iter().map(Cow::Owned))
+    }
+
+    fn lookup_child(&self, name: &str) -> VfsResult<NodeOpsMux> {
+        let partitions = PARTITIONS.lock();
+        let node = partitions.get(name).ok_or(VfsError::NotFound)?;
+        Ok(NodeOpsMux::File(Device::new(
+            self.fs.clone(),
+            NodeType::BlockDevice,
+            node.dev_id,
+            node.ops.clone(),
+        )))
+    }
+}
+
+fn read_exact(disk: &dyn DeviceOps, mut buf: &mut [u8], mut offset: u64) -> VfsResult<()> {
+    while !buf.is_empty() {
+        let read = disk.read_at(buf, offset)?;
+        if read == 0 {
+            return Err(AxError::InvalidData);
+        }
+        buf = &mut buf[read..];
+        offset += read as u64;
+    }
+    Ok(())
+}
+
+fn u32_at(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(buf: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(buf[offset..offset + 8].try_into().unwrap())
+}
+
+/// CRC-32 (IEEE 802.3), as used by GPT.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+/// A partition as `(number, start, size)`, in bytes.
+type PartitionEntry = (usize, u64, u64);
+
+/// Reads the partition table of a disk. Partitions that don't fit in the
+/// disk are skipped.
+fn read_table(disk: &dyn DeviceOps, info: &BlockInfo) -> VfsResult<Vec<PartitionEntry>> {
+    let sector = info.logical_block_size as u64;
+    let mut mbr = vec![0; sector as usize];
+    if info.size < sector * 2 {
+        return Ok(Vec::new());
+    }
+    read_exact(disk, &mut mbr, 0)?;
+    if mbr[510..512] != [0x55, 0xaa] {
+        return Ok(Vec::new());
+    }
+
+    // A protective MBR covers the disk with a single partition of type 0xEE
+    let found = if mbr_entries(&mbr).any(|(ty, ..)| ty == 0xee) {
+        read_gpt(disk, info)?
+    } else {
+        read_mbr(disk, info, &mbr)?
+    };
+    Ok(found
+        .into_iter()
+        .filter(|&(number, start, size)| {
+            let fits = size > 0 && start.checked_add(size).is_some_and(|end| end <= info.size);
+            if !fits {
+                warn!("partition {number} lies outside of the disk, ignoring");
+            }
+            fits
+        })
+        .collect())
+}
+
+/// Returns the `(type, start, sectors)` of the four primary entries of an
+/// MBR.
+fn mbr_entries(mbr: &[u8]) -> impl Iterator<Item = (u8, u32, u32)> + '_ {
+    (0..4).map(|i| {
+        let entry = &mbr[446 + i * 16..446 + (i + 1) * 16];
+        (entry[4], u32_at(entry, 8), u32_at(entry, 12))
+    })
+}
+
+fn is_extended(ty: u8) -> bool {
+    matches!(ty, 0x05 | 0x0f | 0x85)
+}
+
+fn read_mbr(disk: &dyn DeviceOps, info: &BlockInfo, mbr: &[u8]) -> VfsResult<Vec<PartitionEntry>> {
+    let sector = info.logical_block_size as u64;
+    let mut found = Vec::new();
+    let mut extended = None;
+    for (i, (ty, start, sectors)) in mbr_entries(mbr).enumerate() {
+        if ty == 0 || sectors == 0 {
+            continue;
+        }
+        if is_extended(ty) {
+            extended.get_or_insert(start as u64);
+            continue;
+        }
+        found.push((i + 1, start as u64 * sector, sectors as u64 * sector));
+    }
+
+    // Logical partitions are chained through extended boot records, whose
+    // entries are relative to the EBR and the extended partition
+    // respectively.
+    if let Some(base) = extended {
+        let mut ebr = vec![0; sector as usize];
+        let mut next = base;
+        for number in 5..5 + MAX_LOGICAL_PARTITIONS {
+            read_exact(disk, &mut ebr, next * sector)?;
+            if ebr[510..512] != [0x55, 0xaa] {
+                break;
+            }
+            let mut entries = mbr_entries(&ebr);
+            let (ty, start, sectors) = entries.next().unwrap();
+            if ty != 0 && sectors != 0 {
+                found.push((
+                    number,
+                    (next + start as u64) * sector,
+                    sectors as u64 * sector,
+                ));
+            }
+            let (ty, start, _) = entries.next().unwrap();
+            if !is_extended(ty) || start == 0 {
+                break;
+            }
+            next = base + start as u64;
+        }
+    }
+    Ok(found)
+}
+
+fn read_gpt(disk: &dyn DeviceOps, info: &BlockInfo) -> VfsResult<Vec<PartitionEntry>> {
+    let sector = info.logical_block_size as u64;
+    let last_lba = info.size / sector - 1;
+    // Fall back to the backup header in the last sector
+    for lba in [1, last_lba] {
+        match read_gpt_at(disk, info, lba) {
+            Ok(found) => return Ok(found),
+            Err(err) => warn!("invalid GPT header at LBA {lba}: {err:?}"),
+        }
+    }
+    Err(AxError::InvalidData)
+}
+
+fn read_gpt_at(disk: &dyn DeviceOps, info: &BlockInfo, lba: u64) -> VfsResult<Vec<PartitionEntry>> {
+    let sector = info.logical_block_size as u64;
+    let mut header = vec![0; sector as usize];
+    read_exact(disk, &mut header, lba * sector)?;
+    if &header[0..8] != b"EFI PART" {
+        return Err(AxError::InvalidData);
+    }
+    let header_size = u32_at(&header, 12) as usize;
+    if !(92..=header.len()).contains(&header_size) {
+        return Err(AxError::InvalidData);
+    }
+    let header_crc = u32_at(&header, 16);
+    header[16..20].fill(0);
+    if crc32(&header[..header_size]) != header_crc || u64_at(&header, 24) != lba {
+        return Err(AxError::InvalidData);
+    }
+
+    let entries_lba = u64_at(&header, 72);
+    let entry_count = u32_at(&header, 80) as usize;
+    let entry_size = u32_at(&header, 84) as usize;
+    if entry_size < 128 || !entry_size.is_power_of_two() || entry_count > 1024 {
+        return Err(AxError::InvalidData);
+    }
+    let mut entries = vec![0; entry_count * entry_size];
+    read_exact(disk, &mut entries, entries_lba * sector)?;
+    if crc32(&entries) != u32_at(&header, 88) {
+        return Err(AxError::InvalidData);
+    }
+
+    Ok(entries
+        .chunks_exact(entry_size)
+        .enumerate()
+        // An all-zero type GUID marks an unused entry
+        .filter(|(_, entry)| entry[..16].iter().any(|&b| b != 0))
+        .filter_map(|(i, entry)| {
+            let first = u64_at(entry, 32);
+            let last = u64_at(entry, 40);
+            let sectors = last.checked_sub(first)? + 1;
+            Some((
+                i + 1,
+                first.checked_mul(sector)?,
+                sectors.checked_mul(sector)?,
+            ))
+        })
+        .collect())
+}
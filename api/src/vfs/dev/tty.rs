@@ -1,33 +1,40 @@
 use alloc::sync::{Arc, Weak};
-use core::{any::Any, ops::Deref, sync::atomic::Ordering, task::Context};
+use core::{any::Any, ops::Deref, sync::atomic::Ordering, task::Context, time::Duration};
 
 use axerrno::{AxError, AxResult};
 use axfs_ng_vfs::NodeFlags;
 use axpoll::{IoEvents, Pollable};
 use axsync::Mutex;
-use axtask::{current, future::Poller};
+use axtask::{
+    current,
+    future::{Poller, block_on, interruptible, sleep},
+};
+use linux_raw_sys::general::{TIOCM_DTR, TIOCM_LOOP, TIOCM_OUT1, TIOCM_OUT2, TIOCM_RTS};
 use starry_core::{task::AsThread, vfs::SimpleFs};
 use starry_process::Process;
 use starry_vm::{VmMutPtr, VmPtr};
 
 use crate::{
     terminal::{
-        Terminal, WindowSize,
+        SerialICounter, Terminal, WindowSize,
         ldisc::{LineDiscipline, ProcessMode, TtyConfig, TtyRead, TtyWrite},
         termios::{Termios, Termios2},
     },
     vfs::DeviceOps,
 };
 
+pub mod console;
 mod ntty;
 mod ptm;
 mod pts;
 mod pty;
+pub mod vt;
 
 pub use ntty::{N_TTY, NTtyDriver};
 pub use ptm::Ptmx;
 pub use pts::PtsDir;
 pub use pty::PtyDriver;
+pub use vt::VtDriver;
 
 pub fn create_pty_master(fs: Arc<SimpleFs>) -> AxResult<Arc<PtyDriver>> {
     let (master, slave) = pty::create_pty_pair();
@@ -35,6 +42,10 @@ pub fn create_pty_master(fs: Arc<SimpleFs>) -> AxResult<Arc<PtyDriver>> {
     Ok(master)
 }
 
+/// The modem lines that can be set with `TIOCMSET`, the rest being status
+/// lines.
+const MODEM_CONTROL_LINES: u32 = TIOCM_DTR | TIOCM_RTS | TIOCM_OUT1 | TIOCM_OUT2 | TIOCM_LOOP;
+
 /// Tty device
 pub struct Tty<R, W> {
     this: Weak<Self>,
@@ -77,6 +88,28 @@ impl<R: TtyRead, W: TtyWrite> Tty<R, W> {
     pub fn pty_number(&self) -> u32 {
         self.terminal.pty_number.load(Ordering::Acquire)
     }
+
+    /// Sends a break condition for `duration`. Like Linux, this does nothing
+    /// if the tty can't send breaks.
+    fn send_break(&self, duration: Duration) -> AxResult<()> {
+        if !self.writer.break_ctl(true) {
+            return Ok(());
+        }
+        let result = block_on(interruptible(sleep(duration)));
+        self.writer.break_ctl(false);
+        result
+    }
+
+    /// Returns whether this is the master of a pseudo-terminal.
+    pub fn is_master(&self) -> bool {
+        self.is_ptm
+    }
+
+    /// Returns whether this is the slave of a pseudo-terminal that has not
+    /// been unlocked yet.
+    pub fn is_locked_slave(&self) -> bool {
+        !self.is_ptm && self.terminal.pty_locked.load(Ordering::Acquire)
+    }
 }
 
 impl<R: TtyRead, W: TtyWrite> DeviceOps for Tty<R, W> {
@@ -139,8 +172,50 @@ impl<R: TtyRead, W: TtyWrite> DeviceOps for Tty<R, W> {
             TIOCSWINSZ => {
                 *self.terminal.window_size.lock() = (arg as *const WindowSize).vm_read()?;
             }
-            TIOCSPTLCK => {}
-            TIOCGPTN => {
+            TIOCGICOUNT => {
+                // Like Linux, ttys that aren't serial ports reject this with
+                // `EINVAL`
+                let icount = self.writer.icount().ok_or(AxError::InvalidInput)?;
+                (arg as *mut SerialICounter).vm_write(icount)?;
+            }
+            TIOCMGET => {
+                let lines = self.writer.modem_lines().ok_or(AxError::NotATty)?;
+                (arg as *mut u32).vm_write(lines)?;
+            }
+            TIOCMSET | TIOCMBIS | TIOCMBIC => {
+                let lines = self.writer.modem_lines().ok_or(AxError::NotATty)?;
+                let bits = (arg as *const u32).vm_read()?;
+                let control = match cmd {
+                    TIOCMSET => bits,
+                    TIOCMBIS => lines | bits,
+                    _ => lines & !bits,
+                };
+                self.writer.set_modem_control(control & MODEM_CONTROL_LINES);
+            }
+            TCSBRK | TCSBRKP => {
+                // Writes complete synchronously, so there's no output to
+                // drain. A nonzero `TCSBRK` argument only asks for that.
+                let duration = match (cmd, arg) {
+                    (TCSBRK, 0) | (TCSBRKP, 0) => Some(Duration::from_millis(250)),
+                    (TCSBRKP, deciseconds) => Some(Duration::from_millis(deciseconds as u64 * 100)),
+                    _ => None,
+                };
+                if let Some(duration) = duration {
+                    self.send_break(duration)?;
+                }
+            }
+            TIOCSBRK | TIOCCBRK => {
+                self.writer.break_ctl(cmd == TIOCSBRK);
+            }
+            TIOCSPTLCK if self.is_ptm => {
+                let locked = (arg as *const i32).vm_read()? != 0;
+                self.terminal.pty_locked.store(locked, Ordering::Release);
+            }
+            TIOCGPTLCK if self.is_ptm => {
+                let locked = self.terminal.pty_locked.load(Ordering::Acquire);
+                (arg as *mut i32).vm_write(locked as _)?;
+            }
+            TIOCGPTN if self.is_ptm => {
                 (arg as *mut u32).vm_write(self.pty_number())?;
             }
             TIOCSCTTY => {
@@ -166,7 +241,7 @@ impl<R: TtyRead, W: TtyWrite> DeviceOps for Tty<R, W> {
                     warn!("Failed to unset terminal");
                 }
             }
-            _ => return Err(AxError::NotATty),
+            _ => return self.writer.ioctl(cmd, arg),
         }
         Ok(0)
     }
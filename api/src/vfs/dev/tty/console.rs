@@ -0,0 +1,128 @@
+//! The console multiplexer behind `/dev/console`.
+//!
+//! Output written to the console goes to every registered device in the
+//! `console=` list. Input is read from the preferred console, which like
+//! Linux is the last one in the list.
+
+use alloc::{
+    collections::btree_map::BTreeMap,
+    string::{String, ToString},
+    sync::Arc,
+    vec,
+    vec::Vec,
+};
+
+use axerrno::{AxError, AxResult};
+use kspin::SpinNoPreempt;
+use lazy_static::lazy_static;
+
+use super::vt::{self, KD_GRAPHICS};
+
+/// The name of the serial console of axhal.
+pub const SERIAL_CONSOLE: &str = "ttyS0";
+
+/// A device that can be used as a console.
+pub trait ConsoleDriver: Send + Sync {
+    /// Writes bytes to the console.
+    fn write(&self, buf: &[u8]);
+
+    /// Reads the bytes available from the console without blocking.
+    fn read(&self, _buf: &mut [u8]) -> usize {
+        0
+    }
+
+    /// Returns whether the console draws text on a display, which stops in
+    /// [`KD_GRAPHICS`] mode so userspace can own the display.
+    fn is_graphical(&self) -> bool {
+        false
+    }
+}
+
+struct SerialConsole;
+
+impl ConsoleDriver for SerialConsole {
+    fn write(&self, buf: &[u8]) {
+        axhal::console::write_bytes(buf);
+    }
+
+    fn read(&self, buf: &mut [u8]) -> usize {
+        axhal::console::read_bytes(buf)
+    }
+}
+
+struct Consoles {
+    drivers: BTreeMap<String, Arc<dyn ConsoleDriver>>,
+    /// The names in the `console=` list.
+    list: Vec<String>,
+}
+
+impl Consoles {
+    /// Returns the registered consoles in the list, falling back to the
+    /// serial console so that output is never lost.
+    fn enabled(&self) -> Vec<Arc<dyn ConsoleDriver>> {
+        let enabled = self
+            .list
+            .iter()
+            .filter_map(|name| self.drivers.get(name).cloned())
+            .collect::<Vec<_>>();
+        if enabled.is_empty() {
+            vec![Arc::new(SerialConsole)]
+        } else {
+            enabled
+        }
+    }
+}
+
+lazy_static! {
+    static ref CONSOLES: SpinNoPreempt<Consoles> = {
+        let mut drivers = BTreeMap::new();
+        drivers.insert(
+            SERIAL_CONSOLE.to_string(),
+            Arc::new(SerialConsole) as Arc<dyn ConsoleDriver>,
+        );
+        SpinNoPreempt::new(Consoles {
+            drivers,
+            list: vec![SERIAL_CONSOLE.to_string()],
+        })
+    };
+}
+
+/// Registers a console device under `name`, replacing any device with the
+/// same name.
+pub fn register(name: &str, driver: Arc<dyn ConsoleDriver>) {
+    CONSOLES.lock().drivers.insert(name.to_string(), driver);
+}
+
+/// Sets the consoles from a comma-separated `console=` list, like
+/// `ttyS0,tty0`. Names that are not registered yet are kept, so devices can
+/// be registered later.
+pub fn set_console_list(list: &str) -> AxResult<()> {
+    let list = list
+        .split(',')
+        .map(str::trim)
+        .filter(|it| !it.is_empty())
+        .map(ToString::to_string)
+        .collect::<Vec<_>>();
+    if list.is_empty() {
+        return Err(AxError::InvalidInput);
+    }
+    CONSOLES.lock().list = list;
+    Ok(())
+}
+
+/// Writes bytes to every enabled console.
+pub fn write(buf: &[u8]) {
+    let graphics = vt::kd_mode() == KD_GRAPHICS;
+    let enabled = CONSOLES.lock().enabled();
+    for console in enabled {
+        if !(graphics && console.is_graphical()) {
+            console.write(buf);
+        }
+    }
+}
+
+/// Reads the bytes available from the preferred console.
+pub fn read(buf: &mut [u8]) -> usize {
+    let preferred = CONSOLES.lock().enabled().pop().unwrap();
+    preferred.read(buf)
+}
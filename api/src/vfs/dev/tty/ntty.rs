@@ -1,23 +1,71 @@
 use alloc::{boxed::Box, sync::Arc};
+use core::sync::atomic::{AtomicU32, Ordering};
 
+use axerrno::AxResult;
 use axhal::irq::register_irq_waker;
 use lazy_static::lazy_static;
+use linux_raw_sys::general::{TIOCM_CAR, TIOCM_CTS, TIOCM_DSR, TIOCM_DTR, TIOCM_RTS};
 
-use super::Tty;
-use crate::terminal::ldisc::{ProcessMode, TtyConfig, TtyRead, TtyWrite};
+use super::{Tty, console, vt};
+use crate::terminal::{
+    SerialICounter,
+    ldisc::{ProcessMode, TtyConfig, TtyRead, TtyWrite},
+};
 
 pub type NTtyDriver = Tty<Console, Console>;
 
+/// Bytes received from the console UART through the tty.
+static RX_COUNT: AtomicU32 = AtomicU32::new(0);
+/// Bytes sent to the console UART through the tty.
+static TX_COUNT: AtomicU32 = AtomicU32::new(0);
+/// The modem control lines of the console UART. Like Linux, DTR and RTS are
+/// raised when the port is set up.
+static MODEM_CONTROL: AtomicU32 = AtomicU32::new(TIOCM_DTR | TIOCM_RTS);
+
 #[derive(Clone, Copy)]
 pub struct Console;
 impl TtyRead for Console {
     fn read(&mut self, buf: &mut [u8]) -> usize {
-        axhal::console::read_bytes(buf)
+        // The console tty is the first virtual terminal
+        if !vt::is_active(1) {
+            return 0;
+        }
+        let read = console::read(buf);
+        RX_COUNT.fetch_add(read as u32, Ordering::Relaxed);
+        read
     }
 }
 impl TtyWrite for Console {
     fn write(&self, buf: &[u8]) {
-        axhal::console::write_bytes(buf);
+        if !vt::is_active(1) {
+            return;
+        }
+        console::write(buf);
+        TX_COUNT.fetch_add(buf.len() as u32, Ordering::Relaxed);
+    }
+
+    fn icount(&self) -> Option<SerialICounter> {
+        // The console interface only moves bytes, so line errors and modem
+        // line transitions are never reported.
+        Some(SerialICounter {
+            rx: RX_COUNT.load(Ordering::Relaxed) as _,
+            tx: TX_COUNT.load(Ordering::Relaxed) as _,
+            ..Default::default()
+        })
+    }
+
+    fn modem_lines(&self) -> Option<u32> {
+        // The console interface has no modem lines, so like Linux does for
+        // UARTs without them, the status lines always read as asserted.
+        Some(MODEM_CONTROL.load(Ordering::Relaxed) | TIOCM_CAR | TIOCM_DSR | TIOCM_CTS)
+    }
+
+    fn set_modem_control(&self, control: u32) {
+        MODEM_CONTROL.store(control, Ordering::Relaxed);
+    }
+
+    fn ioctl(&self, cmd: u32, arg: usize) -> AxResult<usize> {
+        vt::ioctl(cmd, arg)
     }
 }
 
@@ -1,11 +1,14 @@
-use alloc::{borrow::Cow, boxed::Box, string::ToString, sync::Arc, vec::Vec};
+use alloc::{borrow::Cow, boxed::Box, format, string::ToString, sync::Arc, vec::Vec};
 use core::sync::atomic::Ordering;
 
 use axerrno::{AxError, AxResult};
 use axfs_ng_vfs::{DeviceId, NodeType, VfsResult};
 use flatten_objects::FlattenObjects;
 use kspin::SpinNoIrq;
-use starry_core::vfs::{Device, NodeOpsMux, SimpleDirOps, SimpleFs};
+use starry_core::{
+    hotplug::{self, DeviceAction, DeviceEvent},
+    vfs::{Device, NodeOpsMux, SimpleDirOps, SimpleFs},
+};
 
 use crate::vfs::dev::tty::pty::PtyDriver;
 
@@ -24,10 +27,18 @@ pub fn add_slave(fs: Arc<SimpleFs>, pty: Arc<PtyDriver>) -> AxResult<u32> {
         ))
         .map_err(|_| AxError::TooManyOpenFiles)? as u32;
     terminal.pty_number.store(pty_number, Ordering::Release);
+    let device_id = DeviceId::new(136, pty_number);
     table
         .get(pty_number as usize)
         .unwrap()
-        .set_device_id(DeviceId::new(136, pty_number));
+        .set_device_id(device_id);
+    drop(table);
+    hotplug::notify(DeviceEvent::new(
+        DeviceAction::Add,
+        NodeType::CharacterDevice,
+        device_id,
+        format!("pts/{pty_number}"),
+    ));
     Ok(pty_number)
 }
 
@@ -1,4 +1,5 @@
 use alloc::{boxed::Box, sync::Arc};
+use core::sync::atomic::Ordering;
 
 use axpoll::PollSet;
 use kspin::SpinNoPreempt;
@@ -59,6 +60,8 @@ pub(crate) fn create_pty_pair() -> (Arc<PtyDriver>, Arc<PtyDriver>) {
     let poll_rx_master = Arc::new(PollSet::new());
 
     let terminal = Arc::new(Terminal::default());
+    // Like Linux, the slave stays locked until the master unlocks it.
+    terminal.pty_locked.store(true, Ordering::Release);
 
     let master = Tty::new(
         terminal.clone(),
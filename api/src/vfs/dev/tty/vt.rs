@@ -0,0 +1,335 @@
+//! Virtual terminals of the console.
+//!
+//! The virtual terminals share the consoles of [`super::console`], and only
+//! the active one reads from and writes to them. The first one is the console
+//! tty, and the others are `/dev/tty2` onwards. Keyboard input comes from the
+//! key events of the input devices, translated according to the keyboard
+//! mode.
+
+use alloc::{collections::vec_deque::VecDeque, sync::Arc, vec::Vec};
+use core::{
+    future::poll_fn,
+    sync::atomic::{AtomicU32, Ordering},
+    task::Poll,
+};
+
+use axerrno::{AxError, AxResult, LinuxError};
+use axpoll::PollSet;
+use axtask::future::{block_on, interruptible};
+use bytemuck::AnyBitPattern;
+use kspin::SpinNoPreempt;
+use lazy_static::lazy_static;
+use starry_vm::VmMutPtr;
+
+use super::{Tty, console};
+use crate::terminal::{
+    Terminal,
+    ldisc::{ProcessMode, TtyConfig, TtyRead, TtyWrite},
+};
+
+/// The console name of the keyboards, the input of the virtual terminals.
+pub const VT_CONSOLE: &str = "tty0";
+
+const KDSETMODE: u32 = 0x4b3a;
+const KDGETMODE: u32 = 0x4b3b;
+const KDGKBMODE: u32 = 0x4b44;
+const KDSKBMODE: u32 = 0x4b45;
+const VT_OPENQRY: u32 = 0x5600;
+const VT_GETSTATE: u32 = 0x5603;
+const VT_ACTIVATE: u32 = 0x5606;
+const VT_WAITACTIVE: u32 = 0x5607;
+
+/// The number of virtual terminals.
+pub const NR_VTS: u32 = 4;
+
+/// The active virtual terminal, numbered from 1.
+static ACTIVE: AtomicU32 = AtomicU32::new(1);
+/// The allocated virtual terminals, where bit `n` is `/dev/ttyN`. Like
+/// Linux, bit 0 is always set.
+static ALLOCATED: AtomicU32 = AtomicU32::new(0b11);
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, AnyBitPattern)]
+struct VtStat {
+    v_active: u16,
+    v_signal: u16,
+    v_state: u16,
+}
+
+/// The input and output of a virtual terminal other than the first.
+#[derive(Clone, Copy)]
+pub struct VtPort(u32);
+
+impl TtyRead for VtPort {
+    fn read(&mut self, buf: &mut [u8]) -> usize {
+        if is_active(self.0) {
+            console::read(buf)
+        } else {
+            0
+        }
+    }
+}
+
+impl TtyWrite for VtPort {
+    fn write(&self, buf: &[u8]) {
+        // Like a switched away screen, output of inactive terminals is not
+        // shown, but it's not kept to be redrawn either.
+        if is_active(self.0) {
+            console::write(buf);
+        }
+    }
+
+    fn ioctl(&self, cmd: u32, arg: usize) -> AxResult<usize> {
+        ioctl(cmd, arg)
+    }
+}
+
+pub type VtDriver = Tty<VtPort, VtPort>;
+
+lazy_static! {
+    /// The virtual terminals from `/dev/tty2` onwards.
+    pub static ref VTS: Vec<Arc<VtDriver>> = (2..=NR_VTS)
+        .map(|number| {
+            let port = VtPort(number);
+            Tty::new(
+                Arc::new(Terminal::default()),
+                TtyConfig {
+                    reader: port,
+                    writer: port,
+                    process_mode: ProcessMode::Manual,
+                },
+            )
+        })
+        .collect();
+    /// Woken when the active virtual terminal changes.
+    static ref ACTIVATED: PollSet = PollSet::new();
+}
+
+/// Returns whether `number` is the active virtual terminal.
+pub fn is_active(number: u32) -> bool {
+    ACTIVE.load(Ordering::Acquire) == number
+}
+
+/// Returns the number of a virtual terminal.
+pub fn vt_number(driver: &VtDriver) -> u32 {
+    let index = VTS
+        .iter()
+        .position(|it| core::ptr::eq(it.as_ref(), driver))
+        .unwrap();
+    index as u32 + 2
+}
+
+/// Allocates a virtual terminal when it's first opened.
+pub fn open(driver: &VtDriver) {
+    ALLOCATED.fetch_or(1 << vt_number(driver), Ordering::AcqRel);
+}
+
+fn check_number(arg: usize) -> AxResult<u32> {
+    if (1..=NR_VTS as usize).contains(&arg) {
+        Ok(arg as u32)
+    } else {
+        Err(AxError::Other(LinuxError::ENXIO))
+    }
+}
+
+fn activate(number: u32) {
+    ALLOCATED.fetch_or(1 << number, Ordering::AcqRel);
+    if ACTIVE.swap(number, Ordering::AcqRel) != number {
+        // Pending keys were typed for the old terminal
+        KEYBOARD.lock().input.clear();
+        ACTIVATED.wake();
+    }
+}
+
+/// Text is drawn on the console.
+pub const KD_TEXT: u32 = 0x00;
+/// The console is left to userspace graphics.
+pub const KD_GRAPHICS: u32 = 0x01;
+const KD_TEXT0: u32 = 0x02;
+const KD_TEXT1: u32 = 0x03;
+
+static KD_MODE: AtomicU32 = AtomicU32::new(KD_TEXT);
+
+/// Returns the display mode of the virtual terminals, [`KD_TEXT`] or
+/// [`KD_GRAPHICS`].
+pub fn kd_mode() -> u32 {
+    KD_MODE.load(Ordering::Acquire)
+}
+
+/// Scancodes.
+const K_RAW: u32 = 0x00;
+/// Characters, translated with the keymap.
+const K_XLATE: u32 = 0x01;
+/// Keycodes.
+const K_MEDIUMRAW: u32 = 0x02;
+/// Characters as UTF-8, which is the same as [`K_XLATE`] for this keymap.
+const K_UNICODE: u32 = 0x03;
+/// No input.
+const K_OFF: u32 = 0x04;
+
+static KB_MODE: AtomicU32 = AtomicU32::new(K_XLATE);
+
+/// The number of input bytes buffered before new ones are dropped.
+const INPUT_BUF_SIZE: usize = 256;
+
+const KEY_LEFTCTRL: u16 = 29;
+const KEY_LEFTSHIFT: u16 = 42;
+const KEY_RIGHTSHIFT: u16 = 54;
+const KEY_RIGHTCTRL: u16 = 97;
+const KEY_UP: u16 = 103;
+const KEY_LEFT: u16 = 105;
+const KEY_RIGHT: u16 = 106;
+const KEY_DOWN: u16 = 108;
+/// The largest keycode that is also its set 1 scancode, which is `KEY_F12`.
+const MAX_IDENTITY_SCANCODE: u16 = 88;
+
+/// The US keymap, indexed by keycode.
+const KEYMAP: &[u8] = b"\0\x1b1234567890-=\x7f\tqwertyuiop[]\r\0asdfghjkl;'`\0\\zxcvbnm,./\0*\0 ";
+/// The US keymap with shift held.
+const SHIFT_KEYMAP: &[u8] =
+    b"\0\x1b!@#$%^&*()_+\x7f\tQWERTYUIOP{}\r\0ASDFGHJKL:\"~\0|ZXCVBNM<>?\0*\0 ";
+
+struct Keyboard {
+    shift: bool,
+    ctrl: bool,
+    input: VecDeque<u8>,
+}
+
+impl Keyboard {
+    fn push(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            if self.input.len() < INPUT_BUF_SIZE {
+                self.input.push_back(byte);
+            }
+        }
+    }
+
+    fn translate(&mut self, code: u16) {
+        match code {
+            KEY_UP => self.push(b"\x1b[A"),
+            KEY_DOWN => self.push(b"\x1b[B"),
+            KEY_RIGHT => self.push(b"\x1b[C"),
+            KEY_LEFT => self.push(b"\x1b[D"),
+            _ => {
+                let keymap = if self.shift { SHIFT_KEYMAP } else { KEYMAP };
+                let mut ch = keymap.get(code as usize).copied().unwrap_or(0);
+                if self.ctrl && ch.is_ascii_alphabetic() {
+                    ch &= 0x1f;
+                }
+                if ch != 0 {
+                    self.push(&[ch]);
+                }
+            }
+        }
+    }
+}
+
+static KEYBOARD: SpinNoPreempt<Keyboard> = SpinNoPreempt::new(Keyboard {
+    shift: false,
+    ctrl: false,
+    input: VecDeque::new(),
+});
+
+/// Handles a key event from an input device, where `value` is 0 for a
+/// release, 1 for a press and 2 for a repeat.
+pub fn handle_key(code: u16, value: u32) {
+    let down = value != 0;
+    let mut keyboard = KEYBOARD.lock();
+    match code {
+        KEY_LEFTSHIFT | KEY_RIGHTSHIFT => keyboard.shift = down,
+        KEY_LEFTCTRL | KEY_RIGHTCTRL => keyboard.ctrl = down,
+        _ => {}
+    }
+    let up_flag = if down { 0 } else { 0x80 };
+    match KB_MODE.load(Ordering::Acquire) {
+        K_RAW => {
+            // Other keys need the extended scancodes of the keyboard, which
+            // input devices don't report.
+            if code <= MAX_IDENTITY_SCANCODE {
+                keyboard.push(&[code as u8 | up_flag]);
+            }
+        }
+        K_MEDIUMRAW => {
+            if code < 0x80 {
+                keyboard.push(&[code as u8 | up_flag]);
+            } else {
+                // Like Linux, larger keycodes take three bytes
+                keyboard.push(&[up_flag, (code >> 7) as u8 | 0x80, code as u8 | 0x80]);
+            }
+        }
+        K_XLATE | K_UNICODE if down => keyboard.translate(code),
+        _ => {}
+    }
+}
+
+/// Reads the buffered keyboard input.
+pub fn read_input(buf: &mut [u8]) -> usize {
+    let mut keyboard = KEYBOARD.lock();
+    let len = buf.len().min(keyboard.input.len());
+    for (out, byte) in buf.iter_mut().zip(keyboard.input.drain(..len)) {
+        *out = byte;
+    }
+    len
+}
+
+/// Handles the ioctls of the virtual terminals.
+pub(super) fn ioctl(cmd: u32, arg: usize) -> AxResult<usize> {
+    match cmd {
+        KDSETMODE => {
+            let mode = match arg as u32 {
+                // Like Linux, the obsolete text modes are plain text mode
+                KD_TEXT | KD_TEXT0 | KD_TEXT1 => KD_TEXT,
+                KD_GRAPHICS => KD_GRAPHICS,
+                _ => return Err(AxError::InvalidInput),
+            };
+            KD_MODE.store(mode, Ordering::Release);
+        }
+        KDGETMODE => {
+            (arg as *mut i32).vm_write(kd_mode() as _)?;
+        }
+        KDSKBMODE => {
+            let mode = arg as u32;
+            if mode > K_OFF {
+                return Err(AxError::InvalidInput);
+            }
+            KB_MODE.store(mode, Ordering::Release);
+            // Input in the old mode would be misread in the new one
+            KEYBOARD.lock().input.clear();
+        }
+        KDGKBMODE => {
+            (arg as *mut i32).vm_write(KB_MODE.load(Ordering::Acquire) as _)?;
+        }
+        VT_OPENQRY => {
+            // Like Linux, this reports the first free terminal, or -1
+            let allocated = ALLOCATED.load(Ordering::Acquire);
+            let free = (1..=NR_VTS)
+                .find(|it| allocated & (1 << it) == 0)
+                .map_or(-1, |it| it as i32);
+            (arg as *mut i32).vm_write(free)?;
+        }
+        VT_GETSTATE => {
+            (arg as *mut VtStat).vm_write(VtStat {
+                v_active: ACTIVE.load(Ordering::Acquire) as _,
+                v_signal: 0,
+                v_state: ALLOCATED.load(Ordering::Acquire) as _,
+            })?;
+        }
+        VT_ACTIVATE => activate(check_number(arg)?),
+        VT_WAITACTIVE => {
+            let number = check_number(arg)?;
+            block_on(interruptible(poll_fn(|cx| {
+                if is_active(number) {
+                    return Poll::Ready(());
+                }
+                ACTIVATED.register(cx.waker());
+                if is_active(number) {
+                    Poll::Ready(())
+                } else {
+                    Poll::Pending
+                }
+            })))?;
+        }
+        _ => return Err(AxError::NotATty),
+    }
+    Ok(0)
+}
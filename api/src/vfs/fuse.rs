@@ -0,0 +1,978 @@
+//! Filesystems in userspace (FUSE).
+//!
+//! A server opens `/dev/fuse`, creating a [`FuseConn`], and mounts a `fuse`
+//! filesystem with the `fd=N` option naming it. Operations on the filesystem
+//! are then sent as requests the server reads from the device, and wait for
+//! the replies it writes back.
+//!
+//! Only the core requests are sent: `LOOKUP`, `GETATTR`, `OPEN`, `READ`,
+//! `WRITE`, `READDIR`, `FSYNC`, `SETATTR` for truncation, and the matching
+//! `RELEASE`s and `FORGET`s. Nothing is cached, so every access goes to the
+//! server, and changes to the tree or to other attributes of files are
+//! refused.
+
+use alloc::{
+    borrow::ToOwned,
+    collections::{VecDeque, btree_map::BTreeMap},
+    sync::Arc,
+    vec::Vec,
+};
+use core::{
+    any::Any,
+    sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
+    task::Context,
+    time::Duration,
+};
+
+use axerrno::{AxError, AxResult, LinuxError};
+use axfs_ng_vfs::{
+    DeviceId, DirEntry, DirEntrySink, DirNode, DirNodeOps, FileNode, FileNodeOps, Filesystem,
+    FilesystemOps, Location, Metadata, MetadataUpdate, NodeFlags, NodeOps, NodePermission,
+    NodeType, Reference, StatFs, VfsError, VfsResult, WeakDirEntry,
+};
+use axpoll::{IoEvents, PollSet, Pollable};
+use axsync::Mutex;
+use axtask::{current, future::Poller};
+use linux_raw_sys::general::{O_RDONLY, O_WRONLY};
+use starry_core::{task::AsThread, vfs::dummy_stat_fs};
+use zerocopy::{FromBytes, FromZeros, Immutable, IntoBytes};
+
+/// `FUSE_SUPER_MAGIC`.
+const FUSE_SUPER_MAGIC: u32 = 0x6573_5546;
+
+const FUSE_KERNEL_VERSION: u32 = 7;
+/// The protocol minor version spoken, the last one with a 16 bytes
+/// `fuse_init_in`.
+const FUSE_KERNEL_MINOR_VERSION: u32 = 31;
+
+/// The node ID of the root directory.
+const FUSE_ROOT_ID: u64 = 1;
+
+/// The smallest buffer servers may read requests into, like Linux.
+pub const FUSE_MIN_READ_BUFFER: usize = 8192;
+
+/// The largest amount of data read at once.
+const FUSE_MAX_READ: usize = 128 * 1024;
+
+const FUSE_LOOKUP: u32 = 1;
+const FUSE_FORGET: u32 = 2;
+const FUSE_GETATTR: u32 = 3;
+const FUSE_SETATTR: u32 = 4;
+const FUSE_OPEN: u32 = 14;
+const FUSE_READ: u32 = 15;
+const FUSE_WRITE: u32 = 16;
+const FUSE_RELEASE: u32 = 18;
+const FUSE_FSYNC: u32 = 20;
+const FUSE_INIT: u32 = 26;
+const FUSE_OPENDIR: u32 = 27;
+const FUSE_READDIR: u32 = 28;
+const FUSE_RELEASEDIR: u32 = 29;
+const FUSE_FSYNCDIR: u32 = 30;
+
+/// Attributes changed by `FUSE_SETATTR`.
+const FATTR_SIZE: u32 = 1 << 3;
+const FATTR_FH: u32 = 1 << 6;
+
+/// Only the data, and the metadata needed to read it back, is synced.
+const FUSE_FSYNC_FDATASYNC: u32 = 1 << 0;
+
+/// `struct fuse_in_header`.
+#[repr(C)]
+#[derive(FromBytes, IntoBytes, Immutable)]
+struct InHeader {
+    len: u32,
+    opcode: u32,
+    unique: u64,
+    nodeid: u64,
+    uid: u32,
+    gid: u32,
+    pid: u32,
+    total_extlen: u16,
+    padding: u16,
+}
+
+/// `struct fuse_out_header`.
+#[repr(C)]
+#[derive(FromBytes, IntoBytes, Immutable)]
+struct OutHeader {
+    len: u32,
+    error: i32,
+    unique: u64,
+}
+
+/// `struct fuse_init_in`, as of the 7.31 protocol.
+#[repr(C)]
+#[derive(FromBytes, IntoBytes, Immutable)]
+struct InitIn {
+    major: u32,
+    minor: u32,
+    max_readahead: u32,
+    flags: u32,
+}
+
+/// The beginning of `struct fuse_init_out`.
+#[repr(C)]
+#[derive(FromBytes, IntoBytes, Immutable)]
+struct InitOut {
+    major: u32,
+    minor: u32,
+    max_readahead: u32,
+    flags: u32,
+    max_background: u16,
+    congestion_threshold: u16,
+    max_write: u32,
+}
+
+/// `struct fuse_attr`.
+#[repr(C)]
+#[derive(FromBytes, IntoBytes, Immutable)]
+struct Attr {
+    ino: u64,
+    size: u64,
+    blocks: u64,
+    atime: u64,
+    mtime: u64,
+    ctime: u64,
+    atimensec: u32,
+    mtimensec: u32,
+    ctimensec: u32,
+    mode: u32,
+    nlink: u32,
+    uid: u32,
+    gid: u32,
+    rdev: u32,
+    blksize: u32,
+    flags: u32,
+}
+
+/// `struct fuse_entry_out`.
+#[repr(C)]
+#[derive(FromBytes, IntoBytes, Immutable)]
+struct EntryOut {
+    nodeid: u64,
+    generation: u64,
+    entry_valid: u64,
+    attr_valid: u64,
+    entry_valid_nsec: u32,
+    attr_valid_nsec: u32,
+    attr: Attr,
+}
+
+/// `struct fuse_getattr_in`.
+#[repr(C)]
+#[derive(FromBytes, IntoBytes, Immutable)]
+struct GetattrIn {
+    getattr_flags: u32,
+    dummy: u32,
+    fh: u64,
+}
+
+/// `struct fuse_attr_out`.
+#[repr(C)]
+#[derive(FromBytes, IntoBytes, Immutable)]
+struct AttrOut {
+    attr_valid: u64,
+    attr_valid_nsec: u32,
+    dummy: u32,
+    attr: Attr,
+}
+
+/// `struct fuse_open_in`.
+#[repr(C)]
+#[derive(FromBytes, IntoBytes, Immutable)]
+struct OpenIn {
+    flags: u32,
+    open_flags: u32,
+}
+
+/// `struct fuse_open_out`.
+#[repr(C)]
+#[derive(FromBytes, IntoBytes, Immutable)]
+struct OpenOut {
+    fh: u64,
+    open_flags: u32,
+    backing_id: i32,
+}
+
+/// `struct fuse_read_in`, also used as `struct fuse_write_in`.
+#[repr(C)]
+#[derive(FromBytes, IntoBytes, Immutable)]
+struct IoIn {
+    fh: u64,
+    offset: u64,
+    size: u32,
+    io_flags: u32,
+    lock_owner: u64,
+    flags: u32,
+    padding: u32,
+}
+
+/// `struct fuse_write_out`.
+#[repr(C)]
+#[derive(FromBytes, IntoBytes, Immutable)]
+struct WriteOut {
+    size: u32,
+    padding: u32,
+}
+
+/// `struct fuse_release_in`.
+#[repr(C)]
+#[derive(FromBytes, IntoBytes, Immutable)]
+struct ReleaseIn {
+    fh: u64,
+    flags: u32,
+    release_flags: u32,
+    lock_owner: u64,
+}
+
+/// `struct fuse_setattr_in`.
+#[repr(C)]
+#[derive(FromBytes, IntoBytes, Immutable)]
+struct SetattrIn {
+    valid: u32,
+    padding: u32,
+    fh: u64,
+    size: u64,
+    lock_owner: u64,
+    atime: u64,
+    mtime: u64,
+    ctime: u64,
+    atimensec: u32,
+    mtimensec: u32,
+    ctimensec: u32,
+    mode: u32,
+    unused4: u32,
+    uid: u32,
+    gid: u32,
+    unused5: u32,
+}
+
+/// `struct fuse_fsync_in`.
+#[repr(C)]
+#[derive(FromBytes, IntoBytes, Immutable)]
+struct FsyncIn {
+    fh: u64,
+    fsync_flags: u32,
+    padding: u32,
+}
+
+/// `struct fuse_forget_in`.
+#[repr(C)]
+#[derive(FromBytes, IntoBytes, Immutable)]
+struct ForgetIn {
+    nlookup: u64,
+}
+
+/// `struct fuse_dirent`, followed by the name padded to 8 bytes.
+#[repr(C)]
+#[derive(FromBytes, IntoBytes, Immutable)]
+struct Dirent {
+    ino: u64,
+    off: u64,
+    namelen: u32,
+    ty: u32,
+}
+
+/// Converts an error number replied by the server.
+fn reply_error(errno: i32) -> AxError {
+    match LinuxError::try_from(errno) {
+        Ok(LinuxError::ENOENT) => AxError::NotFound,
+        Ok(LinuxError::EPERM) => AxError::OperationNotPermitted,
+        Ok(LinuxError::EACCES) => AxError::PermissionDenied,
+        Ok(LinuxError::EEXIST) => AxError::AlreadyExists,
+        Ok(LinuxError::EINVAL) => AxError::InvalidInput,
+        Ok(LinuxError::ENOTDIR) => AxError::NotADirectory,
+        Ok(LinuxError::EISDIR) => AxError::IsADirectory,
+        Ok(err) => AxError::Other(err),
+        Err(_) => AxError::Other(LinuxError::EIO),
+    }
+}
+
+fn node_type(mode: u32) -> NodeType {
+    match mode & 0o170000 {
+        0o010000 => NodeType::Fifo,
+        0o020000 => NodeType::CharacterDevice,
+        0o040000 => NodeType::Directory,
+        0o060000 => NodeType::BlockDevice,
+        0o100000 => NodeType::RegularFile,
+        0o120000 => NodeType::Symlink,
+        0o140000 => NodeType::Socket,
+        _ => NodeType::Unknown,
+    }
+}
+
+fn attr_to_metadata(attr: &Attr) -> Metadata {
+    Metadata {
+        device: 0,
+        inode: attr.ino,
+        nlink: attr.nlink as _,
+        mode: NodePermission::from_bits_truncate(attr.mode as u16 & 0o7777),
+        node_type: node_type(attr.mode),
+        uid: attr.uid,
+        gid: attr.gid,
+        size: attr.size,
+        block_size: attr.blksize as _,
+        blocks: attr.blocks,
+        rdev: DeviceId::default(),
+        atime: Duration::new(attr.atime, attr.atimensec),
+        mtime: Duration::new(attr.mtime, attr.mtimensec),
+        ctime: Duration::new(attr.ctime, attr.ctimensec),
+    }
+}
+
+fn parse<T: FromBytes>(data: &[u8]) -> AxResult<T> {
+    T::read_from_prefix(data)
+        .map(|(it, _)| it)
+        .map_err(|_| AxError::Other(LinuxError::EIO))
+}
+
+/// The reply to a request, awaited by the task that sent it.
+struct Reply {
+    result: Mutex<Option<AxResult<Vec<u8>>>>,
+    poll: PollSet,
+}
+
+impl Reply {
+    fn set(&self, result: AxResult<Vec<u8>>) {
+        *self.result.lock() = Some(result);
+        self.poll.wake();
+    }
+
+    fn wait(&self) -> AxResult<Vec<u8>> {
+        Poller::new(self, IoEvents::IN).poll(|| {
+            self.result
+                .lock()
+                .take()
+                .unwrap_or(Err(AxError::WouldBlock))
+        })
+    }
+}
+
+impl Pollable for Reply {
+    fn poll(&self) -> IoEvents {
+        let mut events = IoEvents::empty();
+        events.set(IoEvents::IN, self.result.lock().is_some());
+        events
+    }
+
+    fn register(&self, context: &mut Context<'_>, events: IoEvents) {
+        if events.contains(IoEvents::IN) {
+            self.poll.register(context.waker());
+        }
+    }
+}
+
+/// What to do with the reply to a request.
+enum Waiter {
+    /// Hand it to the task waiting for it.
+    Task(Arc<Reply>),
+    /// Negotiate the connection parameters.
+    Init,
+    /// Drop it.
+    Discard,
+}
+
+/// A connection between a FUSE filesystem and its server, created by
+/// opening `/dev/fuse`.
+pub struct FuseConn {
+    requests: Mutex<VecDeque<Vec<u8>>>,
+    /// The requests read by the server and not answered yet, by unique ID.
+    waiting: Mutex<BTreeMap<u64, Waiter>>,
+    next_unique: AtomicU64,
+    mounted: AtomicBool,
+    /// Set once the server closed the device or the filesystem was
+    /// unmounted.
+    aborted: AtomicBool,
+    max_write: AtomicU32,
+    /// Set once the server replied `ENOSYS` to `FUSE_FSYNC`, which then
+    /// always succeeds.
+    no_fsync: AtomicBool,
+    poll_rx: PollSet,
+}
+
+impl FuseConn {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            requests: Mutex::new(VecDeque::new()),
+            waiting: Mutex::new(BTreeMap::new()),
+            next_unique: AtomicU64::new(1),
+            mounted: AtomicBool::new(false),
+            aborted: AtomicBool::new(false),
+            max_write: AtomicU32::new(4096),
+            no_fsync: AtomicBool::new(false),
+            poll_rx: PollSet::new(),
+        })
+    }
+
+    /// Queues a request, whose reply is handled by `waiter`.
+    fn queue(&self, opcode: u32, nodeid: u64, args: &[&[u8]], waiter: Waiter) -> AxResult<()> {
+        let (uid, gid, pid) = match current().try_as_thread() {
+            Some(thr) => {
+                let cred = thr.proc_data.cred.read();
+                (cred.uid.fs, cred.gid.fs, thr.proc_data.proc.pid())
+            }
+            None => (0, 0, 0),
+        };
+        let unique = self.next_unique.fetch_add(1, Ordering::Relaxed);
+        let len = size_of::<InHeader>() + args.iter().map(|it| it.len()).sum::<usize>();
+        let header = InHeader {
+            len: len as u32,
+            opcode,
+            unique,
+            nodeid,
+            uid,
+            gid,
+            pid,
+            total_extlen: 0,
+            padding: 0,
+        };
+        let mut data = Vec::with_capacity(len);
+        data.extend_from_slice(header.as_bytes());
+        for arg in args {
+            data.extend_from_slice(arg);
+        }
+
+        // Checked under the lock, so that an abort fails the request
+        let mut waiting = self.waiting.lock();
+        if self.aborted.load(Ordering::Acquire) {
+            return Err(AxError::Other(LinuxError::ENOTCONN));
+        }
+        // FUSE_FORGET has no reply
+        if opcode != FUSE_FORGET {
+            waiting.insert(unique, waiter);
+        }
+        self.requests.lock().push_back(data);
+        drop(waiting);
+        self.poll_rx.wake();
+        Ok(())
+    }
+
+    /// Sends a request and waits for its reply.
+    fn request(&self, opcode: u32, nodeid: u64, args: &[&[u8]]) -> AxResult<Vec<u8>> {
+        let reply = Arc::new(Reply {
+            result: Mutex::new(None),
+            poll: PollSet::new(),
+        });
+        self.queue(opcode, nodeid, args, Waiter::Task(reply.clone()))?;
+        reply.wait()
+    }
+
+    /// Sends a request without waiting for its reply.
+    fn send(&self, opcode: u32, nodeid: u64, args: &[&[u8]]) {
+        let _ = self.queue(opcode, nodeid, args, Waiter::Discard);
+    }
+
+    /// Marks the connection as mounted and starts the handshake.
+    fn mount(&self) -> AxResult<()> {
+        if self.mounted.swap(true, Ordering::AcqRel) {
+            return Err(AxError::InvalidInput);
+        }
+        let init = InitIn {
+            major: FUSE_KERNEL_VERSION,
+            minor: FUSE_KERNEL_MINOR_VERSION,
+            max_readahead: FUSE_MAX_READ as u32,
+            flags: 0,
+        };
+        // Requests sent before the reply are queued behind it
+        self.queue(FUSE_INIT, 0, &[init.as_bytes()], Waiter::Init)
+    }
+
+    /// Fails the pending requests and the later ones.
+    pub fn abort(&self) {
+        let waiting = {
+            let mut waiting = self.waiting.lock();
+            self.aborted.store(true, Ordering::Release);
+            self.requests.lock().clear();
+            core::mem::take(&mut *waiting)
+        };
+        for (_, waiter) in waiting {
+            if let Waiter::Task(reply) = waiter {
+                reply.set(Err(AxError::Other(LinuxError::ENOTCONN)));
+            }
+        }
+        self.poll_rx.wake();
+    }
+
+    /// Takes the next request, for the server to read into a buffer of
+    /// `capacity` bytes.
+    pub fn read_request(&self, capacity: usize) -> AxResult<Vec<u8>> {
+        if self.aborted.load(Ordering::Acquire) {
+            return Err(AxError::Other(LinuxError::ENODEV));
+        }
+        if !self.mounted.load(Ordering::Acquire) {
+            return Err(AxError::OperationNotPermitted);
+        }
+        if capacity < FUSE_MIN_READ_BUFFER {
+            return Err(AxError::InvalidInput);
+        }
+        let mut requests = self.requests.lock();
+        let request = requests.front().ok_or(AxError::WouldBlock)?;
+        if request.len() > capacity {
+            return Err(AxError::InvalidInput);
+        }
+        Ok(requests.pop_front().unwrap())
+    }
+
+    /// Handles a reply written by the server.
+    pub fn write_reply(&self, data: &[u8]) -> AxResult<()> {
+        let header = parse::<OutHeader>(data).map_err(|_| AxError::InvalidInput)?;
+        if header.len as usize != data.len() || !(-1000..=0).contains(&header.error) {
+            return Err(AxError::InvalidInput);
+        }
+        // Notifications are not supported
+        if header.unique == 0 {
+            return Err(AxError::InvalidInput);
+        }
+        let waiter = self
+            .waiting
+            .lock()
+            .remove(&header.unique)
+            .ok_or(AxError::NotFound)?;
+        let payload = &data[size_of::<OutHeader>()..];
+        let result = if header.error == 0 {
+            Ok(payload.to_vec())
+        } else {
+            Err(reply_error(-header.error))
+        };
+        match waiter {
+            Waiter::Task(reply) => reply.set(result),
+            Waiter::Init => match result.and_then(|it| parse::<InitOut>(&it)) {
+                Ok(init) if init.major == FUSE_KERNEL_VERSION => {
+                    self.max_write
+                        .store(init.max_write.max(4096), Ordering::Relaxed);
+                }
+                _ => {
+                    warn!("FUSE server rejected the handshake");
+                    self.abort();
+                }
+            },
+            Waiter::Discard => {}
+        }
+        Ok(())
+    }
+}
+
+impl Pollable for FuseConn {
+    fn poll(&self) -> IoEvents {
+        let mut events = IoEvents::OUT;
+        events.set(
+            IoEvents::IN,
+            self.aborted.load(Ordering::Acquire) || !self.requests.lock().is_empty(),
+        );
+        events
+    }
+
+    fn register(&self, context: &mut Context<'_>, events: IoEvents) {
+        if events.contains(IoEvents::IN) {
+            self.poll_rx.register(context.waker());
+        }
+    }
+}
+
+/// A filesystem served by a FUSE server.
+pub struct FuseFs {
+    conn: Arc<FuseConn>,
+    root: Mutex<Option<DirEntry>>,
+}
+
+impl FuseFs {
+    /// Creates a filesystem served through `conn`, which must not be
+    /// mounted yet.
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(conn: Arc<FuseConn>) -> AxResult<Filesystem> {
+        conn.mount()?;
+        let fs = Arc::new(Self {
+            conn,
+            root: Mutex::default(),
+        });
+        *fs.root.lock() = Some(DirEntry::new_dir(
+            |this| {
+                DirNode::new(FuseNode::new(
+                    fs.clone(),
+                    FUSE_ROOT_ID,
+                    FUSE_ROOT_ID,
+                    NodeType::Directory,
+                    Some(this),
+                ))
+            },
+            Reference::root(),
+        ));
+        Ok(Filesystem::new(fs))
+    }
+}
+
+impl FilesystemOps for FuseFs {
+    fn name(&self) -> &str {
+        "fuse"
+    }
+
+    fn root_dir(&self) -> DirEntry {
+        self.root.lock().clone().unwrap()
+    }
+
+    fn stat(&self) -> VfsResult<StatFs> {
+        Ok(dummy_stat_fs(FUSE_SUPER_MAGIC))
+    }
+}
+
+/// Aborts the connection of the FUSE filesystem unmounted from `root`, so
+/// its server stops.
+pub fn on_unmount(root: &Location) {
+    if let Ok(node) = root.entry().downcast::<FuseNode>() {
+        node.fs.conn.abort();
+    }
+}
+
+/// A file or directory of a [`FuseFs`].
+pub struct FuseNode {
+    fs: Arc<FuseFs>,
+    nodeid: u64,
+    ino: u64,
+    node_type: NodeType,
+    this: Option<WeakDirEntry>,
+    /// The handles opened for reading and for writing.
+    handles: Mutex<[Option<u64>; 2]>,
+}
+
+impl FuseNode {
+    fn new(
+        fs: Arc<FuseFs>,
+        nodeid: u64,
+        ino: u64,
+        node_type: NodeType,
+        this: Option<WeakDirEntry>,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            fs,
+            nodeid,
+            ino,
+            node_type,
+            this,
+            handles: Mutex::new([None; 2]),
+        })
+    }
+
+    fn conn(&self) -> &FuseConn {
+        &self.fs.conn
+    }
+
+    /// Returns the handle opened for writing if `write` is set, or for
+    /// reading otherwise, opening it if needed.
+    fn handle(&self, write: bool) -> AxResult<u64> {
+        let mut handles = self.handles.lock();
+        if let Some(fh) = handles[write as usize] {
+            return Ok(fh);
+        }
+        let opcode = if self.node_type == NodeType::Directory {
+            FUSE_OPENDIR
+        } else {
+            FUSE_OPEN
+        };
+        let open = OpenIn {
+            flags: if write { O_WRONLY } else { O_RDONLY },
+            open_flags: 0,
+        };
+        let reply = self
+            .conn()
+            .request(opcode, self.nodeid, &[open.as_bytes()])?;
+        let fh = parse::<OpenOut>(&reply)?.fh;
+        handles[write as usize] = Some(fh);
+        Ok(fh)
+    }
+}
+
+impl Drop for FuseNode {
+    fn drop(&mut self) {
+        let opcode = if self.node_type == NodeType::Directory {
+            FUSE_RELEASEDIR
+        } else {
+            FUSE_RELEASE
+        };
+        let handles = *self.handles.lock();
+        for (write, fh) in handles.into_iter().enumerate() {
+            if let Some(fh) = fh {
+                let release = ReleaseIn {
+                    fh,
+                    flags: if write == 1 { O_WRONLY } else { O_RDONLY },
+                    release_flags: 0,
+                    lock_owner: 0,
+                };
+                self.conn().send(opcode, self.nodeid, &[release.as_bytes()]);
+            }
+        }
+        // Each node but the root comes from a lookup
+        if self.nodeid != FUSE_ROOT_ID {
+            let forget = ForgetIn { nlookup: 1 };
+            self.conn()
+                .send(FUSE_FORGET, self.nodeid, &[forget.as_bytes()]);
+        }
+    }
+}
+
+impl NodeOps for FuseNode {
+    fn inode(&self) -> u64 {
+        self.ino
+    }
+
+    fn metadata(&self) -> VfsResult<Metadata> {
+        let getattr = GetattrIn {
+            getattr_flags: 0,
+            dummy: 0,
+            fh: 0,
+        };
+        let reply = self
+            .conn()
+            .request(FUSE_GETATTR, self.nodeid, &[getattr.as_bytes()])?;
+        Ok(attr_to_metadata(&parse::<AttrOut>(&reply)?.attr))
+    }
+
+    fn len(&self) -> VfsResult<u64> {
+        Ok(self.metadata()?.size)
+    }
+
+    fn update_metadata(&self, update: MetadataUpdate) -> VfsResult<()> {
+        // Timestamps are left to the server
+        if update.mode.is_some() || update.owner.is_some() {
+            return Err(VfsError::OperationNotPermitted);
+        }
+        Ok(())
+    }
+
+    fn filesystem(&self) -> &dyn FilesystemOps {
+        self.fs.as_ref()
+    }
+
+    fn sync(&self, data_only: bool) -> VfsResult<()> {
+        let conn = self.conn();
+        if conn.no_fsync.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        // Prefer the handle data was written through
+        let fh = match self.handles.lock()[1] {
+            Some(fh) => fh,
+            None => self.handle(false)?,
+        };
+        let opcode = if self.node_type == NodeType::Directory {
+            FUSE_FSYNCDIR
+        } else {
+            FUSE_FSYNC
+        };
+        let fsync = FsyncIn {
+            fh,
+            fsync_flags: if data_only { FUSE_FSYNC_FDATASYNC } else { 0 },
+            padding: 0,
+        };
+        match conn.request(opcode, self.nodeid, &[fsync.as_bytes()]) {
+            Ok(_) => Ok(()),
+            Err(AxError::Other(LinuxError::ENOSYS)) => {
+                conn.no_fsync.store(true, Ordering::Relaxed);
+                Ok(())
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    fn into_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
+        self
+    }
+
+    fn flags(&self) -> NodeFlags {
+        NodeFlags::NON_CACHEABLE
+    }
+}
+
+impl FileNodeOps for FuseNode {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> VfsResult<usize> {
+        let read = IoIn {
+            fh: self.handle(false)?,
+            offset,
+            size: buf.len().min(FUSE_MAX_READ) as u32,
+            io_flags: 0,
+            lock_owner: 0,
+            flags: O_RDONLY,
+            padding: 0,
+        };
+        let reply = self
+            .conn()
+            .request(FUSE_READ, self.nodeid, &[read.as_bytes()])?;
+        let len = reply.len().min(buf.len());
+        buf[..len].copy_from_slice(&reply[..len]);
+        Ok(len)
+    }
+
+    fn write_at(&self, buf: &[u8], offset: u64) -> VfsResult<usize> {
+        let fh = self.handle(true)?;
+        let max_write = self.conn().max_write.load(Ordering::Relaxed) as usize;
+        let mut written = 0;
+        for chunk in buf.chunks(max_write) {
+            let write = IoIn {
+                fh,
+                offset: offset + written as u64,
+                size: chunk.len() as u32,
+                io_flags: 0,
+                lock_owner: 0,
+                flags: O_WRONLY,
+                padding: 0,
+            };
+            let reply = self
+                .conn()
+                .request(FUSE_WRITE, self.nodeid, &[write.as_bytes(), chunk])?;
+            let size = (parse::<WriteOut>(&reply)?.size as usize).min(chunk.len());
+            written += size;
+            if size < chunk.len() {
+                break;
+            }
+        }
+        Ok(written)
+    }
+
+    fn append(&self, buf: &[u8]) -> VfsResult<(usize, u64)> {
+        let offset = self.len()?;
+        let written = self.write_at(buf, offset)?;
+        Ok((written, offset + written as u64))
+    }
+
+    fn set_len(&self, len: u64) -> VfsResult<()> {
+        // The server decides how the file is extended or shrunk, e.g. with a
+        // hole instead of zeroed blocks
+        let mut setattr = SetattrIn::new_zeroed();
+        setattr.valid = FATTR_SIZE;
+        setattr.size = len;
+        if let Some(fh) = self.handles.lock()[1] {
+            setattr.valid |= FATTR_FH;
+            setattr.fh = fh;
+        }
+        self.conn()
+            .request(FUSE_SETATTR, self.nodeid, &[setattr.as_bytes()])?;
+        Ok(())
+    }
+
+    fn set_symlink(&self, _target: &str) -> VfsResult<()> {
+        Err(VfsError::OperationNotPermitted)
+    }
+}
+
+impl Pollable for FuseNode {
+    fn poll(&self) -> IoEvents {
+        IoEvents::IN | IoEvents::OUT
+    }
+
+    fn register(&self, _context: &mut Context<'_>, _events: IoEvents) {}
+}
+
+impl DirNodeOps for FuseNode {
+    fn read_dir(&self, offset: u64, sink: &mut dyn DirEntrySink) -> VfsResult<usize> {
+        let fh = self.handle(false)?;
+        let mut offset = offset;
+        let mut count = 0;
+        loop {
+            let read = IoIn {
+                fh,
+                offset,
+                size: 4096,
+                io_flags: 0,
+                lock_owner: 0,
+                flags: O_RDONLY,
+                padding: 0,
+            };
+            let reply = self
+                .conn()
+                .request(FUSE_READDIR, self.nodeid, &[read.as_bytes()])?;
+            if reply.is_empty() {
+                return Ok(count);
+            }
+            let mut rest = &reply[..];
+            while !rest.is_empty() {
+                let dirent = parse::<Dirent>(rest)?;
+                let name_start = size_of::<Dirent>();
+                let name = rest
+                    .get(name_start..name_start + dirent.namelen as usize)
+                    .and_then(|it| str::from_utf8(it).ok())
+                    .ok_or(AxError::Other(LinuxError::EIO))?;
+                if !sink.accept(name, dirent.ino, node_type(dirent.ty << 12), dirent.off) {
+                    return Ok(count);
+                }
+                count += 1;
+                offset = dirent.off;
+                let len = (name_start + dirent.namelen as usize).next_multiple_of(8);
+                rest = rest.get(len..).unwrap_or_default();
+            }
+        }
+    }
+
+    fn lookup(&self, name: &str) -> VfsResult<DirEntry> {
+        let reply = self
+            .conn()
+            .request(FUSE_LOOKUP, self.nodeid, &[name.as_bytes(), b"\0"])?;
+        let entry = parse::<EntryOut>(&reply)?;
+        // A node ID of 0 is a negative entry
+        if entry.nodeid == 0 {
+            return Err(VfsError::NotFound);
+        }
+        let node_type = node_type(entry.attr.mode);
+        let fs = self.fs.clone();
+        let reference = Reference::new(
+            self.this.as_ref().and_then(WeakDirEntry::upgrade),
+            name.to_owned(),
+        );
+        Ok(if node_type == NodeType::Directory {
+            DirEntry::new_dir(
+                |this| {
+                    DirNode::new(FuseNode::new(
+                        fs,
+                        entry.nodeid,
+                        entry.attr.ino,
+                        node_type,
+                        Some(this),
+                    ))
+                },
+                reference,
+            )
+        } else {
+            DirEntry::new_file(
+                FileNode::new(FuseNode::new(
+                    fs,
+                    entry.nodeid,
+                    entry.attr.ino,
+                    node_type,
+                    None,
+                )),
+                node_type,
+                reference,
+            )
+        })
+    }
+
+    fn is_cacheable(&self) -> bool {
+        false
+    }
+
+    fn create(
+        &self,
+        _name: &str,
+        _node_type: NodeType,
+        _permission: NodePermission,
+    ) -> VfsResult<DirEntry> {
+        Err(VfsError::OperationNotPermitted)
+    }
+
+    fn link(&self, _name: &str, _node: &DirEntry) -> VfsResult<DirEntry> {
+        Err(VfsError::OperationNotPermitted)
+    }
+
+    fn unlink(&self, _name: &str) -> VfsResult<()> {
+        Err(VfsError::OperationNotPermitted)
+    }
+
+    fn rename(&self, _src_name: &str, _dst_dir: &DirNode, _dst_name: &str) -> VfsResult<()> {
+        Err(VfsError::OperationNotPermitted)
+    }
+}
+
+/// Returns the `fd=N` option of the data of a `fuse` mount.
+pub fn parse_mount_fd(data: &str) -> AxResult<i32> {
+    data.split(',')
+        .find_map(|it| it.strip_prefix("fd="))
+        .and_then(|fd| fd.parse().ok())
+        .ok_or(AxError::InvalidInput)
+}
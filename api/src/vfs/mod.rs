@@ -1,15 +1,17 @@
 //! Virtual filesystems
 
+pub mod cgroup;
 pub mod dev;
+pub mod fuse;
+pub mod mounts;
 mod proc;
+mod sys;
 mod tmp;
+mod tracing;
 
 use axerrno::LinuxResult;
 use axfs_ng::{FS_CONTEXT, FsContext};
-use axfs_ng_vfs::{
-    Filesystem, NodePermission,
-    path::{Path, PathBuf},
-};
+use axfs_ng_vfs::{Filesystem, NodePermission};
 pub use starry_core::vfs::{Device, DeviceOps, DirMapping, SimpleFs};
 pub use tmp::MemoryFs;
 
@@ -20,6 +22,7 @@ fn mount_at(fs: &FsContext, path: &str, mount_fs: Filesystem) -> LinuxResult<()>
         fs.create_dir(path, DIR_PERMISSION)?;
     }
     fs.resolve(path)?.mount(&mount_fs)?;
+    mounts::add(&fs.resolve(path)?, mount_fs.name(), mount_fs.name(), "rw")?;
     info!("Mounted {} at {}", mount_fs.name(), path);
     Ok(())
 }
@@ -27,21 +30,15 @@ fn mount_at(fs: &FsContext, path: &str, mount_fs: Filesystem) -> LinuxResult<()>
 /// Mount all filesystems
 pub fn mount_all() -> LinuxResult<()> {
     let fs = FS_CONTEXT.lock();
+    let root = fs.resolve("/")?;
+    mounts::add(&root, "rootfs", root.filesystem().name(), "rw")?;
     mount_at(&fs, "/dev", dev::new_devfs())?;
     mount_at(&fs, "/dev/shm", tmp::MemoryFs::new())?;
     mount_at(&fs, "/tmp", tmp::MemoryFs::new())?;
     mount_at(&fs, "/proc", proc::new_procfs())?;
 
-    mount_at(&fs, "/sys", tmp::MemoryFs::new())?;
-    let mut path = PathBuf::new();
-    for comp in Path::new("/sys/class/graphics/fb0/device").components() {
-        path.push(comp.as_str());
-        if fs.resolve(&path).is_err() {
-            fs.create_dir(&path, DIR_PERMISSION)?;
-        }
-    }
-    path.push("subsystem");
-    fs.symlink("whatever", &path)?;
+    mount_at(&fs, "/sys", sys::new_sysfs())?;
+    mount_at(&fs, "/sys/fs/cgroup", cgroup::new_cgroupfs())?;
     drop(fs);
 
     #[cfg(feature = "dev-log")]
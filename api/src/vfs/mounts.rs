@@ -0,0 +1,189 @@
+//! The mount table, backing `/proc/mounts`, `statmount` and `listmount`.
+
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::fmt::Write;
+
+use axerrno::AxResult;
+use axfs_ng_vfs::Location;
+use axsync::Mutex;
+use linux_raw_sys::general::{MS_NODEV, MS_NOEXEC, MS_NOSUID, MS_RDONLY};
+
+/// Unique mount IDs start above the range of the old, reused IDs shown in
+/// `/proc/<pid>/mountinfo`, like Linux.
+const MNT_UNIQUE_ID_OFFSET: u64 = 1 << 32;
+
+/// An entry of the mount table.
+#[derive(Debug, Clone)]
+pub struct MountInfo {
+    /// Unique mount ID.
+    pub id: u64,
+    /// Unique ID of the parent mount, or `id` for the root mount.
+    pub parent: u64,
+    pub source: String,
+    pub fs_type: String,
+    /// Absolute path of the mount point.
+    pub target: String,
+    /// Comma separated mount options, e.g. `rw,nosuid`.
+    pub options: String,
+    /// `..._SUPER_MAGIC` of the filesystem.
+    pub magic: u64,
+    pub device: u64,
+}
+
+impl MountInfo {
+    /// The old mount ID, as shown in `/proc/<pid>/mountinfo`.
+    pub fn old_id(&self) -> u32 {
+        (self.id - MNT_UNIQUE_ID_OFFSET) as u32
+    }
+
+    /// Returns whether the mount has the option `name`, like `noexec`.
+    pub fn has_option(&self, name: &str) -> bool {
+        self.options.split(',').any(|it| it == name)
+    }
+}
+
+struct MountTable {
+    mounts: Vec<MountInfo>,
+    next_id: u64,
+}
+
+static MOUNTS: Mutex<MountTable> = Mutex::new(MountTable {
+    mounts: Vec::new(),
+    next_id: MNT_UNIQUE_ID_OFFSET,
+});
+
+/// Returns whether `path` is `dir` or inside of it.
+fn is_under(path: &str, dir: &str) -> bool {
+    match path.strip_prefix(dir) {
+        Some(rest) => rest.is_empty() || rest.starts_with('/') || dir.ends_with('/'),
+        None => false,
+    }
+}
+
+/// Records the filesystem mounted at `root`, the root directory of the new
+/// mount. Returns the ID of the mount.
+pub fn add(root: &Location, source: &str, fs_type: &str, options: &str) -> AxResult<u64> {
+    let target = root.absolute_path()?.to_string();
+    let magic = root.filesystem().stat()?.fs_type as u64;
+    let device = root.mountpoint().device();
+
+    let mut table = MOUNTS.lock();
+    let id = table.next_id;
+    table.next_id += 1;
+    let parent = table
+        .mounts
+        .iter()
+        .filter(|it| is_under(&target, &it.target))
+        .max_by_key(|it| it.target.len())
+        .map_or(id, |it| it.id);
+    table.mounts.push(MountInfo {
+        id,
+        parent,
+        source: source.to_string(),
+        fs_type: fs_type.to_string(),
+        target,
+        options: options.to_string(),
+        magic,
+        device,
+    });
+    Ok(id)
+}
+
+/// Removes the topmost mount at `target`.
+pub fn remove(target: &Location) -> AxResult<()> {
+    let target = target.absolute_path()?;
+    let mut table = MOUNTS.lock();
+    if let Some(index) = table
+        .mounts
+        .iter()
+        .rposition(|it| it.target == target.as_str())
+    {
+        table.mounts.remove(index);
+    }
+    Ok(())
+}
+
+/// Returns a snapshot of the mount table, in mount order.
+pub fn mounts() -> Vec<MountInfo> {
+    MOUNTS.lock().mounts.clone()
+}
+
+/// Returns the mount with the unique ID `id`.
+pub fn find(id: u64) -> Option<MountInfo> {
+    MOUNTS.lock().mounts.iter().find(|it| it.id == id).cloned()
+}
+
+/// Returns the topmost mount containing the absolute path `path`.
+pub fn containing(path: &str) -> Option<MountInfo> {
+    MOUNTS
+        .lock()
+        .mounts
+        .iter()
+        .filter(|it| is_under(path, &it.target))
+        .max_by_key(|it| it.target.len())
+        .cloned()
+}
+
+/// Returns whether the mount containing `loc` has the option `name`.
+pub fn has_option(loc: &Location, name: &str) -> bool {
+    loc.absolute_path()
+        .is_ok_and(|path| containing(path.as_str()).is_some_and(|it| it.has_option(name)))
+}
+
+/// Generates the content of `/proc/mounts`.
+pub fn proc_mounts() -> String {
+    let mut out = String::new();
+    for mount in mounts() {
+        let _ = writeln!(
+            out,
+            "{} {} {} {} 0 0",
+            mount.source, mount.target, mount.fs_type, mount.options
+        );
+    }
+    out
+}
+
+/// Generates the content of `/proc/<pid>/mountinfo`.
+pub fn proc_mountinfo() -> String {
+    let mounts = mounts();
+    let mut out = String::new();
+    for mount in &mounts {
+        let parent = mounts
+            .iter()
+            .find(|it| it.id == mount.parent)
+            .map_or(mount.old_id(), MountInfo::old_id);
+        let _ = writeln!(
+            out,
+            "{} {} {}:{} / {} {} - {} {} {}",
+            mount.old_id(),
+            parent,
+            mount.device >> 8,
+            mount.device & 0xff,
+            mount.target,
+            mount.options,
+            mount.fs_type,
+            mount.source,
+            mount.options
+        );
+    }
+    out
+}
+
+/// Returns the mount options for the `MS_*` mount flags.
+pub fn options_for_flags(flags: u32) -> String {
+    let mut options = String::from(if flags & MS_RDONLY != 0 { "ro" } else { "rw" });
+    for (flag, name) in [
+        (MS_NOSUID, "nosuid"),
+        (MS_NODEV, "nodev"),
+        (MS_NOEXEC, "noexec"),
+    ] {
+        if flags & flag != 0 {
+            options.push(',');
+            options.push_str(name);
+        }
+    }
+    options
+}
@@ -1,27 +1,51 @@
 use alloc::{
     borrow::Cow,
     boxed::Box,
+    collections::{BTreeMap, BTreeSet},
     format,
     string::{String, ToString},
     sync::{Arc, Weak},
     vec,
     vec::Vec,
 };
-use core::{ffi::CStr, iter};
+use core::{
+    ffi::CStr,
+    fmt::Write,
+    iter,
+    ops::RangeInclusive,
+    sync::atomic::{AtomicU64, Ordering},
+};
 
+use axerrno::LinuxError;
 use axfs_ng_vfs::{Filesystem, NodeType, VfsError, VfsResult};
-use axtask::{AxTaskRef, WeakAxTaskRef, current};
+use axhal::{
+    paging::{MappingFlags, PageSize},
+    time::{NANOS_PER_SEC, monotonic_time_nanos},
+};
+use axmm::{AddrSpace, backend::Backend};
+use axsync::Mutex;
+use axtask::{AxTaskRef, TaskState, WeakAxTaskRef, current};
 use indoc::indoc;
+use memory_addr::{MemoryAddr, PAGE_SIZE_4K, VirtAddr};
 use starry_core::{
-    task::{AsThread, TaskStat, get_task, tasks},
+    config::USER_STACK_TOP,
+    ksym,
+    psi::{self, Resource},
+    schedstat,
+    task::{self, AsThread, ProcessData, TaskStat, get_task, tasks},
+    time::{NsClock, TimeNamespace},
     vfs::{
         DirMaker, DirMapping, NodeOpsMux, RwFile, SimpleDir, SimpleDirOps, SimpleFile,
-        SimpleFileOperation, SimpleFs,
+        SimpleFileOperation, SimpleFileOps, SimpleFs,
     },
 };
 use starry_process::Process;
 
-use crate::file::FD_TABLE;
+use crate::{
+    file::{self, FD_TABLE, writeback},
+    mm,
+    vfs::mounts,
+};
 
 const DUMMY_MEMINFO: &str = indoc! {"
     MemTotal:       32536204 kB
@@ -143,6 +167,370 @@ fn task_status(task: &AxTaskRef) -> String {
     )
 }
 
+#[rustfmt::skip]
+fn task_io(task: &AxTaskRef) -> String {
+    let io = &task.as_thread().proc_data.io;
+    format!(
+        "rchar: {}\n\
+        wchar: {}\n\
+        syscr: {}\n\
+        syscw: {}\n\
+        read_bytes: {}\n\
+        write_bytes: {}\n\
+        cancelled_write_bytes: 0\n",
+        io.rchar.load(Ordering::Relaxed),
+        io.wchar.load(Ordering::Relaxed),
+        io.syscr.load(Ordering::Relaxed),
+        io.syscw.load(Ordering::Relaxed),
+        io.read_bytes.load(Ordering::Relaxed),
+        io.write_bytes.load(Ordering::Relaxed),
+    )
+}
+
+/// The clocks of `/proc/[pid]/timens_offsets`, with their names and IDs.
+const TIMENS_CLOCKS: [(NsClock, &str, u32); 2] = [
+    (NsClock::Monotonic, "monotonic", 1),
+    (NsClock::Boottime, "boottime", 7),
+];
+
+fn timens_offsets(ns: &TimeNamespace) -> String {
+    let mut out = String::new();
+    for (clock, name, _) in TIMENS_CLOCKS {
+        let offset = ns.offset(clock);
+        let nanos = NANOS_PER_SEC as i64;
+        // Like a timespec, negative offsets have positive nanoseconds
+        let _ = writeln!(
+            out,
+            "{name:<10} {:>10} {:>9}",
+            offset.div_euclid(nanos),
+            offset.rem_euclid(nanos)
+        );
+    }
+    out
+}
+
+/// Parses lines of `<clock> <seconds> <nanoseconds>`, where the clock is
+/// given by name or ID.
+fn parse_timens_offsets(data: &[u8]) -> VfsResult<Vec<(NsClock, i64)>> {
+    let text = str::from_utf8(data).map_err(|_| VfsError::InvalidInput)?;
+    let mut offsets = Vec::new();
+    for line in text.lines().filter(|it| !it.trim().is_empty()) {
+        let [clock, secs, nanos] = line
+            .split_ascii_whitespace()
+            .collect::<Vec<_>>()
+            .try_into()
+            .map_err(|_| VfsError::InvalidInput)?;
+        let (clock, ..) = TIMENS_CLOCKS
+            .into_iter()
+            .find(|(_, name, id)| clock == *name || clock.parse::<u32>() == Ok(*id))
+            .ok_or(VfsError::InvalidInput)?;
+        let secs = secs.parse::<i64>().map_err(|_| VfsError::InvalidInput)?;
+        let nanos = nanos.parse::<i64>().map_err(|_| VfsError::InvalidInput)?;
+        if !(0..NANOS_PER_SEC as i64).contains(&nanos) {
+            return Err(VfsError::InvalidInput);
+        }
+        let offset = secs
+            .checked_mul(NANOS_PER_SEC as i64)
+            .and_then(|it| it.checked_add(nanos))
+            .ok_or(VfsError::Other(LinuxError::ERANGE))?;
+        offsets.push((clock, offset));
+    }
+    Ok(offsets)
+}
+
+/// Resident pages of a mapping, in bytes.
+#[derive(Default)]
+struct MappingUsage {
+    shared_clean: usize,
+    shared_dirty: usize,
+    private_clean: usize,
+    private_dirty: usize,
+    /// Each page divided by the number of address spaces mapping its frame.
+    pss: usize,
+}
+
+impl MappingUsage {
+    fn rss(&self) -> usize {
+        self.shared_clean + self.shared_dirty + self.private_clean + self.private_dirty
+    }
+}
+
+/// Calls `f` with the frame address, flags and size of each resident page in
+/// `[start, end)`.
+fn for_each_page(
+    aspace: &AddrSpace,
+    start: VirtAddr,
+    end: VirtAddr,
+    mut f: impl FnMut(usize, MappingFlags, usize),
+) {
+    let mut vaddr = start;
+    while vaddr < end {
+        let Ok((paddr, flags, page_size)) = aspace.page_table().query(vaddr) else {
+            vaddr += PageSize::Size4K as usize;
+            continue;
+        };
+        let size: usize = page_size.into();
+        f(paddr.as_usize().align_down(size), flags, size);
+        vaddr = vaddr.align_down(size) + size;
+    }
+}
+
+/// Counts the address spaces mapping each resident frame of `aspace`, to
+/// split the shared ones in `Pss`.
+///
+/// The address spaces are locked one at a time, so the counts may be
+/// slightly off if they change meanwhile.
+fn frame_mappers(aspace: &Mutex<AddrSpace>) -> BTreeMap<usize, usize> {
+    let mut mappers = BTreeMap::new();
+    let own = aspace.lock();
+    for area in own.areas() {
+        if !matches!(area.backend(), Backend::Linear(_)) {
+            for_each_page(&own, area.start(), area.end(), |paddr, _, _| {
+                mappers.insert(paddr, 0);
+            });
+        }
+    }
+    drop(own);
+    let mut seen = BTreeSet::new();
+    for proc_data in task::processes() {
+        // Processes created with `CLONE_VM` share an address space
+        if !seen.insert(Arc::as_ptr(&proc_data.aspace)) {
+            continue;
+        }
+        let other = proc_data.aspace.lock();
+        for area in other.areas() {
+            if matches!(area.backend(), Backend::Linear(_)) {
+                continue;
+            }
+            for_each_page(&other, area.start(), area.end(), |paddr, _, _| {
+                if let Some(count) = mappers.get_mut(&paddr) {
+                    *count += 1;
+                }
+            });
+        }
+    }
+    mappers
+}
+
+/// Walks the page table over `[start, end)` to find the resident pages.
+///
+/// There is no dirty bit tracking, so pages mapped writable are reported as
+/// dirty: private pages only become writable once they have been copied on
+/// write.
+fn mapping_usage(
+    aspace: &AddrSpace,
+    start: VirtAddr,
+    end: VirtAddr,
+    shared: bool,
+    mappers: &BTreeMap<usize, usize>,
+) -> MappingUsage {
+    let mut usage = MappingUsage::default();
+    for_each_page(aspace, start, end, |paddr, flags, size| {
+        let dirty = flags.contains(MappingFlags::WRITE);
+        *match (shared, dirty) {
+            (true, false) => &mut usage.shared_clean,
+            (true, true) => &mut usage.shared_dirty,
+            (false, false) => &mut usage.private_clean,
+            (false, true) => &mut usage.private_dirty,
+        } += size;
+        usage.pss += size / mappers.get(&paddr).copied().unwrap_or(1).max(1);
+    });
+    usage
+}
+
+/// `/proc/[pid]/pagemap`, holding a 64-bit entry for each virtual page of the
+/// process.
+struct Pagemap {
+    proc_data: Arc<ProcessData>,
+}
+
+impl Pagemap {
+    const ENTRY_SIZE: usize = 8;
+    const FILE_OR_SHARED: u64 = 1 << 61;
+    const PFN_MASK: u64 = (1 << 55) - 1;
+    const PRESENT: u64 = 1 << 63;
+
+    /// Returns the entry of the page at `vaddr`. The frame number is only
+    /// exposed to privileged readers, like Linux does.
+    fn entry(aspace: &AddrSpace, vaddr: VirtAddr, show_pfn: bool) -> u64 {
+        let Ok((paddr, _, page_size)) = aspace.page_table().query(vaddr) else {
+            return 0;
+        };
+        let mut entry = Self::PRESENT;
+        let size: usize = page_size.into();
+        // Pages of huge mappings are numbered from the start of the frame
+        let paddr = paddr.as_usize().align_down(size) + vaddr.as_usize() % size;
+        if show_pfn {
+            entry |= (paddr / PAGE_SIZE_4K) as u64 & Self::PFN_MASK;
+        }
+        if aspace
+            .find_area(vaddr)
+            .is_some_and(|area| matches!(area.backend(), Backend::File(_) | Backend::Shared(_)))
+        {
+            entry |= Self::FILE_OR_SHARED;
+        }
+        entry
+    }
+}
+
+impl SimpleFileOps for Pagemap {
+    fn read_all(&self) -> VfsResult<Cow<[u8]>> {
+        Err(VfsError::InvalidInput)
+    }
+
+    fn write_all(&self, _data: &[u8]) -> VfsResult<()> {
+        Err(VfsError::BadFileDescriptor)
+    }
+
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> VfsResult<usize> {
+        if offset % Self::ENTRY_SIZE as u64 != 0 || buf.len() % Self::ENTRY_SIZE != 0 {
+            return Err(VfsError::InvalidInput);
+        }
+        let show_pfn = current().as_thread().proc_data.cred.read().is_privileged();
+        let aspace = self.proc_data.aspace.lock();
+        let first = offset as usize / Self::ENTRY_SIZE;
+        let count = buf.len() / Self::ENTRY_SIZE;
+        let end = (aspace.end().as_usize() / PAGE_SIZE_4K).min(first.saturating_add(count));
+        let mut read = 0;
+        for page in first..end {
+            let entry = Self::entry(&aspace, VirtAddr::from(page * PAGE_SIZE_4K), show_pfn);
+            buf[read..read + Self::ENTRY_SIZE].copy_from_slice(&entry.to_ne_bytes());
+            read += Self::ENTRY_SIZE;
+        }
+        Ok(read)
+    }
+
+    fn len(&self) -> VfsResult<u64> {
+        let end = self.proc_data.aspace.lock().end().as_usize();
+        Ok((end / PAGE_SIZE_4K * Self::ENTRY_SIZE) as u64)
+    }
+}
+
+/// Where [`VDSO_MAPS`] starts.
+const VDSO_MAPS_START: usize = 0x7f00_0000;
+
+/// There is no vDSO, but some programs look for it in `/proc/[pid]/maps`, so
+/// the placeholder lines it always had are still listed there.
+const VDSO_MAPS: &str = indoc! {"
+    7f000000-7f001000 r--p 00000000 00:00 0          [vdso]
+    7f001000-7f003000 r-xp 00001000 00:00 0          [vdso]
+    7f003000-7f005000 r--p 00003000 00:00 0          [vdso]
+    7f005000-7f007000 rw-p 00005000 00:00 0          [vdso]
+"};
+
+/// Generates `/proc/[pid]/maps`, or `/proc/[pid]/smaps` if `detailed` is set.
+fn task_maps(task: &AxTaskRef, detailed: bool) -> String {
+    let proc_data = &task.as_thread().proc_data;
+    let heap_bottom = proc_data.get_heap_bottom();
+    let mappers = if detailed {
+        frame_mappers(&proc_data.aspace)
+    } else {
+        BTreeMap::new()
+    };
+    let aspace = proc_data.aspace.lock();
+
+    let mut buf = String::new();
+    let mut vdso_listed = false;
+    for area in aspace.areas() {
+        let (start, end) = (area.start(), area.end());
+        if !detailed && !vdso_listed && start.as_usize() >= VDSO_MAPS_START {
+            buf.push_str(VDSO_MAPS);
+            vdso_listed = true;
+        }
+        let flags = area.flags();
+        let shared = !matches!(area.backend(), Backend::Cow(_));
+        let name = if (start.as_usize()..end.as_usize()).contains(&heap_bottom) {
+            "[heap]"
+        } else if end.as_usize() == USER_STACK_TOP {
+            "[stack]"
+        } else {
+            ""
+        };
+        let mut perms = String::with_capacity(4);
+        for (flag, c) in [
+            (MappingFlags::READ, 'r'),
+            (MappingFlags::WRITE, 'w'),
+            (MappingFlags::EXECUTE, 'x'),
+        ] {
+            perms.push(if flags.contains(flag) { c } else { '-' });
+        }
+        perms.push(if shared { 's' } else { 'p' });
+        let _ = writeln!(
+            buf,
+            "{:08x}-{:08x} {perms} 00000000 00:00 0          {name}",
+            start.as_usize(),
+            end.as_usize(),
+        );
+        if !detailed {
+            continue;
+        }
+
+        // Device memory is not counted, like `VM_PFNMAP` mappings in Linux.
+        let usage = if matches!(area.backend(), Backend::Linear(_)) {
+            MappingUsage::default()
+        } else {
+            mapping_usage(&aspace, start, end, shared, &mappers)
+        };
+        let rss = usage.rss() / 1024;
+        // Private pages that have been written to are anonymous copies.
+        let anonymous = match area.backend() {
+            Backend::Cow(_) => usage.private_dirty,
+            Backend::Shared(_) => usage.rss(),
+            _ => 0,
+        };
+        let mut vm_flags = String::new();
+        for (flag, name) in [
+            (MappingFlags::READ, "rd "),
+            (MappingFlags::WRITE, "wr "),
+            (MappingFlags::EXECUTE, "ex "),
+        ] {
+            if flags.contains(flag) {
+                vm_flags.push_str(name);
+            }
+        }
+        if shared {
+            vm_flags.push_str("sh ");
+        }
+        let _ = write!(
+            buf,
+            "Size:           {:8} kB\n\
+            KernelPageSize: {:8} kB\n\
+            MMUPageSize:    {:8} kB\n\
+            Rss:            {:8} kB\n\
+            Pss:            {:8} kB\n\
+            Shared_Clean:   {:8} kB\n\
+            Shared_Dirty:   {:8} kB\n\
+            Private_Clean:  {:8} kB\n\
+            Private_Dirty:  {:8} kB\n\
+            Referenced:     {:8} kB\n\
+            Anonymous:      {:8} kB\n\
+            Swap:           {:8} kB\n\
+            SwapPss:        {:8} kB\n\
+            Locked:         {:8} kB\n\
+            VmFlags: {}\n",
+            area.size() / 1024,
+            4,
+            4,
+            rss,
+            usage.pss / 1024,
+            usage.shared_clean / 1024,
+            usage.shared_dirty / 1024,
+            usage.private_clean / 1024,
+            usage.private_dirty / 1024,
+            rss,
+            anonymous / 1024,
+            0,
+            0,
+            0,
+            vm_flags,
+        );
+    }
+    if !detailed && !vdso_listed {
+        buf.push_str(VDSO_MAPS);
+    }
+    buf
+}
+
 /// The /proc/[pid]/fd directory
 struct ThreadFdDir {
     fs: Arc<SimpleFs>,
@@ -196,11 +584,19 @@ impl SimpleDirOps for ThreadDir {
                 "stat",
                 "status",
                 "oom_score_adj",
+                "schedstat",
                 "task",
                 "maps",
+                "smaps",
+                "pagemap",
+                "io",
                 "mounts",
+                "mountinfo",
                 "cmdline",
                 "comm",
+                "wchan",
+                "timens_offsets",
+                "cgroup",
                 "exe",
                 "fd",
             ]
@@ -218,6 +614,12 @@ impl SimpleDirOps for ThreadDir {
             })
             .into(),
             "status" => SimpleFile::new_regular(fs, move || Ok(task_status(&task))).into(),
+            "schedstat" => SimpleFile::new_regular(fs, move || {
+                let stat = &task.as_thread().sched_stat;
+                // The time spent waiting on a run queue is not tracked
+                Ok(format!("{} 0 {}\n", stat.run_ns(), stat.timeslices()))
+            })
+            .into(),
             "oom_score_adj" => SimpleFile::new_regular(
                 fs,
                 RwFile::new(move |req| match req {
@@ -245,19 +647,18 @@ impl SimpleDirOps for ThreadDir {
                 }),
             )
             .into(),
-            "maps" => SimpleFile::new_regular(fs, move || {
-                Ok(indoc! {"
-                    7f000000-7f001000 r--p 00000000 00:00 0          [vdso]
-                    7f001000-7f003000 r-xp 00001000 00:00 0          [vdso]
-                    7f003000-7f005000 r--p 00003000 00:00 0          [vdso]
-                    7f005000-7f007000 rw-p 00005000 00:00 0          [vdso]
-                "})
-            })
-            .into(),
-            "mounts" => SimpleFile::new_regular(fs, move || {
-                Ok("proc /proc proc rw,nosuid,nodev,noexec,relatime 0 0\n")
-            })
+            "maps" => SimpleFile::new_regular(fs, move || Ok(task_maps(&task, false))).into(),
+            "smaps" => SimpleFile::new_regular(fs, move || Ok(task_maps(&task, true))).into(),
+            "pagemap" => SimpleFile::new_regular(
+                fs,
+                Pagemap {
+                    proc_data: task.as_thread().proc_data.clone(),
+                },
+            )
             .into(),
+            "io" => SimpleFile::new_regular(fs, move || Ok(task_io(&task))).into(),
+            "mounts" => SimpleFile::new_regular(fs, || Ok(mounts::proc_mounts())).into(),
+            "mountinfo" => SimpleFile::new_regular(fs, || Ok(mounts::proc_mountinfo())).into(),
             "cmdline" => SimpleFile::new_regular(fs, move || {
                 let cmdline = task.as_thread().proc_data.cmdline.read();
                 let mut buf = Vec::new();
@@ -296,6 +697,41 @@ impl SimpleDirOps for ThreadDir {
                 }),
             )
             .into(),
+            "timens_offsets" => SimpleFile::new_regular(
+                fs,
+                RwFile::new(move |req| {
+                    // Like Linux, this shows and changes the namespace of
+                    // the children
+                    let ns = task.as_thread().proc_data.time_ns_for_children();
+                    match req {
+                        SimpleFileOperation::Read => Ok(Some(timens_offsets(&ns).into_bytes())),
+                        SimpleFileOperation::Write(data) => {
+                            let offsets = parse_timens_offsets(data)?;
+                            if !current().as_thread().proc_data.cred.read().is_privileged() {
+                                return Err(VfsError::OperationNotPermitted);
+                            }
+                            ns.set_offsets(&offsets)?;
+                            Ok(None)
+                        }
+                    }
+                }),
+            )
+            .into(),
+            "wchan" => SimpleFile::new_regular(fs, move || {
+                let wchan = task.as_thread().wchan();
+                // Like Linux, the symbol name is shown without an offset, and
+                // "0" for tasks that aren't waiting
+                let sym = (matches!(task.state(), TaskState::Blocked) && wchan != 0)
+                    .then(|| ksym::resolve(wchan))
+                    .flatten();
+                Ok(sym.map_or("0", |(sym, _)| sym.name).as_bytes().to_vec())
+            })
+            .into(),
+            "cgroup" => SimpleFile::new_regular(fs, move || {
+                let cgroup = task.as_thread().proc_data.cgroup();
+                Ok(format!("0::{}\n", cgroup.path()))
+            })
+            .into(),
             "exe" => SimpleFile::new(fs, NodeType::Symlink, move || {
                 Ok(task.as_thread().proc_data.exe_path.read().clone())
             })
@@ -352,13 +788,77 @@ impl SimpleDirOps for ProcFsHandler {
     }
 }
 
+/// Generates /proc/schedstat, in version 15 of the format.
+fn proc_schedstat() -> String {
+    let mut out = format!(
+        "version 15\ntimestamp {}\n",
+        monotonic_time_nanos() / (NANOS_PER_SEC / 100)
+    );
+    for cpu in 0..axconfig::plat::CPU_NUM {
+        let stat = schedstat::cpu(cpu).unwrap();
+        // Wakeups, idle switches and run queue delays are not tracked
+        writeln!(
+            out,
+            "cpu{cpu} {} 0 {} 0 0 0 {} 0 {}",
+            stat.yields(),
+            stat.switches(),
+            stat.run_ns(),
+            stat.switches()
+        )
+        .unwrap();
+    }
+    out
+}
+
+/// Generates the contents of `/proc/kallsyms`, with addresses zeroed unless
+/// `kernel.kptr_restrict` lets the reader see them.
+fn proc_kallsyms() -> String {
+    let show_addr = match ksym::KPTR_RESTRICT.load(Ordering::Relaxed) {
+        0 => true,
+        1 => current().as_thread().proc_data.cred.read().is_privileged(),
+        _ => false,
+    };
+    let mut out = String::new();
+    for sym in ksym::symbols() {
+        let addr = if show_addr { sym.addr } else { 0 };
+        writeln!(out, "{addr:016x} {} {}", sym.ty, sym.name).unwrap();
+    }
+    out
+}
+
+/// A sysctl file backed by an integer tunable. Like Linux, writes outside
+/// `range` fail with `EINVAL`.
+fn sysctl_file(
+    fs: Arc<SimpleFs>,
+    value: &'static AtomicU64,
+    range: RangeInclusive<u64>,
+) -> Arc<SimpleFile> {
+    SimpleFile::new_regular(
+        fs,
+        RwFile::new(move |req| match req {
+            SimpleFileOperation::Read => Ok(Some(
+                format!("{}\n", value.load(Ordering::Relaxed)).into_bytes(),
+            )),
+            SimpleFileOperation::Write(data) => {
+                if !data.is_empty() {
+                    let new = str::from_utf8(data)
+                        .ok()
+                        .and_then(|it| it.trim().parse::<u64>().ok())
+                        .filter(|it| range.contains(it))
+                        .ok_or(VfsError::InvalidInput)?;
+                    value.store(new, Ordering::Relaxed);
+                }
+                Ok(None)
+            }
+        }),
+    )
+}
+
 fn builder(fs: Arc<SimpleFs>) -> DirMaker {
     let mut root = DirMapping::new();
     root.add(
         "mounts",
-        SimpleFile::new_regular(fs.clone(), || {
-            Ok("proc /proc proc rw,nosuid,nodev,noexec,relatime 0 0\n")
-        }),
+        SimpleFile::new_regular(fs.clone(), || Ok(mounts::proc_mounts())),
     );
     root.add(
         "meminfo",
@@ -384,10 +884,33 @@ fn builder(fs: Arc<SimpleFs>) -> DirMaker {
             }
         }),
     );
+    root.add(
+        "schedstat",
+        SimpleFile::new_regular(fs.clone(), || Ok(proc_schedstat())),
+    );
     root.add(
         "interrupts",
         SimpleFile::new_regular(fs.clone(), || Ok(format!("0: {}", crate::time::irq_cnt()))),
     );
+    root.add(
+        "kallsyms",
+        SimpleFile::new_regular(fs.clone(), || Ok(proc_kallsyms())),
+    );
+
+    root.add("pressure", {
+        let mut pressure = DirMapping::new();
+        for (name, resource) in [
+            ("cpu", Resource::Cpu),
+            ("memory", Resource::Memory),
+            ("io", Resource::Io),
+        ] {
+            pressure.add(
+                name,
+                SimpleFile::new_regular(fs.clone(), move || Ok(psi::report(resource))),
+            );
+        }
+        SimpleDir::new_maker(fs.clone(), Arc::new(pressure))
+    });
 
     root.add("sys", {
         let mut sys = DirMapping::new();
@@ -397,12 +920,82 @@ fn builder(fs: Arc<SimpleFs>) -> DirMaker {
 
             kernel.add(
                 "pid_max",
-                SimpleFile::new_regular(fs.clone(), || Ok("32768\n")),
+                sysctl_file(fs.clone(), &task::PID_MAX, 301..=task::PID_MAX_LIMIT),
+            );
+            kernel.add(
+                "threads-max",
+                sysctl_file(fs.clone(), &task::THREADS_MAX, 20..=0x3fff_ffff),
+            );
+            kernel.add(
+                "kptr_restrict",
+                sysctl_file(fs.clone(), &ksym::KPTR_RESTRICT, 0..=2),
             );
 
             SimpleDir::new_maker(fs.clone(), Arc::new(kernel))
         });
 
+        sys.add("vm", {
+            let mut vm = DirMapping::new();
+
+            vm.add(
+                "dirty_writeback_centisecs",
+                sysctl_file(
+                    fs.clone(),
+                    &writeback::DIRTY_WRITEBACK_CENTISECS,
+                    0..=i32::MAX as u64,
+                ),
+            );
+            vm.add(
+                "dirty_expire_centisecs",
+                sysctl_file(
+                    fs.clone(),
+                    &writeback::DIRTY_EXPIRE_CENTISECS,
+                    0..=i32::MAX as u64,
+                ),
+            );
+            vm.add(
+                "overcommit_memory",
+                sysctl_file(
+                    fs.clone(),
+                    &mm::OVERCOMMIT_MEMORY,
+                    mm::OVERCOMMIT_GUESS..=mm::OVERCOMMIT_NEVER,
+                ),
+            );
+            vm.add(
+                "overcommit_ratio",
+                sysctl_file(fs.clone(), &mm::OVERCOMMIT_RATIO, 0..=u64::MAX),
+            );
+            vm.add(
+                "dirty_ratio",
+                sysctl_file(fs.clone(), &writeback::DIRTY_RATIO, 0..=100),
+            );
+
+            SimpleDir::new_maker(fs.clone(), Arc::new(vm))
+        });
+
+        sys.add("fs", {
+            let mut fs_dir = DirMapping::new();
+
+            fs_dir.add(
+                "file-max",
+                sysctl_file(fs.clone(), &file::FILE_MAX, 0..=i64::MAX as u64),
+            );
+            fs_dir.add(
+                "file-nr",
+                SimpleFile::new_regular(fs.clone(), || {
+                    // Freed files are not kept for reuse, so the second field
+                    // is always 0
+                    Ok(format!(
+                        "{}\t0\t{}\n",
+                        file::nr_open_files(),
+                        file::FILE_MAX.load(Ordering::Relaxed)
+                    ))
+                }),
+            );
+
+            SimpleDir::new_maker(fs.clone(), Arc::new(fs_dir))
+        });
+
         SimpleDir::new_maker(fs.clone(), Arc::new(sys))
     });
 
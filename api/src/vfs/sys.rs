@@ -0,0 +1,530 @@
+//! The sysfs, mounted at `/sys`.
+
+use alloc::{
+    borrow::Cow,
+    boxed::Box,
+    format,
+    string::{String, ToString},
+    sync::Arc,
+    vec,
+    vec::Vec,
+};
+
+use axconfig::plat::CPU_NUM;
+use axfs_ng_vfs::{DeviceId, Filesystem, NodeType, VfsError, VfsResult};
+use starry_core::{
+    cpufreq::{self, CpuFreq, Governor},
+    thermal,
+    vfs::{
+        BlockInfo, DirMaker, DirMapping, NodeOpsMux, RwFile, SimpleDir, SimpleDirOps, SimpleFile,
+        SimpleFileOperation, SimpleFs,
+    },
+};
+
+use crate::{
+    netif::{self, NetInterface},
+    vfs::{
+        dev::{block_devices, find_block_device},
+        tracing,
+    },
+};
+
+const SYSFS_MAGIC: u32 = 0x6265_6572;
+
+pub fn new_sysfs() -> Filesystem {
+    SimpleFs::new_with("sysfs".into(), SYSFS_MAGIC, builder)
+}
+
+/// An attribute of the network interface `index`, read live from the
+/// interface table.
+fn net_attr(
+    fs: &Arc<SimpleFs>,
+    index: u32,
+    attr: impl Fn(&NetInterface) -> String + Send + Sync + 'static,
+) -> Arc<SimpleFile> {
+    SimpleFile::new_regular(fs.clone(), move || {
+        netif::find_by_index(index)
+            .map(|iface| attr(&iface))
+            .ok_or(VfsError::NotFound)
+    })
+}
+
+/// `/sys/class/net/<iface>`.
+fn net_iface_dir(fs: &Arc<SimpleFs>, index: u32) -> DirMaker {
+    let mut dir = DirMapping::new();
+    dir.add(
+        "address",
+        net_attr(fs, index, |iface| {
+            let mac = iface.mac.map(|b| format!("{b:02x}")).join(":");
+            format!("{mac}\n")
+        }),
+    );
+    dir.add(
+        "ifindex",
+        net_attr(fs, index, |iface| format!("{}\n", iface.index)),
+    );
+    dir.add(
+        "mtu",
+        net_attr(fs, index, |iface| format!("{}\n", iface.mtu)),
+    );
+    dir.add(
+        "type",
+        net_attr(fs, index, |iface| format!("{}\n", iface.hw_type)),
+    );
+    dir.add(
+        "flags",
+        net_attr(fs, index, |iface| format!("{:#x}\n", iface.flags)),
+    );
+    dir.add(
+        "operstate",
+        net_attr(fs, index, |iface| {
+            if iface.is_loopback() {
+                "unknown\n"
+            } else {
+                "up\n"
+            }
+            .into()
+        }),
+    );
+
+    dir.add("statistics", {
+        let mut stats = DirMapping::new();
+        let counters: [(&str, fn(&NetInterface) -> u64); 8] = [
+            ("rx_bytes", |iface| iface.stats.rx_bytes),
+            ("rx_packets", |iface| iface.stats.rx_packets),
+            ("rx_dropped", |iface| iface.stats.rx_dropped),
+            ("rx_errors", |_| 0),
+            ("tx_bytes", |iface| iface.stats.tx_bytes),
+            ("tx_packets", |iface| iface.stats.tx_packets),
+            ("tx_dropped", |iface| iface.stats.tx_dropped),
+            ("tx_errors", |_| 0),
+        ];
+        for (name, counter) in counters {
+            stats.add(
+                name,
+                net_attr(fs, index, move |iface| format!("{}\n", counter(iface))),
+            );
+        }
+        SimpleDir::new_maker(fs.clone(), Arc::new(stats))
+    });
+
+    SimpleDir::new_maker(fs.clone(), Arc::new(dir))
+}
+
+/// `/sys/class/net`, with a directory for each network interface.
+struct NetClassDir(Arc<SimpleFs>);
+
+impl SimpleDirOps for NetClassDir {
+    fn child_names<'a>(&'a self) -> Box<dyn Iterator<Item = Cow<'a, str>> + 'a> {
+        Box::new(
+            netif::interfaces()
+                .into_iter()
+                .map(|iface| Cow::Owned(iface.name)),
+        )
+    }
+
+    fn lookup_child(&self, name: &str) -> VfsResult<NodeOpsMux> {
+        let iface = netif::find_by_name(name).ok_or(VfsError::NotFound)?;
+        Ok(NodeOpsMux::Dir(net_iface_dir(&self.0, iface.index)))
+    }
+
+    fn is_cacheable(&self) -> bool {
+        false
+    }
+}
+
+/// An attribute of the block device `name`, read live from its driver.
+fn block_attr(
+    fs: &Arc<SimpleFs>,
+    name: &str,
+    attr: impl Fn(DeviceId, &BlockInfo) -> String + Send + Sync + 'static,
+) -> Arc<SimpleFile> {
+    let name = name.to_string();
+    SimpleFile::new_regular(fs.clone(), move || {
+        let (dev_id, ops) = find_block_device(&name).ok_or(VfsError::NotFound)?;
+        let info = ops.block_info().ok_or(VfsError::NotFound)?;
+        Ok(attr(dev_id, &info))
+    })
+}
+
+/// `/sys/block/<dev>`.
+fn block_dir(fs: &Arc<SimpleFs>, name: &str) -> DirMaker {
+    let mut dir = DirMapping::new();
+    dir.add(
+        "dev",
+        block_attr(fs, name, |dev_id, _| {
+            format!("{}:{}\n", dev_id.major(), dev_id.minor())
+        }),
+    );
+    // In 512-byte sectors, whatever the block size.
+    dir.add(
+        "size",
+        block_attr(fs, name, |_, info| format!("{}\n", info.size / 512)),
+    );
+    dir.add(
+        "ro",
+        block_attr(fs, name, |_, info| format!("{}\n", info.read_only as u8)),
+    );
+    dir.add("removable", block_attr(fs, name, |_, _| "0\n".into()));
+
+    dir.add("queue", {
+        let mut queue = DirMapping::new();
+        for attr in [
+            "logical_block_size",
+            "physical_block_size",
+            "hw_sector_size",
+        ] {
+            queue.add(
+                attr,
+                block_attr(fs, name, |_, info| format!("{}\n", info.logical_block_size)),
+            );
+        }
+        queue.add(
+            "rotational",
+            block_attr(fs, name, |_, info| format!("{}\n", info.rotational as u8)),
+        );
+        SimpleDir::new_maker(fs.clone(), Arc::new(queue))
+    });
+
+    SimpleDir::new_maker(fs.clone(), Arc::new(dir))
+}
+
+/// `/sys/block`, with a directory for each block device.
+struct BlockDir(Arc<SimpleFs>);
+
+impl SimpleDirOps for BlockDir {
+    fn child_names<'a>(&'a self) -> Box<dyn Iterator<Item = Cow<'a, str>> + 'a> {
+        Box::new(
+            block_devices()
+                .into_iter()
+                .map(|(name, _)| Cow::Owned(name)),
+        )
+    }
+
+    fn lookup_child(&self, name: &str) -> VfsResult<NodeOpsMux> {
+        let (_, ops) = find_block_device(name).ok_or(VfsError::NotFound)?;
+        if ops.block_info().is_none() {
+            return Err(VfsError::NotFound);
+        }
+        Ok(NodeOpsMux::Dir(block_dir(&self.0, name)))
+    }
+
+    fn is_cacheable(&self) -> bool {
+        false
+    }
+}
+
+/// `/sys/class/thermal/thermal_zone<index>`.
+fn thermal_zone_dir(fs: &Arc<SimpleFs>, zone: Arc<thermal::ThermalZone>) -> DirMaker {
+    let mut dir = DirMapping::new();
+    dir.add(
+        "type",
+        SimpleFile::new_regular(fs.clone(), {
+            let name = zone.name;
+            move || Ok(format!("{name}\n"))
+        }),
+    );
+    dir.add(
+        "mode",
+        SimpleFile::new_regular(fs.clone(), || Ok("enabled\n")),
+    );
+    for (i, trip) in zone.trips.iter().copied().enumerate() {
+        dir.add(
+            format!("trip_point_{i}_temp"),
+            SimpleFile::new_regular(fs.clone(), move || Ok(format!("{}\n", trip.temp))),
+        );
+        dir.add(
+            format!("trip_point_{i}_type"),
+            SimpleFile::new_regular(fs.clone(), move || Ok(format!("{}\n", trip.kind.as_str()))),
+        );
+    }
+    dir.add(
+        "temp",
+        SimpleFile::new_regular(fs.clone(), move || {
+            Ok(format!("{}\n", zone.sensor.temperature()?))
+        }),
+    );
+    SimpleDir::new_maker(fs.clone(), Arc::new(dir))
+}
+
+/// `/sys/class/thermal`, with a directory for each thermal zone.
+struct ThermalClassDir(Arc<SimpleFs>);
+
+impl SimpleDirOps for ThermalClassDir {
+    fn child_names<'a>(&'a self) -> Box<dyn Iterator<Item = Cow<'a, str>> + 'a> {
+        Box::new((0..thermal::zone_count()).map(|i| Cow::Owned(format!("thermal_zone{i}"))))
+    }
+
+    fn lookup_child(&self, name: &str) -> VfsResult<NodeOpsMux> {
+        let zone = name
+            .strip_prefix("thermal_zone")
+            .and_then(|it| it.parse().ok())
+            .and_then(thermal::zone)
+            .ok_or(VfsError::NotFound)?;
+        Ok(NodeOpsMux::Dir(thermal_zone_dir(&self.0, zone)))
+    }
+
+    fn is_cacheable(&self) -> bool {
+        false
+    }
+}
+
+/// Formats a set of CPUs as a list of ranges, like `0-3,6`.
+fn cpu_list(cpus: impl IntoIterator<Item = usize>) -> String {
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    for cpu in cpus {
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == cpu => *end = cpu,
+            _ => ranges.push((cpu, cpu)),
+        }
+    }
+    let list = ranges
+        .into_iter()
+        .map(|(start, end)| {
+            if start == end {
+                format!("{start}")
+            } else {
+                format!("{start}-{end}")
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",");
+    format!("{list}\n")
+}
+
+/// Formats a set of CPUs as a hexadecimal mask, in comma-separated groups of
+/// 32 bits like Linux.
+fn cpu_mask(cpus: impl IntoIterator<Item = usize>) -> String {
+    let mut words = vec![0u32; CPU_NUM.div_ceil(32)];
+    for cpu in cpus {
+        words[cpu / 32] |= 1 << (cpu % 32);
+    }
+    let mask = words
+        .iter()
+        .rev()
+        .map(|word| format!("{word:08x}"))
+        .collect::<Vec<_>>()
+        .join(",");
+    format!("{mask}\n")
+}
+
+/// `/sys/devices/system/cpu/cpu<cpu>/cpufreq`.
+fn cpufreq_dir(fs: &Arc<SimpleFs>, freq: Arc<CpuFreq>) -> DirMaker {
+    let mut dir = DirMapping::new();
+    let attrs: [(&str, fn(&CpuFreq) -> VfsResult<String>); 7] = [
+        ("cpuinfo_cur_freq", |freq| Ok(format!("{}\n", freq.cur()?))),
+        ("scaling_cur_freq", |freq| Ok(format!("{}\n", freq.cur()?))),
+        ("cpuinfo_min_freq", |freq| {
+            Ok(format!("{}\n", freq.min_freq()))
+        }),
+        ("cpuinfo_max_freq", |freq| {
+            Ok(format!("{}\n", freq.max_freq()))
+        }),
+        ("scaling_min_freq", |freq| {
+            Ok(format!("{}\n", freq.min_freq()))
+        }),
+        ("scaling_max_freq", |freq| {
+            Ok(format!("{}\n", freq.max_freq()))
+        }),
+        ("scaling_available_frequencies", |freq| {
+            let list = freq
+                .frequencies()
+                .iter()
+                .map(|it| it.to_string())
+                .collect::<Vec<_>>()
+                .join(" ");
+            Ok(format!("{list}\n"))
+        }),
+    ];
+    for (name, attr) in attrs {
+        dir.add(
+            name,
+            SimpleFile::new_regular(fs.clone(), {
+                let freq = freq.clone();
+                move || attr(&freq)
+            }),
+        );
+    }
+    dir.add(
+        "scaling_available_governors",
+        SimpleFile::new_regular(fs.clone(), || {
+            let list = Governor::ALL.map(|it| it.as_str()).join(" ");
+            Ok(format!("{list}\n"))
+        }),
+    );
+
+    dir.add(
+        "scaling_governor",
+        SimpleFile::new_regular(fs.clone(), {
+            let freq = freq.clone();
+            RwFile::new(move |req| match req {
+                SimpleFileOperation::Read => {
+                    Ok(Some(format!("{}\n", freq.governor().as_str()).into_bytes()))
+                }
+                SimpleFileOperation::Write(data) => {
+                    let governor = str::from_utf8(data)
+                        .ok()
+                        .and_then(|it| Governor::from_name(it.trim()))
+                        .ok_or(VfsError::InvalidInput)?;
+                    freq.set_governor(governor)?;
+                    Ok(None)
+                }
+            })
+        }),
+    );
+    dir.add(
+        "scaling_setspeed",
+        SimpleFile::new_regular(
+            fs.clone(),
+            RwFile::new(move |req| match req {
+                SimpleFileOperation::Read => Ok(Some(
+                    if freq.governor() == Governor::Userspace {
+                        format!("{}\n", freq.cur()?)
+                    } else {
+                        "<unsupported>\n".into()
+                    }
+                    .into_bytes(),
+                )),
+                SimpleFileOperation::Write(data) => {
+                    let speed = str::from_utf8(data)
+                        .ok()
+                        .and_then(|it| it.trim().parse::<u64>().ok())
+                        .ok_or(VfsError::InvalidInput)?;
+                    freq.set_speed(speed)?;
+                    Ok(None)
+                }
+            }),
+        ),
+    );
+
+    SimpleDir::new_maker(fs.clone(), Arc::new(dir))
+}
+
+/// `/sys/devices/system/cpu/cpu<cpu>`.
+///
+/// Every CPU is a single-threaded core of the same package.
+fn cpu_dir(fs: &Arc<SimpleFs>, cpu: usize) -> DirMaker {
+    let mut dir = DirMapping::new();
+    // CPU hotplug isn't supported, so CPUs can be "onlined" but never taken
+    // offline.
+    dir.add(
+        "online",
+        SimpleFile::new_regular(
+            fs.clone(),
+            RwFile::new(|req| match req {
+                SimpleFileOperation::Read => Ok(Some(b"1\n".to_vec())),
+                SimpleFileOperation::Write(data) => match str::from_utf8(data).map(str::trim) {
+                    Ok("1") => Ok(None),
+                    Ok("0") => Err(VfsError::Unsupported),
+                    _ => Err(VfsError::InvalidInput),
+                },
+            }),
+        ),
+    );
+
+    dir.add("topology", {
+        let mut topology = DirMapping::new();
+        let attrs: [(&str, String); 6] = [
+            ("core_id", format!("{cpu}\n")),
+            ("physical_package_id", "0\n".into()),
+            ("thread_siblings", cpu_mask([cpu])),
+            ("thread_siblings_list", cpu_list([cpu])),
+            ("core_siblings", cpu_mask(0..CPU_NUM)),
+            ("core_siblings_list", cpu_list(0..CPU_NUM)),
+        ];
+        for (name, value) in attrs {
+            topology.add(
+                name,
+                SimpleFile::new_regular(fs.clone(), move || Ok(value.clone())),
+            );
+        }
+        SimpleDir::new_maker(fs.clone(), Arc::new(topology))
+    });
+
+    if let Some(freq) = cpufreq::cpu(cpu) {
+        dir.add("cpufreq", cpufreq_dir(fs, freq));
+    }
+
+    SimpleDir::new_maker(fs.clone(), Arc::new(dir))
+}
+
+fn builder(fs: Arc<SimpleFs>) -> DirMaker {
+    let mut root = DirMapping::new();
+
+    root.add(
+        "block",
+        SimpleDir::new_maker(fs.clone(), Arc::new(BlockDir(fs.clone()))),
+    );
+
+    root.add("class", {
+        let mut class = DirMapping::new();
+
+        class.add("graphics", {
+            let mut device = DirMapping::new();
+            device.add(
+                "subsystem",
+                SimpleFile::new(fs.clone(), NodeType::Symlink, || Ok("whatever")),
+            );
+            let mut fb0 = DirMapping::new();
+            fb0.add("device", SimpleDir::new_maker(fs.clone(), Arc::new(device)));
+            let mut graphics = DirMapping::new();
+            graphics.add("fb0", SimpleDir::new_maker(fs.clone(), Arc::new(fb0)));
+            SimpleDir::new_maker(fs.clone(), Arc::new(graphics))
+        });
+
+        class.add(
+            "net",
+            SimpleDir::new_maker(fs.clone(), Arc::new(NetClassDir(fs.clone()))),
+        );
+
+        class.add(
+            "thermal",
+            SimpleDir::new_maker(fs.clone(), Arc::new(ThermalClassDir(fs.clone()))),
+        );
+
+        SimpleDir::new_maker(fs.clone(), Arc::new(class))
+    });
+
+    root.add("devices", {
+        let mut cpu = DirMapping::new();
+        for name in ["online", "possible", "present"] {
+            cpu.add(
+                name,
+                SimpleFile::new_regular(fs.clone(), || Ok(cpu_list(0..CPU_NUM))),
+            );
+        }
+        cpu.add(
+            "kernel_max",
+            SimpleFile::new_regular(fs.clone(), || Ok(format!("{}\n", CPU_NUM - 1))),
+        );
+        for i in 0..CPU_NUM {
+            cpu.add(format!("cpu{i}"), cpu_dir(&fs, i));
+        }
+
+        let mut system = DirMapping::new();
+        system.add("cpu", SimpleDir::new_maker(fs.clone(), Arc::new(cpu)));
+        let mut devices = DirMapping::new();
+        devices.add("system", SimpleDir::new_maker(fs.clone(), Arc::new(system)));
+        SimpleDir::new_maker(fs.clone(), Arc::new(devices))
+    });
+
+    root.add("fs", {
+        let mut fs_dir = DirMapping::new();
+        // The mount point of the cgroup filesystem
+        fs_dir.add(
+            "cgroup",
+            SimpleDir::new_maker(fs.clone(), Arc::new(DirMapping::new())),
+        );
+        SimpleDir::new_maker(fs.clone(), Arc::new(fs_dir))
+    });
+
+    root.add("kernel", {
+        let mut debug = DirMapping::new();
+        debug.add("tracing", tracing::tracing_dir(&fs));
+        let mut kernel = DirMapping::new();
+        kernel.add("debug", SimpleDir::new_maker(fs.clone(), Arc::new(debug)));
+        SimpleDir::new_maker(fs.clone(), Arc::new(kernel))
+    });
+
+    SimpleDir::new_maker(fs, Arc::new(root))
+}
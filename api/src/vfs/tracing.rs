@@ -0,0 +1,631 @@
+//! The tracing interface, at `/sys/kernel/debug/tracing`.
+
+use alloc::{boxed::Box, format, string::String, sync::Arc, vec::Vec};
+use core::{
+    fmt::Write,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use axconfig::plat::CPU_NUM;
+use axerrno::{AxError, AxResult};
+use axfs_ng::FS_CONTEXT;
+use axfs_ng_vfs::VfsResult;
+use axhal::{percpu::this_cpu_id, time::monotonic_time_nanos};
+use axsync::Mutex;
+use axtask::current;
+use starry_core::{
+    kprobe::{self, Kprobe, KprobeKind},
+    ksym,
+    task::AsThread,
+    trace::{self, TRACING_ON, TraceEntry},
+    uprobe::{self, Uprobe},
+    vfs::{DirMaker, DirMapping, RwFile, SimpleDir, SimpleFile, SimpleFileOperation, SimpleFs},
+};
+
+/// Fails unless the current process may change tracing settings.
+fn check_privileged() -> VfsResult<()> {
+    if current().as_thread().proc_data.cred.read().is_privileged() {
+        Ok(())
+    } else {
+        Err(AxError::PermissionDenied)
+    }
+}
+
+/// The group of kprobe events defined without one.
+const DEFAULT_GROUP: &str = "kprobes";
+
+/// Where a kprobe event is placed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ProbeTarget {
+    Symbol(String, usize),
+    Addr(usize),
+}
+
+impl ProbeTarget {
+    fn resolve(&self) -> AxResult<usize> {
+        match self {
+            ProbeTarget::Symbol(symbol, offset) => ksym::lookup(symbol)
+                .map(|addr| addr + offset)
+                .ok_or(AxError::NotFound),
+            ProbeTarget::Addr(addr) => Ok(*addr),
+        }
+    }
+}
+
+/// A kprobe event, as defined in `kprobe_events`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ProbeDef {
+    kind: KprobeKind,
+    group: String,
+    event: String,
+    target: ProbeTarget,
+}
+
+impl ProbeDef {
+    fn to_line(&self) -> String {
+        let kind = match self.kind {
+            KprobeKind::Entry => 'p',
+            KprobeKind::Return => 'r',
+        };
+        let target = match &self.target {
+            ProbeTarget::Symbol(symbol, 0) => symbol.clone(),
+            ProbeTarget::Symbol(symbol, offset) => format!("{symbol}+{offset}"),
+            ProbeTarget::Addr(addr) => format!("{addr:#x}"),
+        };
+        format!("{kind}:{}/{} {target}\n", self.group, self.event)
+    }
+}
+
+/// A command written to `kprobe_events` or `uprobe_events`.
+enum ProbeCommand<D> {
+    Define(D),
+    Remove(Option<String>, String),
+}
+
+fn parse_name(name: &str) -> AxResult<(Option<String>, String)> {
+    let (group, event) = match name.split_once('/') {
+        Some((group, event)) => (Some(group), event),
+        None => (None, name),
+    };
+    let valid = |it: &str| {
+        it.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && it.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    };
+    if !valid(event) || group.is_some_and(|group| !valid(group)) {
+        return Err(AxError::InvalidInput);
+    }
+    Ok((group.map(String::from), event.into()))
+}
+
+/// Parses a line of `kprobe_events`:
+///
+/// ```text
+/// p[:[GRP/]EVENT] SYM[+OFFS]|ADDR
+/// r[MAXACTIVE][:[GRP/]EVENT] SYM[+0]|ADDR
+/// -:[GRP/]EVENT
+/// ```
+fn parse_command(line: &str) -> AxResult<ProbeCommand<ProbeDef>> {
+    let mut fields = line.split_ascii_whitespace();
+    let head = fields.next().ok_or(AxError::InvalidInput)?;
+    let (kind, name) = head.split_once(':').unwrap_or((head, ""));
+    let name = (!name.is_empty()).then(|| parse_name(name)).transpose()?;
+
+    if kind == "-" {
+        let (group, event) = name.ok_or(AxError::InvalidInput)?;
+        if fields.next().is_some() {
+            return Err(AxError::InvalidInput);
+        }
+        return Ok(ProbeCommand::Remove(group, event));
+    }
+    let kind = match kind.split_at(1) {
+        ("p", "") => KprobeKind::Entry,
+        // The number of concurrent returns is not limited
+        ("r", max) if max.bytes().all(|b| b.is_ascii_digit()) => KprobeKind::Return,
+        _ => return Err(AxError::InvalidInput),
+    };
+
+    let target = fields.next().ok_or(AxError::InvalidInput)?;
+    // Fetch arguments are not supported
+    if fields.next().is_some() {
+        return Err(AxError::InvalidInput);
+    }
+    let target = if let Some(addr) = target.strip_prefix("0x") {
+        ProbeTarget::Addr(usize::from_str_radix(addr, 16).map_err(|_| AxError::InvalidInput)?)
+    } else {
+        let (symbol, offset) = target.split_once('+').unwrap_or((target, "0"));
+        let offset = match offset.strip_prefix("0x") {
+            Some(hex) => usize::from_str_radix(hex, 16),
+            None => offset.parse(),
+        }
+        .map_err(|_| AxError::InvalidInput)?;
+        ProbeTarget::Symbol(symbol.into(), offset)
+    };
+    if kind == KprobeKind::Return && matches!(target, ProbeTarget::Symbol(_, offset) if offset != 0)
+    {
+        return Err(AxError::InvalidInput);
+    }
+
+    let (group, event) = match name {
+        Some((group, event)) => (group, event),
+        None => {
+            let prefix = if kind == KprobeKind::Entry { 'p' } else { 'r' };
+            let event = match &target {
+                ProbeTarget::Symbol(symbol, offset) => format!("{prefix}_{symbol}_{offset}"),
+                ProbeTarget::Addr(addr) => format!("{prefix}_{addr:#x}"),
+            };
+            (
+                None,
+                event.replace(|c: char| !c.is_ascii_alphanumeric(), "_"),
+            )
+        }
+    };
+    Ok(ProbeCommand::Define(ProbeDef {
+        kind,
+        group: group.unwrap_or_else(|| DEFAULT_GROUP.into()),
+        event,
+        target,
+    }))
+}
+
+/// The kprobe events, in order of definition.
+static PROBE_EVENTS: Mutex<Vec<(ProbeDef, Arc<Kprobe>)>> = Mutex::new(Vec::new());
+
+fn kprobe_events() -> String {
+    PROBE_EVENTS
+        .lock()
+        .iter()
+        .map(|(def, _)| def.to_line())
+        .collect()
+}
+
+/// Runs the commands of `data` written to `kprobe_events` or
+/// `uprobe_events`, returning the resulting definitions.
+///
+/// Appending to the file writes its current content followed by the new
+/// commands, so events that are already defined are kept as they are.
+/// Truncating it removes all events.
+fn parse_events<D>(
+    data: &[u8],
+    parse: impl Fn(&str) -> AxResult<ProbeCommand<D>>,
+    name: impl Fn(&D) -> (&str, &str),
+) -> AxResult<Vec<D>> {
+    let data = str::from_utf8(data).map_err(|_| AxError::InvalidInput)?;
+    let mut defs: Vec<D> = Vec::new();
+    for line in data.lines() {
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+        match parse(line)? {
+            ProbeCommand::Define(def) => {
+                if defs.iter().any(|it| name(it) == name(&def)) {
+                    return Err(AxError::AlreadyExists);
+                }
+                defs.push(def);
+            }
+            ProbeCommand::Remove(group, event) => {
+                let index = defs
+                    .iter()
+                    .position(|it| {
+                        let (it_group, it_event) = name(it);
+                        it_event == event && group.as_ref().is_none_or(|group| group == it_group)
+                    })
+                    .ok_or(AxError::NotFound)?;
+                defs.remove(index);
+            }
+        }
+    }
+    Ok(defs)
+}
+
+/// Replaces the kprobe events by those defined in `data`.
+fn set_kprobe_events(data: &[u8]) -> VfsResult<()> {
+    check_privileged()?;
+    let defs = parse_events(data, parse_command, |def: &ProbeDef| {
+        (&def.group, &def.event)
+    })?;
+
+    let mut events = PROBE_EVENTS.lock();
+    events.retain(|(def, probe)| {
+        let keep = defs.contains(def);
+        if !keep {
+            let _ = kprobe::unregister_kprobe(probe);
+        }
+        keep
+    });
+    for def in defs {
+        if events.iter().any(|(it, _)| *it == def) {
+            continue;
+        }
+        let addr = def.target.resolve()?;
+        let probe = kprobe::register_kprobe(addr, def.kind, None, None)?;
+        events.push((def, probe));
+    }
+    Ok(())
+}
+
+/// Lists the hits and misses of each kprobe event.
+fn kprobe_profile() -> String {
+    let mut out = String::new();
+    for (def, probe) in PROBE_EVENTS.lock().iter() {
+        writeln!(
+            out,
+            "  {:<44} {:>15} {:>15}",
+            def.event,
+            probe.hits(),
+            probe.missed()
+        )
+        .unwrap();
+    }
+    out
+}
+
+/// The group of uprobe events defined without one.
+const DEFAULT_UPROBE_GROUP: &str = "uprobes";
+
+/// A uprobe event, as defined in `uprobe_events`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct UprobeDef {
+    group: String,
+    event: String,
+    path: String,
+    offset: u64,
+}
+
+/// Parses a line of `uprobe_events`:
+///
+/// ```text
+/// p[:[GRP/]EVENT] PATH:OFFSET
+/// -:[GRP/]EVENT
+/// ```
+///
+/// Return probes are not supported.
+fn parse_uprobe_command(line: &str) -> AxResult<ProbeCommand<UprobeDef>> {
+    let mut fields = line.split_ascii_whitespace();
+    let head = fields.next().ok_or(AxError::InvalidInput)?;
+    let (kind, name) = head.split_once(':').unwrap_or((head, ""));
+    let name = (!name.is_empty()).then(|| parse_name(name)).transpose()?;
+
+    if kind == "-" {
+        let (group, event) = name.ok_or(AxError::InvalidInput)?;
+        if fields.next().is_some() {
+            return Err(AxError::InvalidInput);
+        }
+        return Ok(ProbeCommand::Remove(group, event));
+    }
+    if kind != "p" {
+        return Err(AxError::InvalidInput);
+    }
+
+    let target = fields.next().ok_or(AxError::InvalidInput)?;
+    // Fetch arguments are not supported
+    if fields.next().is_some() {
+        return Err(AxError::InvalidInput);
+    }
+    let (path, offset) = target.rsplit_once(':').ok_or(AxError::InvalidInput)?;
+    let offset = match offset.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => offset.parse(),
+    }
+    .map_err(|_| AxError::InvalidInput)?;
+    if !path.starts_with('/') {
+        return Err(AxError::InvalidInput);
+    }
+
+    let (group, event) = match name {
+        Some((group, event)) => (group, event),
+        None => {
+            let file = path.rsplit('/').next().unwrap();
+            let event = format!("p_{file}_{offset:#x}");
+            (
+                None,
+                event.replace(|c: char| !c.is_ascii_alphanumeric(), "_"),
+            )
+        }
+    };
+    Ok(ProbeCommand::Define(UprobeDef {
+        group: group.unwrap_or_else(|| DEFAULT_UPROBE_GROUP.into()),
+        event,
+        path: path.into(),
+        offset,
+    }))
+}
+
+/// The uprobe events, in order of definition.
+static UPROBE_EVENTS: Mutex<Vec<(UprobeDef, Arc<Uprobe>)>> = Mutex::new(Vec::new());
+
+fn uprobe_events() -> String {
+    UPROBE_EVENTS
+        .lock()
+        .iter()
+        .map(|(def, _)| {
+            format!(
+                "p:{}/{} {}:{:#x}\n",
+                def.group, def.event, def.path, def.offset
+            )
+        })
+        .collect()
+}
+
+/// Replaces the uprobe events by those defined in `data`.
+fn set_uprobe_events(data: &[u8]) -> VfsResult<()> {
+    check_privileged()?;
+    let defs = parse_events(data, parse_uprobe_command, |def: &UprobeDef| {
+        (&def.group, &def.event)
+    })?;
+
+    let mut events = UPROBE_EVENTS.lock();
+    events.retain(|(def, probe)| {
+        let keep = defs.contains(def);
+        if !keep {
+            let _ = uprobe::unregister_uprobe(probe);
+        }
+        keep
+    });
+    for def in defs {
+        if events.iter().any(|(it, _)| *it == def) {
+            continue;
+        }
+        let loc = FS_CONTEXT.lock().resolve(&def.path)?;
+        let probe = uprobe::register_uprobe(&loc, def.offset, None)?;
+        events.push((def, probe));
+    }
+    Ok(())
+}
+
+/// Lists the hits of each uprobe event.
+fn uprobe_profile() -> String {
+    let mut out = String::new();
+    for (def, probe) in UPROBE_EVENTS.lock().iter() {
+        writeln!(out, "  {} {:<44} {:>15}", def.path, def.event, probe.hits()).unwrap();
+    }
+    out
+}
+
+/// The function graph tracer.
+///
+/// Each function of the filter gets a kretprobe, recording its entry and
+/// exit, indented by the nesting depth, and its duration. Unlike Linux, the
+/// functions called by filtered functions are not traced, so an empty filter
+/// traces nothing.
+struct GraphTracer {
+    enabled: bool,
+    functions: Vec<&'static str>,
+    probes: Vec<Arc<Kprobe>>,
+}
+
+static GRAPH_TRACER: Mutex<GraphTracer> = Mutex::new(GraphTracer {
+    enabled: false,
+    functions: Vec::new(),
+    probes: Vec::new(),
+});
+
+/// The nesting depth of traced functions on each CPU.
+static GRAPH_DEPTH: [AtomicUsize; CPU_NUM] = [const { AtomicUsize::new(0) }; CPU_NUM];
+
+fn graph_entry(name: &'static str) {
+    let cpu = this_cpu_id();
+    let depth = GRAPH_DEPTH[cpu].fetch_add(1, Ordering::Relaxed);
+    trace::write(TraceEntry::FuncEntry { cpu, depth, name });
+}
+
+fn graph_return(name: &'static str, entered_ns: u64) {
+    let cpu = this_cpu_id();
+    let depth = GRAPH_DEPTH[cpu]
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |it| {
+            Some(it.saturating_sub(1))
+        })
+        .unwrap()
+        .saturating_sub(1);
+    trace::write(TraceEntry::FuncReturn {
+        cpu,
+        depth,
+        name,
+        duration_ns: monotonic_time_nanos().saturating_sub(entered_ns),
+    });
+}
+
+impl GraphTracer {
+    /// Registers the probes of the filtered functions if the tracer is
+    /// enabled, replacing the previous ones.
+    fn update(&mut self) -> AxResult<()> {
+        for probe in self.probes.drain(..) {
+            let _ = kprobe::unregister_kprobe(&probe);
+        }
+        if !self.enabled {
+            return Ok(());
+        }
+        for &name in &self.functions {
+            let probe = ksym::lookup(name)
+                .ok_or(AxError::NotFound)
+                .and_then(|addr| {
+                    kprobe::register_kprobe(
+                        addr,
+                        KprobeKind::Return,
+                        Some(Box::new(move |_, _| graph_entry(name))),
+                        Some(Box::new(move |_, _, entered_ns| {
+                            graph_return(name, entered_ns)
+                        })),
+                    )
+                });
+            match probe {
+                Ok(probe) => self.probes.push(probe),
+                Err(err) => {
+                    self.enabled = false;
+                    for probe in self.probes.drain(..) {
+                        let _ = kprobe::unregister_kprobe(&probe);
+                    }
+                    return Err(err);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn current_tracer() -> &'static str {
+    if GRAPH_TRACER.lock().enabled {
+        "function_graph"
+    } else {
+        "nop"
+    }
+}
+
+fn set_current_tracer(data: &[u8]) -> VfsResult<()> {
+    check_privileged()?;
+    let enabled = match str::from_utf8(data).map(str::trim) {
+        Ok("nop") => false,
+        Ok("function_graph") => true,
+        _ => return Err(AxError::InvalidInput),
+    };
+    let mut tracer = GRAPH_TRACER.lock();
+    if tracer.enabled != enabled {
+        tracer.enabled = enabled;
+        tracer.update()?;
+    }
+    Ok(())
+}
+
+fn set_graph_function(data: &[u8]) -> VfsResult<()> {
+    check_privileged()?;
+    let data = str::from_utf8(data).map_err(|_| AxError::InvalidInput)?;
+    let mut functions = Vec::new();
+    for name in data.split_ascii_whitespace() {
+        let sym = ksym::symbols()
+            .iter()
+            .find(|sym| sym.name == name && matches!(sym.ty, 't' | 'T'))
+            .ok_or(AxError::InvalidInput)?;
+        if !functions.contains(&sym.name) {
+            functions.push(sym.name);
+        }
+    }
+    let mut tracer = GRAPH_TRACER.lock();
+    tracer.functions = functions;
+    tracer.update()
+}
+
+fn trace() -> String {
+    let tracer = current_tracer();
+    let mut out = format!("# tracer: {tracer}\n#\n");
+    if tracer == "function_graph" {
+        out += "# CPU  DURATION          FUNCTION CALLS\n";
+        out += "# |     |   |             |   |   |   |\n";
+    }
+    out + &trace::read()
+}
+
+/// `/sys/kernel/debug/tracing`.
+pub fn tracing_dir(fs: &Arc<SimpleFs>) -> DirMaker {
+    let mut dir = DirMapping::new();
+    dir.add(
+        "kprobe_events",
+        SimpleFile::new_regular(
+            fs.clone(),
+            RwFile::new(|req| match req {
+                SimpleFileOperation::Read => Ok(Some(kprobe_events())),
+                SimpleFileOperation::Write(data) => {
+                    set_kprobe_events(data)?;
+                    Ok(None)
+                }
+            }),
+        ),
+    );
+    dir.add(
+        "kprobe_profile",
+        SimpleFile::new_regular(fs.clone(), || Ok(kprobe_profile())),
+    );
+    dir.add(
+        "uprobe_events",
+        SimpleFile::new_regular(
+            fs.clone(),
+            RwFile::new(|req| match req {
+                SimpleFileOperation::Read => Ok(Some(uprobe_events())),
+                SimpleFileOperation::Write(data) => {
+                    set_uprobe_events(data)?;
+                    Ok(None)
+                }
+            }),
+        ),
+    );
+    dir.add(
+        "uprobe_profile",
+        SimpleFile::new_regular(fs.clone(), || Ok(uprobe_profile())),
+    );
+    dir.add(
+        "available_tracers",
+        SimpleFile::new_regular(fs.clone(), || Ok("function_graph nop\n")),
+    );
+    dir.add(
+        "current_tracer",
+        SimpleFile::new_regular(
+            fs.clone(),
+            RwFile::new(|req| match req {
+                SimpleFileOperation::Read => Ok(Some(format!("{}\n", current_tracer()))),
+                SimpleFileOperation::Write(data) => {
+                    set_current_tracer(data)?;
+                    Ok(None)
+                }
+            }),
+        ),
+    );
+    dir.add(
+        "set_graph_function",
+        SimpleFile::new_regular(
+            fs.clone(),
+            RwFile::new(|req| match req {
+                SimpleFileOperation::Read => {
+                    let tracer = GRAPH_TRACER.lock();
+                    Ok(Some(
+                        tracer
+                            .functions
+                            .iter()
+                            .map(|it| format!("{it}\n"))
+                            .collect::<String>(),
+                    ))
+                }
+                SimpleFileOperation::Write(data) => {
+                    set_graph_function(data)?;
+                    Ok(None)
+                }
+            }),
+        ),
+    );
+    dir.add(
+        "tracing_on",
+        SimpleFile::new_regular(
+            fs.clone(),
+            RwFile::new(|req| match req {
+                SimpleFileOperation::Read => Ok(Some(format!(
+                    "{}\n",
+                    TRACING_ON.load(Ordering::Relaxed) as u8
+                ))),
+                SimpleFileOperation::Write(data) => {
+                    check_privileged()?;
+                    let on = match str::from_utf8(data).map(str::trim) {
+                        Ok("0") => false,
+                        Ok("1") => true,
+                        _ => return Err(AxError::InvalidInput),
+                    };
+                    TRACING_ON.store(on, Ordering::Relaxed);
+                    Ok(None)
+                }
+            }),
+        ),
+    );
+    dir.add(
+        "trace",
+        SimpleFile::new_regular(
+            fs.clone(),
+            RwFile::new(|req| match req {
+                SimpleFileOperation::Read => Ok(Some(trace())),
+                // Any write, usually a truncation, clears the buffer
+                SimpleFileOperation::Write(_) => {
+                    check_privileged()?;
+                    trace::clear();
+                    Ok(None)
+                }
+            }),
+        ),
+    );
+    SimpleDir::new_maker(fs.clone(), Arc::new(dir))
+}
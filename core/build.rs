@@ -0,0 +1,20 @@
+use std::{env, fs, path::Path};
+
+/// Embeds the kernel symbol table listed by `nm -n` in the file named by
+/// `STARRY_KSYMS`, or an empty one if it is unset or empty.
+///
+/// The table can only be produced from a linked kernel, so it takes a second
+/// build to embed it. It lands in `.rodata`, after `.text`, so function
+/// addresses are unaffected by its size.
+fn main() {
+    println!("cargo:rerun-if-env-changed=STARRY_KSYMS");
+    let out = Path::new(&env::var("OUT_DIR").unwrap()).join("ksyms.txt");
+    let symbols = match env::var("STARRY_KSYMS") {
+        Ok(path) if !path.is_empty() => {
+            println!("cargo:rerun-if-changed={path}");
+            fs::read_to_string(&path).unwrap_or_else(|err| panic!("failed to read {path}: {err}"))
+        }
+        _ => String::new(),
+    };
+    fs::write(out, symbols).unwrap();
+}
@@ -0,0 +1,304 @@
+//! Control groups, as a single cgroup v2 hierarchy.
+//!
+//! Every process belongs to one cgroup, inherited on fork. The `pids`
+//! controller limits the number of tasks in a subtree, and the `memory`
+//! controller only reports the resident memory of the processes in it.
+//!
+//! Membership is only recorded in the processes, so usage is counted by
+//! walking the process table instead of being charged and uncharged.
+
+use alloc::{
+    collections::btree_map::BTreeMap,
+    format,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+
+use axerrno::{AxError, AxResult};
+use axhal::paging::PageSize;
+use axmm::backend::Backend;
+use axsync::Mutex;
+use lazy_static::lazy_static;
+use memory_addr::MemoryAddr;
+
+use crate::task::{ProcessData, processes};
+
+/// A controller of the hierarchy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum Controller {
+    /// Reports the memory usage.
+    Memory = 1 << 0,
+    /// Limits the number of tasks.
+    Pids   = 1 << 1,
+}
+
+impl Controller {
+    /// All controllers, in the order they are listed.
+    pub const ALL: [Self; 2] = [Self::Memory, Self::Pids];
+
+    /// Returns the name of the controller.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Memory => "memory",
+            Self::Pids => "pids",
+        }
+    }
+
+    /// Finds a controller by name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|it| it.name() == name)
+    }
+}
+
+/// A control group.
+pub struct Cgroup {
+    name: String,
+    parent: Option<Arc<Cgroup>>,
+    children: Mutex<BTreeMap<String, Arc<Cgroup>>>,
+    removed: AtomicBool,
+    /// The controllers enabled for the children, as a bitmask of
+    /// [`Controller`]s.
+    subtree_control: AtomicU32,
+    /// The maximum number of tasks, or `usize::MAX` for no limit.
+    pids_max: AtomicUsize,
+}
+
+lazy_static! {
+    static ref ROOT: Arc<Cgroup> = Arc::new(Cgroup::new(String::new(), None));
+}
+
+/// Returns the root cgroup.
+pub fn root() -> &'static Arc<Cgroup> {
+    &ROOT
+}
+
+impl Cgroup {
+    fn new(name: String, parent: Option<Arc<Cgroup>>) -> Self {
+        Self {
+            name,
+            parent,
+            children: Mutex::new(BTreeMap::new()),
+            removed: AtomicBool::new(false),
+            subtree_control: AtomicU32::new(0),
+            pids_max: AtomicUsize::new(usize::MAX),
+        }
+    }
+
+    /// Returns whether this is the root cgroup.
+    pub fn is_root(&self) -> bool {
+        self.parent.is_none()
+    }
+
+    /// Returns the parent cgroup.
+    pub fn parent(&self) -> Option<&Arc<Cgroup>> {
+        self.parent.as_ref()
+    }
+
+    /// Returns the path of the cgroup from the root, like `/a/b`.
+    pub fn path(&self) -> String {
+        match &self.parent {
+            None => "/".to_string(),
+            Some(parent) if parent.is_root() => format!("/{}", self.name),
+            Some(parent) => format!("{}/{}", parent.path(), self.name),
+        }
+    }
+
+    /// Returns whether the cgroup has been removed.
+    pub fn is_removed(&self) -> bool {
+        self.removed.load(Ordering::Acquire)
+    }
+
+    /// Returns whether `self` is `other` or one of its ancestors.
+    pub fn contains(&self, other: &Cgroup) -> bool {
+        let mut cgroup = Some(other);
+        while let Some(it) = cgroup {
+            if core::ptr::eq(it, self) {
+                return true;
+            }
+            cgroup = it.parent.as_deref();
+        }
+        false
+    }
+
+    /// Returns the names of the child cgroups.
+    pub fn child_names(&self) -> Vec<String> {
+        self.children.lock().keys().cloned().collect()
+    }
+
+    /// Finds a child cgroup by name.
+    pub fn child(&self, name: &str) -> Option<Arc<Cgroup>> {
+        self.children.lock().get(name).cloned()
+    }
+
+    /// Creates a child cgroup.
+    pub fn create_child(self: &Arc<Self>, name: &str) -> AxResult<Arc<Cgroup>> {
+        if self.is_removed() {
+            return Err(AxError::NotFound);
+        }
+        let mut children = self.children.lock();
+        if children.contains_key(name) {
+            return Err(AxError::AlreadyExists);
+        }
+        let child = Arc::new(Cgroup::new(name.to_string(), Some(self.clone())));
+        children.insert(name.to_string(), child.clone());
+        Ok(child)
+    }
+
+    /// Removes a child cgroup, which must have neither children nor
+    /// processes.
+    pub fn remove_child(&self, name: &str) -> AxResult<()> {
+        let mut children = self.children.lock();
+        let child = children.get(name).ok_or(AxError::NotFound)?;
+        if !child.children.lock().is_empty() || !child.processes().is_empty() {
+            return Err(AxError::ResourceBusy);
+        }
+        child.removed.store(true, Ordering::Release);
+        children.remove(name);
+        Ok(())
+    }
+
+    /// Returns the processes directly in the cgroup.
+    pub fn processes(&self) -> Vec<Arc<ProcessData>> {
+        processes()
+            .into_iter()
+            .filter(|it| core::ptr::eq(it.cgroup().as_ref(), self))
+            .collect()
+    }
+
+    /// Returns the processes in the cgroup and its descendants.
+    fn subtree_processes(&self) -> Vec<Arc<ProcessData>> {
+        processes()
+            .into_iter()
+            .filter(|it| self.contains(&it.cgroup()))
+            .collect()
+    }
+
+    /// Returns the controllers enabled for the children.
+    pub fn subtree_control(&self) -> Vec<Controller> {
+        let mask = self.subtree_control.load(Ordering::Relaxed);
+        Controller::ALL
+            .into_iter()
+            .filter(|it| mask & *it as u32 != 0)
+            .collect()
+    }
+
+    /// Returns the controllers that can be enabled for the children, which
+    /// are those enabled for the cgroup by its parent.
+    pub fn controllers(&self) -> Vec<Controller> {
+        match &self.parent {
+            None => Controller::ALL.to_vec(),
+            Some(parent) => parent.subtree_control(),
+        }
+    }
+
+    /// Enables or disables a controller for the children.
+    pub fn set_subtree_control(&self, controller: Controller, enabled: bool) -> AxResult<()> {
+        if enabled && !self.controllers().contains(&controller) {
+            return Err(AxError::NotFound);
+        }
+        // Like Linux, controllers can't be enabled for the children of a
+        // cgroup with processes, other than the root.
+        if enabled && !self.is_root() && !self.processes().is_empty() {
+            return Err(AxError::ResourceBusy);
+        }
+        if enabled {
+            self.subtree_control
+                .fetch_or(controller as u32, Ordering::Relaxed);
+        } else {
+            self.subtree_control
+                .fetch_and(!(controller as u32), Ordering::Relaxed);
+        }
+        Ok(())
+    }
+
+    /// Checks that processes can be moved into the cgroup.
+    pub fn check_attach(&self) -> AxResult<()> {
+        if self.is_removed() {
+            return Err(AxError::NotFound);
+        }
+        // Like Linux, processes can't be in a cgroup whose children have
+        // controllers enabled, other than the root.
+        if !self.is_root() && !self.subtree_control().is_empty() {
+            return Err(AxError::ResourceBusy);
+        }
+        Ok(())
+    }
+
+    /// Returns the number of tasks in the cgroup and its descendants.
+    pub fn pids_current(&self) -> usize {
+        self.subtree_processes()
+            .iter()
+            .map(|it| it.proc.threads().len())
+            .sum()
+    }
+
+    /// Returns the maximum number of tasks, or `None` for no limit.
+    pub fn pids_max(&self) -> Option<usize> {
+        match self.pids_max.load(Ordering::Relaxed) {
+            usize::MAX => None,
+            max => Some(max),
+        }
+    }
+
+    /// Sets the maximum number of tasks, or `None` for no limit.
+    pub fn set_pids_max(&self, max: Option<usize>) {
+        self.pids_max
+            .store(max.unwrap_or(usize::MAX), Ordering::Relaxed);
+    }
+
+    /// Checks that a task can be created in the cgroup, failing with
+    /// `EAGAIN` if it would exceed the `pids.max` of the cgroup or one of
+    /// its ancestors.
+    pub fn check_new_task(&self) -> AxResult<()> {
+        let mut cgroup = Some(self);
+        while let Some(it) = cgroup {
+            if it.pids_max().is_some_and(|max| it.pids_current() >= max) {
+                return Err(AxError::WouldBlock);
+            }
+            cgroup = it.parent.as_deref();
+        }
+        Ok(())
+    }
+
+    /// Returns the resident memory of the processes in the cgroup and its
+    /// descendants, in bytes.
+    pub fn memory_current(&self) -> usize {
+        self.subtree_processes()
+            .iter()
+            .map(|it| resident_size(it))
+            .sum()
+    }
+}
+
+/// Returns the resident memory of a process, in bytes. Device memory is not
+/// counted.
+fn resident_size(proc_data: &ProcessData) -> usize {
+    let aspace = proc_data.aspace.lock();
+    let mut size = 0;
+    for area in aspace.areas() {
+        if matches!(area.backend(), Backend::Linear(_)) {
+            continue;
+        }
+        let mut vaddr = area.start();
+        while vaddr < area.end() {
+            let Ok((_, _, page_size)) = aspace.page_table().query(vaddr) else {
+                vaddr += PageSize::Size4K as usize;
+                continue;
+            };
+            let page_size: usize = page_size.into();
+            size += page_size;
+            vaddr = vaddr.align_down(page_size) + page_size;
+        }
+    }
+    size
+}
+
+/// Moves a process into `cgroup`.
+pub fn move_process(proc_data: &ProcessData, cgroup: &Arc<Cgroup>) -> AxResult<()> {
+    cgroup.check_attach()?;
+    proc_data.set_cgroup(cgroup.clone());
+    Ok(())
+}
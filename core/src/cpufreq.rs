@@ -0,0 +1,145 @@
+//! CPU frequency scaling.
+//!
+//! Drivers of CPU clocks register a policy here for the CPUs they clock,
+//! which is then exposed as `/sys/devices/system/cpu/cpu<N>/cpufreq`.
+//! Drivers register at boot, before sysfs is mounted. Governors act
+//! immediately when selected, like the `performance`, `powersave` and
+//! `userspace` governors of Linux.
+
+use alloc::{collections::btree_map::BTreeMap, sync::Arc};
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use axerrno::{AxError, AxResult};
+use axsync::Mutex;
+
+/// A driver of the clock of some CPUs.
+pub trait CpuFreqDriver: Send + Sync {
+    /// Returns the frequencies the CPUs can run at, in kHz, in ascending
+    /// order. Must not be empty.
+    fn frequencies(&self) -> &[u64];
+
+    /// Reads the current frequency, in kHz.
+    fn get(&self) -> AxResult<u64>;
+
+    /// Sets the frequency to `freq` (in kHz), one of
+    /// [`CpuFreqDriver::frequencies`].
+    fn set(&self, freq: u64) -> AxResult<()>;
+}
+
+/// A frequency scaling governor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Governor {
+    /// Runs at the highest frequency.
+    Performance,
+    /// Runs at the lowest frequency.
+    Powersave,
+    /// Runs at the frequency set by userspace through `scaling_setspeed`.
+    Userspace,
+}
+
+impl Governor {
+    /// All the available governors.
+    pub const ALL: [Governor; 3] = [Self::Performance, Self::Powersave, Self::Userspace];
+
+    /// Returns the name of the governor.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Performance => "performance",
+            Self::Powersave => "powersave",
+            Self::Userspace => "userspace",
+        }
+    }
+
+    /// Looks up a governor by name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|it| it.as_str() == name)
+    }
+}
+
+/// The frequency scaling policy of the CPUs sharing a clock.
+pub struct CpuFreq {
+    driver: Arc<dyn CpuFreqDriver>,
+    governor: AtomicU8,
+}
+
+impl CpuFreq {
+    /// Returns the frequencies the CPUs can run at, in kHz, in ascending
+    /// order.
+    pub fn frequencies(&self) -> &[u64] {
+        self.driver.frequencies()
+    }
+
+    /// Returns the lowest available frequency, in kHz.
+    pub fn min_freq(&self) -> u64 {
+        self.frequencies()[0]
+    }
+
+    /// Returns the highest available frequency, in kHz.
+    pub fn max_freq(&self) -> u64 {
+        self.frequencies()[self.frequencies().len() - 1]
+    }
+
+    /// Reads the current frequency, in kHz.
+    pub fn cur(&self) -> AxResult<u64> {
+        self.driver.get()
+    }
+
+    /// Returns the current governor.
+    pub fn governor(&self) -> Governor {
+        Governor::ALL[self.governor.load(Ordering::Acquire) as usize]
+    }
+
+    /// Switches to `governor`.
+    pub fn set_governor(&self, governor: Governor) -> AxResult<()> {
+        match governor {
+            Governor::Performance => self.driver.set(self.max_freq())?,
+            Governor::Powersave => self.driver.set(self.min_freq())?,
+            Governor::Userspace => {}
+        }
+        self.governor.store(governor as u8, Ordering::Release);
+        Ok(())
+    }
+
+    /// Sets the frequency to the highest available one not above `freq`
+    /// (in kHz), or the lowest one. Only allowed under the `userspace`
+    /// governor.
+    pub fn set_speed(&self, freq: u64) -> AxResult<()> {
+        if self.governor() != Governor::Userspace {
+            return Err(AxError::InvalidInput);
+        }
+        let freq = self
+            .frequencies()
+            .iter()
+            .rev()
+            .find(|&&it| it <= freq)
+            .copied()
+            .unwrap_or_else(|| self.min_freq());
+        self.driver.set(freq)
+    }
+}
+
+static CPUS: Mutex<BTreeMap<usize, Arc<CpuFreq>>> = Mutex::new(BTreeMap::new());
+
+/// Registers `driver` as the clock of `cpus`, starting them under the
+/// `performance` governor.
+pub fn register(
+    cpus: impl IntoIterator<Item = usize>,
+    driver: Arc<dyn CpuFreqDriver>,
+) -> AxResult<()> {
+    let policy = Arc::new(CpuFreq {
+        driver,
+        governor: AtomicU8::new(Governor::Performance as u8),
+    });
+    policy.set_governor(Governor::Performance)?;
+    let mut map = CPUS.lock();
+    for cpu in cpus {
+        map.insert(cpu, policy.clone());
+    }
+    Ok(())
+}
+
+/// Returns the frequency scaling policy of `cpu`, if its clock has a driver.
+pub fn cpu(cpu: usize) -> Option<Arc<CpuFreq>> {
+    CPUS.lock().get(&cpu).cloned()
+}
@@ -0,0 +1,145 @@
+//! Process credentials.
+//!
+//! There are no capabilities: a process with an effective user ID of 0 is
+//! privileged, and may do anything.
+
+use axerrno::{AxError, AxResult};
+
+/// The real, effective, saved and filesystem IDs of a user or group.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct IdSet {
+    /// The real ID.
+    pub real: u32,
+    /// The effective ID, used for permission checks.
+    pub effective: u32,
+    /// The saved set-ID, which the effective ID can be restored to.
+    pub saved: u32,
+    /// The filesystem ID, used for file accesses.
+    pub fs: u32,
+}
+
+impl IdSet {
+    fn contains(&self, id: u32) -> bool {
+        id == self.real || id == self.effective || id == self.saved
+    }
+
+    /// Changes the IDs as `setuid` does.
+    ///
+    /// Privileged processes set all IDs, others can only set the effective ID
+    /// to the real or saved ID.
+    pub fn set(&mut self, id: u32, privileged: bool) -> AxResult<()> {
+        if privileged {
+            *self = IdSet {
+                real: id,
+                effective: id,
+                saved: id,
+                fs: id,
+            };
+        } else if id == self.real || id == self.saved {
+            self.effective = id;
+            self.fs = id;
+        } else {
+            return Err(AxError::OperationNotPermitted);
+        }
+        Ok(())
+    }
+
+    /// Changes the real and effective IDs as `setreuid` does, leaving `None`
+    /// ones unchanged.
+    pub fn set_re(
+        &mut self,
+        real: Option<u32>,
+        effective: Option<u32>,
+        privileged: bool,
+    ) -> AxResult<()> {
+        if !privileged
+            && (real.is_some_and(|id| id != self.real && id != self.effective)
+                || effective.is_some_and(|id| !self.contains(id)))
+        {
+            return Err(AxError::OperationNotPermitted);
+        }
+        let old_real = self.real;
+        if let Some(id) = real {
+            self.real = id;
+        }
+        if let Some(id) = effective {
+            self.effective = id;
+        }
+        // The saved ID follows the effective one whenever it may differ from
+        // the real ID afterwards.
+        if real.is_some() || effective.is_some_and(|id| id != old_real) {
+            self.saved = self.effective;
+        }
+        self.fs = self.effective;
+        Ok(())
+    }
+
+    /// Changes the real, effective and saved IDs as `setresuid` does, leaving
+    /// `None` ones unchanged.
+    pub fn set_res(
+        &mut self,
+        real: Option<u32>,
+        effective: Option<u32>,
+        saved: Option<u32>,
+        privileged: bool,
+    ) -> AxResult<()> {
+        if !privileged
+            && [real, effective, saved]
+                .into_iter()
+                .flatten()
+                .any(|id| !self.contains(id))
+        {
+            return Err(AxError::OperationNotPermitted);
+        }
+        if let Some(id) = real {
+            self.real = id;
+        }
+        if let Some(id) = effective {
+            self.effective = id;
+        }
+        if let Some(id) = saved {
+            self.saved = id;
+        }
+        self.fs = self.effective;
+        Ok(())
+    }
+}
+
+/// The credentials of a process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Credentials {
+    /// The user IDs.
+    pub uid: IdSet,
+    /// The group IDs.
+    pub gid: IdSet,
+    /// Whether `execve` can't grant privileges anymore, as set with
+    /// `PR_SET_NO_NEW_PRIVS`. It's inherited and can't be unset.
+    pub no_new_privs: bool,
+}
+
+impl Credentials {
+    /// Returns whether the process is privileged, i.e. its effective user is
+    /// root.
+    pub fn is_privileged(&self) -> bool {
+        self.uid.effective == 0
+    }
+
+    /// Changes the IDs as `execve` does, where a set-user-ID or set-group-ID
+    /// file sets the effective user or group ID to its owner, unless
+    /// [`Self::no_new_privs`] is set. The saved and filesystem IDs follow the
+    /// effective ones.
+    pub fn exec(&mut self, uid: Option<u32>, gid: Option<u32>) {
+        let (uid, gid) = if self.no_new_privs {
+            (None, None)
+        } else {
+            (uid, gid)
+        };
+        for (ids, id) in [(&mut self.uid, uid), (&mut self.gid, gid)] {
+            if let Some(id) = id {
+                ids.effective = id;
+            }
+            ids.saved = ids.effective;
+            ids.fs = ids.effective;
+        }
+    }
+}
@@ -0,0 +1,174 @@
+//! Device hotplug events.
+//!
+//! Device nodes report additions, removals and changes here. Consumers (e.g.
+//! `/dev/device-events`) subscribe to get a private queue that receives every
+//! event emitted after the subscription.
+
+use alloc::{
+    collections::VecDeque,
+    string::String,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::{
+    sync::atomic::{AtomicU64, Ordering},
+    task::Context,
+};
+
+use axfs_ng_vfs::{DeviceId, NodeType};
+use axpoll::{IoEvents, PollSet, Pollable};
+use axsync::Mutex;
+
+/// Maximum number of pending events kept per subscriber. Older events are
+/// dropped once the limit is reached.
+const MAX_PENDING_EVENTS: usize = 256;
+
+/// The kind of change a [`DeviceEvent`] reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceAction {
+    /// A device was added.
+    Add,
+    /// A device was removed.
+    Remove,
+    /// The state of a device changed (e.g. a loop device got a backing file).
+    Change,
+}
+
+impl DeviceAction {
+    /// Returns the name of the action as used by uevents.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeviceAction::Add => "add",
+            DeviceAction::Remove => "remove",
+            DeviceAction::Change => "change",
+        }
+    }
+}
+
+/// A device hotplug event.
+#[derive(Debug, Clone)]
+pub struct DeviceEvent {
+    /// What happened to the device.
+    pub action: DeviceAction,
+    /// The type of the device node.
+    pub node_type: NodeType,
+    /// The device ID.
+    pub device_id: DeviceId,
+    /// The device name, relative to `/dev`.
+    pub name: String,
+    /// The sequence number of the event, assigned by [`notify`].
+    pub seqnum: u64,
+}
+
+impl DeviceEvent {
+    /// Creates a new device event.
+    pub fn new(
+        action: DeviceAction,
+        node_type: NodeType,
+        device_id: DeviceId,
+        name: impl Into<String>,
+    ) -> Self {
+        Self {
+            action,
+            node_type,
+            device_id,
+            name: name.into(),
+            seqnum: 0,
+        }
+    }
+
+    /// Returns the subsystem name of the device node.
+    pub fn subsystem(&self) -> &'static str {
+        match self.node_type {
+            NodeType::BlockDevice => "block",
+            _ => "char",
+        }
+    }
+}
+
+/// A subscriber queue of device events.
+pub struct DeviceEventQueue {
+    events: Mutex<VecDeque<DeviceEvent>>,
+    poll_rx: PollSet,
+}
+
+impl DeviceEventQueue {
+    fn new() -> Self {
+        Self {
+            events: Mutex::new(VecDeque::new()),
+            poll_rx: PollSet::new(),
+        }
+    }
+
+    fn push(&self, event: DeviceEvent) {
+        let mut events = self.events.lock();
+        if events.len() >= MAX_PENDING_EVENTS {
+            events.pop_front();
+        }
+        events.push_back(event);
+        drop(events);
+        self.poll_rx.wake();
+    }
+
+    /// Removes the oldest pending event if `f` maps it to a value, and
+    /// returns that value.
+    pub fn pop_if<T>(&self, f: impl FnOnce(&DeviceEvent) -> Option<T>) -> Option<T> {
+        let mut events = self.events.lock();
+        let value = f(events.front()?)?;
+        events.pop_front();
+        Some(value)
+    }
+
+    /// Removes and returns the oldest pending event.
+    pub fn pop(&self) -> Option<DeviceEvent> {
+        self.events.lock().pop_front()
+    }
+
+    /// Returns whether there are no pending events.
+    pub fn is_empty(&self) -> bool {
+        self.events.lock().is_empty()
+    }
+}
+
+impl Pollable for DeviceEventQueue {
+    fn poll(&self) -> IoEvents {
+        let mut events = IoEvents::empty();
+        events.set(IoEvents::IN, !self.is_empty());
+        events
+    }
+
+    fn register(&self, context: &mut Context<'_>, events: IoEvents) {
+        if events.contains(IoEvents::IN) {
+            self.poll_rx.register(context.waker());
+        }
+    }
+}
+
+static SUBSCRIBERS: Mutex<Vec<Weak<DeviceEventQueue>>> = Mutex::new(Vec::new());
+
+static SEQNUM: AtomicU64 = AtomicU64::new(1);
+
+/// Subscribes to device events.
+///
+/// The returned queue receives all events emitted from now on until it is
+/// dropped.
+pub fn subscribe() -> Arc<DeviceEventQueue> {
+    let queue = Arc::new(DeviceEventQueue::new());
+    SUBSCRIBERS.lock().push(Arc::downgrade(&queue));
+    queue
+}
+
+/// Broadcasts a device event to all subscribers.
+pub fn notify(mut event: DeviceEvent) {
+    event.seqnum = SEQNUM.fetch_add(1, Ordering::Relaxed);
+    debug!("device event: {event:?}");
+    let mut subscribers = SUBSCRIBERS.lock();
+    subscribers.retain(|queue| {
+        if let Some(queue) = queue.upgrade() {
+            queue.push(event.clone());
+            true
+        } else {
+            false
+        }
+    });
+}
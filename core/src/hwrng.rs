@@ -0,0 +1,34 @@
+//! Hardware random number generators.
+//!
+//! Drivers of entropy sources register here, and `/dev/hwrng` reads from the
+//! most recently registered one, like Linux's `hw_random` core.
+
+use alloc::{sync::Arc, vec::Vec};
+
+use axerrno::AxResult;
+use axsync::Mutex;
+
+/// A hardware random number generator.
+pub trait HwRng: Send + Sync {
+    /// Returns the name of the generator, e.g. `virtio_rng.0`.
+    fn name(&self) -> &str;
+
+    /// Fills `buf` with random bytes, returning how many were written.
+    ///
+    /// May return fewer bytes than requested, but at least one unless `buf`
+    /// is empty.
+    fn read(&self, buf: &mut [u8]) -> AxResult<usize>;
+}
+
+static RNGS: Mutex<Vec<Arc<dyn HwRng>>> = Mutex::new(Vec::new());
+
+/// Registers a hardware random number generator, making it the current one.
+pub fn register(rng: Arc<dyn HwRng>) {
+    info!("hwrng: registered {}", rng.name());
+    RNGS.lock().push(rng);
+}
+
+/// Returns the current hardware random number generator.
+pub fn current() -> Option<Arc<dyn HwRng>> {
+    RNGS.lock().last().cloned()
+}
@@ -0,0 +1,329 @@
+//! Key retention, with `user` keys and keyrings.
+//!
+//! Keys are found by serial number, or through the special keyrings of the
+//! current process: its process keyring, its session keyring, which is
+//! inherited across forks, and the user and user session keyrings of its real
+//! user. Like Linux, a process without a session keyring uses the user session
+//! keyring.
+//!
+//! Permissions are simplified: a key can be used by the user owning it and by
+//! privileged processes only.
+
+use alloc::{
+    collections::btree_map::BTreeMap,
+    format,
+    string::String,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+
+use axerrno::{AxError, AxResult, LinuxError};
+use axsync::Mutex;
+
+use crate::cred::Credentials;
+
+/// The maximum depth of nested keyrings searched.
+const MAX_SEARCH_DEPTH: usize = 6;
+
+/// The type of a key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyType {
+    /// A key holding arbitrary data.
+    User,
+    /// A keyring, holding links to other keys.
+    Keyring,
+}
+
+impl KeyType {
+    /// Returns the key type with a name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "user" => Some(Self::User),
+            "keyring" => Some(Self::Keyring),
+            _ => None,
+        }
+    }
+
+    /// Returns the name of the key type.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::User => "user",
+            Self::Keyring => "keyring",
+        }
+    }
+}
+
+enum Payload {
+    Data(Vec<u8>),
+    Keyring(Vec<Arc<Key>>),
+}
+
+/// A key.
+pub struct Key {
+    serial: i32,
+    key_type: KeyType,
+    description: String,
+    uid: u32,
+    gid: u32,
+    revoked: AtomicBool,
+    payload: Mutex<Payload>,
+}
+
+static NEXT_SERIAL: AtomicI32 = AtomicI32::new(1);
+/// The keys by serial number.
+static KEYS: Mutex<BTreeMap<i32, Weak<Key>>> = Mutex::new(BTreeMap::new());
+
+impl Key {
+    /// Creates a key owned by the filesystem user and group of `cred`.
+    /// Keyrings are created empty and ignore `data`.
+    pub fn new(
+        key_type: KeyType,
+        description: String,
+        cred: &Credentials,
+        data: Vec<u8>,
+    ) -> Arc<Self> {
+        let payload = match key_type {
+            KeyType::User => Payload::Data(data),
+            KeyType::Keyring => Payload::Keyring(Vec::new()),
+        };
+        let key = Arc::new(Self {
+            serial: NEXT_SERIAL.fetch_add(1, Ordering::Relaxed),
+            key_type,
+            description,
+            uid: cred.uid.fs,
+            gid: cred.gid.fs,
+            revoked: AtomicBool::new(false),
+            payload: Mutex::new(payload),
+        });
+        let mut keys = KEYS.lock();
+        keys.retain(|_, it| it.strong_count() > 0);
+        keys.insert(key.serial, Arc::downgrade(&key));
+        key
+    }
+
+    /// Returns the serial number of the key.
+    pub fn serial(&self) -> i32 {
+        self.serial
+    }
+
+    /// Returns the type of the key.
+    pub fn key_type(&self) -> KeyType {
+        self.key_type
+    }
+
+    /// Returns the description of the key.
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+
+    /// Returns the user and group owning the key.
+    pub fn owner(&self) -> (u32, u32) {
+        (self.uid, self.gid)
+    }
+
+    fn check_revoked(&self) -> AxResult<()> {
+        if self.revoked.load(Ordering::Acquire) {
+            Err(AxError::Other(LinuxError::EKEYREVOKED))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Fails with `EACCES` unless `cred` may use the key.
+    pub fn check_access(&self, cred: &Credentials) -> AxResult<()> {
+        if cred.uid.fs == self.uid || cred.is_privileged() {
+            Ok(())
+        } else {
+            Err(AxError::PermissionDenied)
+        }
+    }
+
+    /// Revokes the key, after which it can't be read, updated or found.
+    pub fn revoke(&self) {
+        self.revoked.store(true, Ordering::Release);
+        // Like Linux, the payload of a revoked key is discarded
+        if let Payload::Data(data) = &mut *self.payload.lock() {
+            *data = Vec::new();
+        }
+    }
+
+    /// Returns the payload of the key. The payload of a keyring is the serial
+    /// numbers of its keys.
+    pub fn read(&self) -> AxResult<Vec<u8>> {
+        self.check_revoked()?;
+        Ok(match &*self.payload.lock() {
+            Payload::Data(data) => data.clone(),
+            Payload::Keyring(keys) => keys
+                .iter()
+                .flat_map(|key| key.serial.to_ne_bytes())
+                .collect(),
+        })
+    }
+
+    /// Replaces the payload of a `user` key.
+    pub fn update(&self, data: Vec<u8>) -> AxResult<()> {
+        self.check_revoked()?;
+        match &mut *self.payload.lock() {
+            Payload::Data(old) => *old = data,
+            Payload::Keyring(_) => return Err(AxError::OperationNotSupported),
+        }
+        Ok(())
+    }
+
+    fn with_keys<R>(&self, f: impl FnOnce(&mut Vec<Arc<Key>>) -> R) -> AxResult<R> {
+        self.check_revoked()?;
+        match &mut *self.payload.lock() {
+            Payload::Keyring(keys) => Ok(f(keys)),
+            Payload::Data(_) => Err(AxError::NotADirectory),
+        }
+    }
+
+    /// Returns the key of a type and description linked directly in this
+    /// keyring.
+    pub fn find(&self, key_type: KeyType, description: &str) -> AxResult<Option<Arc<Key>>> {
+        self.with_keys(|keys| {
+            keys.iter()
+                .find(|key| key.key_type == key_type && key.description == description)
+                .cloned()
+        })
+    }
+
+    /// Links a key into this keyring, replacing the key of the same type and
+    /// description.
+    pub fn link(&self, key: Arc<Key>) -> AxResult<()> {
+        if key.key_type == KeyType::Keyring && key.reaches(self, 0) {
+            return Err(AxError::Other(LinuxError::EDEADLK));
+        }
+        self.with_keys(|keys| {
+            keys.retain(|it| it.key_type != key.key_type || it.description != key.description);
+            keys.push(key);
+        })
+    }
+
+    /// Returns whether `target` is this keyring or nested in it.
+    fn reaches(&self, target: &Key, depth: usize) -> bool {
+        if core::ptr::eq(self, target) {
+            return true;
+        }
+        if depth >= MAX_SEARCH_DEPTH {
+            return false;
+        }
+        let nested = match &*self.payload.lock() {
+            Payload::Keyring(keys) => keys.clone(),
+            Payload::Data(_) => return false,
+        };
+        nested.iter().any(|key| key.reaches(target, depth + 1))
+    }
+
+    /// Searches this keyring and the keyrings nested in it for a key of a
+    /// type and description that `cred` may use, failing with `ENOKEY` if
+    /// there is none, or `EKEYREVOKED` if it's revoked.
+    pub fn search(
+        &self,
+        key_type: KeyType,
+        description: &str,
+        cred: &Credentials,
+    ) -> AxResult<Arc<Key>> {
+        let mut result = Err(AxError::Other(LinuxError::ENOKEY));
+        self.search_at(key_type, description, cred, 0, &mut result);
+        result
+    }
+
+    fn search_at(
+        &self,
+        key_type: KeyType,
+        description: &str,
+        cred: &Credentials,
+        depth: usize,
+        result: &mut AxResult<Arc<Key>>,
+    ) -> bool {
+        let Ok(keys) = self.with_keys(|keys| keys.clone()) else {
+            return false;
+        };
+        // Keys directly in a keyring are found before the nested ones
+        for key in &keys {
+            if key.key_type != key_type
+                || key.description != description
+                || key.check_access(cred).is_err()
+            {
+                continue;
+            }
+            if key.revoked.load(Ordering::Acquire) {
+                *result = Err(AxError::Other(LinuxError::EKEYREVOKED));
+            } else {
+                *result = Ok(key.clone());
+                return true;
+            }
+        }
+        depth < MAX_SEARCH_DEPTH
+            && keys.iter().any(|key| {
+                key.key_type == KeyType::Keyring
+                    && key.check_access(cred).is_ok()
+                    && key.search_at(key_type, description, cred, depth + 1, result)
+            })
+    }
+}
+
+/// Returns the key with a serial number.
+pub fn lookup(serial: i32) -> Option<Arc<Key>> {
+    KEYS.lock().get(&serial)?.upgrade()
+}
+
+/// The user and user session keyrings of a user.
+struct UserKeyrings {
+    user: Arc<Key>,
+    session: Arc<Key>,
+}
+
+static USER_KEYRINGS: Mutex<BTreeMap<u32, UserKeyrings>> = Mutex::new(BTreeMap::new());
+
+fn with_user_keyrings<R>(cred: &Credentials, f: impl FnOnce(&UserKeyrings) -> R) -> R {
+    let uid = cred.uid.real;
+    let mut keyrings = USER_KEYRINGS.lock();
+    let keyrings = keyrings.entry(uid).or_insert_with(|| {
+        // Like Linux, they are owned by the user whoever creates them
+        let mut owner = *cred;
+        owner.uid.fs = uid;
+        UserKeyrings {
+            user: Key::new(KeyType::Keyring, format!("_uid.{uid}"), &owner, Vec::new()),
+            session: Key::new(
+                KeyType::Keyring,
+                format!("_uid_ses.{uid}"),
+                &owner,
+                Vec::new(),
+            ),
+        }
+    });
+    f(keyrings)
+}
+
+/// Returns the user keyring of the real user of `cred`.
+pub fn user_keyring(cred: &Credentials) -> Arc<Key> {
+    with_user_keyrings(cred, |it| it.user.clone())
+}
+
+/// Returns the user session keyring of the real user of `cred`.
+pub fn user_session_keyring(cred: &Credentials) -> Arc<Key> {
+    with_user_keyrings(cred, |it| it.session.clone())
+}
+
+/// The keyrings of a process.
+#[derive(Default)]
+pub struct ProcessKeyrings {
+    /// The process keyring, created when first used.
+    pub process: Option<Arc<Key>>,
+    /// The session keyring, or `None` to use the user session keyring.
+    pub session: Option<Arc<Key>>,
+}
+
+impl ProcessKeyrings {
+    /// Returns the keyrings of a child created by `fork`, which only keeps the
+    /// session keyring.
+    pub fn for_child(&self) -> Self {
+        Self {
+            process: None,
+            session: self.session.clone(),
+        }
+    }
+}
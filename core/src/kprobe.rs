@@ -0,0 +1,386 @@
+//! Kernel probes.
+//!
+//! A kprobe replaces the probed instruction with a breakpoint. When it is
+//! hit, the entry handler runs, then the original instruction is executed out
+//! of line in a slot followed by another breakpoint, which resumes execution
+//! after the probed instruction.
+//!
+//! A kretprobe also redirects the return address of the function to a
+//! trampoline, whose breakpoint runs the return handler before going back to
+//! the caller.
+//!
+//! Only riscv64 is supported. Instructions that depend on the PC (jumps,
+//! branches and `auipc`) can't run out of line, so they can't be probed.
+
+use alloc::{boxed::Box, collections::btree_map::BTreeMap, sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+
+use axconfig::plat::CPU_NUM;
+use axcpu::{
+    TrapFrame,
+    trap::{BREAKPOINT, register_trap_handler},
+};
+use axerrno::{AxError, AxResult};
+use axhal::{paging::MappingFlags, percpu::this_cpu_id, time::monotonic_time_nanos};
+use axsync::Mutex;
+use kspin::SpinNoIrq;
+use memory_addr::{MemoryAddr, VirtAddr};
+
+/// The maximum number of kprobes registered at once.
+pub const MAX_KPROBES: usize = 64;
+
+/// The kind of a kprobe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KprobeKind {
+    /// Fires when the probed instruction is reached.
+    Entry,
+    /// Fires when the probed function returns. It must be placed at the
+    /// start of the function.
+    Return,
+}
+
+/// Called when a kprobe is hit, before the probed instruction runs.
+pub type EntryHandler = Box<dyn Fn(&Kprobe, &TrapFrame) + Send + Sync>;
+
+/// Called when the function of a kretprobe returns, with the time it was
+/// entered, in nanoseconds.
+pub type ReturnHandler = Box<dyn Fn(&Kprobe, &TrapFrame, u64) + Send + Sync>;
+
+/// A registered kprobe.
+pub struct Kprobe {
+    addr: usize,
+    kind: KprobeKind,
+    slot: usize,
+    /// The halfword replaced by the breakpoint.
+    saved: u16,
+    on_entry: Option<EntryHandler>,
+    on_return: Option<ReturnHandler>,
+    registered: AtomicBool,
+    hits: AtomicU64,
+    missed: AtomicU64,
+}
+
+impl Kprobe {
+    /// Returns the probed address.
+    pub fn addr(&self) -> usize {
+        self.addr
+    }
+
+    /// Returns the kind of the probe.
+    pub fn kind(&self) -> KprobeKind {
+        self.kind
+    }
+
+    /// Returns the number of times the probe was hit.
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of hits whose handlers were skipped, because they
+    /// happened while running the handler of another probe.
+    pub fn missed(&self) -> u64 {
+        self.missed.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(target_arch = "riscv64")]
+pub(crate) mod arch {
+    use super::TrapFrame;
+
+    /// `ebreak`.
+    pub const BREAK_INSN: u32 = 0x0010_0073;
+    /// `c.ebreak`, which replaces the first halfword of probed instructions.
+    pub const C_BREAK_INSN: u16 = 0x9002;
+    /// `c.nop`, padding compressed instructions in their slot.
+    pub const C_NOP: u16 = 0x0001;
+
+    /// Returns the length of the instruction starting with `first`.
+    pub fn insn_len(first: u16) -> usize {
+        if first & 0b11 == 0b11 { 4 } else { 2 }
+    }
+
+    /// Returns whether `insn` behaves the same when run out of line.
+    pub fn can_step_out_of_line(insn: u32, len: usize) -> bool {
+        if len == 4 {
+            // auipc, jal, jalr and branches, as well as ecall, ebreak and sret
+            !matches!(insn & 0x7f, 0x17 | 0x6f | 0x67 | 0x63)
+                && !matches!(insn, 0x0000_0073 | BREAK_INSN | 0x1020_0073)
+        } else {
+            let funct3 = (insn >> 13) & 0b111;
+            match insn & 0b11 {
+                // c.j, c.beqz and c.bnez
+                0b01 => !matches!(funct3, 0b101 | 0b110 | 0b111),
+                // c.jr, c.jalr and c.ebreak
+                0b10 => !(funct3 == 0b100 && (insn >> 2) & 0x1f == 0),
+                _ => true,
+            }
+        }
+    }
+
+    pub fn return_addr(tf: &TrapFrame) -> usize {
+        tf.regs.ra
+    }
+
+    pub fn set_return_addr(tf: &mut TrapFrame, addr: usize) {
+        tf.regs.ra = addr;
+    }
+
+    pub fn flush_icache() {
+        unsafe { core::arch::asm!("fence.i") };
+    }
+}
+
+#[cfg(not(target_arch = "riscv64"))]
+pub(crate) mod arch {
+    use super::TrapFrame;
+
+    pub const BREAK_INSN: u32 = 0;
+    pub const C_BREAK_INSN: u16 = 0;
+    pub const C_NOP: u16 = 0;
+
+    pub fn insn_len(_first: u16) -> usize {
+        4
+    }
+
+    pub fn can_step_out_of_line(_insn: u32, _len: usize) -> bool {
+        false
+    }
+
+    pub fn return_addr(_tf: &TrapFrame) -> usize {
+        0
+    }
+
+    pub fn set_return_addr(_tf: &mut TrapFrame, _addr: usize) {}
+
+    pub fn flush_icache() {}
+}
+
+/// Out-of-line slots, each holding a probed instruction (padded to 4 bytes)
+/// followed by a breakpoint.
+///
+/// The slots are written by [`patch_text`] when kprobes are registered, so
+/// they are only accessed through raw pointers.
+#[unsafe(link_section = ".text.kprobes")]
+static mut SLOTS: [[u32; 2]; MAX_KPROBES] = [[arch::BREAK_INSN; 2]; MAX_KPROBES];
+
+/// The address kretprobes return to.
+#[unsafe(link_section = ".text.kprobes")]
+static TRAMPOLINE: u32 = arch::BREAK_INSN;
+
+/// The address execution resumes at after the instruction in each slot.
+static RESUME: [AtomicUsize; MAX_KPROBES] = [const { AtomicUsize::new(0) }; MAX_KPROBES];
+
+/// Serializes the registration of kprobes, which patches the kernel text.
+static REGISTRATION: Mutex<()> = Mutex::new(());
+
+/// Registered kprobes by address.
+static PROBES: SpinNoIrq<BTreeMap<usize, Arc<Kprobe>>> = SpinNoIrq::new(BTreeMap::new());
+
+/// Whether a probe handler is running on each CPU.
+static RUNNING: [AtomicBool; CPU_NUM] = [const { AtomicBool::new(false) }; CPU_NUM];
+
+/// A function entered through a kretprobe that has yet to return.
+struct PendingReturn {
+    probe: Arc<Kprobe>,
+    /// The stack pointer on entry, which is restored on return and tells
+    /// apart the pending returns of different tasks and recursion levels.
+    sp: usize,
+    ret_addr: usize,
+    entered_ns: u64,
+}
+
+static PENDING_RETURNS: SpinNoIrq<Vec<PendingReturn>> = SpinNoIrq::new(Vec::new());
+
+fn slot_addr(slot: usize) -> usize {
+    (&raw const SLOTS[slot]) as usize
+}
+
+fn trampoline_addr() -> usize {
+    &TRAMPOLINE as *const u32 as usize
+}
+
+fn is_kernel_text(addr: usize) -> bool {
+    unsafe extern "C" {
+        fn _stext();
+        fn _etext();
+    }
+    (_stext as usize.._etext as usize).contains(&addr)
+}
+
+/// Writes `bytes` over the kernel text at `addr`.
+fn patch_text(addr: usize, bytes: &[u8]) -> AxResult<()> {
+    let start = VirtAddr::from(addr).align_down_4k();
+    let size = VirtAddr::from(addr + bytes.len()).align_up_4k() - start;
+    let mut aspace = axmm::kernel_aspace().lock();
+    aspace.protect(
+        start,
+        size,
+        MappingFlags::READ | MappingFlags::WRITE | MappingFlags::EXECUTE,
+    )?;
+    // Halfwords are written at once, so that a breakpoint is never seen half
+    // written.
+    for (i, half) in bytes.chunks(2).enumerate() {
+        let half = u16::from_le_bytes([half[0], half.get(1).copied().unwrap_or(0)]);
+        // SAFETY: the text was just made writable, and instructions are
+        // aligned to 2 bytes.
+        unsafe { ((addr + i * 2) as *mut u16).write_volatile(half) };
+    }
+    aspace.protect(start, size, MappingFlags::READ | MappingFlags::EXECUTE)?;
+    drop(aspace);
+    axhal::asm::flush_tlb(None);
+    arch::flush_icache();
+    Ok(())
+}
+
+/// Registers a kprobe at the kernel address `addr`.
+///
+/// `on_return` is only called for [`KprobeKind::Return`] probes.
+pub fn register_kprobe(
+    addr: usize,
+    kind: KprobeKind,
+    on_entry: Option<EntryHandler>,
+    on_return: Option<ReturnHandler>,
+) -> AxResult<Arc<Kprobe>> {
+    if cfg!(not(target_arch = "riscv64")) {
+        return Err(AxError::Unsupported);
+    }
+    let slots = slot_addr(0)..slot_addr(MAX_KPROBES - 1) + 8;
+    if addr & 1 != 0 || !is_kernel_text(addr) || slots.contains(&addr) || addr == trampoline_addr()
+    {
+        return Err(AxError::InvalidInput);
+    }
+
+    let _guard = REGISTRATION.lock();
+    let slot = {
+        let probes = PROBES.lock();
+        if probes.contains_key(&addr) {
+            return Err(AxError::AlreadyExists);
+        }
+        let used = probes.values().map(|probe| probe.slot).collect::<Vec<_>>();
+        (0..MAX_KPROBES)
+            .find(|slot| !used.contains(slot))
+            .ok_or(AxError::NoMemory)?
+    };
+
+    let mut code = [0u8; 8];
+    // SAFETY: the address lies in the kernel text.
+    let first = unsafe { (addr as *const u16).read_volatile() };
+    let len = arch::insn_len(first);
+    code[..len].copy_from_slice(unsafe { core::slice::from_raw_parts(addr as *const u8, len) });
+    let insn = u32::from_le_bytes(code[..4].try_into().unwrap());
+    if !arch::can_step_out_of_line(if len == 4 { insn } else { first as u32 }, len) {
+        return Err(AxError::InvalidInput);
+    }
+    if len == 2 {
+        code[2..4].copy_from_slice(&arch::C_NOP.to_le_bytes());
+    }
+    code[4..].copy_from_slice(&arch::BREAK_INSN.to_le_bytes());
+    patch_text(slot_addr(slot), &code)?;
+    RESUME[slot].store(addr + len, Ordering::Release);
+
+    let probe = Arc::new(Kprobe {
+        addr,
+        kind,
+        slot,
+        saved: first,
+        on_entry,
+        on_return,
+        registered: AtomicBool::new(true),
+        hits: AtomicU64::new(0),
+        missed: AtomicU64::new(0),
+    });
+    PROBES.lock().insert(addr, probe.clone());
+    if let Err(err) = patch_text(addr, &arch::C_BREAK_INSN.to_le_bytes()) {
+        PROBES.lock().remove(&addr);
+        return Err(err);
+    }
+    Ok(probe)
+}
+
+/// Unregisters a kprobe, restoring the probed instruction.
+///
+/// Pending returns of a kretprobe still go back to their callers, without
+/// calling the handler.
+pub fn unregister_kprobe(probe: &Kprobe) -> AxResult<()> {
+    let _guard = REGISTRATION.lock();
+    if !probe.registered.swap(false, Ordering::AcqRel) {
+        return Err(AxError::NotFound);
+    }
+    patch_text(probe.addr, &probe.saved.to_le_bytes())?;
+    // The slot keeps its resume address until it is reused, in case a task
+    // was preempted in it.
+    PROBES.lock().remove(&probe.addr);
+    Ok(())
+}
+
+/// Looks up the probe at `addr`.
+pub fn find_kprobe(addr: usize) -> Option<Arc<Kprobe>> {
+    PROBES.lock().get(&addr).cloned()
+}
+
+fn handle_probe(tf: &mut TrapFrame, probe: &Arc<Kprobe>) {
+    probe.hits.fetch_add(1, Ordering::Relaxed);
+    let running = &RUNNING[this_cpu_id()];
+    if running.swap(true, Ordering::Acquire) {
+        probe.missed.fetch_add(1, Ordering::Relaxed);
+    } else {
+        if let Some(handler) = &probe.on_entry {
+            handler(probe, tf);
+        }
+        if probe.kind == KprobeKind::Return {
+            PENDING_RETURNS.lock().push(PendingReturn {
+                probe: probe.clone(),
+                sp: tf.sp(),
+                ret_addr: arch::return_addr(tf),
+                entered_ns: monotonic_time_nanos(),
+            });
+            arch::set_return_addr(tf, trampoline_addr());
+        }
+        running.store(false, Ordering::Release);
+    }
+    tf.set_ip(slot_addr(probe.slot));
+}
+
+/// Returns from a function through the trampoline, returning whether it had
+/// a pending return.
+fn handle_return(tf: &mut TrapFrame) -> bool {
+    let sp = tf.sp();
+    let pending = {
+        let mut pending = PENDING_RETURNS.lock();
+        let Some(index) = pending.iter().rposition(|it| it.sp == sp) else {
+            warn!("kretprobe trampoline reached without a pending return");
+            return false;
+        };
+        pending.remove(index)
+    };
+    let probe = &pending.probe;
+    let running = &RUNNING[this_cpu_id()];
+    if probe.registered.load(Ordering::Acquire) && !running.swap(true, Ordering::Acquire) {
+        if let Some(handler) = &probe.on_return {
+            handler(probe, tf, pending.entered_ns);
+        }
+        running.store(false, Ordering::Release);
+    }
+    tf.set_ip(pending.ret_addr);
+    true
+}
+
+#[register_trap_handler(BREAKPOINT)]
+fn handle_breakpoint(tf: &mut TrapFrame) -> bool {
+    let pc = tf.ip();
+    if pc == trampoline_addr() {
+        return handle_return(tf);
+    }
+    if let Some(slot) = (0..MAX_KPROBES).find(|&slot| slot_addr(slot) + 4 == pc) {
+        tf.set_ip(RESUME[slot].load(Ordering::Acquire));
+        return true;
+    }
+    let Some(probe) = find_kprobe(pc) else {
+        // The probe may have been removed since the breakpoint was hit, in
+        // which case the restored instruction is run again.
+        // SAFETY: the breakpoint was hit at `pc`.
+        return unsafe { (pc as *const u16).read_volatile() } != arch::C_BREAK_INSN;
+    };
+    handle_probe(tf, &probe);
+    true
+}
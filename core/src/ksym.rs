@@ -0,0 +1,66 @@
+//! The kernel symbol table, as listed by `/proc/kallsyms`.
+//!
+//! The table is the `nm -n` listing of a previous build of the kernel,
+//! embedded at build time through the `STARRY_KSYMS` environment variable
+//! (see `build.rs`). Without it, the table is empty.
+
+use alloc::vec::Vec;
+use core::sync::atomic::AtomicU64;
+
+use lazy_static::lazy_static;
+
+/// Controls who may see symbol addresses, like `kernel.kptr_restrict`: `0`
+/// shows them to everyone, `1` only to privileged readers, and `2` to no one.
+/// Hidden addresses read as zero.
+pub static KPTR_RESTRICT: AtomicU64 = AtomicU64::new(1);
+
+/// A kernel symbol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Symbol {
+    /// The address of the symbol.
+    pub addr: usize,
+    /// The `nm` type of the symbol, e.g. `T` for a global function.
+    pub ty: char,
+    /// The name of the symbol.
+    pub name: &'static str,
+}
+
+static RAW_SYMBOLS: &str = include_str!(concat!(env!("OUT_DIR"), "/ksyms.txt"));
+
+lazy_static! {
+    static ref SYMBOLS: Vec<Symbol> = {
+        let mut symbols = RAW_SYMBOLS
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.split_ascii_whitespace();
+                // Undefined symbols have no address and are skipped
+                let addr = usize::from_str_radix(fields.next()?, 16).ok()?;
+                let ty = fields.next()?.chars().next()?;
+                let name = fields.next()?;
+                Some(Symbol { addr, ty, name })
+            })
+            .collect::<Vec<_>>();
+        symbols.sort_by_key(|sym| sym.addr);
+        symbols
+    };
+}
+
+/// Returns all kernel symbols, sorted by address.
+pub fn symbols() -> &'static [Symbol] {
+    &SYMBOLS
+}
+
+/// Looks up the address of the symbol `name`.
+pub fn lookup(name: &str) -> Option<usize> {
+    SYMBOLS
+        .iter()
+        .find(|sym| sym.name == name)
+        .map(|sym| sym.addr)
+}
+
+/// Returns the symbol containing `addr` and the offset of `addr` into it.
+pub fn resolve(addr: usize) -> Option<(&'static Symbol, usize)> {
+    let index = SYMBOLS.partition_point(|sym| sym.addr <= addr);
+    let sym = SYMBOLS.get(index.checked_sub(1)?)?;
+    Some((sym, addr - sym.addr))
+}
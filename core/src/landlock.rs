@@ -0,0 +1,150 @@
+//! Landlock, unprivileged filesystem access restriction.
+//!
+//! A ruleset handles some access rights, and its rules allow some of them
+//! beneath paths. Once a process restricts itself with a ruleset, an access
+//! right handled by it is only allowed beneath a path a rule allows it for.
+//! Restrictions stack, as every ruleset the process was restricted with must
+//! allow an access, and they are inherited by children and can't be lifted.
+//!
+//! Unlike Linux, rules hold the absolute paths of their files when added,
+//! rather than the files themselves.
+
+use alloc::{collections::btree_map::BTreeMap, string::String, sync::Arc, vec::Vec};
+
+use axerrno::{AxError, AxResult, LinuxError};
+use axsync::Mutex;
+
+/// Execute a file.
+pub const LANDLOCK_ACCESS_FS_EXECUTE: u64 = 1 << 0;
+/// Open a file with write access.
+pub const LANDLOCK_ACCESS_FS_WRITE_FILE: u64 = 1 << 1;
+/// Open a file with read access.
+pub const LANDLOCK_ACCESS_FS_READ_FILE: u64 = 1 << 2;
+/// Open a directory or list its content.
+pub const LANDLOCK_ACCESS_FS_READ_DIR: u64 = 1 << 3;
+/// Remove an empty directory or rename one.
+pub const LANDLOCK_ACCESS_FS_REMOVE_DIR: u64 = 1 << 4;
+/// Unlink or rename a file.
+pub const LANDLOCK_ACCESS_FS_REMOVE_FILE: u64 = 1 << 5;
+/// Create, rename or link a character device.
+pub const LANDLOCK_ACCESS_FS_MAKE_CHAR: u64 = 1 << 6;
+/// Create or rename a directory.
+pub const LANDLOCK_ACCESS_FS_MAKE_DIR: u64 = 1 << 7;
+/// Create, rename or link a regular file.
+pub const LANDLOCK_ACCESS_FS_MAKE_REG: u64 = 1 << 8;
+/// Create, rename or link a socket.
+pub const LANDLOCK_ACCESS_FS_MAKE_SOCK: u64 = 1 << 9;
+/// Create, rename or link a named pipe.
+pub const LANDLOCK_ACCESS_FS_MAKE_FIFO: u64 = 1 << 10;
+/// Create, rename or link a block device.
+pub const LANDLOCK_ACCESS_FS_MAKE_BLOCK: u64 = 1 << 11;
+/// Create, rename or link a symbolic link.
+pub const LANDLOCK_ACCESS_FS_MAKE_SYM: u64 = 1 << 12;
+
+/// The access rights of the supported ABI version.
+pub const ACCESS_FS_ALL: u64 = (1 << 13) - 1;
+/// The access rights that apply to files rather than to directories.
+pub const ACCESS_FILE: u64 =
+    LANDLOCK_ACCESS_FS_EXECUTE | LANDLOCK_ACCESS_FS_WRITE_FILE | LANDLOCK_ACCESS_FS_READ_FILE;
+
+/// The supported Landlock ABI version.
+pub const ABI_VERSION: u32 = 1;
+
+/// The maximum number of stacked rulesets.
+const MAX_LAYERS: usize = 16;
+
+/// Returns whether `path` is `dir` or beneath it.
+fn is_beneath(path: &str, dir: &str) -> bool {
+    dir == "/"
+        || path
+            .strip_prefix(dir)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// The rules of a ruleset, as it was when a process restricted itself.
+struct Layer {
+    handled: u64,
+    /// The access rights allowed beneath each path.
+    rules: BTreeMap<String, u64>,
+}
+
+impl Layer {
+    fn allows(&self, path: &str, access: u64) -> bool {
+        let handled = access & self.handled;
+        if handled == 0 {
+            return true;
+        }
+        let allowed = self
+            .rules
+            .iter()
+            .filter(|(dir, _)| is_beneath(path, dir))
+            .fold(0, |allowed, (_, access)| allowed | access);
+        allowed & handled == handled
+    }
+}
+
+/// A ruleset being built with `landlock_add_rule`.
+pub struct Ruleset {
+    handled: u64,
+    rules: Mutex<BTreeMap<String, u64>>,
+}
+
+impl Ruleset {
+    /// Creates a ruleset handling the access rights `handled`.
+    pub fn new(handled: u64) -> AxResult<Self> {
+        if handled & !ACCESS_FS_ALL != 0 {
+            return Err(AxError::InvalidInput);
+        }
+        if handled == 0 {
+            return Err(AxError::Other(LinuxError::ENOMSG));
+        }
+        Ok(Self {
+            handled,
+            rules: Mutex::new(BTreeMap::new()),
+        })
+    }
+
+    /// Allows the access rights `access` beneath `path`, which is a
+    /// directory if `is_dir` is set.
+    pub fn add_rule(&self, path: String, access: u64, is_dir: bool) -> AxResult<()> {
+        if access == 0 {
+            return Err(AxError::Other(LinuxError::ENOMSG));
+        }
+        if access & !self.handled != 0 || (!is_dir && access & !ACCESS_FILE != 0) {
+            return Err(AxError::InvalidInput);
+        }
+        *self.rules.lock().entry(path).or_default() |= access;
+        Ok(())
+    }
+}
+
+/// The rulesets a process is restricted with.
+pub struct Domain {
+    layers: Vec<Arc<Layer>>,
+}
+
+impl Domain {
+    /// Returns the domain restricted further with `ruleset`. Later rules
+    /// added to the ruleset don't apply to it.
+    pub fn restrict(parent: Option<&Domain>, ruleset: &Ruleset) -> AxResult<Arc<Self>> {
+        let mut layers = parent.map_or_else(Vec::new, |it| it.layers.clone());
+        if layers.len() >= MAX_LAYERS {
+            return Err(AxError::Other(LinuxError::E2BIG));
+        }
+        layers.push(Arc::new(Layer {
+            handled: ruleset.handled,
+            rules: ruleset.rules.lock().clone(),
+        }));
+        Ok(Arc::new(Self { layers }))
+    }
+
+    /// Fails with `EACCES` unless every ruleset allows the access rights
+    /// `access` on the absolute path `path`.
+    pub fn check(&self, path: &str, access: u64) -> AxResult<()> {
+        if self.layers.iter().all(|layer| layer.allows(path, access)) {
+            Ok(())
+        } else {
+            Err(AxError::PermissionDenied)
+        }
+    }
+}
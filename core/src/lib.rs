@@ -10,11 +10,27 @@ extern crate alloc;
 #[macro_use]
 extern crate axlog;
 
+pub mod cgroup;
 pub mod config;
+pub mod cpufreq;
+pub mod cred;
 pub mod futex;
+pub mod hotplug;
+pub mod hwrng;
+pub mod keys;
+pub mod kprobe;
+pub mod ksym;
+pub mod landlock;
 pub mod mm;
+pub mod psi;
 pub mod resources;
+pub mod sched;
+pub mod schedstat;
 pub mod shm;
 pub mod task;
+pub mod thermal;
 pub mod time;
+pub mod trace;
+pub mod uprobe;
+pub mod verity;
 pub mod vfs;
@@ -10,7 +10,7 @@ use core::{
 };
 
 use axerrno::{AxError, AxResult};
-use axfs_ng::{CachedFile, FS_CONTEXT, FileBackend};
+use axfs_ng::{CachedFile, FS_CONTEXT, FileBackend, FileFlags};
 use axfs_ng_vfs::Location;
 use axhal::{
     asm::user_copy,
@@ -20,14 +20,21 @@ use axhal::{
 use axmm::{AddrSpace, backend::Backend};
 use axsync::Mutex;
 use extern_trait::extern_trait;
-use kernel_elf_parser::{AuxEntry, ELFHeaders, ELFHeadersBuilder, ELFParser, app_stack_region};
+use kernel_elf_parser::{
+    AuxEntry, AuxType, ELFHeaders, ELFHeadersBuilder, ELFParser, app_stack_region,
+};
 use kernel_guard::IrqSave;
-use memory_addr::{MemoryAddr, PAGE_SIZE_4K, VirtAddr};
+use memory_addr::{MemoryAddr, PAGE_SIZE_4K, VirtAddr, VirtAddrRange};
 use ouroboros::self_referencing;
 use starry_vm::{VmError, VmIo, VmResult};
 use uluru::LRUCache;
 
-use crate::config::{USER_SPACE_BASE, USER_SPACE_SIZE};
+use crate::{
+    config::{USER_SPACE_BASE, USER_SPACE_SIZE},
+    cred::Credentials,
+    uprobe::{self, FileId, FileMapping},
+    verity,
+};
 
 /// Creates a new empty user address space.
 pub fn new_user_aspace_empty() -> AxResult<AddrSpace> {
@@ -81,17 +88,20 @@ fn mapping_flags(flags: xmas_elf::program::Flags) -> MappingFlags {
 ///
 /// # Arguments
 /// - `uspace`: The address space of the user app.
+/// - `file_maps`: The file mappings of the user app, to add the segments to.
 /// - `elf`: The elf file.
 ///
 /// # Returns
 /// - The entry point of the user app.
 fn map_elf<'a>(
     uspace: &mut AddrSpace,
+    file_maps: &mut RangeMap<FileMapping>,
     base: usize,
     entry: &'a ElfCacheEntry,
 ) -> AxResult<ELFParser<'a>> {
     let elf_parser = ELFParser::new(entry.borrow_elf(), base).map_err(|_| AxError::InvalidData)?;
     let cache = entry.borrow_cache();
+    let file = FileId::of(cache.location())?;
 
     for ph in elf_parser
         .headers()
@@ -130,6 +140,11 @@ fn map_elf<'a>(
             backend,
         )?;
 
+        let range = VirtAddrRange::from_start_size(seg_start.align_down_4k(), seg_align_size);
+        let mapping = FileMapping::new(file, seg_start.align_down_4k(), ph.offset - seg_pad as u64);
+        file_maps.insert(range, mapping);
+        uprobe::on_map(uspace, range, mapping);
+
         // TDOO: flush the I-cache
     }
 
@@ -184,8 +199,18 @@ impl ElfLoader {
         Self(LRUCache::new())
     }
 
-    fn load(&mut self, uspace: &mut AddrSpace, path: &str) -> AxResult<LoadResult> {
+    fn load(
+        &mut self,
+        uspace: &mut AddrSpace,
+        file_maps: &mut RangeMap<FileMapping>,
+        path: &str,
+        check_exec: &dyn Fn(&Location) -> AxResult<()>,
+    ) -> AxResult<LoadResult> {
         let loc = FS_CONTEXT.lock().resolve(path)?;
+        check_exec(&loc)?;
+        // The pages of the program are mapped without being read, so files
+        // with fs-verity are verified as a whole
+        verity::verify_all(&loc)?;
 
         if !self.0.touch(|e| e.borrow_cache().location().ptr_eq(&loc)) {
             match ElfCacheEntry::load(loc)? {
@@ -199,6 +224,7 @@ impl ElfLoader {
         }
 
         uspace.clear();
+        file_maps.clear();
         map_trampoline(uspace)?;
 
         let entry = self.0.front().unwrap();
@@ -225,6 +251,7 @@ impl ElfLoader {
 
         let (elf, ldso) = if let Some(ldso) = ldso {
             let loc = FS_CONTEXT.lock().resolve(ldso)?;
+            check_exec(&loc)?;
             if !self.0.touch(|e| e.borrow_cache().location().ptr_eq(&loc)) {
                 let e = ElfCacheEntry::load(loc)?.map_err(|_| AxError::InvalidInput)?;
                 self.0.insert(e);
@@ -238,9 +265,9 @@ impl ElfLoader {
             (entry, None)
         };
 
-        let elf = map_elf(uspace, crate::config::USER_SPACE_BASE, elf)?;
+        let elf = map_elf(uspace, file_maps, crate::config::USER_SPACE_BASE, elf)?;
         let ldso = ldso
-            .map(|elf| map_elf(uspace, crate::config::USER_INTERP_BASE, elf))
+            .map(|elf| map_elf(uspace, file_maps, crate::config::USER_INTERP_BASE, elf))
             .transpose()?;
 
         let entry = VirtAddr::from_usize(
@@ -268,18 +295,27 @@ pub fn clear_elf_cache() {
 ///
 /// # Arguments
 /// - `uspace`: The address space of the user app.
+/// - `file_maps`: The file mappings of the user app, replaced by those of the
+///   loaded ELF files.
 /// - `args`: The arguments of the user app. The first argument is the path of
 ///   the user app.
 /// - `envs`: The environment variables of the user app.
+/// - `check_exec`: Checks whether a file may be executed. It's called for the
+///   user app, the interpreters of scripts and the dynamic linker.
+/// - `cred`: The credentials the user app runs with, passed in its auxiliary
+///   vector. `AT_SECURE` is set if its effective IDs differ from its real ones.
 ///
 /// # Returns
 /// - The entry point of the user app.
 /// - The stack pointer of the user app.
 pub fn load_user_app(
     uspace: &mut AddrSpace,
+    file_maps: &mut RangeMap<FileMapping>,
     path: Option<&str>,
     args: &[String],
     envs: &[String],
+    check_exec: &dyn Fn(&Location) -> AxResult<()>,
+    cred: &Credentials,
 ) -> AxResult<(VirtAddr, VirtAddr)> {
     let path = path
         .or_else(|| args.first().map(String::as_str))
@@ -290,10 +326,14 @@ pub fn load_user_app(
         let new_args: Vec<String> = iter::once("/bin/sh".to_owned())
             .chain(args.iter().cloned())
             .collect();
-        return load_user_app(uspace, None, &new_args, envs);
+        return load_user_app(uspace, file_maps, None, &new_args, envs, check_exec, cred);
     }
 
-    let (entry, auxv) = match { ELF_LOADER.lock().load(uspace, path)? } {
+    let (entry, auxv) = match {
+        ELF_LOADER
+            .lock()
+            .load(uspace, file_maps, path, check_exec)?
+    } {
         Ok((entry, auxv)) => (entry, auxv),
         Err(data) => {
             if data.starts_with(b"#!") {
@@ -308,12 +348,24 @@ pub fn load_user_app(
                     .chain(iter::once(path.to_owned()))
                     .chain(args.iter().skip(1).cloned())
                     .collect();
-                return load_user_app(uspace, None, &new_args, envs);
+                return load_user_app(uspace, file_maps, None, &new_args, envs, check_exec, cred);
             }
             return Err(AxError::InvalidExecutable);
         }
     };
 
+    let secure = cred.uid.effective != cred.uid.real || cred.gid.effective != cred.gid.real;
+    let auxv = [
+        AuxEntry::new(AuxType::UID, cred.uid.real as usize),
+        AuxEntry::new(AuxType::EUID, cred.uid.effective as usize),
+        AuxEntry::new(AuxType::GID, cred.gid.real as usize),
+        AuxEntry::new(AuxType::EGID, cred.gid.effective as usize),
+        AuxEntry::new(AuxType::SECURE, secure as usize),
+    ]
+    .into_iter()
+    .chain(auxv)
+    .collect::<Vec<_>>();
+
     let ustack_top = VirtAddr::from_usize(crate::config::USER_STACK_TOP);
     let ustack_size = crate::config::USER_STACK_SIZE;
     let ustack_start = ustack_top - ustack_size;
@@ -410,3 +462,413 @@ unsafe impl VmIo for Vm {
         }
     }
 }
+
+/// Attributes of address ranges that the address space doesn't track, like
+/// the NUMA home node.
+#[derive(Clone, Default)]
+pub struct RangeMap<T>(Vec<(VirtAddrRange, T)>);
+
+impl<T: Clone> RangeMap<T> {
+    /// Creates an empty map.
+    pub const fn new() -> Self {
+        Self(Vec::new())
+    }
+
+    /// Returns the value of the range containing `addr`.
+    pub fn get(&self, addr: VirtAddr) -> Option<T> {
+        self.find(addr).map(|(_, value)| value)
+    }
+
+    /// Returns the range containing `addr`, with its value.
+    pub fn find(&self, addr: VirtAddr) -> Option<(VirtAddrRange, T)> {
+        self.0
+            .iter()
+            .find(|(range, _)| range.contains(addr))
+            .cloned()
+    }
+
+    /// Sets the value of `range`, trimming the ranges it overlaps.
+    pub fn insert(&mut self, range: VirtAddrRange, value: T) {
+        self.remove(range);
+        self.0.push((range, value));
+    }
+
+    /// Removes `range`, trimming the ranges it overlaps.
+    pub fn remove(&mut self, range: VirtAddrRange) {
+        let mut trimmed = Vec::with_capacity(self.0.len() + 1);
+        for (old, old_value) in self.0.drain(..) {
+            if !old.overlaps(range) {
+                trimmed.push((old, old_value));
+                continue;
+            }
+            if old.start < range.start {
+                trimmed.push((
+                    VirtAddrRange::new(old.start, range.start),
+                    old_value.clone(),
+                ));
+            }
+            if old.end > range.end {
+                trimmed.push((VirtAddrRange::new(range.end, old.end), old_value));
+            }
+        }
+        self.0 = trimmed;
+    }
+
+    /// Returns whether the ranges cover all of `range`.
+    pub fn covers(&self, range: VirtAddrRange) -> bool {
+        let covered: usize = self
+            .overlapping(range)
+            .iter()
+            .map(|(range, _)| range.size())
+            .sum();
+        covered == range.size()
+    }
+
+    /// Returns the total size of the ranges.
+    pub fn total_size(&self) -> usize {
+        self.0.iter().map(|(range, _)| range.size()).sum()
+    }
+
+    /// Returns the ranges overlapping `range`, clipped to it, with their
+    /// values.
+    pub fn overlapping(&self, range: VirtAddrRange) -> Vec<(VirtAddrRange, T)> {
+        self.0
+            .iter()
+            .filter(|(old, _)| old.overlaps(range))
+            .map(|(old, value)| {
+                let start = old.start.max(range.start);
+                let end = old.end.min(range.end);
+                (VirtAddrRange::new(start, end), value.clone())
+            })
+            .collect()
+    }
+
+    /// Removes all the ranges.
+    pub fn clear(&mut self) {
+        self.0.clear();
+    }
+
+    /// Returns an iterator over the ranges and their values.
+    pub fn iter(&self) -> impl Iterator<Item = &(VirtAddrRange, T)> {
+        self.0.iter()
+    }
+}
+
+/// A shared mapping of a file through its page cache, mapping the file
+/// offset `addr - base` at each address `addr` of its range.
+#[derive(Clone)]
+pub struct SharedFileMapping {
+    /// The page cache of the file.
+    pub cache: CachedFile,
+    /// The flags of the mapped file.
+    pub flags: FileFlags,
+    /// The address where offset 0 of the file would be mapped.
+    pub base: usize,
+}
+
+/// The number of memory protection keys, like on x86_64.
+pub const PKEY_COUNT: usize = 16;
+
+/// Memory protection keys of a process, set with `pkey_alloc` and
+/// `pkey_mprotect`.
+///
+/// Without hardware keys, the access rights of a key are applied to the page
+/// table permissions of the ranges it protects.
+#[derive(Clone)]
+pub struct ProtectionKeys {
+    /// The access rights (`PKEY_DISABLE_*`) of the allocated keys. Key 0 is
+    /// the default key and always allocated.
+    rights: [Option<u32>; PKEY_COUNT],
+    /// The key of each range, if not the default one.
+    pub ranges: RangeMap<u32>,
+}
+
+impl Default for ProtectionKeys {
+    fn default() -> Self {
+        let mut rights = [None; PKEY_COUNT];
+        rights[0] = Some(0);
+        Self {
+            rights,
+            ranges: RangeMap::new(),
+        }
+    }
+}
+
+impl ProtectionKeys {
+    /// Allocates a key with the access rights `rights`.
+    pub fn alloc(&mut self, rights: u32) -> Option<u32> {
+        let (key, slot) = self
+            .rights
+            .iter_mut()
+            .enumerate()
+            .find(|(_, slot)| slot.is_none())?;
+        *slot = Some(rights);
+        Some(key as u32)
+    }
+
+    /// Frees `key`, returning whether it was allocated.
+    pub fn free(&mut self, key: u32) -> bool {
+        match self.rights.get_mut(key as usize) {
+            Some(slot @ Some(_)) if key != 0 => {
+                *slot = None;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns the access rights of `key`, if allocated.
+    pub fn rights(&self, key: u32) -> Option<u32> {
+        self.rights.get(key as usize).copied().flatten()
+    }
+}
+
+/// Maps the shared anonymous mappings of `parent` to the same pages in
+/// `child`, its copy made by `fork`, so that writes from either process are
+/// seen by the other. Private mappings are left copy-on-write.
+pub fn share_mappings(parent: &AddrSpace, child: &mut AddrSpace) -> AxResult<()> {
+    let shared = parent
+        .areas()
+        .filter(|area| matches!(area.backend(), Backend::Shared(_)))
+        .map(|area| {
+            (
+                area.start(),
+                area.size(),
+                area.flags(),
+                area.backend().clone(),
+            )
+        })
+        .collect::<Vec<_>>();
+    for (start, size, flags, backend) in shared {
+        child.unmap(start, size)?;
+        child.map(start, size, flags, false, backend)?;
+    }
+    Ok(())
+}
+
+/// Drops the pages of shared file mappings in `range` from the page table,
+/// as `MADV_PAGEOUT` does. Returns the number of bytes dropped.
+///
+/// The pages stay in the page cache, and are faulted in again on access.
+/// Without swap, anonymous and copied-on-write pages can't be paged out.
+pub fn page_out(aspace: &mut AddrSpace, range: VirtAddrRange) -> AxResult<usize> {
+    let areas = aspace
+        .areas()
+        .filter(|area| area.start() < range.end && area.end() > range.start)
+        .filter(|area| matches!(area.backend(), Backend::File(_)))
+        .map(|area| {
+            let start = area.start().max(range.start);
+            let end = area.end().min(range.end);
+            (start, end - start, area.flags(), area.backend().clone())
+        })
+        .collect::<Vec<_>>();
+    let mut dropped = 0;
+    for (start, size, flags, backend) in areas {
+        aspace.unmap(start, size)?;
+        aspace.map(start, size, flags, false, backend)?;
+        dropped += size;
+    }
+    Ok(dropped)
+}
+
+/// Private pages freed with `MADV_FREE`, which may be reclaimed until they
+/// are written again.
+///
+/// There is no dirty bit tracking, so freed pages are mapped read-only to
+/// catch the first write, which cancels the free. The map holds the flags
+/// the pages were mapped with before.
+#[derive(Clone, Default)]
+pub struct LazyFree {
+    /// The freed ranges, with their original mapping flags.
+    pub ranges: RangeMap<MappingFlags>,
+}
+
+impl LazyFree {
+    /// Marks the private pages of `range` as freeable. Other mappings in the
+    /// range fail with `EINVAL`.
+    pub fn free(&mut self, aspace: &mut AddrSpace, range: VirtAddrRange) -> AxResult<()> {
+        if aspace
+            .areas()
+            .filter(|area| area.start() < range.end && area.end() > range.start)
+            .any(|area| !matches!(area.backend(), Backend::Cow(_)))
+        {
+            return Err(AxError::InvalidInput);
+        }
+
+        // Pages freed again keep their original flags
+        self.restore(aspace, range)?;
+        let areas = aspace
+            .areas()
+            .filter(|area| area.start() < range.end && area.end() > range.start)
+            .map(|area| {
+                let start = area.start().max(range.start);
+                let end = area.end().min(range.end);
+                (VirtAddrRange::new(start, end), area.flags())
+            })
+            .collect::<Vec<_>>();
+        for (range, flags) in areas {
+            if flags.contains(MappingFlags::WRITE) {
+                aspace.protect(range.start, range.size(), flags - MappingFlags::WRITE)?;
+            }
+            self.ranges.insert(range, flags);
+        }
+        Ok(())
+    }
+
+    /// Cancels the free of the pages overlapping `range`, mapping them with
+    /// their original flags again.
+    pub fn restore(&mut self, aspace: &mut AddrSpace, range: VirtAddrRange) -> AxResult<()> {
+        for (range, flags) in self.ranges.overlapping(range) {
+            aspace.protect(range.start, range.size(), flags)?;
+            self.ranges.remove(range);
+        }
+        Ok(())
+    }
+
+    /// Handles a write fault at `vaddr`, returning whether it hit a freed
+    /// page. The whole freed range is kept, as writes aren't tracked by page.
+    pub fn handle_write_fault(&mut self, aspace: &mut AddrSpace, vaddr: VirtAddr) -> bool {
+        let Some((range, _)) = self.ranges.find(vaddr) else {
+            return false;
+        };
+        self.restore(aspace, range).is_ok()
+    }
+
+    /// Reclaims the freed pages overlapping `range`, which read as zero
+    /// afterwards. Returns the number of bytes reclaimed.
+    pub fn reclaim(&mut self, aspace: &mut AddrSpace, range: VirtAddrRange) -> AxResult<usize> {
+        let mut reclaimed = 0;
+        for (range, flags) in self.ranges.overlapping(range) {
+            aspace.unmap(range.start, range.size())?;
+            aspace.map(
+                range.start,
+                range.size(),
+                flags,
+                false,
+                Backend::new_alloc(range.start, PageSize::Size4K),
+            )?;
+            self.ranges.remove(range);
+            reclaimed += range.size();
+        }
+        Ok(reclaimed)
+    }
+}
+
+/// The size of transparent huge pages.
+const HUGE_PAGE_SIZE: usize = PageSize::Size2M as usize;
+
+/// Ranges backed by transparent huge pages after `MADV_HUGEPAGE`, each one
+/// huge page large.
+#[derive(Clone, Default)]
+pub struct HugePages {
+    /// The huge pages.
+    pub ranges: RangeMap<()>,
+}
+
+impl HugePages {
+    /// Backs the 2 MiB aligned chunks of `range` by huge pages, so that they
+    /// are faulted in as such.
+    ///
+    /// Only chunks of anonymous private mappings (in `anon`) that have no
+    /// resident pages yet are promoted; others keep their pages.
+    pub fn promote(
+        &mut self,
+        aspace: &mut AddrSpace,
+        anon: &RangeMap<()>,
+        range: VirtAddrRange,
+    ) -> AxResult<()> {
+        let mut start = range.start.align_up(HUGE_PAGE_SIZE);
+        while start + HUGE_PAGE_SIZE <= range.end {
+            let chunk = VirtAddrRange::from_start_size(start, HUGE_PAGE_SIZE);
+            start += HUGE_PAGE_SIZE;
+            if self.ranges.get(chunk.start).is_some() || !anon.covers(chunk) {
+                continue;
+            }
+            let Some(area) = aspace.find_area(chunk.start) else {
+                continue;
+            };
+            if area.end() < chunk.end || !matches!(area.backend(), Backend::Cow(_)) {
+                continue;
+            }
+            let flags = area.flags();
+            let resident = (0..HUGE_PAGE_SIZE / PAGE_SIZE_4K).any(|i| {
+                aspace
+                    .page_table()
+                    .query(chunk.start + i * PAGE_SIZE_4K)
+                    .is_ok()
+            });
+            if resident {
+                continue;
+            }
+            aspace.unmap(chunk.start, HUGE_PAGE_SIZE)?;
+            aspace.map(
+                chunk.start,
+                HUGE_PAGE_SIZE,
+                flags,
+                false,
+                Backend::new_alloc(chunk.start, PageSize::Size2M),
+            )?;
+            self.ranges.insert(chunk, ());
+        }
+        Ok(())
+    }
+
+    /// Splits the huge pages overlapping `range` into 4 KiB pages, keeping
+    /// their contents.
+    pub fn demote(&mut self, aspace: &mut AddrSpace, range: VirtAddrRange) -> AxResult<()> {
+        for (chunk, _) in self.ranges.overlapping(range) {
+            let chunk = VirtAddrRange::from_start_size(
+                chunk.start.align_down(HUGE_PAGE_SIZE),
+                HUGE_PAGE_SIZE,
+            );
+            self.demote_chunk(aspace, chunk)?;
+        }
+        Ok(())
+    }
+
+    /// Splits the huge pages that `range` only partially covers, before that
+    /// part is unmapped or changed.
+    pub fn split(&mut self, aspace: &mut AddrSpace, range: VirtAddrRange) -> AxResult<()> {
+        for edge in [range.start, range.end] {
+            if !edge.is_aligned(HUGE_PAGE_SIZE) {
+                self.demote(aspace, VirtAddrRange::from_start_size(edge, 1))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn demote_chunk(&mut self, aspace: &mut AddrSpace, chunk: VirtAddrRange) -> AxResult<()> {
+        let flags = aspace
+            .find_area(chunk.start)
+            .ok_or(AxError::NoMemory)?
+            .flags();
+        let data = if aspace.page_table().query(chunk.start).is_ok() {
+            let mut data = vec![0; HUGE_PAGE_SIZE];
+            aspace.read(chunk.start, &mut data)?;
+            Some(data)
+        } else {
+            None
+        };
+
+        aspace.unmap(chunk.start, HUGE_PAGE_SIZE)?;
+        let backend = Backend::new_alloc(chunk.start, PageSize::Size4K);
+        if let Some(data) = data {
+            // The pages are populated writable to copy the contents back
+            let writable = flags | MappingFlags::READ | MappingFlags::WRITE;
+            aspace.map(chunk.start, HUGE_PAGE_SIZE, writable, false, backend)?;
+            aspace.populate_area(
+                chunk.start,
+                HUGE_PAGE_SIZE,
+                MappingFlags::READ | MappingFlags::WRITE,
+            )?;
+            aspace.write(chunk.start, &data)?;
+            if writable != flags {
+                aspace.protect(chunk.start, HUGE_PAGE_SIZE, flags)?;
+            }
+        } else {
+            aspace.map(chunk.start, HUGE_PAGE_SIZE, flags, false, backend)?;
+        }
+        self.ranges.remove(chunk);
+        Ok(())
+    }
+}
@@ -0,0 +1,243 @@
+//! Pressure stall information, as reported by `/proc/pressure/`.
+//!
+//! A resource is under some pressure while at least one thread is stalled on
+//! it, and under full pressure while every runnable thread is stalled on it.
+//! Only user threads are accounted, and they stall:
+//!
+//! - on the CPU, while runnable after being switched out. Waits after wakeups
+//!   are not seen, as the scheduler doesn't report them.
+//! - on memory, while handling page faults with little free memory left, where
+//!   Linux would reclaim memory.
+//! - on I/O, while writing back dirty data, either when throttled or for
+//!   `fsync`.
+//!
+//! Full pressure is only reevaluated when a stall starts or ends. Like
+//! Linux, full CPU pressure is always zero, and the averages are updated
+//! every 2 seconds, lazily when read.
+
+use alloc::{format, string::String};
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use axhal::time::monotonic_time_nanos;
+use axtask::{TaskState, current};
+use kspin::SpinNoIrq;
+
+use crate::task::{AsThread, tasks};
+
+/// A resource threads can stall on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resource {
+    /// The CPU.
+    Cpu    = 0,
+    /// Memory.
+    Memory = 1,
+    /// I/O.
+    Io     = 2,
+}
+
+impl Resource {
+    fn bit(self) -> u32 {
+        1 << self as u32
+    }
+}
+
+const NANOS_PER_MICRO: u64 = 1_000;
+/// The period the averages are updated at.
+const PERIOD_NS: u64 = 2_000_000_000;
+/// Missed periods after which the averages have decayed to nothing.
+const MAX_MISSED_PERIODS: u64 = 1024;
+
+/// Fixed-point 1.0 of the averages.
+const FIXED_1: u64 = 1 << 11;
+/// The decay of the 10s, 60s and 300s averages per period, which is
+/// `FIXED_1 / exp(period / window)`.
+const EXP: [u64; 3] = [1677, 1981, 2034];
+
+#[derive(Clone, Copy)]
+struct Stat {
+    /// The total stall time, in nanoseconds.
+    total: u64,
+    /// [`Self::total`] when the averages were last updated.
+    avg_total: u64,
+    /// The fixed-point percentages of the stall time over 10s, 60s and 300s.
+    avgs: [u64; 3],
+}
+
+impl Stat {
+    const fn new() -> Self {
+        Self {
+            total: 0,
+            avg_total: 0,
+            avgs: [0; 3],
+        }
+    }
+
+    fn update_avgs(&mut self, elapsed: u64, periods: u64) {
+        let delta = self.total - self.avg_total;
+        self.avg_total = self.total;
+        // The stall time of missed periods is spread over them
+        let pct = (delta * 100 * FIXED_1 / elapsed).min(100 * FIXED_1);
+        for _ in 0..periods.min(MAX_MISSED_PERIODS) {
+            for (avg, exp) in self.avgs.iter_mut().zip(EXP) {
+                *avg = (*avg * exp + pct * (FIXED_1 - exp)) / FIXED_1;
+            }
+        }
+    }
+
+    fn format(&self, kind: &str) -> String {
+        let [avg10, avg60, avg300] = self.avgs.map(|avg| {
+            let frac = (avg % FIXED_1) * 100 / FIXED_1;
+            format!("{}.{frac:02}", avg / FIXED_1)
+        });
+        format!(
+            "{kind} avg10={avg10} avg60={avg60} avg300={avg300} total={}\n",
+            self.total / NANOS_PER_MICRO
+        )
+    }
+}
+
+struct Pressure {
+    /// The number of stalled threads.
+    nr_stalled: usize,
+    full: bool,
+    /// When the totals were last accrued.
+    since: u64,
+    /// When the averages were last updated.
+    avg_since: u64,
+    some: Stat,
+    full_stat: Stat,
+}
+
+impl Pressure {
+    const fn new() -> Self {
+        Self {
+            nr_stalled: 0,
+            full: false,
+            since: 0,
+            avg_since: 0,
+            some: Stat::new(),
+            full_stat: Stat::new(),
+        }
+    }
+
+    /// Adds the time since the last change to the totals.
+    fn accrue(&mut self, now: u64) {
+        let delta = now.saturating_sub(self.since);
+        self.since = now;
+        if self.nr_stalled > 0 {
+            self.some.total += delta;
+        }
+        if self.full {
+            self.full_stat.total += delta;
+        }
+    }
+
+    fn update_avgs(&mut self, now: u64) {
+        let elapsed = now.saturating_sub(self.avg_since);
+        if elapsed < PERIOD_NS {
+            return;
+        }
+        let periods = elapsed / PERIOD_NS;
+        self.some.update_avgs(elapsed, periods);
+        self.full_stat.update_avgs(elapsed, periods);
+        self.avg_since += periods * PERIOD_NS;
+    }
+}
+
+static PRESSURE: [SpinNoIrq<Pressure>; 3] = [const { SpinNoIrq::new(Pressure::new()) }; 3];
+
+/// Returns whether a runnable user thread is not stalled on `resource`.
+fn has_productive_thread(resource: Resource) -> bool {
+    tasks().iter().any(|task| {
+        matches!(task.state(), TaskState::Running | TaskState::Ready)
+            && task
+                .try_as_thread()
+                .is_some_and(|thr| thr.psi.stalls.load(Ordering::Acquire) & resource.bit() == 0)
+    })
+}
+
+fn change_stalled(resource: Resource, stalled: bool) {
+    // The CPU is never fully under pressure, and the threads are not walked
+    // during context switches.
+    let full = resource != Resource::Cpu && !has_productive_thread(resource);
+    let now = monotonic_time_nanos();
+    let mut pressure = PRESSURE[resource as usize].lock();
+    pressure.accrue(now);
+    if stalled {
+        pressure.nr_stalled += 1;
+    } else {
+        pressure.nr_stalled -= 1;
+    }
+    pressure.full = pressure.nr_stalled > 0 && full;
+}
+
+/// The stall state of a thread.
+pub struct ThreadPsi {
+    /// The resources the thread is stalled on, as a bitmask of
+    /// [`Resource`]s.
+    stalls: AtomicU32,
+}
+
+impl ThreadPsi {
+    pub(crate) const fn new() -> Self {
+        Self {
+            stalls: AtomicU32::new(0),
+        }
+    }
+
+    fn start(&self, resource: Resource) -> bool {
+        let first = self.stalls.fetch_or(resource.bit(), Ordering::AcqRel) & resource.bit() == 0;
+        if first {
+            change_stalled(resource, true);
+        }
+        first
+    }
+
+    fn end(&self, resource: Resource) {
+        if self.stalls.fetch_and(!resource.bit(), Ordering::AcqRel) & resource.bit() != 0 {
+            change_stalled(resource, false);
+        }
+    }
+
+    /// Called when the thread is switched in.
+    pub(crate) fn enter(&self) {
+        self.end(Resource::Cpu);
+    }
+
+    /// Called when the thread is switched out, while still `runnable` or
+    /// not.
+    pub(crate) fn leave(&self, runnable: bool) {
+        if runnable {
+            self.start(Resource::Cpu);
+        }
+    }
+}
+
+/// A stall of the current thread, which ends when dropped.
+pub struct Stall(Resource);
+
+impl Drop for Stall {
+    fn drop(&mut self) {
+        if let Some(thr) = current().try_as_thread() {
+            thr.psi.end(self.0);
+        }
+    }
+}
+
+/// Starts a stall of the current thread on `resource`. Returns `None` for
+/// kernel tasks and for threads already stalled on it.
+pub fn stall(resource: Resource) -> Option<Stall> {
+    let curr = current();
+    let thr = curr.try_as_thread()?;
+    thr.psi.start(resource).then_some(Stall(resource))
+}
+
+/// Returns the pressure stall information of `resource`, in the format of
+/// `/proc/pressure/`.
+pub fn report(resource: Resource) -> String {
+    let now = monotonic_time_nanos();
+    let mut pressure = PRESSURE[resource as usize].lock();
+    pressure.accrue(now);
+    pressure.update_avgs(now);
+    pressure.some.format("some") + &pressure.full_stat.format("full")
+}
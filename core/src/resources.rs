@@ -2,13 +2,21 @@
 
 use core::ops::{Index, IndexMut};
 
-use linux_raw_sys::general::{RLIM_NLIMITS, RLIMIT_NOFILE, RLIMIT_STACK};
+use linux_raw_sys::general::{
+    RLIM_NLIMITS, RLIMIT_MEMLOCK, RLIMIT_NOFILE, RLIMIT_NPROC, RLIMIT_STACK,
+};
 
 /// The maximum number of open files
 pub const AX_FILE_LIMIT: usize = 1024;
 
+/// The default limit of memory locked by a process, like Linux (8 MiB)
+pub const DEFAULT_MEMLOCK_LIMIT: u64 = 8 << 20;
+
+/// An unlimited resource (`RLIM_INFINITY`)
+pub const RLIM_INFINITY: u64 = u64::MAX;
+
 /// The limit for a specific resource
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct Rlimit {
     /// The current limit for the resource (soft)
     pub current: u64,
@@ -36,6 +44,7 @@ impl From<u64> for Rlimit {
 }
 
 /// Process resource limits
+#[derive(Clone)]
 pub struct Rlimits([Rlimit; RLIM_NLIMITS as usize]);
 
 impl Default for Rlimits {
@@ -43,6 +52,8 @@ impl Default for Rlimits {
         let mut result = Self(Default::default());
         result[RLIMIT_STACK] = (crate::config::USER_STACK_SIZE as u64).into();
         result[RLIMIT_NOFILE] = (AX_FILE_LIMIT as u64).into();
+        result[RLIMIT_NPROC] = RLIM_INFINITY.into();
+        result[RLIMIT_MEMLOCK] = DEFAULT_MEMLOCK_LIMIT.into();
         result
     }
 }
@@ -0,0 +1,139 @@
+//! Scheduling policies and deadline bandwidth admission.
+//!
+//! axtask schedules every task round-robin, so policies are bookkept here for
+//! userspace to query, and `SCHED_DEADLINE` threads are admission-controlled
+//! like Linux does: the sum of their bandwidths (`runtime / period`) may not
+//! exceed the real-time share of all CPUs.
+//!
+//! Nice values are approximated on top of round-robin: threads weighing less
+//! than nice 0 give up the CPU once they have run for their share of a time
+//! slice.
+
+use axconfig::{TICKS_PER_SEC, plat::CPU_NUM};
+use axerrno::{AxError, AxResult};
+use axhal::time::NANOS_PER_SEC;
+use axsync::Mutex;
+use linux_raw_sys::general::{SCHED_BATCH, SCHED_DEADLINE, SCHED_IDLE, SCHED_NORMAL};
+
+/// Fixed-point shift of bandwidths.
+const BW_SHIFT: u32 = 20;
+/// The share of each CPU available to deadline threads, like the default
+/// `sched_rt_runtime_us / sched_rt_period_us` of Linux (95%).
+const BW_PER_CPU: u64 = (95 << BW_SHIFT) / 100;
+
+/// The time slice of round-robin threads: axtask preempts a task after this
+/// many timer ticks.
+pub const RR_TIMESLICE_TICKS: u64 = 5;
+
+/// The weights of nice values from -20 to 19, like `sched_prio_to_weight` in
+/// Linux: each step changes the share of the CPU by about 10%.
+const NICE_TO_WEIGHT: [u64; 40] = [
+    88761, 71755, 56483, 46273, 36291, 29154, 23254, 18705, 14949, 11916, 9548, 7620, 6100, 4904,
+    3906, 3121, 2501, 1991, 1586, 1277, 1024, 820, 655, 526, 423, 335, 272, 215, 172, 137, 110, 87,
+    70, 56, 45, 36, 29, 23, 18, 15,
+];
+/// The weight of nice 0.
+const NICE_0_WEIGHT: u64 = 1024;
+/// The weight of `SCHED_IDLE` threads, below that of nice 19.
+const IDLE_WEIGHT: u64 = 3;
+
+/// The smallest runtime of a deadline thread, in nanoseconds.
+pub const DL_MIN_RUNTIME: u64 = 1 << 10;
+/// The smallest period of a deadline thread (100 µs), in nanoseconds.
+pub const DL_MIN_PERIOD: u64 = 100_000;
+/// The largest period of a deadline thread (4 s), in nanoseconds.
+pub const DL_MAX_PERIOD: u64 = 4_000_000_000;
+
+/// The scheduling policy of a thread.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SchedAttr {
+    /// The policy, one of the `SCHED_*` constants.
+    pub policy: u32,
+    /// Whether children are reset to `SCHED_NORMAL`.
+    pub reset_on_fork: bool,
+    /// The nice value, for `SCHED_NORMAL` and `SCHED_BATCH`.
+    pub nice: i32,
+    /// The static priority, from 1 to 99 for real-time policies and 0
+    /// otherwise.
+    pub priority: u32,
+    /// The runtime of `SCHED_DEADLINE`, in nanoseconds.
+    pub runtime: u64,
+    /// The relative deadline of `SCHED_DEADLINE`, in nanoseconds.
+    pub deadline: u64,
+    /// The period of `SCHED_DEADLINE`, in nanoseconds.
+    pub period: u64,
+}
+
+impl SchedAttr {
+    /// Returns the share of a CPU reserved by the thread, in fixed point.
+    fn bandwidth(&self) -> u64 {
+        if self.policy == SCHED_DEADLINE && self.period != 0 {
+            ((self.runtime as u128) << BW_SHIFT).div_ceil(self.period as u128) as u64
+        } else {
+            0
+        }
+    }
+
+    /// Returns the weight of a `SCHED_NORMAL`, `SCHED_BATCH` or `SCHED_IDLE`
+    /// thread.
+    fn weight(&self) -> Option<u64> {
+        match self.policy {
+            SCHED_NORMAL | SCHED_BATCH => {
+                Some(NICE_TO_WEIGHT[(self.nice.clamp(-20, 19) + 20) as usize])
+            }
+            SCHED_IDLE => Some(IDLE_WEIGHT),
+            _ => None,
+        }
+    }
+
+    /// Returns how long the thread may run after being switched in before
+    /// giving up the CPU, in nanoseconds, or `None` if it runs until it is
+    /// preempted.
+    pub fn timeslice_ns(&self) -> Option<u64> {
+        let weight = self.weight()?;
+        if weight >= NICE_0_WEIGHT {
+            return None;
+        }
+        let full = RR_TIMESLICE_TICKS * NANOS_PER_SEC / TICKS_PER_SEC as u64;
+        Some(full * weight / NICE_0_WEIGHT)
+    }
+
+    /// Returns the policy of a child created by `clone`.
+    ///
+    /// Deadline threads can only fork if their children are reset, as the
+    /// bandwidth can't be split.
+    pub fn for_child(&self) -> AxResult<SchedAttr> {
+        if !self.reset_on_fork {
+            if self.policy == SCHED_DEADLINE {
+                return Err(AxError::WouldBlock);
+            }
+            return Ok(*self);
+        }
+        let mut attr = *self;
+        if !matches!(attr.policy, SCHED_NORMAL | SCHED_BATCH | SCHED_IDLE) {
+            attr = SchedAttr::default();
+        }
+        attr.nice = attr.nice.max(0);
+        attr.reset_on_fork = false;
+        Ok(attr)
+    }
+}
+
+/// The bandwidth reserved by all deadline threads.
+static TOTAL_BW: Mutex<u64> = Mutex::new(0);
+
+/// Replaces the bandwidth reserved by a thread with `old` policy by that of
+/// `new`, failing with `EBUSY` if it would over-commit the CPUs.
+pub(crate) fn update_bandwidth(old: &SchedAttr, new: &SchedAttr) -> AxResult<()> {
+    let (old, new) = (old.bandwidth(), new.bandwidth());
+    if old == new {
+        return Ok(());
+    }
+    let mut total = TOTAL_BW.lock();
+    let updated = *total - old + new;
+    if new > old && updated > BW_PER_CPU * CPU_NUM as u64 {
+        return Err(AxError::ResourceBusy);
+    }
+    *total = updated;
+    Ok(())
+}
@@ -0,0 +1,108 @@
+//! Scheduler statistics, as reported by `/proc/schedstat` and
+//! `/proc/<pid>/schedstat`.
+//!
+//! Only user threads are accounted, as they are switched in and out through
+//! [`TaskExt`](axtask::TaskExt).
+
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use axconfig::plat::CPU_NUM;
+use axhal::{percpu::this_cpu_id, time::monotonic_time_nanos};
+
+/// Scheduler statistics of a CPU.
+pub struct CpuStat {
+    yields: AtomicU64,
+    switches: AtomicU64,
+    run_ns: AtomicU64,
+}
+
+impl CpuStat {
+    const fn new() -> Self {
+        Self {
+            yields: AtomicU64::new(0),
+            switches: AtomicU64::new(0),
+            run_ns: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the number of `sched_yield` calls made on the CPU.
+    pub fn yields(&self) -> u64 {
+        self.yields.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of times a thread was switched in on the CPU.
+    pub fn switches(&self) -> u64 {
+        self.switches.load(Ordering::Relaxed)
+    }
+
+    /// Returns the time threads spent running on the CPU, in nanoseconds.
+    pub fn run_ns(&self) -> u64 {
+        self.run_ns.load(Ordering::Relaxed)
+    }
+}
+
+static CPUS: [CpuStat; CPU_NUM] = [const { CpuStat::new() }; CPU_NUM];
+
+/// Returns the scheduler statistics of `cpu`.
+pub fn cpu(cpu: usize) -> Option<&'static CpuStat> {
+    CPUS.get(cpu)
+}
+
+/// Records a `sched_yield` call on the current CPU.
+pub fn record_yield() {
+    CPUS[this_cpu_id()].yields.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Scheduler statistics of a thread.
+pub struct ThreadStat {
+    run_ns: AtomicU64,
+    timeslices: AtomicU64,
+    entered_ns: AtomicU64,
+    cpu: AtomicUsize,
+}
+
+impl ThreadStat {
+    pub(crate) const fn new() -> Self {
+        Self {
+            run_ns: AtomicU64::new(0),
+            timeslices: AtomicU64::new(0),
+            entered_ns: AtomicU64::new(0),
+            cpu: AtomicUsize::new(0),
+        }
+    }
+
+    /// Called when the thread is switched in.
+    pub(crate) fn enter(&self) {
+        let cpu = this_cpu_id();
+        self.entered_ns
+            .store(monotonic_time_nanos(), Ordering::Relaxed);
+        self.cpu.store(cpu, Ordering::Relaxed);
+        self.timeslices.fetch_add(1, Ordering::Relaxed);
+        CPUS[cpu].switches.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Called when the thread is switched out.
+    pub(crate) fn leave(&self) {
+        let delta = monotonic_time_nanos().saturating_sub(self.entered_ns.load(Ordering::Relaxed));
+        self.run_ns.fetch_add(delta, Ordering::Relaxed);
+        CPUS[self.cpu.load(Ordering::Relaxed)]
+            .run_ns
+            .fetch_add(delta, Ordering::Relaxed);
+    }
+
+    /// Returns the time the thread has been running since it was last
+    /// switched in, in nanoseconds.
+    pub fn running_ns(&self) -> u64 {
+        monotonic_time_nanos().saturating_sub(self.entered_ns.load(Ordering::Relaxed))
+    }
+
+    /// Returns the time the thread spent running, in nanoseconds.
+    pub fn run_ns(&self) -> u64 {
+        self.run_ns.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of times the thread was switched in.
+    pub fn timeslices(&self) -> u64 {
+        self.timeslices.load(Ordering::Relaxed)
+    }
+}
@@ -11,14 +11,14 @@ use alloc::{
 use core::{
     cell::RefCell,
     ops::Deref,
-    sync::atomic::{AtomicBool, AtomicI32, AtomicU32, AtomicUsize, Ordering},
+    sync::atomic::{AtomicBool, AtomicI32, AtomicU16, AtomicU32, AtomicU64, AtomicUsize, Ordering},
 };
 
 use axerrno::{AxError, AxResult};
 use axmm::AddrSpace;
 use axpoll::PollSet;
 use axsync::{Mutex, spin::SpinNoIrq};
-use axtask::{AxTaskRef, TaskExt, TaskInner, WeakAxTaskRef, current};
+use axtask::{AxTaskRef, TaskExt, TaskInner, TaskState, WeakAxTaskRef, current};
 use extern_trait::extern_trait;
 use hashbrown::HashMap;
 use lazy_static::lazy_static;
@@ -33,9 +33,18 @@ use weak_map::WeakMap;
 
 pub use self::stat::TaskStat;
 use crate::{
+    cgroup::{self, Cgroup},
+    cred::Credentials,
     futex::{FutexKey, FutexTable},
+    keys::ProcessKeyrings,
+    landlock::Domain,
+    mm::{HugePages, LazyFree, ProtectionKeys, RangeMap, SharedFileMapping},
+    psi::ThreadPsi,
     resources::Rlimits,
-    time::{TimeManager, TimerState},
+    sched::{self, SchedAttr},
+    schedstat::ThreadStat,
+    time::{self, TimeManager, TimeNamespace, TimerState},
+    uprobe::{FileId, FileMapping},
 };
 
 ///  A wrapper type that assumes the inner type is `Sync`.
@@ -84,10 +93,40 @@ pub struct ThreadInner {
     /// The OOM score adjustment value.
     oom_score_adj: AtomicI32,
 
+    /// The I/O priority, as encoded by `ioprio_set`.
+    ioprio: AtomicU16,
+
+    /// The scheduling policy
+    sched_attr: Mutex<SchedAttr>,
+
+    /// Scheduler statistics
+    pub sched_stat: ThreadStat,
+
+    /// Pressure stall state
+    pub(crate) psi: ThreadPsi,
+
+    /// Where to resume after the instruction of a uprobe run out of line.
+    uprobe_resume: AtomicUsize,
+
+    /// The address of the kernel function the thread is waiting in, or 0.
+    wchan: AtomicUsize,
+
     /// Ready to exit
     exit: AtomicBool,
 }
 
+/// Restores the previous wait channel of a thread when dropped.
+pub struct WaitChannel<'a> {
+    thread: &'a ThreadInner,
+    prev: usize,
+}
+
+impl Drop for WaitChannel<'_> {
+    fn drop(&mut self) {
+        self.thread.wchan.store(self.prev, Ordering::Relaxed);
+    }
+}
+
 impl ThreadInner {
     /// Create a new [`ThreadInner`].
     pub fn new(tid: u32, proc_data: Arc<ProcessData>) -> Self {
@@ -99,10 +138,41 @@ impl ThreadInner {
             rseq_area: AtomicUsize::new(0),
             time: AssumeSync(RefCell::new(TimeManager::new())),
             oom_score_adj: AtomicI32::new(200),
+            ioprio: AtomicU16::new(0),
+            sched_attr: Mutex::new(SchedAttr::default()),
+            sched_stat: ThreadStat::new(),
+            psi: ThreadPsi::new(),
+            uprobe_resume: AtomicUsize::new(0),
+            wchan: AtomicUsize::new(0),
             exit: AtomicBool::new(false),
         }
     }
 
+    /// Takes the address to resume at after the instruction of a uprobe run
+    /// out of line, or 0 if there is none.
+    pub fn take_uprobe_resume(&self) -> usize {
+        self.uprobe_resume.swap(0, Ordering::Relaxed)
+    }
+
+    /// Sets the address to resume at after the instruction of a uprobe run
+    /// out of line.
+    pub fn set_uprobe_resume(&self, addr: usize) {
+        self.uprobe_resume.store(addr, Ordering::Relaxed);
+    }
+
+    /// Returns the address of the kernel function the thread is waiting in,
+    /// or 0 if it isn't waiting.
+    pub fn wchan(&self) -> usize {
+        self.wchan.load(Ordering::Relaxed)
+    }
+
+    /// Records that the thread waits in the kernel function at `addr`, until
+    /// the returned guard is dropped.
+    pub fn wait_in(&self, addr: usize) -> WaitChannel<'_> {
+        let prev = self.wchan.swap(addr, Ordering::Relaxed);
+        WaitChannel { thread: self, prev }
+    }
+
     /// Get the clear child tid field.
     pub fn clear_child_tid(&self) -> usize {
         self.clear_child_tid.load(Ordering::Relaxed)
@@ -145,6 +215,38 @@ impl ThreadInner {
         self.oom_score_adj.store(value, Ordering::SeqCst);
     }
 
+    /// Get the scheduling policy.
+    pub fn sched_attr(&self) -> SchedAttr {
+        *self.sched_attr.lock()
+    }
+
+    /// Set the scheduling policy, reserving CPU bandwidth for
+    /// `SCHED_DEADLINE`.
+    pub fn set_sched_attr(&self, attr: SchedAttr) -> AxResult<()> {
+        let mut current = self.sched_attr.lock();
+        sched::update_bandwidth(&current, &attr)?;
+        *current = attr;
+        Ok(())
+    }
+
+    /// Returns whether the thread has run for its share of the time slice
+    /// since it was switched in, and should give up the CPU.
+    pub fn timeslice_expired(&self) -> bool {
+        self.sched_attr()
+            .timeslice_ns()
+            .is_some_and(|slice| self.sched_stat.running_ns() >= slice)
+    }
+
+    /// Get the I/O priority.
+    pub fn ioprio(&self) -> u16 {
+        self.ioprio.load(Ordering::Relaxed)
+    }
+
+    /// Set the I/O priority.
+    pub fn set_ioprio(&self, ioprio: u16) {
+        self.ioprio.store(ioprio, Ordering::Relaxed);
+    }
+
     /// Check if the thread is ready to exit.
     pub fn pending_exit(&self) -> bool {
         self.exit.load(Ordering::Acquire)
@@ -170,6 +272,8 @@ impl Deref for Thread {
 #[extern_trait]
 unsafe impl TaskExt for Thread {
     fn on_enter(&self) {
+        self.sched_stat.enter();
+        self.psi.enter();
         let scope = self.proc_data.scope.read();
         unsafe { ActiveScope::set(&scope) };
         core::mem::forget(scope);
@@ -178,6 +282,10 @@ unsafe impl TaskExt for Thread {
     fn on_leave(&self) {
         ActiveScope::set_global();
         unsafe { self.proc_data.scope.force_read_decrement() };
+        self.sched_stat.leave();
+        // Preempted threads are still runnable, unlike blocked ones
+        let runnable = matches!(current().state(), TaskState::Running | TaskState::Ready);
+        self.psi.leave(runnable);
     }
 }
 
@@ -205,6 +313,85 @@ impl Thread {
     }
 }
 
+/// Per-process I/O counters, as reported by `/proc/[pid]/io`.
+#[derive(Default)]
+pub struct IoAccounting {
+    /// Bytes read through read syscalls.
+    pub rchar: AtomicU64,
+    /// Bytes written through write syscalls.
+    pub wchar: AtomicU64,
+    /// Number of read syscalls.
+    pub syscr: AtomicU64,
+    /// Number of write syscalls.
+    pub syscw: AtomicU64,
+    /// Bytes read from storage-backed files.
+    ///
+    /// Block device I/O can't be attributed to processes, so unlike Linux
+    /// this is the `rchar` of those files, whether or not the data was
+    /// already in the page cache.
+    pub read_bytes: AtomicU64,
+    /// Bytes written to storage-backed files.
+    ///
+    /// Like `read_bytes`, this is the `wchar` of those files rather than what
+    /// is eventually written back.
+    pub write_bytes: AtomicU64,
+}
+
+impl IoAccounting {
+    /// Accounts a read syscall that transferred `bytes` bytes.
+    pub fn account_read(&self, bytes: usize, storage: bool) {
+        self.syscr.fetch_add(1, Ordering::Relaxed);
+        self.rchar.fetch_add(bytes as u64, Ordering::Relaxed);
+        if storage {
+            self.read_bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+        }
+    }
+
+    /// Accounts a write syscall that transferred `bytes` bytes.
+    pub fn account_write(&self, bytes: usize, storage: bool) {
+        self.syscw.fetch_add(1, Ordering::Relaxed);
+        self.wchar.fetch_add(bytes as u64, Ordering::Relaxed);
+        if storage {
+            self.write_bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Per-process page fault counters, as reported by `getrusage` and
+/// `/proc/[pid]/stat`.
+#[derive(Default)]
+pub struct FaultAccounting {
+    /// Faults handled without reading a file.
+    pub minflt: AtomicU64,
+    /// Faults on pages of a file that were not mapped yet, which may read
+    /// it.
+    pub majflt: AtomicU64,
+    /// Minor faults of exited children and their descendants.
+    pub cminflt: AtomicU64,
+    /// Major faults of exited children and their descendants.
+    pub cmajflt: AtomicU64,
+}
+
+impl FaultAccounting {
+    /// Accounts a handled page fault.
+    pub fn account(&self, major: bool) {
+        if major {
+            self.majflt.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.minflt.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Adds the faults of an exited child, including those of its own
+    /// children, to the counters of the children.
+    pub fn add_child(&self, child: &FaultAccounting) {
+        let minflt = child.minflt.load(Ordering::Relaxed) + child.cminflt.load(Ordering::Relaxed);
+        let majflt = child.majflt.load(Ordering::Relaxed) + child.cmajflt.load(Ordering::Relaxed);
+        self.cminflt.fetch_add(minflt, Ordering::Relaxed);
+        self.cmajflt.fetch_add(majflt, Ordering::Relaxed);
+    }
+}
+
 /// [`Process`]-shared data.
 pub struct ProcessData {
     /// The process.
@@ -225,6 +412,8 @@ pub struct ProcessData {
 
     /// The resource limits
     pub rlim: RwLock<Rlimits>,
+    /// The user and group IDs.
+    pub cred: RwLock<Credentials>,
 
     /// The child exit wait event
     pub child_exit_event: Arc<PollSet>,
@@ -241,6 +430,50 @@ pub struct ProcessData {
 
     /// The default mask for file permissions.
     umask: AtomicU32,
+
+    /// The I/O counters.
+    pub io: IoAccounting,
+
+    /// The page fault counters.
+    pub faults: FaultAccounting,
+
+    /// The preferred NUMA node of address ranges, set with
+    /// `set_mempolicy_home_node`.
+    pub home_nodes: Mutex<RangeMap<u32>>,
+    /// The memory protection keys.
+    pub pkeys: Mutex<ProtectionKeys>,
+    /// The ranges locked into memory with `mlock`.
+    pub mlocked: Mutex<RangeMap<()>>,
+    /// The pages freed with `MADV_FREE`.
+    pub lazy_free: Mutex<LazyFree>,
+    /// The private anonymous mappings created with `mmap`.
+    pub anon_mappings: Mutex<RangeMap<()>>,
+    /// The transparent huge pages.
+    pub huge_pages: Mutex<HugePages>,
+    /// The ranges sealed with `mseal`, which can't be unmapped or changed.
+    pub sealed: Mutex<RangeMap<()>>,
+    /// The mappings of files from `noexec` mounts, which can't be made
+    /// executable.
+    pub noexec_maps: Mutex<RangeMap<()>>,
+    /// The private file mappings, where uprobes are placed.
+    pub file_maps: Mutex<RangeMap<FileMapping>>,
+    /// The shared writable file mappings, whose files are written back while
+    /// they're mapped.
+    pub shared_file_maps: Mutex<RangeMap<FileId>>,
+    /// The shared mappings of files through their page cache, to map them
+    /// elsewhere with `mremap`.
+    pub shared_file_backends: Mutex<RangeMap<SharedFileMapping>>,
+    /// The cgroup of the process.
+    cgroup: RwLock<Arc<Cgroup>>,
+    /// The process and session keyrings.
+    pub keyrings: Mutex<ProcessKeyrings>,
+    /// The Landlock rulesets the process is restricted with.
+    landlock: RwLock<Option<Arc<Domain>>>,
+    /// The time namespace of the process.
+    time_ns: RwLock<Arc<TimeNamespace>>,
+    /// The time namespace that children of the process enter, which differs
+    /// from [`Self::time_ns`] after `unshare(CLONE_NEWTIME)`.
+    time_ns_for_children: RwLock<Arc<TimeNamespace>>,
 }
 
 impl ProcessData {
@@ -263,6 +496,7 @@ impl ProcessData {
             heap_top: AtomicUsize::new(crate::config::USER_HEAP_BASE),
 
             rlim: RwLock::default(),
+            cred: RwLock::default(),
 
             child_exit_event: Arc::default(),
             exit_event: Arc::default(),
@@ -276,6 +510,26 @@ impl ProcessData {
             futex_table: Arc::new(FutexTable::new()),
 
             umask: AtomicU32::new(0o022),
+
+            io: IoAccounting::default(),
+            faults: FaultAccounting::default(),
+
+            home_nodes: Mutex::new(RangeMap::new()),
+            pkeys: Mutex::default(),
+            mlocked: Mutex::new(RangeMap::new()),
+            lazy_free: Mutex::default(),
+            anon_mappings: Mutex::new(RangeMap::new()),
+            huge_pages: Mutex::default(),
+            sealed: Mutex::new(RangeMap::new()),
+            noexec_maps: Mutex::new(RangeMap::new()),
+            file_maps: Mutex::new(RangeMap::new()),
+            shared_file_maps: Mutex::new(RangeMap::new()),
+            shared_file_backends: Mutex::new(RangeMap::new()),
+            cgroup: RwLock::new(cgroup::root().clone()),
+            keyrings: Mutex::default(),
+            landlock: RwLock::new(None),
+            time_ns: RwLock::new(time::root_time_ns().clone()),
+            time_ns_for_children: RwLock::new(time::root_time_ns().clone()),
         })
     }
 
@@ -299,6 +553,49 @@ impl ProcessData {
         self.heap_top.store(top, Ordering::Release)
     }
 
+    /// Get the cgroup of the process.
+    pub fn cgroup(&self) -> Arc<Cgroup> {
+        self.cgroup.read().clone()
+    }
+
+    /// Set the cgroup of the process.
+    pub fn set_cgroup(&self, cgroup: Arc<Cgroup>) {
+        *self.cgroup.write() = cgroup;
+    }
+
+    /// Get the Landlock rulesets the process is restricted with.
+    pub fn landlock(&self) -> Option<Arc<Domain>> {
+        self.landlock.read().clone()
+    }
+
+    /// Set the Landlock rulesets the process is restricted with.
+    pub fn set_landlock(&self, domain: Option<Arc<Domain>>) {
+        *self.landlock.write() = domain;
+    }
+
+    /// Get the time namespace of the process.
+    pub fn time_ns(&self) -> Arc<TimeNamespace> {
+        self.time_ns.read().clone()
+    }
+
+    /// Get the time namespace of children of the process.
+    pub fn time_ns_for_children(&self) -> Arc<TimeNamespace> {
+        self.time_ns_for_children.read().clone()
+    }
+
+    /// Set the time namespace of children of the process.
+    pub fn set_time_ns_for_children(&self, ns: Arc<TimeNamespace>) {
+        *self.time_ns_for_children.write() = ns;
+    }
+
+    /// Moves the process into a time namespace, which also becomes the one
+    /// of its children.
+    pub fn enter_time_ns(&self, ns: Arc<TimeNamespace>) {
+        ns.freeze();
+        *self.time_ns.write() = ns.clone();
+        *self.time_ns_for_children.write() = ns;
+    }
+
     /// Linux manual: A "clone" child is one which delivers no signal, or a
     /// signal other than SIGCHLD to its parent upon termination.
     pub fn is_clone_child(&self) -> bool {
@@ -421,6 +718,26 @@ pub fn tasks() -> Vec<AxTaskRef> {
     TASK_TABLE.read().values().collect()
 }
 
+/// The largest value `kernel.pid_max` can be set to.
+pub const PID_MAX_LIMIT: u64 = 4 * 1024 * 1024;
+
+/// `/proc/sys/kernel/pid_max`. Task IDs are never reused, so rather than the
+/// largest task ID, this limits the number of live tasks. It defaults to
+/// [`PID_MAX_LIMIT`], leaving the limit to `kernel.threads-max`.
+pub static PID_MAX: AtomicU64 = AtomicU64::new(PID_MAX_LIMIT);
+/// The maximum number of tasks (`/proc/sys/kernel/threads-max`).
+pub static THREADS_MAX: AtomicU64 = AtomicU64::new(32768);
+
+/// Checks that another task can be created under `kernel.pid_max` and
+/// `kernel.threads-max`, failing with `EAGAIN` otherwise.
+pub fn check_task_limits() -> AxResult<()> {
+    let live = tasks().len() as u64;
+    if live >= PID_MAX.load(Ordering::Relaxed) || live >= THREADS_MAX.load(Ordering::Relaxed) {
+        return Err(AxError::WouldBlock);
+    }
+    Ok(())
+}
+
 /// Finds the task with the given TID.
 pub fn get_task(tid: Pid) -> AxResult<AxTaskRef> {
     if tid == 0 {
@@ -1,4 +1,5 @@
 use alloc::{borrow::ToOwned, fmt, string::String};
+use core::sync::atomic::Ordering;
 
 use axerrno::AxResult;
 use axtask::{TaskInner, TaskState};
@@ -84,6 +85,7 @@ impl TaskStat {
         let ppid = proc.parent().map_or(0, |p| p.pid());
         let pgrp = proc.group().pgid();
         let session = proc.group().session().sid();
+        let faults = &proc_data.faults;
         Ok(Self {
             pid,
             comm: comm.to_owned(),
@@ -91,6 +93,10 @@ impl TaskStat {
             ppid,
             pgrp,
             session,
+            minflt: faults.minflt.load(Ordering::Relaxed),
+            cminflt: faults.cminflt.load(Ordering::Relaxed),
+            majflt: faults.majflt.load(Ordering::Relaxed),
+            cmajflt: faults.cmajflt.load(Ordering::Relaxed),
             num_threads: proc.threads().len() as u32,
             exit_signal: proc_data.exit_signal.unwrap_or(Signo::SIGCHLD) as u8,
             exit_code: proc.exit_code(),
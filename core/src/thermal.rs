@@ -0,0 +1,78 @@
+//! Thermal zones.
+//!
+//! Drivers of temperature sensors register a zone here, which is then
+//! exposed as `/sys/class/thermal/thermal_zone<N>`.
+
+use alloc::{sync::Arc, vec::Vec};
+
+use axerrno::AxResult;
+use axsync::Mutex;
+
+/// A temperature sensor.
+pub trait ThermalSensor: Send + Sync {
+    /// Reads the temperature, in millidegrees Celsius.
+    fn temperature(&self) -> AxResult<i32>;
+}
+
+/// The kind of a trip point.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TripType {
+    /// Cooling devices (e.g. fans) are turned on.
+    Active,
+    /// The CPU is throttled.
+    Passive,
+    /// Userspace is notified.
+    Hot,
+    /// The system is shut down.
+    Critical,
+}
+
+impl TripType {
+    /// Returns the name of the trip type as used by sysfs.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Active => "active",
+            Self::Passive => "passive",
+            Self::Hot => "hot",
+            Self::Critical => "critical",
+        }
+    }
+}
+
+/// A temperature at which the system acts.
+#[derive(Debug, Clone, Copy)]
+pub struct TripPoint {
+    /// The temperature, in millidegrees Celsius.
+    pub temp: i32,
+    /// What happens at the temperature.
+    pub kind: TripType,
+}
+
+/// A thermal zone, monitored by a sensor.
+pub struct ThermalZone {
+    /// The type of the zone, e.g. `cpu-thermal`.
+    pub name: &'static str,
+    /// The sensor of the zone.
+    pub sensor: Arc<dyn ThermalSensor>,
+    /// The trip points of the zone.
+    pub trips: Vec<TripPoint>,
+}
+
+static ZONES: Mutex<Vec<Arc<ThermalZone>>> = Mutex::new(Vec::new());
+
+/// Registers a thermal zone, returning its index.
+pub fn register_zone(zone: ThermalZone) -> usize {
+    let mut zones = ZONES.lock();
+    zones.push(Arc::new(zone));
+    zones.len() - 1
+}
+
+/// Returns the number of thermal zones.
+pub fn zone_count() -> usize {
+    ZONES.lock().len()
+}
+
+/// Returns the thermal zone `index`.
+pub fn zone(index: usize) -> Option<Arc<ThermalZone>> {
+    ZONES.lock().get(index).cloned()
+}
@@ -1,9 +1,14 @@
 //! Time management module.
 
 use alloc::{borrow::ToOwned, collections::binary_heap::BinaryHeap, sync::Arc};
-use core::{mem, time::Duration};
+use core::{
+    mem,
+    sync::atomic::{AtomicI64, Ordering},
+    time::Duration,
+};
 
-use axhal::time::{NANOS_PER_SEC, TimeValue, monotonic_time_nanos, wall_time};
+use axerrno::{AxError, AxResult};
+use axhal::time::{NANOS_PER_SEC, TimeValue, monotonic_time_nanos, wall_time, wall_time_nanos};
 use axtask::{
     WeakAxTaskRef, current,
     future::{block_on, timeout_at},
@@ -14,7 +19,7 @@ use spin::Mutex;
 use starry_signal::Signo;
 use strum::FromRepr;
 
-use crate::task::poll_timer;
+use crate::task::{AsThread, poll_timer};
 
 fn time_value_from_nanos(nanos: usize) -> TimeValue {
     let secs = nanos as u64 / NANOS_PER_SEC;
@@ -22,6 +27,114 @@ fn time_value_from_nanos(nanos: usize) -> TimeValue {
     TimeValue::new(secs, nsecs as u32)
 }
 
+/// The offset of `CLOCK_REALTIME` from the wall time of the platform, in
+/// nanoseconds, which changes when the clock is set.
+static REALTIME_OFFSET_NS: AtomicI64 = AtomicI64::new(0);
+
+/// Returns the time of `CLOCK_REALTIME`.
+pub fn realtime() -> TimeValue {
+    let nanos = wall_time_nanos() as i64 + REALTIME_OFFSET_NS.load(Ordering::Acquire);
+    Duration::from_nanos(nanos.max(0) as u64)
+}
+
+/// Sets `CLOCK_REALTIME` to `time`. Timers keep running on the wall time of
+/// the platform, so they are not affected.
+pub fn set_realtime(time: TimeValue) {
+    let offset = time.as_nanos() as i64 - wall_time_nanos() as i64;
+    REALTIME_OFFSET_NS.store(offset, Ordering::Release);
+}
+
+/// A clock that is offset in time namespaces.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NsClock {
+    /// `CLOCK_MONOTONIC`.
+    Monotonic = 0,
+    /// `CLOCK_BOOTTIME`.
+    Boottime  = 1,
+}
+
+struct NsOffsets {
+    /// The offsets of the clocks, in nanoseconds, indexed by [`NsClock`].
+    offsets: [i64; 2],
+    /// Whether a process has entered the namespace, after which the offsets
+    /// can't change.
+    frozen: bool,
+}
+
+/// A time namespace, where `CLOCK_MONOTONIC` and `CLOCK_BOOTTIME` are offset
+/// from the host.
+pub struct TimeNamespace(Mutex<NsOffsets>);
+
+lazy_static! {
+    static ref ROOT_TIME_NS: Arc<TimeNamespace> = Arc::new(TimeNamespace(Mutex::new(NsOffsets {
+        offsets: [0; 2],
+        frozen: true,
+    })));
+}
+
+/// Returns the initial time namespace.
+pub fn root_time_ns() -> &'static Arc<TimeNamespace> {
+    &ROOT_TIME_NS
+}
+
+impl TimeNamespace {
+    /// Creates a namespace with the offsets of this one, which can be changed
+    /// until a process enters it.
+    pub fn new_child(&self) -> Arc<Self> {
+        let offsets = self.0.lock().offsets;
+        Arc::new(Self(Mutex::new(NsOffsets {
+            offsets,
+            frozen: false,
+        })))
+    }
+
+    /// Returns the offset of `clock`, in nanoseconds.
+    pub fn offset(&self, clock: NsClock) -> i64 {
+        self.0.lock().offsets[clock as usize]
+    }
+
+    /// Sets the offsets of clocks, in nanoseconds. Fails with `EACCES` once a
+    /// process has entered the namespace, and with `EINVAL` if a clock would
+    /// become negative.
+    pub fn set_offsets(&self, offsets: &[(NsClock, i64)]) -> AxResult<()> {
+        let mut inner = self.0.lock();
+        if inner.frozen {
+            return Err(AxError::PermissionDenied);
+        }
+        // Both clocks are the monotonic time of the platform
+        let now = monotonic_time_nanos() as i64;
+        if offsets
+            .iter()
+            .any(|&(_, offset)| now.checked_add(offset).is_none_or(|it| it < 0))
+        {
+            return Err(AxError::InvalidInput);
+        }
+        for &(clock, offset) in offsets {
+            inner.offsets[clock as usize] = offset;
+        }
+        Ok(())
+    }
+
+    /// Freezes the offsets, when a process enters the namespace.
+    pub fn freeze(&self) {
+        self.0.lock().frozen = true;
+    }
+
+    /// Returns the time of `clock` in the namespace.
+    pub fn clock_time(&self, clock: NsClock) -> TimeValue {
+        let nanos = (monotonic_time_nanos() as i64).saturating_add(self.offset(clock));
+        Duration::from_nanos(nanos.max(0) as u64)
+    }
+}
+
+/// Returns the time of `clock` in the time namespace of the current process.
+pub fn ns_clock_time(clock: NsClock) -> TimeValue {
+    match current().try_as_thread() {
+        Some(thr) => thr.proc_data.time_ns().clock_time(clock),
+        None => root_time_ns().clock_time(clock),
+    }
+}
+
 struct Entry {
     deadline: Duration,
     task: WeakAxTaskRef,
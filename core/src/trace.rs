@@ -0,0 +1,118 @@
+//! The trace ring buffer, read through `/sys/kernel/debug/tracing/trace`.
+//!
+//! Tracers append fixed-size entries, which may happen from trap handlers,
+//! so appending never allocates nor waits for the buffer: entries that come
+//! while it is being read or written elsewhere are dropped. Entries are only
+//! formatted when the buffer is read. Once the buffer is full, the oldest
+//! entries are dropped.
+
+use alloc::{string::String, vec::Vec};
+use core::{
+    fmt::{self, Write},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use kspin::SpinNoIrq;
+
+/// The number of entries the trace buffer holds.
+pub const TRACE_BUFFER_ENTRIES: usize = 2048;
+
+/// Whether tracers write to the buffer, like `tracing_on`.
+pub static TRACING_ON: AtomicBool = AtomicBool::new(true);
+
+/// An entry of the trace buffer.
+#[derive(Debug, Clone, Copy)]
+pub enum TraceEntry {
+    /// A traced function was entered, at nesting depth `depth`.
+    FuncEntry {
+        cpu: usize,
+        depth: usize,
+        name: &'static str,
+    },
+    /// A traced function returned after `duration_ns`.
+    FuncReturn {
+        cpu: usize,
+        depth: usize,
+        name: &'static str,
+        duration_ns: u64,
+    },
+}
+
+impl fmt::Display for TraceEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::FuncEntry { cpu, depth, name } => write!(
+                f,
+                "{cpu:>3}) {:14} |  {:indent$}{name}() {{",
+                "",
+                "",
+                indent = depth * 2
+            ),
+            Self::FuncReturn {
+                cpu,
+                depth,
+                name,
+                duration_ns,
+            } => write!(
+                f,
+                "{cpu:>3}) {:>7}.{:03} us |  {:indent$}}} /* {name} */",
+                duration_ns / 1000,
+                duration_ns % 1000,
+                "",
+                indent = depth * 2
+            ),
+        }
+    }
+}
+
+struct TraceBuffer {
+    entries: [Option<TraceEntry>; TRACE_BUFFER_ENTRIES],
+    /// The index of the oldest entry.
+    head: usize,
+    len: usize,
+}
+
+static BUFFER: SpinNoIrq<TraceBuffer> = SpinNoIrq::new(TraceBuffer {
+    entries: [None; TRACE_BUFFER_ENTRIES],
+    head: 0,
+    len: 0,
+});
+
+/// Appends `entry` to the trace buffer, unless it is busy.
+pub fn write(entry: TraceEntry) {
+    if !TRACING_ON.load(Ordering::Relaxed) {
+        return;
+    }
+    let Some(mut buffer) = BUFFER.try_lock() else {
+        return;
+    };
+    let index = (buffer.head + buffer.len) % TRACE_BUFFER_ENTRIES;
+    buffer.entries[index] = Some(entry);
+    if buffer.len == TRACE_BUFFER_ENTRIES {
+        buffer.head = (buffer.head + 1) % TRACE_BUFFER_ENTRIES;
+    } else {
+        buffer.len += 1;
+    }
+}
+
+/// Returns the content of the trace buffer.
+pub fn read() -> String {
+    let entries = {
+        let buffer = BUFFER.lock();
+        (0..buffer.len)
+            .filter_map(|i| buffer.entries[(buffer.head + i) % TRACE_BUFFER_ENTRIES])
+            .collect::<Vec<_>>()
+    };
+    let mut out = String::new();
+    for entry in entries {
+        writeln!(out, "{entry}").unwrap();
+    }
+    out
+}
+
+/// Empties the trace buffer.
+pub fn clear() {
+    let mut buffer = BUFFER.lock();
+    buffer.head = 0;
+    buffer.len = 0;
+}
@@ -0,0 +1,315 @@
+//! User probes.
+//!
+//! A uprobe is placed at an offset into an executable file, and fires in any
+//! process executing the file at that offset. Like kprobes, the probed
+//! instruction is replaced with a breakpoint, and executed out of line in a
+//! slot of a page mapped into each probed process, followed by another
+//! breakpoint going back after the probed instruction.
+//!
+//! The breakpoint is written to a private copy of the page, so the file and
+//! other mappings of it are unaffected. Only private executable mappings are
+//! probed, and only on riscv64.
+
+use alloc::{boxed::Box, collections::btree_map::BTreeMap, sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use axerrno::{AxError, AxResult};
+use axfs_ng::CachedFile;
+use axfs_ng_vfs::Location;
+use axhal::{
+    paging::{MappingFlags, PageSize},
+    uspace::UserContext,
+};
+use axmm::{AddrSpace, backend::Backend};
+use axsync::Mutex;
+use memory_addr::{MemoryAddr, PAGE_SIZE_4K, VirtAddr, VirtAddrRange};
+
+use crate::{
+    config::SIGNAL_TRAMPOLINE,
+    kprobe::arch,
+    task::{ProcessData, Thread, processes},
+};
+
+/// The maximum number of uprobes registered at once.
+pub const MAX_UPROBES: usize = PAGE_SIZE_4K / 8;
+
+/// The page holding the out-of-line slots in probed processes, next to the
+/// signal trampoline.
+const XOL_PAGE: usize = SIGNAL_TRAMPOLINE + PAGE_SIZE_4K;
+
+/// Identifies a file by its device and inode numbers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct FileId {
+    /// The device of the filesystem.
+    pub device: u64,
+    /// The inode number.
+    pub inode: u64,
+}
+
+impl FileId {
+    /// Returns the ID of the file at `loc`.
+    pub fn of(loc: &Location) -> AxResult<Self> {
+        let metadata = loc.metadata()?;
+        Ok(Self {
+            device: metadata.device,
+            inode: metadata.inode,
+        })
+    }
+}
+
+/// A private mapping of a file, mapping the file offset `addr - base` at
+/// each address `addr` of its range.
+#[derive(Debug, Clone, Copy)]
+pub struct FileMapping {
+    /// The mapped file.
+    pub file: FileId,
+    /// The address where offset 0 of the file would be mapped.
+    pub base: usize,
+}
+
+impl FileMapping {
+    /// Creates a mapping of `file` from `offset` at `start`.
+    pub fn new(file: FileId, start: VirtAddr, offset: u64) -> Self {
+        Self {
+            file,
+            base: start.as_usize().wrapping_sub(offset as usize),
+        }
+    }
+}
+
+/// Called when a uprobe is hit, before the probed instruction runs.
+pub type UprobeHandler = Box<dyn Fn(&Uprobe, &UserContext) + Send + Sync>;
+
+/// A registered uprobe.
+pub struct Uprobe {
+    file: FileId,
+    offset: u64,
+    slot: usize,
+    /// The probed instruction, padded to 4 bytes and followed by a
+    /// breakpoint.
+    code: [u8; 8],
+    len: usize,
+    on_hit: Option<UprobeHandler>,
+    hits: AtomicU64,
+}
+
+impl Uprobe {
+    /// Returns the probed file.
+    pub fn file(&self) -> FileId {
+        self.file
+    }
+
+    /// Returns the probed offset into the file.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Returns the number of times the probe was hit.
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    fn slot_addr(&self) -> VirtAddr {
+        VirtAddr::from(XOL_PAGE + self.slot * 8)
+    }
+}
+
+static UPROBES: Mutex<BTreeMap<(FileId, u64), Arc<Uprobe>>> = Mutex::new(BTreeMap::new());
+
+/// Writes `bytes` at `addr` of a read-only mapping, breaking the sharing of
+/// its page first.
+fn write_text(aspace: &mut AddrSpace, addr: VirtAddr, bytes: &[u8]) -> AxResult<()> {
+    let page = addr.align_down_4k();
+    let flags = aspace.find_area(addr).ok_or(AxError::BadAddress)?.flags();
+    aspace.protect(page, PAGE_SIZE_4K, flags | MappingFlags::WRITE)?;
+    let result = aspace
+        .populate_area(page, PAGE_SIZE_4K, MappingFlags::WRITE)
+        .and_then(|_| aspace.write(addr, bytes));
+    aspace.protect(page, PAGE_SIZE_4K, flags)?;
+    result?;
+    arch::flush_icache();
+    Ok(())
+}
+
+/// Places `probe` at `addr` of `aspace`.
+fn install_at(aspace: &mut AddrSpace, addr: VirtAddr, probe: &Uprobe) -> AxResult<()> {
+    let area = aspace.find_area(addr).ok_or(AxError::BadAddress)?;
+    if !area.flags().contains(MappingFlags::EXECUTE) {
+        return Ok(());
+    }
+    if aspace.find_area(XOL_PAGE.into()).is_none() {
+        aspace.map(
+            XOL_PAGE.into(),
+            PAGE_SIZE_4K,
+            MappingFlags::READ | MappingFlags::EXECUTE | MappingFlags::USER,
+            true,
+            Backend::new_alloc(XOL_PAGE.into(), PageSize::Size4K),
+        )?;
+    }
+    write_text(aspace, probe.slot_addr(), &probe.code)?;
+    write_text(aspace, addr, &arch::C_BREAK_INSN.to_le_bytes())
+}
+
+/// Returns the addresses `probe` is mapped at in `proc_data`.
+fn probe_sites(proc_data: &ProcessData, probe: &Uprobe) -> Vec<VirtAddr> {
+    proc_data
+        .file_maps
+        .lock()
+        .iter()
+        .filter(|(_, mapping)| mapping.file == probe.file)
+        .map(|(range, mapping)| {
+            let addr = VirtAddr::from(mapping.base.wrapping_add(probe.offset as usize));
+            (range, addr)
+        })
+        .filter(|(range, addr)| range.contains(*addr))
+        .map(|(_, addr)| addr)
+        .collect()
+}
+
+/// Places the uprobes of a file mapped at `range` of `aspace`.
+pub fn on_map(aspace: &mut AddrSpace, range: VirtAddrRange, mapping: FileMapping) {
+    let probes = UPROBES
+        .lock()
+        .range((mapping.file, 0)..=(mapping.file, u64::MAX))
+        .map(|(_, probe)| probe.clone())
+        .collect::<Vec<_>>();
+    for probe in probes {
+        let addr = VirtAddr::from(mapping.base.wrapping_add(probe.offset as usize));
+        if !range.contains(addr) {
+            continue;
+        }
+        if let Err(err) = install_at(aspace, addr, &probe) {
+            warn!("Failed to place uprobe at {addr:#x}: {err:?}");
+        }
+    }
+}
+
+/// Registers a uprobe at `offset` into the file at `loc`, and places it in
+/// the processes mapping the file.
+pub fn register_uprobe(
+    loc: &Location,
+    offset: u64,
+    on_hit: Option<UprobeHandler>,
+) -> AxResult<Arc<Uprobe>> {
+    if cfg!(not(target_arch = "riscv64")) {
+        return Err(AxError::Unsupported);
+    }
+    let file = FileId::of(loc)?;
+    let mut code = [0u8; 8];
+    let read = CachedFile::get_or_create(loc.clone()).read_at(&mut &mut code[..4], offset)?;
+    let first = u16::from_le_bytes([code[0], code[1]]);
+    let len = arch::insn_len(first);
+    if offset & 1 != 0 || read < len {
+        return Err(AxError::InvalidInput);
+    }
+    let insn = if len == 4 {
+        u32::from_le_bytes(code[..4].try_into().unwrap())
+    } else {
+        first as u32
+    };
+    if !arch::can_step_out_of_line(insn, len) {
+        return Err(AxError::InvalidInput);
+    }
+    if len == 2 {
+        code[2..4].copy_from_slice(&arch::C_NOP.to_le_bytes());
+    }
+    code[4..].copy_from_slice(&arch::BREAK_INSN.to_le_bytes());
+
+    let probe = {
+        let mut probes = UPROBES.lock();
+        if probes.contains_key(&(file, offset)) {
+            return Err(AxError::AlreadyExists);
+        }
+        let used = probes.values().map(|probe| probe.slot).collect::<Vec<_>>();
+        let slot = (0..MAX_UPROBES)
+            .find(|slot| !used.contains(slot))
+            .ok_or(AxError::NoMemory)?;
+        let probe = Arc::new(Uprobe {
+            file,
+            offset,
+            slot,
+            code,
+            len,
+            on_hit,
+            hits: AtomicU64::new(0),
+        });
+        probes.insert((file, offset), probe.clone());
+        probe
+    };
+
+    for proc_data in processes() {
+        let sites = probe_sites(&proc_data, &probe);
+        if sites.is_empty() {
+            continue;
+        }
+        let mut aspace = proc_data.aspace.lock();
+        for addr in sites {
+            if let Err(err) = install_at(&mut aspace, addr, &probe) {
+                warn!("Failed to place uprobe at {addr:#x}: {err:?}");
+            }
+        }
+    }
+    Ok(probe)
+}
+
+/// Unregisters a uprobe, restoring the probed instruction in the processes
+/// it was placed in.
+pub fn unregister_uprobe(probe: &Uprobe) -> AxResult<()> {
+    if UPROBES.lock().remove(&(probe.file, probe.offset)).is_none() {
+        return Err(AxError::NotFound);
+    }
+    for proc_data in processes() {
+        let sites = probe_sites(&proc_data, probe);
+        if sites.is_empty() {
+            continue;
+        }
+        let mut aspace = proc_data.aspace.lock();
+        for addr in sites {
+            let mut current = [0u8; 2];
+            if aspace.read(addr, &mut current).is_ok()
+                && u16::from_le_bytes(current) == arch::C_BREAK_INSN
+            {
+                write_text(&mut aspace, addr, &probe.code[..2])?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Handles a breakpoint hit by `thr` in user space, returning whether it
+/// belongs to a uprobe.
+pub fn handle_breakpoint(thr: &Thread, uctx: &mut UserContext) -> bool {
+    let pc = uctx.ip();
+    if (XOL_PAGE..XOL_PAGE + PAGE_SIZE_4K).contains(&pc) {
+        // The breakpoint after an instruction run out of line
+        let resume = thr.take_uprobe_resume();
+        if resume == 0 || (pc - XOL_PAGE) % 8 != 4 {
+            return false;
+        }
+        uctx.set_ip(resume);
+        return true;
+    }
+
+    let proc_data = &thr.proc_data;
+    let Some((_, mapping)) = proc_data.file_maps.lock().find(pc.into()) else {
+        return false;
+    };
+    let offset = pc.wrapping_sub(mapping.base) as u64;
+    let Some(probe) = UPROBES.lock().get(&(mapping.file, offset)).cloned() else {
+        // The probe may have been removed since the breakpoint was hit, in
+        // which case the restored instruction is run again.
+        let mut current = [0u8; 2];
+        return proc_data
+            .aspace
+            .lock()
+            .read(pc.into(), &mut current)
+            .is_ok_and(|_| u16::from_le_bytes(current) != arch::C_BREAK_INSN);
+    };
+    probe.hits.fetch_add(1, Ordering::Relaxed);
+    if let Some(handler) = &probe.on_hit {
+        handler(&probe, uctx);
+    }
+    thr.set_uprobe_resume(pc + probe.len);
+    uctx.set_ip(probe.slot_addr().as_usize());
+    true
+}
@@ -0,0 +1,362 @@
+//! fs-verity, read-time integrity verification of files.
+//!
+//! Enabling verity on a file builds a Merkle tree of SHA-256 hashes over its
+//! blocks and makes the file read-only. Reads then hash the blocks they touch
+//! and fail with `EIO` on a mismatch, so changes made behind the filesystem,
+//! like on the underlying disk, are detected.
+//!
+//! The filesystems have no place to store the tree after the file data, so
+//! it's kept in memory and verity doesn't survive a reboot. As the tree can't
+//! be tampered with there, only its lowest level, the hashes of the data
+//! blocks, is kept. Blocks are hashed again on every read, and mapped files
+//! are only verified as a whole when executed.
+
+use alloc::{collections::btree_map::BTreeMap, sync::Arc, vec, vec::Vec};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use axerrno::{AxError, AxResult, LinuxError};
+use axfs_ng::CachedFile;
+use axfs_ng_vfs::Location;
+use axsync::Mutex;
+
+use crate::uprobe::FileId;
+
+/// The size of the data blocks and of the tree blocks.
+pub const VERITY_BLOCK_SIZE: usize = 4096;
+/// `FS_VERITY_HASH_ALG_SHA256`, the only supported hash algorithm.
+pub const HASH_ALG_SHA256: u32 = 1;
+/// The size of a SHA-256 digest.
+pub const DIGEST_SIZE: usize = 32;
+/// The maximum size of a salt.
+pub const MAX_SALT_SIZE: usize = 32;
+
+/// A SHA-256 digest.
+pub type Digest = [u8; DIGEST_SIZE];
+
+const SHA256_BLOCK_SIZE: usize = 64;
+
+const SHA256_K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+const SHA256_INIT: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+struct Sha256 {
+    state: [u32; 8],
+    buf: [u8; SHA256_BLOCK_SIZE],
+    buf_len: usize,
+    /// The number of bytes hashed.
+    len: u64,
+}
+
+impl Sha256 {
+    fn new() -> Self {
+        Self {
+            state: SHA256_INIT,
+            buf: [0; SHA256_BLOCK_SIZE],
+            buf_len: 0,
+            len: 0,
+        }
+    }
+
+    fn compress(&mut self, block: &[u8]) {
+        let mut w = [0u32; 64];
+        for (i, word) in block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes(word.try_into().unwrap());
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.state;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(SHA256_K[i])
+                .wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (state, value) in self.state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+
+    fn update(&mut self, mut data: &[u8]) {
+        self.len += data.len() as u64;
+        if self.buf_len > 0 {
+            let len = data.len().min(SHA256_BLOCK_SIZE - self.buf_len);
+            self.buf[self.buf_len..self.buf_len + len].copy_from_slice(&data[..len]);
+            self.buf_len += len;
+            data = &data[len..];
+            if self.buf_len < SHA256_BLOCK_SIZE {
+                return;
+            }
+            let block = self.buf;
+            self.compress(&block);
+            self.buf_len = 0;
+        }
+        let mut blocks = data.chunks_exact(SHA256_BLOCK_SIZE);
+        for block in &mut blocks {
+            self.compress(block);
+        }
+        let rest = blocks.remainder();
+        self.buf[..rest.len()].copy_from_slice(rest);
+        self.buf_len = rest.len();
+    }
+
+    fn finish(mut self) -> Digest {
+        let bits = self.len * 8;
+        // The length goes in the last 8 bytes of the final block
+        let pad_len = (SHA256_BLOCK_SIZE * 2 - 8 - self.buf_len - 1) % SHA256_BLOCK_SIZE + 1;
+        let mut padding = [0u8; SHA256_BLOCK_SIZE];
+        padding[0] = 0x80;
+        self.update(&padding[..pad_len]);
+        self.update(&bits.to_be_bytes());
+        let mut digest = [0; DIGEST_SIZE];
+        for (out, word) in digest.chunks_exact_mut(4).zip(self.state) {
+            out.copy_from_slice(&word.to_be_bytes());
+        }
+        digest
+    }
+}
+
+fn sha256(data: &[u8]) -> Digest {
+    let mut hasher = Sha256::new();
+    hasher.update(data);
+    hasher.finish()
+}
+
+/// Hashes a block, prefixed by the padded salt like Linux.
+fn hash_block(salt: &[u8], block: &[u8]) -> Digest {
+    let mut hasher = Sha256::new();
+    hasher.update(salt);
+    hasher.update(block);
+    hasher.finish()
+}
+
+struct Verity {
+    /// The size of the file.
+    data_size: u64,
+    /// The salt, zero-padded to the SHA-256 block size, or empty.
+    padded_salt: Vec<u8>,
+    /// The hashes of the data blocks.
+    block_hashes: Vec<Digest>,
+    /// The digest of the file, as reported by `FS_IOC_MEASURE_VERITY`.
+    digest: Digest,
+}
+
+static FILES: Mutex<BTreeMap<FileId, Arc<Verity>>> = Mutex::new(BTreeMap::new());
+/// Number of files with verity, to skip the lookup when there are none.
+static FILE_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns whether any file has verity enabled.
+pub fn active() -> bool {
+    FILE_COUNT.load(Ordering::Acquire) > 0
+}
+
+fn get(loc: &Location) -> Option<Arc<Verity>> {
+    if !active() {
+        return None;
+    }
+    let id = FileId::of(loc).ok()?;
+    FILES.lock().get(&id).cloned()
+}
+
+/// Reads block `index` of the file, zero-padded to the block size.
+fn read_block(cache: &CachedFile, data_size: u64, index: u64, buf: &mut [u8]) -> AxResult<()> {
+    let offset = index * VERITY_BLOCK_SIZE as u64;
+    let len = (data_size - offset).min(VERITY_BLOCK_SIZE as u64) as usize;
+    buf.fill(0);
+    let mut read = 0;
+    while read < len {
+        let n = cache.read_at(&mut &mut buf[read..len], offset + read as u64)?;
+        if n == 0 {
+            // The file was truncated behind the filesystem, which the hash
+            // catches
+            break;
+        }
+        read += n;
+    }
+    Ok(())
+}
+
+/// Returns the root hash of the tree over `block_hashes`.
+fn root_hash(padded_salt: &[u8], block_hashes: &[Digest]) -> Digest {
+    // Like Linux, an empty file has an all-zero root hash
+    if block_hashes.is_empty() {
+        return [0; DIGEST_SIZE];
+    }
+    let mut level = block_hashes.to_vec();
+    let mut block = vec![0u8; VERITY_BLOCK_SIZE];
+    loop {
+        // Each level packs the hashes of the level below into blocks
+        level = level
+            .chunks(VERITY_BLOCK_SIZE / DIGEST_SIZE)
+            .map(|hashes| {
+                block.fill(0);
+                for (out, hash) in block.chunks_exact_mut(DIGEST_SIZE).zip(hashes) {
+                    out.copy_from_slice(hash);
+                }
+                hash_block(padded_salt, &block)
+            })
+            .collect();
+        if level.len() == 1 {
+            return level[0];
+        }
+    }
+}
+
+/// Returns the hash of the `fsverity_descriptor` of a file, which is its
+/// digest.
+fn descriptor_digest(data_size: u64, root_hash: &Digest, salt: &[u8]) -> Digest {
+    let mut desc = [0u8; 256];
+    desc[0] = 1;
+    desc[1] = HASH_ALG_SHA256 as u8;
+    desc[2] = VERITY_BLOCK_SIZE.trailing_zeros() as u8;
+    desc[3] = salt.len() as u8;
+    desc[8..16].copy_from_slice(&data_size.to_le_bytes());
+    desc[16..16 + DIGEST_SIZE].copy_from_slice(root_hash);
+    desc[80..80 + salt.len()].copy_from_slice(salt);
+    sha256(&desc)
+}
+
+/// Enables verity on the regular file at `loc`, hashing its blocks prefixed
+/// with `salt`. Fails with `EEXIST` if verity is already enabled.
+pub fn enable(loc: &Location, salt: &[u8]) -> AxResult<()> {
+    if salt.len() > MAX_SALT_SIZE {
+        return Err(AxError::Other(LinuxError::EMSGSIZE));
+    }
+    let id = FileId::of(loc)?;
+    if FILES.lock().contains_key(&id) {
+        return Err(AxError::AlreadyExists);
+    }
+
+    let mut padded_salt = salt.to_vec();
+    if !salt.is_empty() {
+        padded_salt.resize(SHA256_BLOCK_SIZE, 0);
+    }
+    let data_size = loc.len()?;
+    let cache = CachedFile::get_or_create(loc.clone());
+    let mut buf = vec![0u8; VERITY_BLOCK_SIZE];
+    let block_hashes = (0..data_size.div_ceil(VERITY_BLOCK_SIZE as u64))
+        .map(|index| {
+            read_block(&cache, data_size, index, &mut buf)?;
+            Ok(hash_block(&padded_salt, &buf))
+        })
+        .collect::<AxResult<Vec<_>>>()?;
+    let digest = descriptor_digest(data_size, &root_hash(&padded_salt, &block_hashes), salt);
+
+    let mut files = FILES.lock();
+    if files.contains_key(&id) {
+        return Err(AxError::AlreadyExists);
+    }
+    files.insert(
+        id,
+        Arc::new(Verity {
+            data_size,
+            padded_salt,
+            block_hashes,
+            digest,
+        }),
+    );
+    FILE_COUNT.store(files.len(), Ordering::Release);
+    Ok(())
+}
+
+/// Forgets the verity of a file, when its last link is removed.
+pub fn forget(id: &FileId) {
+    let mut files = FILES.lock();
+    files.remove(id);
+    FILE_COUNT.store(files.len(), Ordering::Release);
+}
+
+/// Returns the ID of the file at `loc` if it has verity enabled.
+pub fn id_of(loc: &Location) -> Option<FileId> {
+    get(loc)?;
+    FileId::of(loc).ok()
+}
+
+/// Returns whether the file at `loc` has verity enabled.
+pub fn is_enabled(loc: &Location) -> bool {
+    get(loc).is_some()
+}
+
+/// Fails with `EPERM` if the file at `loc` has verity enabled, which makes
+/// it read-only.
+pub fn check_write(loc: &Location) -> AxResult<()> {
+    if is_enabled(loc) {
+        Err(AxError::OperationNotPermitted)
+    } else {
+        Ok(())
+    }
+}
+
+/// Returns the digest of the file at `loc`, failing with `ENODATA` if it
+/// doesn't have verity enabled.
+pub fn measure(loc: &Location) -> AxResult<Digest> {
+    get(loc)
+        .map(|verity| verity.digest)
+        .ok_or(AxError::Other(LinuxError::ENODATA))
+}
+
+/// Verifies the blocks of the file at `loc` holding `len` bytes from
+/// `offset`, failing with `EIO` if one doesn't match its hash. Files without
+/// verity always pass.
+pub fn verify(loc: &Location, offset: u64, len: usize) -> AxResult<()> {
+    let Some(verity) = get(loc) else {
+        return Ok(());
+    };
+    let end = offset.saturating_add(len as u64).min(verity.data_size);
+    if offset >= end {
+        return Ok(());
+    }
+    let cache = CachedFile::get_or_create(loc.clone());
+    let mut buf = vec![0u8; VERITY_BLOCK_SIZE];
+    let first = offset / VERITY_BLOCK_SIZE as u64;
+    let last = end.div_ceil(VERITY_BLOCK_SIZE as u64);
+    for index in first..last {
+        read_block(&cache, verity.data_size, index, &mut buf)?;
+        if hash_block(&verity.padded_salt, &buf) != verity.block_hashes[index as usize] {
+            warn!(
+                "fs-verity: block {index} of {:?} is corrupted",
+                loc.absolute_path()
+            );
+            return Err(AxError::Other(LinuxError::EIO));
+        }
+    }
+    Ok(())
+}
+
+/// Verifies every block of the file at `loc`, like [`verify`].
+pub fn verify_all(loc: &Location) -> AxResult<()> {
+    match get(loc) {
+        Some(verity) => verify(loc, 0, verity.data_size as usize),
+        None => Ok(()),
+    }
+}
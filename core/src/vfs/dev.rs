@@ -1,6 +1,7 @@
 use alloc::sync::Arc;
 use core::{any::Any, task::Context};
 
+use axerrno::LinuxError;
 use axfs_ng::CachedFile;
 use axfs_ng_vfs::{
     DeviceId, FileNodeOps, FilesystemOps, Metadata, MetadataUpdate, NodeFlags, NodeOps,
@@ -8,7 +9,12 @@ use axfs_ng_vfs::{
 };
 use axpoll::{IoEvents, Pollable};
 use inherit_methods_macro::inherit_methods;
+use linux_raw_sys::{
+    ctypes::{c_int, c_ulong},
+    ioctl::{BLKDISCARD, BLKGETSIZE, BLKGETSIZE64, BLKSSZGET, BLKZEROOUT},
+};
 use memory_addr::PhysAddrRange;
+use starry_vm::{VmMutPtr, VmPtr};
 
 use super::{SimpleFs, SimpleFsNode};
 
@@ -24,6 +30,19 @@ pub enum DeviceMmap {
     Cache(CachedFile),
 }
 
+/// Geometry and state of a block device.
+#[derive(Debug, Clone, Copy)]
+pub struct BlockInfo {
+    /// Size of the device, in bytes.
+    pub size: u64,
+    /// Size of the smallest unit the device can address, in bytes.
+    pub logical_block_size: u32,
+    /// Whether the device has rotating media.
+    pub rotational: bool,
+    /// Whether the device is read-only.
+    pub read_only: bool,
+}
+
 /// Trait for device operations.
 pub trait DeviceOps: Send + Sync {
     /// Reads data from the device at the specified offset.
@@ -48,10 +67,22 @@ pub trait DeviceOps: Send + Sync {
         DeviceMmap::None
     }
 
+    /// Returns the geometry of a block device, or `None` for other devices.
+    fn block_info(&self) -> Option<BlockInfo> {
+        None
+    }
+
     /// Returns the flags for the device node.
     fn flags(&self) -> NodeFlags {
         NodeFlags::empty()
     }
+
+    /// Flushes all pending writes of the device to its backing storage.
+    ///
+    /// Devices without a write-back cache don't need to override this.
+    fn flush(&self) -> VfsResult<()> {
+        Err(VfsError::OperationNotSupported)
+    }
 }
 
 /// A device node in the filesystem.
@@ -87,6 +118,38 @@ impl Device {
     pub fn mmap(&self, offset: u64) -> DeviceMmap {
         self.ops.mmap(offset)
     }
+
+    fn check_range(&self, info: &BlockInfo, offset: u64, len: u64) -> VfsResult<()> {
+        let mask = info.logical_block_size as u64 - 1;
+        if offset & mask != 0 || len & mask != 0 {
+            return Err(VfsError::InvalidInput);
+        }
+        match offset.checked_add(len) {
+            Some(end) if end <= info.size => {}
+            _ => return Err(VfsError::InvalidInput),
+        }
+        if info.read_only {
+            return Err(VfsError::OperationNotPermitted);
+        }
+        Ok(())
+    }
+
+    /// Zeroes the byte range `[offset, offset + len)` by writing zeroes, as
+    /// no block driver has a write-zeroes command.
+    fn zero_out(&self, offset: u64, len: u64) -> VfsResult<()> {
+        const ZEROES: [u8; 4096] = [0; 4096];
+        let end = offset + len;
+        let mut pos = offset;
+        while pos < end {
+            let chunk = (end - pos).min(ZEROES.len() as u64) as usize;
+            let written = self.ops.write_at(&ZEROES[..chunk], pos)?;
+            if written == 0 {
+                return Err(VfsError::Other(LinuxError::EIO));
+            }
+            pos += written as u64;
+        }
+        Ok(())
+    }
 }
 
 #[inherit_methods(from = "self.node")]
@@ -143,6 +206,35 @@ impl FileNodeOps for Device {
     }
 
     fn ioctl(&self, cmd: u32, arg: usize) -> VfsResult<usize> {
+        // Geometry queries are answered the same way for every block device
+        if let Some(info) = self.ops.block_info() {
+            match cmd {
+                BLKGETSIZE64 => {
+                    (arg as *mut u64).vm_write(info.size)?;
+                    return Ok(0);
+                }
+                BLKGETSIZE => {
+                    // In 512-byte sectors, regardless of the logical block size
+                    (arg as *mut c_ulong).vm_write((info.size >> 9) as _)?;
+                    return Ok(0);
+                }
+                BLKSSZGET => {
+                    (arg as *mut c_int).vm_write(info.logical_block_size as _)?;
+                    return Ok(0);
+                }
+                BLKDISCARD | BLKZEROOUT => {
+                    let [offset, len] = (arg as *const [u64; 2]).vm_read()?;
+                    self.check_range(&info, offset, len)?;
+                    if cmd == BLKDISCARD {
+                        // No block driver supports discarding
+                        return Err(VfsError::OperationNotSupported);
+                    }
+                    self.zero_out(offset, len)?;
+                    return Ok(0);
+                }
+                _ => {}
+            }
+        }
         self.ops.ioctl(cmd, arg)
     }
 }
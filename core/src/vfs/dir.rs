@@ -30,6 +30,22 @@ pub trait SimpleDirOps: Send + Sync + 'static {
         true
     }
 
+    /// Create a child directory or file, for directories where users may
+    /// create entries.
+    fn create_child(
+        &self,
+        _name: &str,
+        _node_type: NodeType,
+        _permission: NodePermission,
+    ) -> VfsResult<NodeOpsMux> {
+        Err(VfsError::OperationNotPermitted)
+    }
+
+    /// Remove a child directory or file.
+    fn remove_child(&self, _name: &str) -> VfsResult<()> {
+        Err(VfsError::OperationNotPermitted)
+    }
+
     /// Combines two directories into one.
     fn chain<N: SimpleDirOps>(self, other: N) -> ChainedDirOps<Self, N>
     where
@@ -119,6 +135,24 @@ impl<O: SimpleDirOps> SimpleDir<O> {
             )
         })
     }
+
+    /// Get the directory operations.
+    pub fn ops(&self) -> &Arc<O> {
+        &self.ops
+    }
+
+    fn new_entry(&self, name: &str, ops: NodeOpsMux) -> VfsResult<DirEntry> {
+        let reference = Reference::new(self.this.upgrade(), name.to_owned());
+        Ok(match ops {
+            NodeOpsMux::Dir(maker) => {
+                DirEntry::new_dir(|this| DirNode::new(maker(this)), reference)
+            }
+            NodeOpsMux::File(ops) => {
+                let node_type = ops.metadata()?.node_type;
+                DirEntry::new_file(FileNode::new(ops.clone()), node_type, reference)
+            }
+        })
+    }
 }
 
 #[inherit_methods(from = "self.node")]
@@ -171,16 +205,7 @@ impl<O: SimpleDirOps> DirNodeOps for SimpleDir<O> {
 
     fn lookup(&self, name: &str) -> VfsResult<DirEntry> {
         let ops = self.ops.lookup_child(name)?;
-        let reference = Reference::new(self.this.upgrade(), name.to_owned());
-        Ok(match ops {
-            NodeOpsMux::Dir(maker) => {
-                DirEntry::new_dir(|this| DirNode::new(maker(this)), reference)
-            }
-            NodeOpsMux::File(ops) => {
-                let node_type = ops.metadata()?.node_type;
-                DirEntry::new_file(FileNode::new(ops.clone()), node_type, reference)
-            }
-        })
+        self.new_entry(name, ops)
     }
 
     fn is_cacheable(&self) -> bool {
@@ -189,19 +214,20 @@ impl<O: SimpleDirOps> DirNodeOps for SimpleDir<O> {
 
     fn create(
         &self,
-        _name: &str,
-        _node_type: NodeType,
-        _permission: NodePermission,
+        name: &str,
+        node_type: NodeType,
+        permission: NodePermission,
     ) -> VfsResult<DirEntry> {
-        Err(VfsError::OperationNotPermitted)
+        let ops = self.ops.create_child(name, node_type, permission)?;
+        self.new_entry(name, ops)
     }
 
     fn link(&self, _name: &str, _node: &DirEntry) -> VfsResult<DirEntry> {
         Err(VfsError::OperationNotPermitted)
     }
 
-    fn unlink(&self, _name: &str) -> VfsResult<()> {
-        Err(VfsError::OperationNotPermitted)
+    fn unlink(&self, name: &str) -> VfsResult<()> {
+        self.ops.remove_child(name)
     }
 
     fn rename(&self, _src_name: &str, _dst_dir: &DirNode, _dst_name: &str) -> VfsResult<()> {
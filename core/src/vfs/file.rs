@@ -16,6 +16,26 @@ pub trait SimpleFileOps: Send + Sync + 'static {
     fn read_all(&self) -> VfsResult<Cow<[u8]>>;
     /// Replaces the file's content with `data`.
     fn write_all(&self, data: &[u8]) -> VfsResult<()>;
+
+    /// Reads the content at `offset` into `buf`.
+    ///
+    /// Files too large to be generated at once override this, along with
+    /// [`SimpleFileOps::len`].
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> VfsResult<usize> {
+        let data = self.read_all()?;
+        if offset >= data.len() as u64 {
+            return Ok(0);
+        }
+        let data = &data[offset as usize..];
+        let read = data.len().min(buf.len());
+        buf[..read].copy_from_slice(&data[..read]);
+        Ok(read)
+    }
+
+    /// Returns the size of the content.
+    fn len(&self) -> VfsResult<u64> {
+        Ok(self.read_all()?.len() as u64)
+    }
 }
 
 /// Type representing operation applied to a simple file.
@@ -108,7 +128,7 @@ impl NodeOps for SimpleFile {
     }
 
     fn len(&self) -> VfsResult<u64> {
-        Ok(self.ops.read_all()?.len() as u64)
+        self.ops.len()
     }
 
     fn flags(&self) -> NodeFlags {
@@ -118,14 +138,7 @@ impl NodeOps for SimpleFile {
 
 impl FileNodeOps for SimpleFile {
     fn read_at(&self, buf: &mut [u8], offset: u64) -> VfsResult<usize> {
-        let data = self.ops.read_all()?;
-        if offset >= data.len() as u64 {
-            return Ok(0);
-        }
-        let data = &data[offset as usize..];
-        let read = data.len().min(buf.len());
-        buf[..read].copy_from_slice(&data[..read]);
-        Ok(read)
+        self.ops.read_at(buf, offset)
     }
 
     fn write_at(&self, buf: &[u8], offset: u64) -> VfsResult<usize> {
@@ -14,6 +14,9 @@ mod syscall;
 #[cfg(feature = "uspace")]
 pub mod uspace;
 
+#[cfg(feature = "shstk")]
+pub mod shstk;
+
 pub use self::context::{ExtendedState, FxsaveArea, TaskContext, TrapFrame};
 pub use self::gdt::GdtStruct;
 pub use self::idt::IdtStruct;
@@ -0,0 +1,75 @@
+//! User-mode shadow stacks of Intel CET (Control-flow Enforcement Technology).
+
+use x86_64::registers::model_specific::Msr;
+
+/// `IA32_U_CET`, the user-mode CET configuration.
+const IA32_U_CET: u32 = 0x6a0;
+/// `IA32_PL3_SSP`, the user-mode shadow stack pointer.
+const IA32_PL3_SSP: u32 = 0x6a7;
+
+/// `IA32_U_CET.SH_STK_EN`: enables shadow stacks.
+const CET_SHSTK_EN: u64 = 1 << 0;
+/// `IA32_U_CET.WR_SHSTK_EN`: enables the `WRSS` instruction.
+const CET_WRSS_EN: u64 = 1 << 1;
+
+/// Returns whether the CPU supports shadow stacks
+/// (`CPUID.(EAX=7,ECX=0):ECX.CET_SS`).
+pub fn is_supported() -> bool {
+    let leaf = unsafe { core::arch::x86_64::__cpuid_count(7, 0) };
+    leaf.ecx & (1 << 7) != 0
+}
+
+/// Returns the restore token for a shadow stack whose pointer is `ssp`, and
+/// the address it is stored at, right below `ssp`.
+///
+/// `RSTORSSP` switches to the stack only if the token at the new stack
+/// pointer holds the pointer above it, with bit 0 set for 64-bit mode.
+pub fn restore_token(ssp: usize) -> (usize, u64) {
+    let addr = (ssp & !7) - 8;
+    (addr, ssp as u64 | 1)
+}
+
+/// Enables shadow stacks for user mode, with the stack pointer `ssp`, and
+/// the `WRSS` instruction if `wrss` is set.
+///
+/// # Safety
+///
+/// `CR4.CET` must be set, and `ssp` must point into a shadow stack mapping
+/// of the user address space.
+pub unsafe fn enable_user(ssp: usize, wrss: bool) {
+    let mut cet = CET_SHSTK_EN;
+    if wrss {
+        cet |= CET_WRSS_EN;
+    }
+    unsafe {
+        Msr::new(IA32_PL3_SSP).write(ssp as u64);
+        Msr::new(IA32_U_CET).write(cet);
+    }
+}
+
+/// Disables shadow stacks for user mode.
+///
+/// # Safety
+///
+/// The CPU must support shadow stacks (see [`is_supported`]).
+pub unsafe fn disable_user() {
+    unsafe { Msr::new(IA32_U_CET).write(0) };
+}
+
+/// Returns the user-mode shadow stack pointer.
+///
+/// # Safety
+///
+/// The CPU must support shadow stacks (see [`is_supported`]).
+pub unsafe fn user_ssp() -> usize {
+    unsafe { Msr::new(IA32_PL3_SSP).read() as usize }
+}
+
+/// Sets the user-mode shadow stack pointer, e.g. on context switch.
+///
+/// # Safety
+///
+/// Same as [`enable_user`].
+pub unsafe fn set_user_ssp(ssp: usize) {
+    unsafe { Msr::new(IA32_PL3_SSP).write(ssp as u64) };
+}
@@ -24,6 +24,8 @@ mod power;
 mod smp;
 mod time;
 
+pub use power::system_reset;
+
 pub mod config {
     axconfig_macros::include_configs!(path_env = "AX_CONFIG_PATH", fallback = "axconfig.toml");
 }
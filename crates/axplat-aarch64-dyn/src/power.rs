@@ -1,5 +1,5 @@
 use axplat::power::PowerIf;
-use log::info;
+use log::{info, warn};
 
 struct PowerImpl;
 
@@ -30,3 +30,21 @@ impl PowerIf for PowerImpl {
         somehal::power::shutdown()
     }
 }
+
+/// Resets the whole system through PSCI `SYSTEM_RESET`, using the conduit
+/// given by the `/psci` node of the FDT.
+///
+/// Only returns if the firmware fails to reset the system.
+pub fn system_reset() {
+    let hvc = crate::fdt()
+        .find_nodes("/psci")
+        .next()
+        .and_then(|node| node.find_property("method"))
+        .is_some_and(|method| method.str() == "hvc");
+    let result = if hvc {
+        smccc::psci::system_reset::<smccc::Hvc>()
+    } else {
+        smccc::psci::system_reset::<smccc::Smc>()
+    };
+    warn!("PSCI SYSTEM_RESET failed: {result:?}");
+}
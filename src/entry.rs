@@ -9,7 +9,8 @@ use axsync::Mutex;
 use axtask::{TaskExtProxy, spawn_task};
 use starry_api::{file::FD_TABLE, task::new_user_task, vfs::dev::tty::N_TTY};
 use starry_core::{
-    mm::{copy_from_kernel, load_user_app, new_user_aspace_empty},
+    cred::Credentials,
+    mm::{RangeMap, copy_from_kernel, load_user_app, new_user_aspace_empty},
     task::{ProcessData, Thread, add_task_to_table},
 };
 use starry_process::{Pid, Process};
@@ -42,8 +43,17 @@ pub fn run_initproc(args: &[String], envs: &[String]) -> i32 {
         .expect("Failed to get executable absolute path");
     let name = loc.name();
 
-    let (entry_vaddr, ustack_top) = load_user_app(&mut uspace, None, args, envs)
-        .unwrap_or_else(|e| panic!("Failed to load user app: {}", e));
+    let mut file_maps = RangeMap::new();
+    let (entry_vaddr, ustack_top) = load_user_app(
+        &mut uspace,
+        &mut file_maps,
+        None,
+        args,
+        envs,
+        &|_| Ok(()),
+        &Credentials::default(),
+    )
+    .unwrap_or_else(|e| panic!("Failed to load user app: {}", e));
 
     let uctx = UserContext::new(entry_vaddr.into(), ustack_top, 0);
 
@@ -66,7 +76,8 @@ pub fn run_initproc(args: &[String], envs: &[String]) -> i32 {
         Arc::default(),
         None,
     );
-        
+    *proc_data.file_maps.lock() = file_maps;
+
     // Set the working directory for the process
     if let Some(dir) = rknn_dir {
         let mut scope = proc_data.scope.write();
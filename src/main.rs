@@ -13,6 +13,7 @@ extern crate axdriver_dyn;
 
 use alloc::{borrow::ToOwned, vec::Vec};
 
+use axerrno::AxError;
 use axfs_ng::FS_CONTEXT;
 
 mod entry;
@@ -26,10 +27,15 @@ pub const CMDLINE: &[&str] = &["/bin/sh", "-c", include_str!("init.sh")];
 // pub const CMDLINE: &[&str] = &["/reverse/matmul_fp16_fp16", "1", "768", "768"];
 // pub const CMDLINE: &[&str] = &["/reverse/bench_mark", "2"];
 
+/// The devices that `/dev/console` writes to, like the `console=` kernel
+/// parameter. Input is read from the last one.
+pub const CONSOLE: &str = "ttyS0";
+
 
 #[unsafe(no_mangle)]
 fn main() {
     starry_api::init();
+    starry_api::vfs::dev::tty::console::set_console_list(CONSOLE).expect("Invalid console list");
 
     let args = CMDLINE
         .iter()
@@ -40,6 +46,10 @@ fn main() {
     let exit_code = entry::run_initproc(&args, &envs);
     info!("Init process exited with code: {exit_code:?}");
 
+    // Loop devices write to files on the mounted filesystems, so they're
+    // flushed before those are. Flushing the root filesystem then flushes
+    // the disk it's on.
+    flush_all_block_devices();
     let cx = FS_CONTEXT.lock();
     cx.root_dir()
         .unmount_all()
@@ -50,6 +60,17 @@ fn main() {
         .expect("Failed to flush rootfs");
 }
 
+/// Flushes every registered block device so that data written through them
+/// reaches the backing storage before shutdown.
+fn flush_all_block_devices() {
+    for (name, dev) in starry_api::vfs::dev::block_devices() {
+        match dev.flush() {
+            Ok(()) | Err(AxError::OperationNotSupported) => {}
+            Err(err) => warn!("Failed to flush block device {name}: {err:?}"),
+        }
+    }
+}
+
 #[cfg(feature = "vf2")]
 extern crate axplat_riscv64_visionfive2;
 